@@ -0,0 +1,743 @@
+use crate::{
+  client::CallAuth, AuthScheme, Session, SkynetClient, SkynetError::*, SkynetResult, util::{make_uri, parse_response, read_body, status_error, QueryParam, QueryParams},
+  skylink::normalize_skylink, URI_SKYNET_PREFIX,
+};
+use std::str;
+use hyper::{header::SET_COOKIE, Body, HeaderMap, Method, Request, StatusCode, Uri};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value as Json;
+
+const PAGE_SIZE: usize = 100;
+
+/// The cookie a portal login sets to hold the account's session token.
+pub const JWT_COOKIE_NAME: &str = "skynet-jwt";
+
+#[derive(Debug, Default)]
+pub struct AccountOptions {
+  pub endpoint_path: Option<String>,
+  pub api_key: Option<String>,
+  pub auth: Option<AuthScheme>,
+  pub custom_user_agent: Option<String>,
+}
+
+/// Redundancy of a skylink's data on the hosts, as reported by the portal.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SkylinkHealth {
+  #[serde(rename = "basesectorredundancy")]
+  pub base_sector_redundancy: u64,
+  #[serde(rename = "fanoutredundancy", default)]
+  pub fanout_redundancy: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PinInfo {
+  pub skylink: String,
+  /// Whether the skylink is among the authenticated account's uploads.
+  pub pinned: bool,
+  /// Size in bytes charged against the account, if pinned.
+  pub size: Option<u64>,
+  /// `None` when the portal doesn't expose skylink health.
+  pub health: Option<SkylinkHealth>,
+}
+
+#[derive(Deserialize)]
+struct Upload {
+  skylink: String,
+  size: u64,
+}
+
+#[derive(Deserialize)]
+struct Download {
+  skylink: String,
+  #[serde(default)]
+  size: u64,
+}
+
+/// One page of a paginated account listing such as `/user/uploads`.
+#[derive(Deserialize)]
+struct PageResponse<T> {
+  items: Vec<T>,
+  count: usize,
+}
+
+/// How often and how much of a skylink the authenticated account downloaded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DownloadStats {
+  pub skylink: String,
+  pub downloads: u64,
+  /// Total bytes downloaded.
+  pub bandwidth: u64,
+}
+
+/// The authenticated account.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserInfo {
+  /// The account's ID.
+  pub sub: String,
+  #[serde(default)]
+  pub email: Option<String>,
+  #[serde(default)]
+  pub username: Option<String>,
+  pub tier: u32,
+  #[serde(default)]
+  pub created_at: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Credentials<'a> {
+  email: &'a str,
+  password: &'a str,
+}
+
+/// Limits of the authenticated account's tier.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UserLimits {
+  #[serde(rename = "tierID")]
+  pub tier_id: u32,
+  pub tier_name: String,
+  /// Largest single upload in bytes.
+  pub max_upload_size: u64,
+  #[serde(default)]
+  pub storage_limit: Option<u64>,
+}
+
+/// A portal API key. Public keys only grant access to their listed skylinks.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ApiKey {
+  pub id: String,
+  /// The secret itself, only returned when the key is created.
+  #[serde(default)]
+  pub key: Option<String>,
+  #[serde(default)]
+  pub public: bool,
+  #[serde(default)]
+  pub skylinks: Vec<String>,
+  pub created_at: String,
+  /// `None` if the key was never used or the portal doesn't track usage.
+  #[serde(default)]
+  pub last_used: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApiKeyScope {
+  /// Full access to the account.
+  Account,
+  /// Access limited to these skylinks.
+  Skylinks(Vec<String>),
+}
+
+impl ApiKey {
+  pub fn scope(&self) -> ApiKeyScope {
+    if self.public {
+      ApiKeyScope::Skylinks(self.skylinks.clone())
+    } else {
+      ApiKeyScope::Account
+    }
+  }
+}
+
+#[derive(Serialize)]
+struct ApiKeyRequest {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  public: Option<bool>,
+  skylinks: Vec<String>,
+}
+
+impl ApiKeyRequest {
+  fn new(public: Option<bool>, skylinks: &[&str]) -> Self {
+    Self {
+      public,
+      skylinks: skylinks
+        .iter()
+        .map(|skylink| skylink.strip_prefix(URI_SKYNET_PREFIX).unwrap_or(skylink).to_string())
+        .collect(),
+    }
+  }
+}
+
+/// Sends a request to an account endpoint. Returns the URI, status and body of a successful
+/// response.
+async fn send(
+  client: &SkynetClient,
+  method: Method,
+  endpoint_path: String,
+  extra_path: Option<String>,
+  query: QueryParams,
+  json: Option<Json>,
+  opt: &AccountOptions,
+) -> SkynetResult<(Uri, StatusCode, String)> {
+  let (uri, status, _, body) = send_with_headers(client, method, endpoint_path, extra_path, query, json, opt).await?;
+  Ok((uri, status, body))
+}
+
+/// `send`, also returning the response headers.
+async fn send_with_headers(
+  client: &SkynetClient,
+  method: Method,
+  endpoint_path: String,
+  extra_path: Option<String>,
+  query: QueryParams,
+  json: Option<Json>,
+  opt: &AccountOptions,
+) -> SkynetResult<(Uri, StatusCode, HeaderMap, String)> {
+  let req = Request::builder().method(method);
+
+  let uri = make_uri(
+    &client.get_portal_url(),
+    endpoint_path,
+    opt.api_key.clone(),
+    extra_path,
+    query);
+
+  let mut req = req.uri(uri.clone());
+
+  if let Some(apikey) = &opt.api_key {
+    req = req.header("Skynet-Api-Key", apikey.clone());
+  }
+
+  if let Some(custom_user_agent) = &opt.custom_user_agent {
+    req = req.header("User-Agent", custom_user_agent.clone());
+  }
+
+  req = req.extension(CallAuth(opt.auth.clone()));
+
+  let body = match json {
+    Some(json) => {
+      req = req.header("Content-Type", "application/json");
+      Body::from(json.to_string())
+    }
+    None => Body::from(""),
+  };
+
+  let req = req.body(body).map_err(HttpError)?;
+  let res = client.request(req).await?;
+  let (parts, body) = res.into_parts();
+  let body = read_body(&parts, body).await?;
+
+  if !parts.status.is_success() {
+    return Err(status_error(&parts.extensions, &uri, parts.status, &body));
+  }
+
+  let body_str = str::from_utf8(&body).map_err(Utf8Error)?.to_string();
+  Ok((uri, parts.status, parts.headers, body_str))
+}
+
+async fn get_json<T: DeserializeOwned>(
+  client: &SkynetClient,
+  endpoint_path: String,
+  extra_path: Option<String>,
+  query: QueryParams,
+  opt: &AccountOptions,
+) -> SkynetResult<T> {
+  let (uri, status, body) = send(client, Method::GET, endpoint_path, extra_path, query, None, opt).await?;
+  parse_response(&uri, status, &body)
+}
+
+fn api_keys_path(opt: &AccountOptions) -> String {
+  opt.endpoint_path.clone().unwrap_or_else(|| "/user/apikeys".into())
+}
+
+/// The session cookie among the `Set-Cookie` headers of a login or registration response from
+/// `uri`.
+fn session_cookie(uri: &Uri, headers: &HeaderMap) -> Option<Session> {
+  let host = uri.authority()?.to_string();
+  headers
+    .get_all(SET_COOKIE)
+    .iter()
+    .filter_map(|cookie| cookie.to_str().ok())
+    .filter_map(|cookie| cookie.split(';').next()?.trim().split_once('='))
+    .find(|(name, value)| *name == JWT_COOKIE_NAME && !value.is_empty())
+    .map(|(name, value)| Session {
+      host,
+      auth: AuthScheme::Cookie { name: name.to_string(), value: value.to_string() },
+    })
+}
+
+/// Creates a portal account. If the portal logs the new account in straight away, `client` and
+/// its clones are logged in to it as with `login`. `endpoint_path` overrides the registration
+/// endpoint.
+pub async fn register(
+  client: &SkynetClient,
+  email: &str,
+  password: &str,
+  opt: AccountOptions,
+) -> SkynetResult<UserInfo> {
+  let endpoint_path = opt.endpoint_path.clone().unwrap_or_else(|| "/register".into());
+  let json = serde_json::to_value(Credentials { email, password }).map_err(JsonError)?;
+
+  let (uri, status, headers, body) =
+    send_with_headers(client, Method::POST, endpoint_path, None, QueryParams::new(), Some(json), &opt).await?;
+  if let Some(session) = session_cookie(&uri, &headers) {
+    client.set_session(Some(session));
+  }
+  parse_response(&uri, status, &body)
+}
+
+/// Logs `client` and its clones in to a portal account. The JWT cookie the portal sets is sent
+/// with every request to that portal from then on, uploads and downloads included, until
+/// `logout`. It can be
+/// saved with `SkynetClient::get_session` and restored with `set_session`. `endpoint_path`
+/// overrides the login endpoint.
+pub async fn login(
+  client: &SkynetClient,
+  email: &str,
+  password: &str,
+  opt: AccountOptions,
+) -> SkynetResult<()> {
+  let endpoint_path = opt.endpoint_path.clone().unwrap_or_else(|| "/login".into());
+  let json = serde_json::to_value(Credentials { email, password }).map_err(JsonError)?;
+
+  let (uri, _, headers, _) =
+    send_with_headers(client, Method::POST, endpoint_path, None, QueryParams::new(), Some(json), &opt).await?;
+  let session = session_cookie(&uri, &headers)
+    .ok_or_else(|| PortalResponse(format!("{} didn't set the {} cookie", uri, JWT_COOKIE_NAME)))?;
+  client.set_session(Some(session));
+  Ok(())
+}
+
+/// Ends the session on the portal and forgets it, even if the portal couldn't be reached.
+/// `endpoint_path` overrides the logout endpoint.
+pub async fn logout(
+  client: &SkynetClient,
+  opt: AccountOptions,
+) -> SkynetResult<()> {
+  let endpoint_path = opt.endpoint_path.clone().unwrap_or_else(|| "/logout".into());
+
+  let res = send(client, Method::POST, endpoint_path, None, QueryParams::new(), None, &opt).await;
+  client.set_session(None);
+  res.map(|_| ())
+}
+
+pub async fn get_user(
+  client: &SkynetClient,
+  opt: AccountOptions,
+) -> SkynetResult<UserInfo> {
+  let endpoint_path = opt.endpoint_path.clone().unwrap_or_else(|| "/user".into());
+
+  get_json(client, endpoint_path, None, QueryParams::new(), &opt).await
+}
+
+pub async fn get_user_limits(
+  client: &SkynetClient,
+  opt: AccountOptions,
+) -> SkynetResult<UserLimits> {
+  let endpoint_path = opt.endpoint_path.clone().unwrap_or_else(|| "/user/limits".into());
+
+  get_json(client, endpoint_path, None, QueryParams::new(), &opt).await
+}
+
+pub async fn get_skylink_health(
+  client: &SkynetClient,
+  skylink: &str,
+  opt: AccountOptions,
+) -> SkynetResult<SkylinkHealth> {
+  let skylink = skylink.strip_prefix(URI_SKYNET_PREFIX).unwrap_or(skylink);
+  let endpoint_path = opt.endpoint_path.clone().unwrap_or_else(|| "/skynet/health/skylink".into());
+
+  get_json(client, endpoint_path, Some(skylink.to_string()), QueryParams::new(), &opt).await
+}
+
+/// Looks the skylink up in the account's uploads and fetches its health where the portal
+/// exposes it. `endpoint_path` overrides the uploads endpoint.
+pub async fn get_pin_info(
+  client: &SkynetClient,
+  skylink: &str,
+  opt: AccountOptions,
+) -> SkynetResult<PinInfo> {
+  let skylink = skylink.strip_prefix(URI_SKYNET_PREFIX).unwrap_or(skylink);
+  let endpoint_path = opt.endpoint_path.clone().unwrap_or_else(|| "/user/uploads".into());
+
+  let mut size = None;
+  let mut offset = 0;
+  loop {
+    let mut query = QueryParams::new();
+    query.set(QueryParam::Offset, offset.to_string());
+    query.set(QueryParam::PageSize, PAGE_SIZE.to_string());
+
+    let page: PageResponse<Upload> = get_json(client, endpoint_path.clone(), None, query, &opt).await?;
+    if let Some(upload) = page.items.iter().find(|upload| upload.skylink == skylink) {
+      size = Some(upload.size);
+      break;
+    }
+
+    offset += page.items.len();
+    if page.items.is_empty() || offset >= page.count {
+      break;
+    }
+  }
+
+  let health = get_skylink_health(client, skylink, AccountOptions {
+    endpoint_path: None,
+    ..opt
+  }).await.ok();
+
+  Ok(PinInfo {
+    skylink: skylink.to_string(),
+    pinned: size.is_some(),
+    size,
+    health,
+  })
+}
+
+/// Download counts and bandwidth per skylink from the account's download history, most
+/// downloaded first. `endpoint_path` overrides the downloads endpoint.
+pub async fn get_download_stats(
+  client: &SkynetClient,
+  opt: AccountOptions,
+) -> SkynetResult<Vec<DownloadStats>> {
+  let endpoint_path = opt.endpoint_path.clone().unwrap_or_else(|| "/user/downloads".into());
+
+  let mut stats: Vec<DownloadStats> = Vec::new();
+  let mut offset = 0;
+  loop {
+    let mut query = QueryParams::new();
+    query.set(QueryParam::Offset, offset.to_string());
+    query.set(QueryParam::PageSize, PAGE_SIZE.to_string());
+
+    let page: PageResponse<Download> = get_json(client, endpoint_path.clone(), None, query, &opt).await?;
+    for download in &page.items {
+      match stats.iter_mut().find(|stats| stats.skylink == download.skylink) {
+        Some(stats) => {
+          stats.downloads += 1;
+          stats.bandwidth += download.size;
+        }
+        None => stats.push(DownloadStats {
+          skylink: download.skylink.clone(),
+          downloads: 1,
+          bandwidth: download.size,
+        }),
+      }
+    }
+
+    offset += page.items.len();
+    if page.items.is_empty() || offset >= page.count {
+      break;
+    }
+  }
+
+  stats.sort_by(|a, b| b.downloads.cmp(&a.downloads).then(b.bandwidth.cmp(&a.bandwidth)));
+  Ok(stats)
+}
+
+/// Creates a public API key that can only access `skylinks`. The secret is in `ApiKey::key`.
+pub async fn create_public_api_key(
+  client: &SkynetClient,
+  skylinks: &[&str],
+  opt: AccountOptions,
+) -> SkynetResult<ApiKey> {
+  let json = serde_json::to_value(ApiKeyRequest::new(Some(true), skylinks)).map_err(JsonError)?;
+  let (uri, status, body) = send(client, Method::POST, api_keys_path(&opt), None, QueryParams::new(), Some(json), &opt).await?;
+  parse_response(&uri, status, &body)
+}
+
+pub async fn get_api_key(
+  client: &SkynetClient,
+  id: &str,
+  opt: AccountOptions,
+) -> SkynetResult<ApiKey> {
+  get_json(client, api_keys_path(&opt), Some(id.to_string()), QueryParams::new(), &opt).await
+}
+
+/// Replaces the skylinks a public API key grants access to.
+pub async fn update_api_key_skylinks(
+  client: &SkynetClient,
+  id: &str,
+  skylinks: &[&str],
+  opt: AccountOptions,
+) -> SkynetResult<()> {
+  let json = serde_json::to_value(ApiKeyRequest::new(None, skylinks)).map_err(JsonError)?;
+  send(client, Method::PUT, api_keys_path(&opt), Some(id.to_string()), QueryParams::new(), Some(json), &opt).await?;
+  Ok(())
+}
+
+/// Every API key of the authenticated account, public or not.
+pub async fn list_api_keys(
+  client: &SkynetClient,
+  opt: AccountOptions,
+) -> SkynetResult<Vec<ApiKey>> {
+  get_json(client, api_keys_path(&opt), None, QueryParams::new(), &opt).await
+}
+
+/// A download URL carrying a public API key scoped to its skylink.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShareUrl {
+  pub url: String,
+  /// The key embedded in `url`. Delete it with `delete_api_key` to stop the URL working.
+  pub api_key: ApiKey,
+}
+
+/// Mints a public API key for `skylink` alone and returns a download URL embedding it, so
+/// content that needs an account can be shared without giving out the account's own key.
+pub async fn create_share_url(
+  client: &SkynetClient,
+  skylink: &str,
+  opt: AccountOptions,
+) -> SkynetResult<ShareUrl> {
+  let skylink = normalize_skylink(skylink)?;
+  // Keys are scoped to whole skylinks, so a link to a subfile grants the skylink it's in.
+  let base = skylink.split('/').next().unwrap_or_default();
+  let api_key = create_public_api_key(client, &[base], opt).await?;
+  let key = api_key
+    .key
+    .clone()
+    .ok_or_else(|| PortalResponse(format!("public API key {} was created without its secret", api_key.id)))?;
+
+  let url = make_uri(
+    &client.get_portal_url(),
+    String::new(),
+    None,
+    Some(skylink),
+    QueryParams::new().with(QueryParam::ApiKey, key));
+
+  Ok(ShareUrl { url: url.to_string(), api_key })
+}
+
+/// Revokes an API key. Requests using it fail from then on.
+pub async fn delete_api_key(
+  client: &SkynetClient,
+  id: &str,
+  opt: AccountOptions,
+) -> SkynetResult<()> {
+  send(client, Method::DELETE, api_keys_path(&opt), Some(id.to_string()), QueryParams::new(), None, &opt).await?;
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{MockResponse, MockTransport};
+  use serde_json::json;
+
+  #[tokio::test]
+  async fn test_login_session() {
+    let transport = MockTransport::new();
+    transport.push_response(
+      MockResponse::new(StatusCode::NO_CONTENT, "")
+        .with_header("set-cookie", "other=1; Path=/")
+        .with_header("set-cookie", "skynet-jwt=jwt123; Path=/; HttpOnly"),
+    );
+    transport.push_response(MockResponse::json(json!({ "sub": "u1", "email": "a@b.c", "tier": 1 })));
+    let client = transport.client("https://account.siasky.net");
+
+    let res = login(&client, "a@b.c", "hunter2", AccountOptions::default()).await;
+    println!("{:?}", res);
+    assert!(res.is_ok());
+    let request = transport.last_request().unwrap();
+    assert_eq!(request.uri.path(), "/login");
+    let body: Json = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(body, json!({ "email": "a@b.c", "password": "hunter2" }));
+    let auth = AuthScheme::Cookie { name: "skynet-jwt".into(), value: "jwt123".into() };
+    assert_eq!(client.get_session(), Some(Session { host: "account.siasky.net".into(), auth }));
+
+    // Clones share the session, and every request carries it.
+    let user = get_user(&client.clone(), AccountOptions::default()).await.unwrap();
+    assert_eq!(user.sub, "u1");
+    assert_eq!(user.tier, 1);
+    assert_eq!(transport.last_request().unwrap().headers["cookie"], "skynet-jwt=jwt123");
+    let _ = client.download_data("AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng", Default::default()).await;
+    assert_eq!(transport.last_request().unwrap().headers["cookie"], "skynet-jwt=jwt123");
+
+    // Only that portal gets it.
+    client.set_portal_url("https://siasky.net");
+    let _ = client.download_data("AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng", Default::default()).await;
+    assert!(!transport.last_request().unwrap().headers.contains_key("cookie"));
+    client.set_portal_url("https://account.siasky.net");
+
+    transport.push_response(MockResponse::new(StatusCode::NO_CONTENT, ""));
+    assert!(logout(&client, AccountOptions::default()).await.is_ok());
+    assert_eq!(client.get_session(), None);
+
+    transport.push_response(MockResponse::new(StatusCode::NO_CONTENT, ""));
+    assert!(matches!(login(&client, "a@b.c", "wrong", AccountOptions::default()).await, Err(PortalResponse(_))));
+  }
+
+  #[tokio::test]
+  async fn test_get_pin_info() {
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::json(json!({
+      "items": [{ "id": "1", "skylink": "AAA", "name": "a.txt", "size": 10 }],
+      "offset": 0,
+      "pageSize": 1,
+      "count": 2,
+    })));
+    transport.push_response(MockResponse::json(json!({
+      "items": [{ "id": "2", "skylink": "BBB", "name": "b.txt", "size": 4194304 }],
+      "offset": 1,
+      "pageSize": 1,
+      "count": 2,
+    })));
+    transport.push_response(MockResponse::json(json!({
+      "basesectorredundancy": 10,
+      "fanoutredundancy": 3.5,
+    })));
+    let client = transport.client("https://siasky.net");
+
+    let res = get_pin_info(&client, "sia://BBB", AccountOptions {
+      api_key: Some("foo".into()),
+      ..Default::default()
+    }).await;
+    println!("{:?}", res);
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap(), PinInfo {
+      skylink: "BBB".into(),
+      pinned: true,
+      size: Some(4194304),
+      health: Some(SkylinkHealth {
+        base_sector_redundancy: 10,
+        fanout_redundancy: 3.5,
+      }),
+    });
+
+    let requests = transport.requests();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(requests[1].uri.path(), "/user/uploads");
+    assert_eq!(requests[1].headers["skynet-api-key"], "foo");
+    assert_eq!(requests[2].uri.path(), "/skynet/health/skylink/BBB");
+  }
+
+  #[tokio::test]
+  async fn test_get_pin_info_not_pinned() {
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::json(json!({ "items": [], "offset": 0, "pageSize": 100, "count": 0 })));
+    let client = transport.client("https://siasky.net");
+
+    let info = get_pin_info(&client, "AAA", AccountOptions::default()).await.unwrap();
+    assert!(!info.pinned);
+    assert_eq!(info.size, None);
+    assert_eq!(info.health, None);
+  }
+
+  #[tokio::test]
+  async fn test_public_api_keys() {
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::json(json!({
+      "id": "k1",
+      "key": "secret",
+      "public": true,
+      "skylinks": ["AAA"],
+      "createdAt": "2021-06-01T00:00:00Z",
+    })));
+    transport.push_response(MockResponse::new(StatusCode::NO_CONTENT, ""));
+    transport.push_response(MockResponse::json(json!({
+      "id": "k1",
+      "public": true,
+      "skylinks": ["AAA", "BBB"],
+      "createdAt": "2021-06-01T00:00:00Z",
+    })));
+    transport.push_response(MockResponse::new(StatusCode::NOT_FOUND, "{\"message\":\"not found\"}"));
+    let client = transport.client("https://account.siasky.net");
+
+    let key = create_public_api_key(&client, &["sia://AAA"], AccountOptions::default()).await.unwrap();
+    assert_eq!(key.key.as_deref(), Some("secret"));
+    let request = transport.last_request().unwrap();
+    assert_eq!(request.method, Method::POST);
+    assert_eq!(request.uri.path(), "/user/apikeys");
+    let body: Json = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(body, json!({ "public": true, "skylinks": ["AAA"] }));
+
+    let res = update_api_key_skylinks(&client, &key.id, &["AAA", "BBB"], AccountOptions::default()).await;
+    assert!(res.is_ok());
+    let request = transport.last_request().unwrap();
+    assert_eq!(request.method, Method::PUT);
+    assert_eq!(request.uri.path(), "/user/apikeys/k1");
+    let body: Json = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(body, json!({ "skylinks": ["AAA", "BBB"] }));
+
+    let key = get_api_key(&client, "k1", AccountOptions::default()).await.unwrap();
+    assert_eq!(key.skylinks, vec!["AAA".to_string(), "BBB".to_string()]);
+    assert_eq!(key.key, None);
+
+    let res = get_api_key(&client, "k2", AccountOptions::default()).await;
+    assert!(matches!(res.map_err(crate::SkynetError::into_inner), Err(PortalResponse(_))));
+  }
+
+  #[tokio::test]
+  async fn test_list_delete_api_keys() {
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::json(json!([
+      { "id": "k1", "public": false, "createdAt": "2021-06-01T00:00:00Z", "lastUsed": "2021-06-02T00:00:00Z" },
+      { "id": "k2", "public": true, "skylinks": ["AAA"], "createdAt": "2021-06-03T00:00:00Z" },
+    ])));
+    transport.push_response(MockResponse::new(StatusCode::NO_CONTENT, ""));
+    let client = transport.client("https://account.siasky.net");
+
+    let res = list_api_keys(&client, AccountOptions::default()).await;
+    println!("{:?}", res);
+    let keys = res.unwrap();
+    assert_eq!(keys.len(), 2);
+    assert_eq!(keys[0].scope(), ApiKeyScope::Account);
+    assert_eq!(keys[0].last_used.as_deref(), Some("2021-06-02T00:00:00Z"));
+    assert_eq!(keys[1].scope(), ApiKeyScope::Skylinks(vec!["AAA".into()]));
+    assert_eq!(keys[1].last_used, None);
+
+    assert!(delete_api_key(&client, "k1", AccountOptions::default()).await.is_ok());
+    let request = transport.last_request().unwrap();
+    assert_eq!(request.method, Method::DELETE);
+    assert_eq!(request.uri.path(), "/user/apikeys/k1");
+  }
+
+  #[tokio::test]
+  async fn test_get_download_stats() {
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::json(json!({
+      "items": [
+        { "id": "1", "skylink": "AAA", "name": "a.txt", "size": 10 },
+        { "id": "2", "skylink": "BBB", "name": "b.txt", "size": 100 },
+      ],
+      "offset": 0,
+      "pageSize": 2,
+      "count": 3,
+    })));
+    transport.push_response(MockResponse::json(json!({
+      "items": [{ "id": "3", "skylink": "AAA", "name": "a.txt", "size": 10 }],
+      "offset": 2,
+      "pageSize": 2,
+      "count": 3,
+    })));
+    let client = transport.client("https://account.siasky.net");
+
+    let res = get_download_stats(&client, AccountOptions::default()).await;
+    println!("{:?}", res);
+    assert_eq!(res.unwrap(), vec![
+      DownloadStats { skylink: "AAA".into(), downloads: 2, bandwidth: 20 },
+      DownloadStats { skylink: "BBB".into(), downloads: 1, bandwidth: 100 },
+    ]);
+
+    let requests = transport.requests();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[1].uri.path(), "/user/downloads");
+    assert!(requests[1].uri.query().unwrap().contains("offset=2"));
+  }
+
+  #[tokio::test]
+  async fn test_create_share_url() {
+    let transport = MockTransport::new();
+    let skylink = "AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng";
+    transport.push_response(MockResponse::json(json!({
+      "id": "k1",
+      "key": "s3cr+t",
+      "public": true,
+      "skylinks": [skylink],
+      "createdAt": "2021-06-01T00:00:00Z",
+    })));
+    transport.push_response(MockResponse::json(json!({
+      "id": "k2",
+      "public": true,
+      "skylinks": [skylink],
+      "createdAt": "2021-06-01T00:00:00Z",
+    })));
+    let client = transport.client("https://siasky.net");
+
+    let share = create_share_url(&client, &format!("sia://{}/docs/a.txt", skylink), AccountOptions::default()).await.unwrap();
+    assert_eq!(share.url, format!("https://siasky.net/{}/docs/a.txt?apiKey=s3cr%2Bt", skylink));
+    assert_eq!(share.api_key.id, "k1");
+    let body: Json = serde_json::from_slice(&transport.last_request().unwrap().body).unwrap();
+    assert_eq!(body, json!({ "public": true, "skylinks": [skylink] }));
+
+    let res = create_share_url(&client, skylink, AccountOptions::default()).await;
+    assert!(matches!(res, Err(PortalResponse(_))));
+  }
+}
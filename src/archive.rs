@@ -0,0 +1,125 @@
+use std::{
+  fs::{self, File},
+  io::{self, Read},
+  path::Path,
+};
+use flate2::read::GzDecoder;
+
+/// Archive formats portals can serve a directory skylink as, with `?format=`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+  Tar,
+  TarGz,
+  Zip,
+}
+
+impl ArchiveFormat {
+  /// The value of the `format` query parameter.
+  pub fn as_str(&self) -> &'static str {
+    match self {
+      Self::Tar => "tar",
+      Self::TarGz => "targz",
+      Self::Zip => "zip",
+    }
+  }
+}
+
+/// Extracts the archive `reader` streams into `dir`, one entry at a time. Entries that would
+/// land outside `dir` are skipped, as are links, which could point anywhere.
+pub(crate) fn extract<R: Read>(reader: R, format: ArchiveFormat, dir: &Path) -> io::Result<()> {
+  fs::create_dir_all(dir)?;
+  match format {
+    ArchiveFormat::Tar => extract_tar(reader, dir),
+    ArchiveFormat::TarGz => extract_tar(GzDecoder::new(reader), dir),
+    ArchiveFormat::Zip => extract_zip(reader, dir),
+  }
+}
+
+fn extract_tar<R: Read>(reader: R, dir: &Path) -> io::Result<()> {
+  let mut archive = tar::Archive::new(reader);
+  for entry in archive.entries()? {
+    let mut entry = entry?;
+    // Only files and directories: a symlink would let later entries, or the attributes applied
+    // after extracting, reach outside `dir`.
+    if !matches!(entry.header().entry_type(), tar::EntryType::Regular | tar::EntryType::Directory) {
+      continue;
+    }
+    // `unpack_in` refuses paths escaping `dir` itself.
+    entry.unpack_in(dir)?;
+  }
+  Ok(())
+}
+
+fn extract_zip<R: Read>(mut reader: R, dir: &Path) -> io::Result<()> {
+  let zip_error = |err| io::Error::new(io::ErrorKind::InvalidData, err);
+
+  // Reads local headers as they come rather than seeking to the central directory at the end.
+  while let Some(mut file) = zip::read::read_zipfile_from_stream(&mut reader).map_err(zip_error)? {
+    let target = match file.enclosed_name() {
+      Some(name) => dir.join(name),
+      None => continue,
+    };
+
+    if file.is_dir() {
+      fs::create_dir_all(&target)?;
+      continue;
+    }
+    if let Some(parent) = target.parent() {
+      fs::create_dir_all(parent)?;
+    }
+    io::copy(&mut file, &mut File::create(&target)?)?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::io::{Cursor, Write};
+
+  fn tar_of(files: &[(&str, &str)]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for (name, contents) in files {
+      let mut header = tar::Header::new_gnu();
+      header.set_size(contents.len() as u64);
+      header.set_mode(0o644);
+      header.set_cksum();
+      builder.append_data(&mut header, name, contents.as_bytes()).unwrap();
+    }
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Symlink);
+    header.set_size(0);
+    header.set_link_name("..").unwrap();
+    header.set_cksum();
+    builder.append_data(&mut header, "link", io::empty()).unwrap();
+    builder.into_inner().unwrap()
+  }
+
+  #[test]
+  fn test_extract() {
+    let files = [("a.txt", "a"), ("sub/b.txt", "bb")];
+
+    extract(&tar_of(&files)[..], ArchiveFormat::Tar, Path::new("tmp_archive/tar")).unwrap();
+
+    let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    gz.write_all(&tar_of(&files)).unwrap();
+    extract(&gz.finish().unwrap()[..], ArchiveFormat::TarGz, Path::new("tmp_archive/targz")).unwrap();
+
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, contents) in files.iter().chain(&[("../escaped.txt", "x")]) {
+      zip.start_file(*name, Default::default()).unwrap();
+      zip.write_all(contents.as_bytes()).unwrap();
+    }
+    let zip = zip.finish().unwrap().into_inner();
+    extract(&zip[..], ArchiveFormat::Zip, Path::new("tmp_archive/zip")).unwrap();
+
+    for format in &["tar", "targz", "zip"] {
+      assert_eq!(fs::read_to_string(format!("tmp_archive/{}/a.txt", format)).unwrap(), "a");
+      assert_eq!(fs::read_to_string(format!("tmp_archive/{}/sub/b.txt", format)).unwrap(), "bb");
+    }
+    assert!(fs::symlink_metadata("tmp_archive/tar/link").is_err());
+    assert!(fs::symlink_metadata("tmp_archive/targz/link").is_err());
+    assert!(!Path::new("tmp_archive/escaped.txt").exists());
+    fs::remove_dir_all("tmp_archive").unwrap();
+  }
+}
@@ -0,0 +1,97 @@
+use std::fmt;
+use hyper::{header::HeaderValue, HeaderMap, Uri};
+
+/// How requests authenticate with the portal. Set once on the client and sent with every
+/// request, including TUS uploads. The `auth` of an operation's options replaces it for that
+/// call.
+#[derive(Clone, PartialEq, Eq)]
+pub enum AuthScheme {
+  /// `Skynet-Api-Key: <key>`, what current portals expect.
+  ApiKeyHeader(String),
+  /// `Authorization: Basic <base64 of username:password>`, for older and self-hosted portals
+  /// that sit behind basic auth.
+  BasicAuth { username: String, password: String },
+  /// `Authorization: Bearer <token>`, for portals behind OAuth or JWT auth proxies.
+  BearerToken(String),
+  /// `Cookie: <name>=<value>`, such as the `skynet-jwt` cookie a portal login sets.
+  Cookie { name: String, value: String },
+}
+
+impl AuthScheme {
+  /// The header carrying the credentials and its value.
+  pub fn header(&self) -> (&'static str, String) {
+    match self {
+      Self::ApiKeyHeader(key) => ("Skynet-Api-Key", key.clone()),
+      Self::BasicAuth { username, password } => (
+        "Authorization",
+        format!("Basic {}", base64::encode(format!("{}:{}", username, password))),
+      ),
+      Self::BearerToken(token) => ("Authorization", format!("Bearer {}", token)),
+      Self::Cookie { name, value } => ("Cookie", format!("{}={}", name, value)),
+    }
+  }
+
+  /// Adds the credentials to `headers`, unless the request already set that header itself,
+  /// e.g. from a per-call API key.
+  pub(crate) fn apply(&self, headers: &mut HeaderMap) {
+    let (name, value) = self.header();
+    if headers.contains_key(name) {
+      return;
+    }
+    if let Ok(mut value) = HeaderValue::from_str(&value) {
+      value.set_sensitive(true);
+      headers.insert(name, value);
+    }
+  }
+}
+
+/// Credentials from a portal login, only sent to the host that issued them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+  /// The host the login was made at, with its port if it had one, e.g. `account.siasky.net`.
+  pub host: String,
+  pub auth: AuthScheme,
+}
+
+impl Session {
+  pub(crate) fn applies_to(&self, uri: &Uri) -> bool {
+    uri.authority().is_some_and(|authority| authority.as_str().eq_ignore_ascii_case(&self.host))
+  }
+}
+
+impl fmt::Debug for AuthScheme {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::ApiKeyHeader(_) => f.debug_tuple("ApiKeyHeader").field(&"..").finish(),
+      Self::BasicAuth { username, .. } => f
+        .debug_struct("BasicAuth")
+        .field("username", username)
+        .field("password", &"..")
+        .finish(),
+      Self::BearerToken(_) => f.debug_tuple("BearerToken").field(&"..").finish(),
+      Self::Cookie { name, .. } => f.debug_struct("Cookie").field("name", name).field("value", &"..").finish(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_auth_scheme() {
+    let basic = AuthScheme::BasicAuth { username: "".into(), password: "foo".into() };
+    assert_eq!(basic.header(), ("Authorization", "Basic OmZvbw==".into()));
+    assert_eq!(AuthScheme::BearerToken("t0k".into()).header(), ("Authorization", "Bearer t0k".into()));
+    let cookie = AuthScheme::Cookie { name: "skynet-jwt".into(), value: "jwt".into() };
+    assert_eq!(cookie.header(), ("Cookie", "skynet-jwt=jwt".into()));
+    assert!(!format!("{:?}", basic).contains("foo"));
+
+    let mut headers = HeaderMap::new();
+    headers.insert("Skynet-Api-Key", "per-call".parse().unwrap());
+    AuthScheme::ApiKeyHeader("client".into()).apply(&mut headers);
+    basic.apply(&mut headers);
+    assert_eq!(headers["skynet-api-key"], "per-call");
+    assert_eq!(headers["authorization"], "Basic OmZvbw==");
+  }
+}
@@ -0,0 +1,267 @@
+use crate::{
+  download, registry, upload,
+  DownloadOptions, KeyPair, SkylinkFormat, SkynetClient, SkynetError::*, SkynetResult, UploadOptions,
+  registry::{EntryOptions, PublicKey, RegistryEntry},
+};
+use std::{
+  collections::{BTreeMap, HashMap},
+  fs,
+  path::{Component, Path},
+  str,
+  time::{SystemTime, UNIX_EPOCH},
+};
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackupFile {
+  pub skylink: String,
+  pub size: u64,
+  pub modified: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+  pub timestamp: u64,
+  pub parent: Option<String>,
+  pub files: BTreeMap<String, BackupFile>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BackupOptions {
+  pub data_key: String,
+  pub upload: UploadOptions,
+  pub download: DownloadOptions,
+  pub entry: EntryOptions,
+}
+
+impl Default for BackupOptions {
+  fn default() -> Self {
+    Self {
+      data_key: "skynet-rs-backup".into(),
+      upload: UploadOptions::default(),
+      download: DownloadOptions::default(),
+      entry: EntryOptions::default(),
+    }
+  }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+  time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+pub async fn get_snapshot(
+  client: &SkynetClient,
+  skylink: &str,
+  opt: &BackupOptions,
+) -> SkynetResult<Snapshot> {
+  let data = download::download_data(client, skylink, opt.download.clone()).await?;
+  serde_json::from_slice(&data).map_err(JsonError)
+}
+
+/// Returns the skylink and revision of the latest snapshot, or `None` if nothing was backed up yet.
+pub async fn get_latest_snapshot(
+  client: &SkynetClient,
+  public_key: &PublicKey,
+  opt: &BackupOptions,
+) -> SkynetResult<Option<(String, u64)>> {
+  match registry::find_registry_entry(client, public_key, &opt.data_key, opt.entry.clone()).await? {
+    Some(signed) => {
+      let skylink = str::from_utf8(&signed.entry.data).map_err(Utf8Error)?;
+      Ok(Some((skylink.to_string(), signed.entry.revision)))
+    }
+    None => Ok(None),
+  }
+}
+
+pub async fn list_snapshots(
+  client: &SkynetClient,
+  public_key: &PublicKey,
+  opt: &BackupOptions,
+) -> SkynetResult<Vec<(String, Snapshot)>> {
+  let mut snapshots = Vec::new();
+  let mut next = get_latest_snapshot(client, public_key, opt).await?.map(|(skylink, _)| skylink);
+
+  while let Some(skylink) = next {
+    let snapshot = get_snapshot(client, &skylink, opt).await?;
+    next = snapshot.parent.clone();
+    snapshots.push((skylink, snapshot));
+  }
+
+  Ok(snapshots)
+}
+
+pub async fn backup_directory(
+  client: &SkynetClient,
+  path: &Path,
+  keypair: &KeyPair,
+  opt: &BackupOptions,
+) -> SkynetResult<String> {
+  if !path.is_dir() {
+    return Err(NotDirectory);
+  }
+
+  let public_key = PublicKey::from(keypair);
+  let _writer = client.revisions().lock_entry(&public_key, &opt.data_key).await;
+  let latest = get_latest_snapshot(client, &public_key, opt).await?;
+  let previous = if let Some((ref skylink, _)) = latest {
+    Some(get_snapshot(client, skylink, opt).await?)
+  } else {
+    None
+  };
+
+  let mut files = BTreeMap::new();
+
+  for entry in WalkDir::new(path) {
+    let entry = entry.map_err(|err| FileError(err.into()))?;
+    if !entry.file_type().is_file() {
+      continue;
+    }
+
+    let metadata = entry.metadata().map_err(|err| FileError(err.into()))?;
+    let size = metadata.len();
+    let modified = unix_secs(metadata.modified().map_err(FileError)?);
+    let name = entry.path()
+      .strip_prefix(path)
+      .unwrap()
+      .components()
+      .map(|c| c.as_os_str().to_string_lossy())
+      .collect::<Vec<_>>()
+      .join("/");
+
+    let unchanged = previous
+      .as_ref()
+      .and_then(|snapshot| snapshot.files.get(&name))
+      .filter(|file| file.size == size && file.modified == modified);
+
+    let skylink = if let Some(file) = unchanged {
+      file.skylink.clone()
+    } else {
+      upload::upload_file(client, entry.path(), opt.upload.clone()).await?
+    };
+
+    files.insert(name, BackupFile { skylink, size, modified });
+  }
+
+  let snapshot = Snapshot {
+    timestamp: unix_secs(SystemTime::now()),
+    parent: latest.as_ref().map(|(skylink, _)| skylink.clone()),
+    files,
+  };
+
+  let mut data = HashMap::new();
+  data.insert(
+    "snapshot.json".to_string(),
+    (mime::APPLICATION_JSON, serde_json::to_vec(&snapshot).map_err(JsonError)?.into()));
+  let skylink = upload::upload_data(
+    client,
+    data,
+    // The registry entry keeps the bare skylink, whatever format the caller asked for.
+    UploadOptions { custom_dirname: None, skylink_format: SkylinkFormat::Raw, ..opt.upload.clone() },
+  ).await?;
+
+  let revision = client.revisions().next_revision(
+    &public_key,
+    &opt.data_key,
+    latest.map(|(_, revision)| revision));
+  registry::set_registry_entry(
+    client,
+    &public_key,
+    &keypair.private_key,
+    RegistryEntry {
+      data_key: opt.data_key.clone(),
+      data: skylink.clone().into_bytes(),
+      revision,
+    },
+    opt.entry.clone(),
+  ).await?;
+
+  Ok(skylink)
+}
+
+pub async fn restore_snapshot(
+  client: &SkynetClient,
+  skylink: &str,
+  path: &Path,
+  opt: &BackupOptions,
+) -> SkynetResult<()> {
+  let snapshot = get_snapshot(client, skylink, opt).await?;
+  // Names come from the snapshot, which anyone can write: refuse any that would leave `path`.
+  if let Some(name) = snapshot.files.keys().find(|name| {
+    !Path::new(name).components().all(|component| matches!(component, Component::Normal(_)))
+  }) {
+    return Err(InvalidPath(name.clone()));
+  }
+
+  for (name, file) in &snapshot.files {
+    let dest = path.join(name);
+    if let Some(parent) = dest.parent() {
+      fs::create_dir_all(parent).map_err(FileError)?;
+    }
+    download::download_file(client, &dest, &file.skylink, opt.download.clone()).await?;
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{gen_keypair_and_seed, MockPortal, MockResponse, MockTransport};
+  use hyper::StatusCode;
+
+  #[tokio::test]
+  async fn test_backup_and_restore() {
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let (keypair, _) = gen_keypair_and_seed(64);
+    let opt = BackupOptions::default();
+
+    fs::create_dir_all("tmpbackup/sub").unwrap();
+    fs::write("tmpbackup/1.txt", "hello 1").unwrap();
+    fs::write("tmpbackup/sub/2.txt", "hello 2").unwrap();
+    let first = backup_directory(&client, Path::new("tmpbackup"), &keypair, &opt).await;
+    fs::write("tmpbackup/3.txt", "hello 3").unwrap();
+    let second = backup_directory(&client, Path::new("tmpbackup"), &keypair, &opt).await;
+    fs::remove_dir_all("tmpbackup").unwrap();
+    println!("{:?} {:?}", first, second);
+    assert!(first.is_ok());
+    assert!(second.is_ok());
+
+    let snapshots = list_snapshots(&client, &PublicKey::from(&keypair), &opt).await.unwrap();
+    assert_eq!(snapshots.len(), 2);
+    assert_eq!(snapshots[0].1.files.len(), 3);
+    assert_eq!(snapshots[1].1.files.len(), 2);
+
+    let res = restore_snapshot(&client, &first.unwrap(), Path::new("tmprestore"), &opt).await;
+    println!("{:?}", res);
+    assert!(res.is_ok());
+    assert_eq!(fs::read_to_string("tmprestore/sub/2.txt").unwrap(), "hello 2");
+    assert!(!Path::new("tmprestore/3.txt").exists());
+    fs::remove_dir_all("tmprestore").unwrap();
+
+    let mut files = BTreeMap::new();
+    files.insert("../escaped.txt".to_string(), snapshots[0].1.files["1.txt"].clone());
+    let snapshot = Snapshot { timestamp: 0, parent: None, files };
+    let mut data = HashMap::new();
+    data.insert("snapshot.json".to_string(), (mime::APPLICATION_JSON, serde_json::to_vec(&snapshot).unwrap()));
+    let skylink = client.upload_data(data, UploadOptions::default()).await.unwrap();
+    let res = restore_snapshot(&client, &skylink, Path::new("tmprestore"), &opt).await;
+    assert!(matches!(res, Err(InvalidPath(_))));
+    assert!(!Path::new("escaped.txt").exists());
+  }
+
+  #[tokio::test]
+  async fn test_latest_snapshot_portal_error() {
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::new(StatusCode::INTERNAL_SERVER_ERROR, "unavailable"));
+    let client = transport.client("https://siasky.net");
+    let (keypair, _) = gen_keypair_and_seed(64);
+    let opt = BackupOptions::default();
+
+    let res = get_latest_snapshot(&client, &PublicKey::from(&keypair), &opt).await;
+    assert!(matches!(res.map_err(crate::SkynetError::into_inner), Err(PortalResponse(_))));
+    let res = get_latest_snapshot(&client, &PublicKey::from(&keypair), &opt).await;
+    assert!(matches!(res, Ok(None)));
+  }
+}
@@ -0,0 +1,161 @@
+use crate::{
+  crypto::blake2b_256,
+  skylink::parse_skylink,
+  util::{make_uri, parse_response, read_response, QueryParams},
+  SkynetClient, SkynetError::*, SkynetResult,
+};
+use std::{collections::HashSet, str, sync::{Arc, RwLock}};
+use hex::{FromHex, ToHex};
+use hyper::{Body, Request};
+use serde::Deserialize;
+
+/// Skylinks that downloads refuse with `Blocked`, set as `SkynetClientOptions::blocklist`.
+/// Skylinks are kept as the hashes of their merkle roots, the way portals publish their
+/// blocklists, so a list can be shared without naming what it blocks. Clones share the list.
+#[derive(Debug, Clone, Default)]
+pub struct Blocklist(Arc<RwLock<HashSet<[u8; 32]>>>);
+
+#[derive(Deserialize)]
+struct BlocklistResponse {
+  blocklist: Vec<String>,
+}
+
+impl Blocklist {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// The hex hash a skylink is listed under: the blake2b hash of its merkle root.
+  pub fn hash_skylink(skylink: &str) -> SkynetResult<String> {
+    let (skylink, _) = parse_skylink(skylink)?;
+    Ok(blake2b_256(&[&skylink.merkle_root]).encode_hex())
+  }
+
+  pub fn insert_skylink(&self, skylink: &str) -> SkynetResult<()> {
+    let (skylink, _) = parse_skylink(skylink)?;
+    self.0.write().unwrap().insert(blake2b_256(&[&skylink.merkle_root]));
+    Ok(())
+  }
+
+  /// Adds a hex hash as returned by `hash_skylink` or published by a portal. Returns whether it
+  /// is new.
+  pub fn insert_hash(&self, hash: &str) -> SkynetResult<bool> {
+    let hash = <[u8; 32]>::from_hex(hash).map_err(|_| InvalidHash(hash.to_string()))?;
+    Ok(self.0.write().unwrap().insert(hash))
+  }
+
+  pub fn remove_skylink(&self, skylink: &str) -> SkynetResult<bool> {
+    let (skylink, _) = parse_skylink(skylink)?;
+    Ok(self.0.write().unwrap().remove(&blake2b_256(&[&skylink.merkle_root])))
+  }
+
+  /// Whether `skylink`, with or without a path, is listed. Unparseable skylinks are not.
+  pub fn contains(&self, skylink: &str) -> bool {
+    match parse_skylink(skylink) {
+      Ok((skylink, _)) => self.0.read().unwrap().contains(&blake2b_256(&[&skylink.merkle_root])),
+      Err(_) => false,
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.0.read().unwrap().len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+
+  /// Adds the hashes on the blocklist of `client`'s portal, returning how many were new.
+  pub async fn fetch(&self, client: &SkynetClient) -> SkynetResult<usize> {
+    let uri = make_uri(&client.get_portal_url(), "/skynet/blocklist".to_string(), None, None, QueryParams::new());
+    let req = Request::builder().method("GET").uri(uri.clone()).body(Body::empty()).map_err(HttpError)?;
+    let res = client.request(req).await?;
+    let status = res.status();
+    let body = read_response(res).await?;
+    let body_str = str::from_utf8(&body).map_err(Utf8Error)?;
+    let res: BlocklistResponse = parse_response(&uri, status, body_str)?;
+
+    let mut added = 0;
+    for hash in res.blocklist {
+      if self.insert_hash(&hash)? {
+        added += 1;
+      }
+    }
+    Ok(added)
+  }
+
+  /// Fails with `Blocked` if `skylink` is listed.
+  pub(crate) fn check(&self, skylink: &str) -> SkynetResult<()> {
+    if self.contains(skylink) {
+      return Err(Blocked(skylink.to_string()));
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{DownloadOptions, MockResponse, MockTransport, SkynetClientOptions};
+
+  const SKYLINK: &str = "AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng";
+
+  #[test]
+  fn test_blocklist() {
+    let blocklist = Blocklist::new();
+    assert!(!blocklist.contains(SKYLINK));
+
+    blocklist.insert_skylink(&format!("sia://{}", SKYLINK)).unwrap();
+    assert!(blocklist.contains(SKYLINK));
+    assert!(blocklist.contains(&format!("{}/index.html", SKYLINK)));
+    assert!(!blocklist.insert_hash(&Blocklist::hash_skylink(SKYLINK).unwrap()).unwrap());
+    assert!(matches!(blocklist.insert_hash("xyz"), Err(InvalidHash(_))));
+
+    assert!(blocklist.remove_skylink(SKYLINK).unwrap());
+    assert!(blocklist.is_empty());
+  }
+
+  #[tokio::test]
+  async fn test_blocked_download() {
+    let transport = MockTransport::new();
+    let body = format!(r#"{{"blocklist":["{}"]}}"#, Blocklist::hash_skylink(SKYLINK).unwrap());
+    transport.push_response(MockResponse::new(hyper::StatusCode::OK, body));
+    let blocklist = Blocklist::new();
+    let client = SkynetClient::with_transport("https://siasky.net", SkynetClientOptions {
+      blocklist: Some(blocklist.clone()),
+      ..Default::default()
+    }, Arc::new(transport.clone()));
+
+    assert_eq!(blocklist.fetch(&client).await.unwrap(), 1);
+    assert_eq!(transport.last_request().unwrap().uri.path(), "/skynet/blocklist");
+
+    let err = client.download_data(SKYLINK, DownloadOptions::default()).await.unwrap_err();
+    assert!(matches!(err, Blocked(skylink) if skylink == SKYLINK));
+    assert_eq!(transport.requests().len(), 1);
+  }
+
+  #[cfg(feature = "offline")]
+  #[tokio::test]
+  async fn test_blocked_offline_download() {
+    let dir = std::env::temp_dir().join(format!("skynet-rs-blocked-{}", std::process::id()));
+    let store = crate::OfflineStore::open(&dir).unwrap();
+    store.cache_put(SKYLINK, b"hello world").unwrap();
+    let transport = MockTransport::new();
+    let blocklist = Blocklist::new();
+    blocklist.insert_skylink(SKYLINK).unwrap();
+    let client = SkynetClient::with_transport("https://siasky.net", SkynetClientOptions {
+      blocklist: Some(blocklist),
+      offline: Some(store),
+      ..Default::default()
+    }, Arc::new(transport.clone()));
+
+    let err = client.download_data(SKYLINK, DownloadOptions::default()).await.unwrap_err();
+    assert!(matches!(err, Blocked(skylink) if skylink == SKYLINK));
+    let path = dir.join("blocked.txt");
+    let err = client.download_file(&path, SKYLINK, DownloadOptions::default()).await.unwrap_err();
+    assert!(matches!(err, Blocked(skylink) if skylink == SKYLINK));
+    assert!(!path.exists());
+    assert!(transport.requests().is_empty());
+    std::fs::remove_dir_all(dir).unwrap();
+  }
+}
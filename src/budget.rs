@@ -0,0 +1,62 @@
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Caps the bytes that transfers of a client and its clones buffer at once. Each transfer
+/// reserves its whole size before buffering, so transfers never wait on each other while
+/// holding part of the budget. One larger than the budget waits until it can run alone.
+#[derive(Debug, Clone)]
+pub(crate) struct MemoryBudget {
+  semaphore: Arc<Semaphore>,
+  limit: u32,
+}
+
+impl MemoryBudget {
+  pub(crate) fn new(limit: usize) -> Self {
+    let limit = limit.clamp(1, u32::MAX as usize) as u32;
+    Self {
+      semaphore: Arc::new(Semaphore::new(limit as usize)),
+      limit,
+    }
+  }
+
+  /// Waits until `size` bytes are free and holds them until the permit is dropped. An unknown
+  /// size reserves the whole budget.
+  pub(crate) async fn reserve(&self, size: Option<u64>) -> OwnedSemaphorePermit {
+    let permits = match size {
+      Some(size) => size.min(self.limit as u64) as u32,
+      None => self.limit,
+    };
+    // The semaphore is never closed.
+    self.semaphore.clone().acquire_many_owned(permits).await.unwrap()
+  }
+
+  #[cfg(test)]
+  fn available(&self) -> usize {
+    self.semaphore.available_permits()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::Duration;
+  use tokio::time::timeout;
+
+  #[tokio::test]
+  async fn test_memory_budget() {
+    let budget = MemoryBudget::new(100);
+    let first = budget.reserve(Some(60)).await;
+    assert_eq!(budget.available(), 40);
+    assert!(timeout(Duration::from_millis(20), budget.reserve(Some(60))).await.is_err());
+
+    drop(first);
+    let oversized = budget.reserve(Some(1000)).await;
+    assert_eq!(budget.available(), 0);
+    drop(oversized);
+
+    let small = budget.reserve(Some(10)).await;
+    assert!(timeout(Duration::from_millis(20), budget.reserve(None)).await.is_err());
+    drop(small);
+    drop(budget.reserve(None).await);
+  }
+}
@@ -0,0 +1,73 @@
+use crate::{SkynetError::*, SkynetResult};
+use crypto::{blake2b::Blake2b, digest::Digest, sha2::Sha256};
+use hex::ToHex;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChecksumAlgorithm {
+  Sha256,
+  /// 256-bit BLAKE2b.
+  Blake2b,
+}
+
+impl ChecksumAlgorithm {
+  fn prefix(self) -> &'static str {
+    match self {
+      Self::Sha256 => "sha256",
+      Self::Blake2b => "blake2b",
+    }
+  }
+}
+
+/// The checksum of `data` as `<algorithm>:<hex digest>`, e.g. `sha256:b94d27b9…`.
+pub fn checksum(algorithm: ChecksumAlgorithm, data: &[u8]) -> String {
+  let mut hash = [0; 32];
+  match algorithm {
+    ChecksumAlgorithm::Sha256 => {
+      let mut hasher = Sha256::new();
+      hasher.input(data);
+      hasher.result(&mut hash);
+    }
+    ChecksumAlgorithm::Blake2b => {
+      let mut hasher = Blake2b::new(32);
+      hasher.input(data);
+      hasher.result(&mut hash);
+    }
+  }
+  format!("{}:{}", algorithm.prefix(), hash.encode_hex::<String>())
+}
+
+/// Checks `data` against a checksum produced by `checksum`. Fails with `ChecksumMismatch` if it
+/// doesn't match and `SchemaError` if the algorithm isn't known.
+pub(crate) fn verify(expected: &str, data: &[u8]) -> SkynetResult<()> {
+  let algorithm = match expected.split_once(':').map(|(prefix, _)| prefix) {
+    Some("sha256") => ChecksumAlgorithm::Sha256,
+    Some("blake2b") => ChecksumAlgorithm::Blake2b,
+    _ => return Err(SchemaError(format!("unknown checksum {}", expected))),
+  };
+
+  let actual = checksum(algorithm, data);
+  if !actual.eq_ignore_ascii_case(expected) {
+    return Err(ChecksumMismatch(expected.to_string(), actual));
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_checksum() {
+    assert_eq!(
+      checksum(ChecksumAlgorithm::Sha256, b"hello world"),
+      "sha256:b94d27b9934d3e08a52e52d7da7dabfac484efe37a5380ee9088f7ace2efcde9");
+    let blake2b = checksum(ChecksumAlgorithm::Blake2b, b"hello world");
+    assert!(blake2b.starts_with("blake2b:"));
+    assert_eq!(blake2b.len(), "blake2b:".len() + 64);
+
+    assert!(verify(&blake2b, b"hello world").is_ok());
+    assert!(matches!(verify(&blake2b, b"hello there"), Err(ChecksumMismatch(expected, _)) if expected == blake2b));
+    assert!(matches!(verify("md5:00", b"hello world"), Err(SchemaError(_))));
+  }
+}
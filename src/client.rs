@@ -1,63 +1,619 @@
 use crate::{
-  download, encryption, registry, upload,
-  UploadOptions, DownloadOptions, MetadataOptions, Metadata,
-  Skykey, SkykeyOptions, KeyPair,
-  SkynetResult,
-  util::DEFAULT_PORTAL_URL,
+  budget::MemoryBudget, download, upload, AuthScheme, Session,
+  lifecycle::{Lifecycle, TransferGuard},
+  events::{Event, EVENT_CAPACITY},
+  store::LocalStore,
+  dns::Resolve,
+  hns, HnsOptions, HnsResolution,
+  portal::{self, AutoPortal, BackgroundTask, PUBLIC_PORTALS},
+  UploadOptions, UploadResult, DownloadOptions, MetadataOptions, Metadata, ResolutionChain, ResolvedSkylink, ResponseInfo,
+  RequestContext, StreamedMetadata, SkynetError::*, SkynetResult,
+  util::{chunked_body, escape_stray_percents, make_uri, new_request_id, with_deadline, QueryParams, DEFAULT_PORTAL_URL, REQUEST_ID_HEADER},
+  transport::{HyperTransport, Transport},
 };
-use std::{collections::HashMap, path::Path};
-use hyper::{client::HttpConnector, Client};
-use hyper_tls::HttpsConnector;
+use std::{collections::HashMap, convert::Infallible, path::{Path, PathBuf}, sync::{Arc, RwLock}, time::Duration};
+use bytes::Bytes;
+use futures_util::stream;
+use hyper::{http::uri::PathAndQuery, Body, HeaderMap, Method, Request, Response, Uri};
+use tokio::sync::{broadcast, OwnedSemaphorePermit};
 use mime::Mime;
+#[cfg(feature = "tracing")]
+use tracing::Instrument;
+#[cfg(feature = "account")]
+use crate::{
+  account, pin, AccountOptions, ApiKey, DownloadStats, PinInfo, PinOptions, ShareUrl, SkylinkHealth, UserInfo,
+  UserLimits,
+};
+#[cfg(feature = "backup")]
+use crate::{backup, BackupOptions, Snapshot};
+#[cfg(all(unix, feature = "uds"))]
+use crate::transport::{UnixTransport, UNIX_SOCKET_PORTAL_URL, UNIX_SOCKET_PREFIX};
+#[cfg(feature = "blocklist")]
+use crate::Blocklist;
+#[cfg(any(feature = "backup", feature = "registry"))]
+use crate::KeyPair;
+#[cfg(feature = "dac")]
+use crate::{dac, Profile};
+#[cfg(feature = "dac")]
+use futures_util::stream::Stream;
+#[cfg(feature = "encryption")]
+use crate::{encryption, Skykey, SkykeyCache, SkykeyOptions};
+#[cfg(feature = "ledger")]
+use crate::{LedgerRecord, UploadLedger};
+#[cfg(feature = "skydb")]
+use crate::{index, skydb, DbEntry, Index, SkyDbOptions};
+#[cfg(feature = "skydb")]
+use serde::{de::DeserializeOwned, Serialize};
+#[cfg(feature = "skydb")]
+use serde_json::Value as Json;
+#[cfg(feature = "registry")]
+use crate::{
+  registry, EntryOptions, PrivateKey, PublicKey, RegistryEntry, RevisionCache, SignedRegistryEntry, Skylink,
+};
+#[cfg(feature = "offline")]
+use crate::{offline::{self, FlushedUpload, OfflineStore}, util::blocking};
+#[cfg(feature = "directory")]
+use crate::DirectoryUploadResult;
+#[cfg(all(feature = "directory", feature = "crypto"))]
+use crate::{DedupedUploadResult, DirectoryManifest};
+#[cfg(all(feature = "ledger", feature = "directory"))]
+use walkdir::WalkDir;
 
 #[derive(Debug)]
 pub struct SkynetClientOptions {
+  /// Sent as `Skynet-Api-Key` when `auth` isn't set.
   pub api_key: Option<String>,
+  /// Credentials sent with every request. Takes precedence over `api_key`, while a session set
+  /// by `login` takes precedence over both on requests to the host it came from.
+  pub auth: Option<AuthScheme>,
   pub custom_user_agent: Option<String>,
+  pub http2: bool,
+  /// Resolves portal hostnames in place of the system resolver, for both HTTP stacks.
+  pub resolver: Option<Arc<dyn Resolve>>,
+  /// How many times an idempotent request or a multipart upload is retried after a transient
+  /// connection error.
+  pub retries: u32,
+  /// How often an `auto` client probes its candidate portals in the background to keep its
+  /// failover order current. `None` only checks them when the current portal keeps failing.
+  pub health_probe_interval: Option<Duration>,
+  /// Most bytes that multipart uploads and downloads of this client and its clones buffer at
+  /// once. A transfer waits for room before reading files or sending its request, and holds it
+  /// until the data is sent or written, which bounds memory use no matter how many run
+  /// concurrently. Downloads learn their size with a HEAD request first.
+  pub memory_budget: Option<usize>,
+  /// Read-through cache for `download_data` and `download_file`, checked before the portal and
+  /// filled after. It holds what downloads return, i.e. after any decompression.
+  pub local_store: Option<Arc<dyn LocalStore>>,
+  /// Skylinks that `download_data` and `download_file` refuse with `Blocked`, checked before
+  /// the local store and the portal, and again against the skylink the portal resolved to.
+  #[cfg(feature = "blocklist")]
+  pub blocklist: Option<Blocklist>,
+  /// Where uploads of this client are recorded. Failing to record one is logged rather than
+  /// failing the upload.
+  #[cfg(feature = "ledger")]
+  pub ledger: Option<UploadLedger>,
+  #[cfg(feature = "offline")]
+  pub offline: Option<OfflineStore>,
 }
 
 impl Default for SkynetClientOptions {
   fn default() -> Self {
     Self {
       api_key: None,
+      auth: None,
       custom_user_agent: None,
+      http2: false,
+      resolver: None,
+      retries: 2,
+      health_probe_interval: None,
+      memory_budget: None,
+      local_store: None,
+      #[cfg(feature = "blocklist")]
+      blocklist: None,
+      #[cfg(feature = "ledger")]
+      ledger: None,
+      #[cfg(feature = "offline")]
+      offline: None,
     }
   }
 }
 
-#[derive(Debug)]
+fn is_idempotent(method: &Method) -> bool {
+  matches!(*method, Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS)
+}
+
+/// Request extension marking a non-idempotent request as safe to send again, such as a skyfile
+/// upload: the same content always yields the same skylink. Carries the chunks of the body, so
+/// retries resend them instead of joining the body into one copy.
+#[derive(Debug, Clone)]
+pub(crate) struct Replayable(pub(crate) Vec<Bytes>);
+
+/// Request extension carrying the `auth` of an operation's options, which replaces the client's
+/// credentials for that request when set.
+#[derive(Debug, Clone)]
+pub(crate) struct CallAuth(pub(crate) Option<AuthScheme>);
+
+/// Cheap to clone: clones share the options, connection pool, portal selection and revision
+/// cache.
+#[derive(Debug, Clone)]
 pub struct SkynetClient {
-  portal_url: String,
-  options: SkynetClientOptions,
-  pub http: Client<HttpsConnector<HttpConnector>>,
+  portal_url: Arc<RwLock<String>>,
+  options: Arc<SkynetClientOptions>,
+  session: Arc<RwLock<Option<Session>>>,
+  transport: Arc<dyn Transport>,
+  auto_portal: Option<Arc<AutoPortal>>,
+  prober: Option<Arc<BackgroundTask>>,
+  budget: Option<MemoryBudget>,
+  lifecycle: Lifecycle,
+  events: broadcast::Sender<Event>,
+  #[cfg(feature = "registry")]
+  revisions: RevisionCache,
+  #[cfg(feature = "encryption")]
+  skykeys: SkykeyCache,
+  #[cfg(feature = "tus")]
+  tus: bool,
 }
 
 impl SkynetClient {
+  /// With the `uds` feature, a `portal_url` of `unix://` and a socket path sends requests over
+  /// that socket. The client's portal URL is then `UNIX_SOCKET_PORTAL_URL`, and large files are
+  /// uploaded in one multipart request rather than over tus. Panics if the TLS backend can't be
+  /// set up; build a `HyperTransport` for `with_transport` to handle that instead.
   pub fn new(portal_url: &str, opt: SkynetClientOptions) -> Self {
-    let https = HttpsConnector::new();
-    let http = Client::builder().build::<_, hyper::Body>(https);
+    #[cfg(all(unix, feature = "uds"))]
+    if let Some(socket_path) = portal_url.strip_prefix(UNIX_SOCKET_PREFIX) {
+      let transport = UnixTransport::new(socket_path, opt.http2);
+      #[allow(unused_mut)]
+      let mut client = Self::with_transport(UNIX_SOCKET_PORTAL_URL, opt, Arc::new(transport));
+      // tus uploads go through reqwest, which can't reach the socket.
+      #[cfg(feature = "tus")]
+      {
+        client.tus = false;
+      }
+      return client;
+    }
 
+    let transport = match &opt.resolver {
+      Some(resolver) => HyperTransport::with_resolver(resolver.clone(), opt.http2),
+      None if opt.http2 => HyperTransport::http2(),
+      None => Ok(HyperTransport::new()),
+    };
+    let transport = transport.unwrap_or_else(|err| panic!("SkynetClient::new() failure: {:?}", err));
+    Self::with_transport(portal_url, opt, Arc::new(transport))
+  }
+
+  pub fn with_transport(
+    portal_url: &str,
+    opt: SkynetClientOptions,
+    transport: Arc<dyn Transport>,
+  ) -> Self {
     Self {
-      portal_url: portal_url.to_string(),
-      options: opt,
-      http,
+      portal_url: Arc::new(RwLock::new(portal_url.to_string())),
+      session: Arc::new(RwLock::new(None)),
+      budget: opt.memory_budget.map(MemoryBudget::new),
+      options: Arc::new(opt),
+      transport,
+      auto_portal: None,
+      prober: None,
+      lifecycle: Lifecycle::default(),
+      events: broadcast::channel(EVENT_CAPACITY).0,
+      #[cfg(feature = "registry")]
+      revisions: RevisionCache::default(),
+      #[cfg(feature = "encryption")]
+      skykeys: SkykeyCache::default(),
+      #[cfg(feature = "tus")]
+      tus: true,
     }
   }
 
-  pub fn get_portal_url(&self) -> &str {
-    self.portal_url.as_str()
+  /// Whether large files are uploaded over tus.
+  #[cfg(feature = "tus")]
+  pub(crate) fn uses_tus(&self) -> bool {
+    self.tus
+  }
+
+  /// Picks the first healthy portal in `PUBLIC_PORTALS`. The client moves to another healthy
+  /// one if requests keep failing.
+  pub async fn auto(opt: SkynetClientOptions) -> SkynetResult<Self> {
+    Self::auto_with_portals(PUBLIC_PORTALS, opt).await
+  }
+
+  /// Like `auto`, choosing from `portals` instead of `PUBLIC_PORTALS`. With
+  /// `health_probe_interval` set, the portals are probed in the background until the client
+  /// and all its clones are dropped.
+  pub async fn auto_with_portals(portals: &[&str], opt: SkynetClientOptions) -> SkynetResult<Self> {
+    let candidates: Vec<String> = portals.iter().map(|portal| portal.to_string()).collect();
+    let first = candidates.first().cloned().ok_or_else(|| NoHealthyPortal(Vec::new()))?;
+
+    let mut client = Self::new(&first, opt);
+    let portal_url = portal::select_portal(&client, &candidates).await?;
+    client.set_portal_url(&portal_url);
+    let auto_portal = Arc::new(AutoPortal::new(candidates));
+    client.auto_portal = Some(auto_portal.clone());
+
+    if let Some(interval) = client.options.health_probe_interval {
+      let prober = BackgroundTask::probe_portals(client.clone(), auto_portal, interval);
+      client.prober = Some(Arc::new(prober));
+    }
+    Ok(client)
+  }
+
+  /// Subscribes to the events of this client and its clones, from now on. A subscriber that
+  /// falls too far behind gets `RecvError::Lagged` and then the newest events.
+  pub fn events(&self) -> broadcast::Receiver<Event> {
+    self.events.subscribe()
+  }
+
+  pub(crate) fn emit(&self, event: Event) {
+    // Sending only fails when nobody is subscribed.
+    let _ = self.events.send(event);
+  }
+
+  /// A body that sends `chunks` in turn, emitting `ChunkSent` as each one is taken. Set
+  /// `Content-Length` on the request, since the body doesn't know its length.
+  pub(crate) fn upload_body(&self, chunks: Vec<Bytes>) -> Body {
+    let total = chunks.iter().map(|chunk| chunk.len() as u64).sum();
+    let events = self.events.clone();
+    let mut sent = 0;
+    let chunks = chunks.into_iter().map(move |chunk| {
+      sent += chunk.len() as u64;
+      let _ = events.send(Event::ChunkSent { sent, total });
+      Ok::<_, Infallible>(chunk)
+    });
+    Body::wrap_stream(stream::iter(chunks))
+  }
+
+  /// The credentials the client sends to `uri`: the session if it came from that host, or else
+  /// `auth` or `api_key`.
+  pub(crate) fn auth(&self, uri: &Uri) -> Option<AuthScheme> {
+    self
+      .get_session()
+      .filter(|session| session.applies_to(uri))
+      .map(|session| session.auth)
+      .or_else(|| self.options.auth.clone())
+      .or_else(|| self.options.api_key.clone().map(AuthScheme::ApiKeyHeader))
+  }
+
+  /// Whether requests to the portal carry any credentials.
+  #[cfg(feature = "account")]
+  pub(crate) fn has_auth(&self) -> bool {
+    self.get_portal_url().parse::<Uri>().is_ok_and(|portal| self.auth(&portal).is_some())
+  }
+
+  /// The session of the portal account this client and its clones are logged in to.
+  pub fn get_session(&self) -> Option<Session> {
+    self.session.read().unwrap().clone()
+  }
+
+  /// Logs this client and its clones in with `session`, such as one saved from an earlier
+  /// `login`, or out with `None`. On requests to the session's host, it takes precedence over
+  /// the `auth` and `api_key` options.
+  pub fn set_session(&self, session: Option<Session>) {
+    *self.session.write().unwrap() = session;
+  }
+
+  /// Sends a request through the transport, tagging it with a request ID unless the caller
+  /// already set one and adding the credentials of its `CallAuth`, or else the client's. Clients
+  /// built with `auto` also keep track of failures here. Fails with `ClientShutdown` after
+  /// `shutdown`.
+  pub(crate) async fn request(&self, mut req: Request<Body>) -> SkynetResult<Response<Body>> {
+    let transfer = self.begin_transfer()?;
+    let auth = match req.extensions_mut().remove::<CallAuth>() {
+      Some(CallAuth(Some(auth))) => Some(auth),
+      _ => self.auth(req.uri()),
+    };
+    if let Some(auth) = auth {
+      auth.apply(req.headers_mut());
+    }
+    let res = self.request_with_retries(req).await;
+    if let Some(auto_portal) = &self.auto_portal {
+      auto_portal.record(self, &res).await;
+    }
+    // The response holds the transfer until its body has been read and it is dropped.
+    res.map(|mut res| {
+      res.extensions_mut().insert(transfer);
+      res
+    })
+  }
+
+  /// Sends a request to a portal endpoint this crate doesn't wrap, with the client's portal,
+  /// credentials and retries. `endpoint_path` is relative to the portal, e.g. `/skynet/stats`.
+  /// The response comes back whatever its status, with its body left to stream. A `%` in the
+  /// path that doesn't start an escape is sent as `%25`, and a path a URI can't hold, e.g. one
+  /// with a space or a non-ASCII character, fails with `HttpError`.
+  pub async fn request_raw(
+    &self,
+    method: Method,
+    endpoint_path: &str,
+    query: QueryParams,
+    headers: HeaderMap,
+    body: Bytes,
+  ) -> SkynetResult<Response<Body>> {
+    let path = if endpoint_path.starts_with('/') {
+      endpoint_path.to_string()
+    } else {
+      format!("/{}", endpoint_path)
+    };
+    let path = escape_stray_percents(&path);
+    path.parse::<PathAndQuery>().map_err(|err| HttpError(err.into()))?;
+    let uri = make_uri(&self.get_portal_url(), path, None, None, query);
+    let mut req = Request::builder().method(method).uri(uri).body(Body::from(body)).map_err(HttpError)?;
+    req.headers_mut().extend(headers);
+    self.request(req).await
+  }
+
+  async fn request_with_retries(&self, mut req: Request<Body>) -> SkynetResult<Response<Body>> {
+    let request_id = match req.headers().get(REQUEST_ID_HEADER).and_then(|id| id.to_str().ok()) {
+      Some(request_id) => request_id.to_string(),
+      None => {
+        let request_id = new_request_id()?;
+        req.headers_mut().insert(REQUEST_ID_HEADER, request_id.parse().unwrap());
+        request_id
+      }
+    };
+
+    let replay = req.extensions_mut().remove::<Replayable>();
+    if self.options.retries == 0 || !(replay.is_some() || is_idempotent(req.method())) {
+      return self.send(req, &request_id).await;
+    }
+
+    // Other bodies are buffered so that they can be sent again.
+    let (parts, body) = req.into_parts();
+    let (chunks, upload) = match replay {
+      Some(Replayable(chunks)) => (chunks, true),
+      None => (vec![hyper::body::to_bytes(body).await.map_err(HyperError)?], false),
+    };
+    let mut attempt = 0;
+
+    loop {
+      let body = if upload { self.upload_body(chunks.clone()) } else { chunked_body(chunks.clone()) };
+      let mut req = Request::new(body);
+      *req.method_mut() = parts.method.clone();
+      *req.uri_mut() = parts.uri.clone();
+      *req.version_mut() = parts.version;
+      *req.headers_mut() = parts.headers.clone();
+
+      match self.send(req, &request_id).await {
+        Err(err) if attempt < self.options.retries && err.is_retryable() => {
+          attempt += 1;
+          self.emit(Event::RetryScheduled {
+            request_id: request_id.clone(),
+            attempt,
+            error: format!("{:?}", err),
+          });
+        }
+        res => return res,
+      }
+    }
+  }
+
+  pub(crate) async fn send(&self, req: Request<Body>, request_id: &str) -> SkynetResult<Response<Body>> {
+    #[cfg(feature = "tracing")]
+    let span = tracing::debug_span!(
+      "skynet_request",
+      request_id = %request_id,
+      method = %req.method(),
+      uri = %req.uri());
+
+    #[cfg(feature = "log")]
+    let (method, endpoint, sent, start) = (
+      req.method().clone(),
+      req.uri().path().to_string(),
+      hyper::body::HttpBody::size_hint(req.body()).exact(),
+      std::time::Instant::now(),
+    );
+
+    let context = RequestContext::new(req.uri(), Some(request_id));
+    let res = self.transport.request(req);
+
+    #[cfg(feature = "tracing")]
+    let res = res.instrument(span);
+
+    let res = res.await;
+
+    // Only the path is logged: the query can carry an API key.
+    #[cfg(feature = "log")]
+    {
+      let status = match &res {
+        Ok(res) => res.status().as_u16().to_string(),
+        Err(_) => "error".to_string(),
+      };
+      let received = res.as_ref().ok().and_then(|res| hyper::body::HttpBody::size_hint(res.body()).exact());
+      let bytes = |size: Option<u64>| size.map(|size| size.to_string()).unwrap_or_else(|| "-".to_string());
+      log::debug!(
+        "request_id={} method={} endpoint={} status={} duration_ms={} sent={} received={}",
+        request_id,
+        method,
+        endpoint,
+        status,
+        start.elapsed().as_millis(),
+        bytes(sent),
+        bytes(received));
+    }
+
+    // The response keeps the context too, for errors reading its body.
+    match res {
+      Ok(mut res) => {
+        res.extensions_mut().insert(context);
+        Ok(res)
+      }
+      Err(err) => Err(RequestError(context, Box::new(err))),
+    }
+  }
+
+  /// The portal requests currently go to. Owned, since an `auto` client can switch portals at
+  /// any time.
+  pub fn get_portal_url(&self) -> String {
+    self.portal_url.read().unwrap().clone()
+  }
+
+  pub(crate) fn set_portal_url(&self, portal_url: &str) {
+    let from = std::mem::replace(&mut *self.portal_url.write().unwrap(), portal_url.to_string());
+    if from != portal_url {
+      self.emit(Event::PortalSwitched { from, to: portal_url.to_string() });
+    }
+  }
+
+  /// Switches to `to` only if the client is still on `from`, so that a failover decided on a
+  /// stale portal doesn't undo a switch made meanwhile.
+  pub(crate) fn replace_portal_url(&self, from: &str, to: &str) {
+    {
+      let mut portal_url = self.portal_url.write().unwrap();
+      if *portal_url != from {
+        return;
+      }
+      *portal_url = to.to_string();
+    }
+    if from != to {
+      self.emit(Event::PortalSwitched { from: from.to_string(), to: to.to_string() });
+    }
+  }
+
+  /// The portals an `auto` client chooses from, or `None` if it was given a fixed portal.
+  pub fn get_portal_candidates(&self) -> Option<&[String]> {
+    self.auto_portal.as_deref().map(AutoPortal::candidates)
+  }
+
+  /// The order an `auto` client fails over in, best first. It follows the candidate order until
+  /// background probing ranks the portals by success rate and latency.
+  pub fn get_portal_ranking(&self) -> Option<Vec<String>> {
+    self.auto_portal.as_deref().map(AutoPortal::ranking)
+  }
+
+  /// Registry revisions this client and its clones have read or written.
+  #[cfg(feature = "registry")]
+  pub fn revisions(&self) -> &RevisionCache {
+    &self.revisions
+  }
+
+  /// Skykeys this client and its clones have fetched or created.
+  #[cfg(feature = "encryption")]
+  pub fn skykeys(&self) -> &SkykeyCache {
+    &self.skykeys
+  }
+
+  /// Marks a transfer in flight, which `shutdown` waits for, until the guard is dropped.
+  pub(crate) fn begin_transfer(&self) -> SkynetResult<TransferGuard> {
+    self.lifecycle.begin_transfer()
+  }
+
+  #[cfg(feature = "account")]
+  pub(crate) fn is_shut_down(&self) -> bool {
+    self.lifecycle.is_shut_down()
+  }
+
+  /// Resolves once `shutdown` is called on this client or one of its clones.
+  pub(crate) async fn closed(&self) {
+    self.lifecycle.closed().await
+  }
+
+  /// Stops the background tasks of this client and its clones, such as the portal prober and
+  /// `PinMaintainer`s using them, then waits for the requests in flight to finish, including
+  /// reading their responses. Requests made from then on fail with `ClientShutdown`.
+  pub async fn shutdown(&self) {
+    self.lifecycle.shut_down().await
+  }
+
+  pub(crate) fn has_memory_budget(&self) -> bool {
+    self.budget.is_some()
+  }
+
+  /// Holds `size` bytes of the memory budget, if there is one, until the permit is dropped.
+  pub(crate) async fn reserve_memory(&self, size: Option<u64>) -> Option<OwnedSemaphorePermit> {
+    match &self.budget {
+      Some(budget) => Some(budget.reserve(size).await),
+      None => None,
+    }
   }
 
   pub fn get_options(&self) -> &SkynetClientOptions {
     &self.options
   }
 
-  pub async fn upload_data(
+  #[cfg(feature = "ledger")]
+  pub fn get_ledger(&self) -> Option<&UploadLedger> {
+    self.options.ledger.as_ref()
+  }
+
+  /// The upload already succeeded, so a ledger that can't record it doesn't fail it.
+  #[cfg(feature = "ledger")]
+  fn record_upload(&self, path: &str, skylink: &str, size: u64) {
+    if let Some(ledger) = &self.options.ledger {
+      if let Err(_err) = ledger.record(&LedgerRecord::new(path, skylink, size, &self.get_portal_url())) {
+        #[cfg(feature = "log")]
+        log::warn!("failed to record the upload of {} as {} in the ledger: {:?}", path, skylink, _err);
+      }
+    }
+  }
+
+  #[cfg(feature = "offline")]
+  fn offline_store(&self) -> Option<&OfflineStore> {
+    self.options.offline.as_ref().filter(|store| store.is_offline())
+  }
+
+  #[cfg(feature = "offline")]
+  pub async fn flush(&self) -> SkynetResult<Vec<FlushedUpload>> {
+    let store = if let Some(store) = &self.options.offline {
+      store
+    } else {
+      return Ok(Vec::new());
+    };
+
+    let flushed = offline::flush(self, store).await?;
+
+    #[cfg(feature = "ledger")]
+    for upload in &flushed {
+      self.record_upload(&upload.path, &upload.skylink, upload.size);
+    }
+
+    Ok(flushed)
+  }
+
+  pub async fn upload_data<B: Into<Bytes>>(
     &self,
-    data: HashMap<String, (Mime, Vec<u8>)>,
+    data: HashMap<String, (Mime, B)>,
     opt: UploadOptions,
   ) -> SkynetResult<String> {
-    upload::upload_data(self, data, opt).await
+    self.upload_data_with_result(data, opt).await.map(|res| res.skylink)
+  }
+
+  /// Like `upload_data`, but returns everything the portal said about the upload. Uploads
+  /// queued offline only have their local skylink and size.
+  pub async fn upload_data_with_result<B: Into<Bytes>>(
+    &self,
+    data: HashMap<String, (Mime, B)>,
+    opt: UploadOptions,
+  ) -> SkynetResult<UploadResult> {
+    let data: HashMap<String, (Mime, Bytes)> = data
+      .into_iter()
+      .map(|(filename, (mime, bytes))| (filename, (mime, bytes.into())))
+      .collect();
+
+    #[cfg(feature = "offline")]
+    if let Some(store) = self.offline_store() {
+      let store = store.clone();
+      let size = data.values().map(|(_, bytes)| bytes.len() as u64).sum();
+      let skylink = blocking(move || store.queue_upload(data, &opt)).await?;
+      return Ok(UploadResult::skylink_only(skylink, size));
+    }
+
+    #[cfg(feature = "ledger")]
+    let (path, size) = {
+      let path = if let Some(ref custom_dirname) = opt.custom_dirname {
+        custom_dirname.clone()
+      } else {
+        data.keys().next().cloned().unwrap_or_default()
+      };
+      let size = data.values().map(|(_, bytes)| bytes.len() as u64).sum::<u64>();
+      (path, size)
+    };
+
+    let res = with_deadline(opt.deadline, upload::upload_data_with_result(self, data, opt)).await?;
+
+    #[cfg(feature = "ledger")]
+    self.record_upload(&path, &res.skylink, size);
+
+    Ok(res)
   }
 
   pub async fn upload_file<P: AsRef<Path>>(
@@ -65,23 +621,116 @@ impl SkynetClient {
     path: P,
     opt: UploadOptions,
   ) -> SkynetResult<String> {
-    upload::upload_file(self, path.as_ref(), opt).await
+    self.upload_file_with_result(path, opt).await.map(|res| res.skylink)
+  }
+
+  /// Like `upload_file`, but returns everything the portal said about the upload.
+  pub async fn upload_file_with_result<P: AsRef<Path>>(
+    &self,
+    path: P,
+    opt: UploadOptions,
+  ) -> SkynetResult<UploadResult> {
+    #[cfg(feature = "offline")]
+    if let Some(store) = self.offline_store() {
+      let (store, path) = (store.clone(), path.as_ref().to_path_buf());
+      return blocking(move || {
+        let data = upload::read_file(&path)?;
+        let size = data.values().map(|(_, bytes)| bytes.len() as u64).sum();
+        Ok(UploadResult::skylink_only(store.queue_upload(data, &opt)?, size))
+      }).await;
+    }
+
+    let res = with_deadline(opt.deadline, upload::upload_file_with_result(self, path.as_ref(), opt)).await?;
+
+    #[cfg(feature = "ledger")]
+    {
+      let size = std::fs::metadata(path.as_ref()).map(|m| m.len()).unwrap_or(0);
+      self.record_upload(&path.as_ref().to_string_lossy(), &res.skylink, size);
+    }
+
+    Ok(res)
   }
 
+  #[cfg(feature = "directory")]
   pub async fn upload_directory<P: AsRef<Path>>(
     &self,
     path: P,
     opt: UploadOptions,
   ) -> SkynetResult<String> {
-    upload::upload_directory(self, path.as_ref(), opt).await
+    self.upload_directory_manifest(path, opt).await.map(|result| result.skylink)
+  }
+
+  /// Like `upload_directory`, but also lists the subfiles that were uploaded.
+  #[cfg(feature = "directory")]
+  pub async fn upload_directory_manifest<P: AsRef<Path>>(
+    &self,
+    path: P,
+    opt: UploadOptions,
+  ) -> SkynetResult<DirectoryUploadResult> {
+    #[cfg(feature = "offline")]
+    if let Some(store) = self.offline_store() {
+      let opt = UploadOptions {
+        custom_dirname: Some(upload::directory_name(path.as_ref())),
+        ..opt
+      };
+      let (store, path) = (store.clone(), path.as_ref().to_path_buf());
+      return blocking(move || {
+        let data = upload::read_directory_for_upload(&path, &opt)?;
+        let opt = UploadOptions { hooks: Vec::new(), ..opt };
+        let files = upload::uploaded_files(&data);
+        let skylink = store.queue_upload(data, &opt)?;
+        Ok(DirectoryUploadResult { skylink, files })
+      }).await;
+    }
+
+    let result = with_deadline(opt.deadline, upload::upload_directory_manifest(self, path.as_ref(), opt)).await?;
+
+    #[cfg(feature = "ledger")]
+    {
+      let size = WalkDir::new(path.as_ref())
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum();
+      self.record_upload(&path.as_ref().to_string_lossy(), &result.skylink, size);
+    }
+
+    Ok(result)
+  }
+
+  /// Uploads a directory file by file, reusing the skylinks in `previous` for unchanged files.
+  /// See `upload::upload_directory_deduped`.
+  #[cfg(all(feature = "directory", feature = "crypto"))]
+  pub async fn upload_directory_deduped<P: AsRef<Path>>(
+    &self,
+    path: P,
+    previous: Option<&DirectoryManifest>,
+    opt: UploadOptions,
+  ) -> SkynetResult<DedupedUploadResult> {
+    with_deadline(opt.deadline, upload::upload_directory_deduped(self, path.as_ref(), previous, opt)).await
   }
 
   pub async fn download_data(
     &self,
     skylink: &str,
     opt: DownloadOptions,
-  ) -> SkynetResult<Vec<u8>> {
-    download::download_data(self, skylink, opt).await
+  ) -> SkynetResult<Bytes> {
+    #[cfg(feature = "offline")]
+    if let Some(data) = self.offline_cached(skylink)? {
+      return Ok(data);
+    }
+
+    with_deadline(opt.deadline, download::download_data(self, skylink, opt)).await
+  }
+
+  pub async fn download_data_with_response(
+    &self,
+    skylink: &str,
+    opt: DownloadOptions,
+  ) -> SkynetResult<(Bytes, ResponseInfo)> {
+    with_deadline(opt.deadline, download::download_data_with_response(self, skylink, opt)).await
   }
 
   pub async fn download_file<P: AsRef<Path>>(
@@ -89,8 +738,49 @@ impl SkynetClient {
     path: P,
     skylink: &str,
     opt: DownloadOptions,
+  ) -> SkynetResult<PathBuf> {
+    #[cfg(feature = "offline")]
+    if let Some(data) = self.offline_cached(skylink)? {
+      std::fs::write(path.as_ref(), data).map_err(FileError)?;
+      return Ok(path.as_ref().to_path_buf());
+    }
+
+    with_deadline(opt.deadline, download::download_file(self, path, skylink, opt)).await
+  }
+
+  /// The cached copy of `skylink` while the offline store is offline, and `None` otherwise, in
+  /// which case the download goes to the portal and fills the cache.
+  #[cfg(feature = "offline")]
+  fn offline_cached(&self, skylink: &str) -> SkynetResult<Option<Bytes>> {
+    let store = match self.offline_store() {
+      Some(store) => store,
+      None => return Ok(None),
+    };
+
+    // A skylink blocked after it was cached isn't served from the cache either.
+    #[cfg(feature = "blocklist")]
+    download::check_blocklist(self, skylink)?;
+    let data = store.cache_get(skylink)?.ok_or_else(|| NotCached(skylink.to_string()))?;
+    Ok(Some(Bytes::from(data)))
+  }
+
+  #[cfg(feature = "directory")]
+  pub async fn download_directory<P: AsRef<Path>>(
+    &self,
+    path: P,
+    skylink: &str,
+    opt: DownloadOptions,
   ) -> SkynetResult<()> {
-    download::download_file(self, path, skylink, opt).await
+    with_deadline(opt.deadline, download::download_directory(self, path, skylink, opt)).await
+  }
+
+  pub async fn download_subfiles(
+    &self,
+    skylink: &str,
+    paths: &[&str],
+    opt: DownloadOptions,
+  ) -> SkynetResult<Vec<Bytes>> {
+    with_deadline(opt.deadline, download::download_subfiles(self, skylink, paths, opt)).await
   }
 
   pub async fn get_metadata(
@@ -98,9 +788,72 @@ impl SkynetClient {
     skylink: &str,
     opt: MetadataOptions,
   ) -> SkynetResult<Metadata> {
-    download::get_metadata(self, skylink, opt).await
+    with_deadline(opt.deadline, download::get_metadata(self, skylink, opt)).await
+  }
+
+  /// Fetches the metadata of many skylinks concurrently. See `download::get_metadata_many`.
+  pub async fn get_metadata_many(
+    &self,
+    skylinks: &[&str],
+    opt: MetadataOptions,
+  ) -> Vec<SkynetResult<Metadata>> {
+    download::get_metadata_many(self, skylinks, opt).await
+  }
+
+  pub async fn get_streamed_metadata(
+    &self,
+    skylink: &str,
+    opt: MetadataOptions,
+  ) -> SkynetResult<StreamedMetadata> {
+    with_deadline(opt.deadline, download::get_streamed_metadata(self, skylink, opt)).await
+  }
+
+  pub async fn skylink_exists(
+    &self,
+    skylink: &str,
+    opt: MetadataOptions,
+  ) -> SkynetResult<bool> {
+    with_deadline(opt.deadline, download::skylink_exists(self, skylink, opt)).await
+  }
+
+  pub async fn resolve_skylink_v2(
+    &self,
+    skylink: &str,
+    opt: MetadataOptions,
+  ) -> SkynetResult<ResolvedSkylink> {
+    with_deadline(opt.deadline, download::resolve_skylink_v2(self, skylink, opt)).await
+  }
+
+  pub async fn resolve_skylink_chain(
+    &self,
+    skylink: &str,
+    opt: MetadataOptions,
+  ) -> SkynetResult<ResolutionChain> {
+    with_deadline(opt.deadline, download::resolve_skylink_chain(self, skylink, opt)).await
+  }
+
+  pub async fn resolve_hns(&self, domain: &str, opt: HnsOptions) -> SkynetResult<HnsResolution> {
+    hns::resolve_hns(self, domain, opt).await
+  }
+
+  pub async fn download_hns(&self, domain: &str, path: &str, opt: HnsOptions) -> SkynetResult<Bytes> {
+    hns::download_hns(self, domain, path, opt).await
+  }
+
+  pub async fn download_data_hns(&self, uri: &str, opt: HnsOptions) -> SkynetResult<Bytes> {
+    hns::download_data_hns(self, uri, opt).await
+  }
+
+  pub async fn download_file_hns<P: AsRef<Path>>(&self, path: P, uri: &str, opt: HnsOptions) -> SkynetResult<()> {
+    hns::download_file_hns(self, path, uri, opt).await
   }
 
+  #[cfg(feature = "registry")]
+  pub async fn resolve_hns_skylink(&self, domain: &str, opt: HnsOptions) -> SkynetResult<String> {
+    hns::resolve_hns_skylink(self, domain, opt).await
+  }
+
+  #[cfg(feature = "encryption")]
   pub async fn add_skykey(
     &self,
     skykey: &str,
@@ -109,6 +862,7 @@ impl SkynetClient {
     encryption::add_skykey(self, skykey, opt).await
   }
 
+  #[cfg(feature = "encryption")]
   pub async fn create_skykey(
     &self,
     name: &str,
@@ -118,6 +872,7 @@ impl SkynetClient {
     encryption::create_skykey(self, name, skykey_type, opt).await
   }
 
+  #[cfg(feature = "encryption")]
   pub async fn get_skykey_by_name(
     &self,
     name: &str,
@@ -126,6 +881,7 @@ impl SkynetClient {
     encryption::get_skykey_by_name(self, name, opt).await
   }
 
+  #[cfg(feature = "encryption")]
   pub async fn get_skykey_by_id(
     &self,
     id: &str,
@@ -134,9 +890,383 @@ impl SkynetClient {
     encryption::get_skykey_by_id(self, id, opt).await
   }
 
+  #[cfg(feature = "encryption")]
   pub async fn get_skykeys(&self, opt: SkykeyOptions) -> SkynetResult<Vec<Skykey>> {
     encryption::get_skykeys(self, opt).await
   }
+
+  #[cfg(feature = "account")]
+  pub async fn register(&self, email: &str, password: &str, opt: AccountOptions) -> SkynetResult<UserInfo> {
+    account::register(self, email, password, opt).await
+  }
+
+  #[cfg(feature = "account")]
+  pub async fn login(&self, email: &str, password: &str, opt: AccountOptions) -> SkynetResult<()> {
+    account::login(self, email, password, opt).await
+  }
+
+  #[cfg(feature = "account")]
+  pub async fn logout(&self, opt: AccountOptions) -> SkynetResult<()> {
+    account::logout(self, opt).await
+  }
+
+  #[cfg(feature = "account")]
+  pub async fn get_user(&self, opt: AccountOptions) -> SkynetResult<UserInfo> {
+    account::get_user(self, opt).await
+  }
+
+  #[cfg(feature = "account")]
+  pub async fn get_user_limits(&self, opt: AccountOptions) -> SkynetResult<UserLimits> {
+    account::get_user_limits(self, opt).await
+  }
+
+  #[cfg(feature = "account")]
+  pub async fn get_skylink_health(
+    &self,
+    skylink: &str,
+    opt: AccountOptions,
+  ) -> SkynetResult<SkylinkHealth> {
+    account::get_skylink_health(self, skylink, opt).await
+  }
+
+  #[cfg(feature = "account")]
+  pub async fn get_pin_info(
+    &self,
+    skylink: &str,
+    opt: AccountOptions,
+  ) -> SkynetResult<PinInfo> {
+    account::get_pin_info(self, skylink, opt).await
+  }
+
+  #[cfg(feature = "account")]
+  pub async fn pin_skylink(&self, skylink: &str, opt: PinOptions) -> SkynetResult<()> {
+    pin::pin_skylink(self, skylink, opt).await
+  }
+
+  #[cfg(feature = "account")]
+  pub async fn unpin_skylink(&self, skylink: &str, opt: PinOptions) -> SkynetResult<()> {
+    pin::unpin_skylink(self, skylink, opt).await
+  }
+
+  #[cfg(feature = "account")]
+  pub async fn get_download_stats(&self, opt: AccountOptions) -> SkynetResult<Vec<DownloadStats>> {
+    account::get_download_stats(self, opt).await
+  }
+
+  #[cfg(feature = "account")]
+  pub async fn create_public_api_key(
+    &self,
+    skylinks: &[&str],
+    opt: AccountOptions,
+  ) -> SkynetResult<ApiKey> {
+    account::create_public_api_key(self, skylinks, opt).await
+  }
+
+  #[cfg(feature = "account")]
+  pub async fn get_api_key(
+    &self,
+    id: &str,
+    opt: AccountOptions,
+  ) -> SkynetResult<ApiKey> {
+    account::get_api_key(self, id, opt).await
+  }
+
+  #[cfg(feature = "account")]
+  pub async fn update_api_key_skylinks(
+    &self,
+    id: &str,
+    skylinks: &[&str],
+    opt: AccountOptions,
+  ) -> SkynetResult<()> {
+    account::update_api_key_skylinks(self, id, skylinks, opt).await
+  }
+
+  #[cfg(feature = "account")]
+  pub async fn list_api_keys(&self, opt: AccountOptions) -> SkynetResult<Vec<ApiKey>> {
+    account::list_api_keys(self, opt).await
+  }
+
+  #[cfg(feature = "account")]
+  pub async fn delete_api_key(&self, id: &str, opt: AccountOptions) -> SkynetResult<()> {
+    account::delete_api_key(self, id, opt).await
+  }
+
+  #[cfg(feature = "account")]
+  pub async fn create_share_url(&self, skylink: &str, opt: AccountOptions) -> SkynetResult<ShareUrl> {
+    account::create_share_url(self, skylink, opt).await
+  }
+
+  #[cfg(feature = "registry")]
+  pub async fn get_registry_entry(
+    &self,
+    public_key: &PublicKey,
+    data_key: &str,
+    opt: EntryOptions,
+  ) -> SkynetResult<SignedRegistryEntry> {
+    registry::get_registry_entry(self, public_key, data_key, opt).await
+  }
+
+  #[cfg(feature = "registry")]
+  pub async fn get_registry_entries(
+    &self,
+    entries: &[(&PublicKey, &str)],
+    opt: EntryOptions,
+  ) -> SkynetResult<Vec<SignedRegistryEntry>> {
+    registry::get_registry_entries(self, entries, opt).await
+  }
+
+  #[cfg(feature = "registry")]
+  pub async fn set_registry_entry(
+    &self,
+    public_key: &PublicKey,
+    private_key: &PrivateKey,
+    entry: RegistryEntry,
+    opt: EntryOptions,
+  ) -> SkynetResult<()> {
+    registry::set_registry_entry(self, public_key, private_key, entry, opt).await
+  }
+
+  #[cfg(feature = "registry")]
+  pub async fn set_data_link(
+    &self,
+    keypair: &KeyPair,
+    data_key: &str,
+    skylink: &str,
+    opt: EntryOptions,
+  ) -> SkynetResult<String> {
+    registry::set_data_link(self, keypair, data_key, skylink, opt).await
+  }
+
+  #[cfg(feature = "registry")]
+  pub async fn get_data_link(&self, public_key: &PublicKey, data_key: &str, opt: EntryOptions) -> SkynetResult<Skylink> {
+    registry::get_data_link(self, public_key, data_key, opt).await
+  }
+
+  #[cfg(feature = "registry")]
+  pub async fn download_from_entry(
+    &self,
+    public_key: &PublicKey,
+    data_key: &str,
+    entry_opt: EntryOptions,
+    download_opt: DownloadOptions,
+  ) -> SkynetResult<Bytes> {
+    with_deadline(
+      download_opt.deadline,
+      registry::download_from_entry(self, public_key, data_key, entry_opt, download_opt),
+    ).await
+  }
+
+  #[cfg(feature = "registry")]
+  pub async fn get_entry_data(
+    &self,
+    public_key: &PublicKey,
+    data_key: &str,
+    opt: EntryOptions,
+  ) -> SkynetResult<Option<Vec<u8>>> {
+    registry::get_entry_data(self, public_key, data_key, opt).await
+  }
+
+  #[cfg(feature = "registry")]
+  pub async fn set_entry_data(
+    &self,
+    keypair: &KeyPair,
+    data_key: &str,
+    data: &[u8],
+    opt: EntryOptions,
+  ) -> SkynetResult<()> {
+    registry::set_entry_data(self, keypair, data_key, data, opt).await
+  }
+
+  #[cfg(feature = "registry")]
+  pub async fn delete_entry_data(&self, keypair: &KeyPair, data_key: &str, opt: EntryOptions) -> SkynetResult<()> {
+    registry::delete_entry_data(self, keypair, data_key, opt).await
+  }
+
+  #[cfg(feature = "skydb")]
+  pub async fn db_get<T: DeserializeOwned>(
+    &self,
+    public_key: &PublicKey,
+    data_key: &str,
+    opt: &SkyDbOptions,
+  ) -> SkynetResult<Option<DbEntry<T>>> {
+    skydb::db_get(self, public_key, data_key, opt).await
+  }
+
+  #[cfg(feature = "skydb")]
+  pub async fn db_set<T: Serialize>(
+    &self,
+    keypair: &KeyPair,
+    data_key: &str,
+    data: &T,
+    opt: &SkyDbOptions,
+  ) -> SkynetResult<String> {
+    skydb::db_set(self, keypair, data_key, data, opt).await
+  }
+
+  #[cfg(feature = "skydb")]
+  pub async fn get_json(
+    &self,
+    public_key: &PublicKey,
+    data_key: &str,
+    opt: &SkyDbOptions,
+  ) -> SkynetResult<Option<DbEntry<Json>>> {
+    skydb::get_json(self, public_key, data_key, opt).await
+  }
+
+  #[cfg(feature = "skydb")]
+  pub async fn set_json(
+    &self,
+    keypair: &KeyPair,
+    data_key: &str,
+    data: Json,
+    opt: &SkyDbOptions,
+  ) -> SkynetResult<DbEntry<Json>> {
+    skydb::set_json(self, keypair, data_key, data, opt).await
+  }
+
+  #[cfg(feature = "skydb")]
+  pub async fn db_update<T, F>(
+    &self,
+    keypair: &KeyPair,
+    data_key: &str,
+    update: F,
+    opt: &SkyDbOptions,
+  ) -> SkynetResult<DbEntry<T>>
+  where
+    T: Serialize + DeserializeOwned,
+    F: FnMut(Option<T>) -> T,
+  {
+    skydb::db_update(self, keypair, data_key, update, opt).await
+  }
+
+  #[cfg(feature = "skydb")]
+  pub async fn index_list(&self, public_key: &PublicKey, data_key: &str, opt: &SkyDbOptions) -> SkynetResult<Index> {
+    index::index_list(self, public_key, data_key, opt).await
+  }
+
+  #[cfg(feature = "skydb")]
+  pub async fn index_get(
+    &self,
+    public_key: &PublicKey,
+    data_key: &str,
+    name: &str,
+    opt: &SkyDbOptions,
+  ) -> SkynetResult<Option<String>> {
+    index::index_get(self, public_key, data_key, name, opt).await
+  }
+
+  #[cfg(feature = "skydb")]
+  pub async fn index_insert(
+    &self,
+    keypair: &KeyPair,
+    data_key: &str,
+    name: &str,
+    skylink: &str,
+    opt: &SkyDbOptions,
+  ) -> SkynetResult<Option<String>> {
+    index::index_insert(self, keypair, data_key, name, skylink, opt).await
+  }
+
+  #[cfg(feature = "skydb")]
+  pub async fn index_remove(
+    &self,
+    keypair: &KeyPair,
+    data_key: &str,
+    name: &str,
+    opt: &SkyDbOptions,
+  ) -> SkynetResult<Option<String>> {
+    index::index_remove(self, keypair, data_key, name, opt).await
+  }
+
+  #[cfg(feature = "skydb")]
+  pub async fn index_update<F>(
+    &self,
+    keypair: &KeyPair,
+    data_key: &str,
+    update: F,
+    opt: &SkyDbOptions,
+  ) -> SkynetResult<Index>
+  where
+    F: FnMut(&mut Index),
+  {
+    index::index_update(self, keypair, data_key, update, opt).await
+  }
+
+  #[cfg(feature = "dac")]
+  pub async fn get_profile(
+    &self,
+    user_id: &str,
+    opt: &SkyDbOptions,
+  ) -> SkynetResult<Option<Profile>> {
+    dac::get_profile(self, user_id, opt).await
+  }
+
+  #[cfg(feature = "dac")]
+  pub async fn get_following_page_count(
+    &self,
+    user_id: &str,
+    opt: &SkyDbOptions,
+  ) -> SkynetResult<u32> {
+    dac::get_following_page_count(self, user_id, opt).await
+  }
+
+  #[cfg(feature = "dac")]
+  pub async fn get_following_page(
+    &self,
+    user_id: &str,
+    page: u32,
+    opt: &SkyDbOptions,
+  ) -> SkynetResult<Vec<String>> {
+    dac::get_following_page(self, user_id, page, opt).await
+  }
+
+  #[cfg(feature = "dac")]
+  pub async fn get_following(
+    &self,
+    user_id: &str,
+    opt: &SkyDbOptions,
+  ) -> SkynetResult<Vec<String>> {
+    dac::get_following(self, user_id, opt).await
+  }
+
+  #[cfg(feature = "dac")]
+  pub fn get_feed_posts<'a, T: DeserializeOwned + 'a>(
+    &'a self,
+    user_id: &'a str,
+    skapp: &'a str,
+    opt: &'a SkyDbOptions,
+  ) -> impl Stream<Item = SkynetResult<T>> + 'a {
+    dac::get_feed_posts(self, user_id, skapp, opt)
+  }
+
+  #[cfg(feature = "backup")]
+  pub async fn backup_directory<P: AsRef<Path>>(
+    &self,
+    path: P,
+    keypair: &KeyPair,
+    opt: &BackupOptions,
+  ) -> SkynetResult<String> {
+    backup::backup_directory(self, path.as_ref(), keypair, opt).await
+  }
+
+  #[cfg(feature = "backup")]
+  pub async fn list_snapshots(
+    &self,
+    public_key: &PublicKey,
+    opt: &BackupOptions,
+  ) -> SkynetResult<Vec<(String, Snapshot)>> {
+    backup::list_snapshots(self, public_key, opt).await
+  }
+
+  #[cfg(feature = "backup")]
+  pub async fn restore_snapshot<P: AsRef<Path>>(
+    &self,
+    skylink: &str,
+    path: P,
+    opt: &BackupOptions,
+  ) -> SkynetResult<()> {
+    backup::restore_snapshot(self, skylink, path.as_ref(), opt).await
+  }
 }
 
 impl Default for SkynetClient {
@@ -144,3 +1274,306 @@ impl Default for SkynetClient {
     Self::new(DEFAULT_PORTAL_URL, SkynetClientOptions::default())
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{MockResponse, MockTransport, QueryParam};
+
+  const SKYLINK: &str = "AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng";
+
+  #[tokio::test]
+  async fn test_request_id() {
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::new(hyper::StatusCode::OK, ""));
+    transport.push_response(MockResponse::new(hyper::StatusCode::OK, ""));
+    let client = transport.client("https://siasky.net");
+
+    client.download_data(SKYLINK, DownloadOptions::default()).await.unwrap();
+    client.download_data(SKYLINK, DownloadOptions::default()).await.unwrap();
+    let requests = transport.requests();
+    let first = requests[0].headers[REQUEST_ID_HEADER].to_str().unwrap();
+    let second = requests[1].headers[REQUEST_ID_HEADER].to_str().unwrap();
+    assert_eq!(first.len(), 24);
+    assert_ne!(first, second);
+
+    let client = SkynetClient::new("http://127.0.0.1:1", SkynetClientOptions::default());
+    let err = client.download_data(SKYLINK, DownloadOptions::default()).await.unwrap_err();
+    assert!(matches!(err, RequestError(_, _)));
+    assert_eq!(err.request_id().unwrap().len(), 24);
+    let context = err.context().unwrap();
+    assert_eq!(context.operation, crate::Operation::Download);
+    assert_eq!(context.portal_url, "http://127.0.0.1:1");
+    assert!(context.endpoint.ends_with(SKYLINK));
+
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::new(hyper::StatusCode::INTERNAL_SERVER_ERROR, "oops"));
+    let client = transport.client("https://siasky.net");
+    let err = client.get_metadata(SKYLINK, crate::MetadataOptions {
+      sources: vec![crate::MetadataSource::Endpoint],
+      ..Default::default()
+    }).await.unwrap_err();
+    assert!(matches!(&err, RequestError(_, inner) if matches!(**inner, PortalResponse(_))));
+    assert_eq!(err.context().unwrap().operation, crate::Operation::Download);
+    assert_eq!(err.request_id(), Some(transport.requests()[0].headers[REQUEST_ID_HEADER].to_str().unwrap()));
+  }
+
+  #[tokio::test]
+  async fn test_call_auth() {
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::new(hyper::StatusCode::OK, ""));
+    transport.push_response(MockResponse::new(hyper::StatusCode::OK, ""));
+    let client = SkynetClient::with_transport("https://siasky.net", SkynetClientOptions {
+      auth: Some(AuthScheme::ApiKeyHeader("client".into())),
+      ..Default::default()
+    }, Arc::new(transport.clone()));
+
+    client.download_data(SKYLINK, DownloadOptions::default()).await.unwrap();
+    client.download_data(SKYLINK, DownloadOptions {
+      auth: Some(AuthScheme::BearerToken("call".into())),
+      ..Default::default()
+    }).await.unwrap();
+    let requests = transport.requests();
+    assert_eq!(requests[0].headers["skynet-api-key"], "client");
+    assert!(!requests[0].headers.contains_key("authorization"));
+    assert_eq!(requests[1].headers["authorization"], "Bearer call");
+    assert!(!requests[1].headers.contains_key("skynet-api-key"));
+  }
+
+  #[tokio::test]
+  async fn test_request_raw() {
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::new(hyper::StatusCode::NOT_FOUND, "no such route"));
+    let client = transport.client("https://siasky.net");
+
+    let mut headers = HeaderMap::new();
+    headers.insert("X-Custom", "1".parse().unwrap());
+    let query = QueryParams::new().with(QueryParam::Custom("limit".into()), 5);
+    let res = client.request_raw(Method::POST, "skynet/stats", query, headers, Bytes::from("{}")).await.unwrap();
+    assert_eq!(res.status(), hyper::StatusCode::NOT_FOUND);
+    assert_eq!(hyper::body::to_bytes(res.into_body()).await.unwrap(), "no such route");
+
+    let request = transport.last_request().unwrap();
+    assert_eq!(request.method, Method::POST);
+    assert_eq!(request.uri.to_string(), "https://siasky.net/skynet/stats?limit=5");
+    assert_eq!(request.headers["x-custom"], "1");
+    assert_eq!(request.body, b"{}");
+
+    for path in ["skynet/my stats", "skynet/stätś"] {
+      let res = client.request_raw(Method::GET, path, QueryParams::new(), HeaderMap::new(), Bytes::new()).await;
+      assert!(matches!(res, Err(HttpError(_))));
+    }
+    assert_eq!(transport.requests().len(), 1);
+
+    client.request_raw(Method::GET, "skynet/100%", QueryParams::new(), HeaderMap::new(), Bytes::new()).await.unwrap();
+    assert_eq!(transport.last_request().unwrap().uri.path(), "/skynet/100%25");
+  }
+
+  /// Fails with a real connection error until `failures` runs out, then answers `200 OK` with
+  /// an upload response.
+  #[derive(Debug)]
+  struct FlakyTransport {
+    failures: std::sync::atomic::AtomicU32,
+    attempts: std::sync::atomic::AtomicU32,
+  }
+
+  impl Transport for FlakyTransport {
+    fn request(&self, _req: Request<Body>) -> crate::transport::TransportFuture {
+      use std::sync::atomic::Ordering::SeqCst;
+
+      self.attempts.fetch_add(1, SeqCst);
+      let fail = self.failures.fetch_update(SeqCst, SeqCst, |n| n.checked_sub(1)).is_ok();
+      Box::pin(async move {
+        if fail {
+          let uri = "http://127.0.0.1:1".parse().unwrap();
+          hyper::Client::new().get(uri).await.map_err(HyperError)
+        } else {
+          Ok(Response::new(Body::from(format!(r#"{{"skylink":"{}"}}"#, SKYLINK))))
+        }
+      })
+    }
+  }
+
+  #[tokio::test]
+  async fn test_retries() {
+    use std::sync::atomic::{AtomicU32, Ordering::SeqCst};
+
+    let flaky = |failures| Arc::new(FlakyTransport {
+      failures: AtomicU32::new(failures),
+      attempts: AtomicU32::new(0),
+    });
+
+    let transport = flaky(2);
+    let client = SkynetClient::with_transport("https://siasky.net", SkynetClientOptions::default(), transport.clone());
+    client.download_data(SKYLINK, DownloadOptions::default()).await.unwrap();
+    assert_eq!(transport.attempts.load(SeqCst), 3);
+
+    let transport = flaky(3);
+    let client = SkynetClient::with_transport("https://siasky.net", SkynetClientOptions::default(), transport.clone());
+    let err = client.download_data(SKYLINK, DownloadOptions::default()).await.unwrap_err();
+    assert!(err.is_retryable());
+    assert_eq!(transport.attempts.load(SeqCst), 3);
+
+    // Uploads are POSTs, but sending the same multipart body again is safe.
+    let transport = flaky(1);
+    let client = SkynetClient::with_transport("https://siasky.net", SkynetClientOptions::default(), transport.clone());
+    let mut data = HashMap::new();
+    data.insert("foo.txt".to_string(), (mime::TEXT_PLAIN, "foo"));
+    assert_eq!(client.upload_data(data, UploadOptions::default()).await.unwrap(), format!("sia://{}", SKYLINK));
+    assert_eq!(transport.attempts.load(SeqCst), 2);
+
+    // Other POSTs aren't retried.
+    let transport = flaky(1);
+    let client = SkynetClient::with_transport("https://siasky.net", SkynetClientOptions::default(), transport.clone());
+    let req = Request::post("https://siasky.net/skynet/registry").body(Body::empty()).unwrap();
+    assert!(client.request(req).await.is_err());
+    assert_eq!(transport.attempts.load(SeqCst), 1);
+  }
+
+  #[tokio::test]
+  async fn test_events() {
+    let transport = Arc::new(FlakyTransport {
+      failures: std::sync::atomic::AtomicU32::new(1),
+      attempts: std::sync::atomic::AtomicU32::new(0),
+    });
+    let client = SkynetClient::with_transport("https://siasky.net", SkynetClientOptions::default(), transport);
+    let mut events = client.events();
+
+    let mut data = HashMap::new();
+    data.insert("foo.txt".to_string(), (mime::TEXT_PLAIN, "foo"));
+    client.upload_data(data, UploadOptions::default()).await.unwrap();
+    client.download_data(SKYLINK, DownloadOptions::default()).await.unwrap();
+    client.set_portal_url("https://skynetfree.net");
+
+    assert_eq!(events.recv().await.unwrap(), Event::UploadStarted { files: 1, size: 3 });
+    assert!(matches!(events.recv().await.unwrap(), Event::RetryScheduled { attempt: 1, .. }));
+    assert_eq!(events.recv().await.unwrap(), Event::UploadCompleted { skylink: SKYLINK.into(), size: 3 });
+    let size = format!(r#"{{"skylink":"{}"}}"#, SKYLINK).len() as u64;
+    assert_eq!(events.recv().await.unwrap(), Event::DownloadCompleted { skylink: SKYLINK.into(), size });
+    assert_eq!(events.recv().await.unwrap(), Event::PortalSwitched {
+      from: "https://siasky.net".into(),
+      to: "https://skynetfree.net".into(),
+    });
+
+    // The mock transport reads the body, so each part is taken in turn.
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::json(serde_json::json!({ "skylink": SKYLINK })));
+    let client = transport.client("https://siasky.net");
+    let mut events = client.events();
+    let mut data = HashMap::new();
+    data.insert("foo.txt".to_string(), (mime::TEXT_PLAIN, "foo"));
+    client.upload_data(data, UploadOptions::default()).await.unwrap();
+
+    events.recv().await.unwrap();
+    let mut sent = Vec::new();
+    while let Ok(Event::ChunkSent { sent: bytes, total }) = events.try_recv() {
+      assert_eq!(total, transport.last_request().unwrap().body.len() as u64);
+      sent.push(bytes);
+    }
+    assert_eq!(sent.len(), 4);
+    assert_eq!(sent.last(), Some(&(transport.last_request().unwrap().body.len() as u64)));
+  }
+
+  /// Never answers.
+  #[derive(Debug)]
+  struct StalledTransport;
+
+  impl Transport for StalledTransport {
+    fn request(&self, _req: Request<Body>) -> crate::transport::TransportFuture {
+      Box::pin(futures_util::future::pending())
+    }
+  }
+
+  #[tokio::test]
+  async fn test_deadline() {
+    let deadline = std::time::Duration::from_millis(50);
+    let client = SkynetClient::with_transport("https://siasky.net", SkynetClientOptions::default(), Arc::new(StalledTransport));
+
+    let opt = DownloadOptions { deadline: Some(deadline), ..Default::default() };
+    let err = client.download_data(SKYLINK, opt).await.unwrap_err();
+    assert!(matches!(err, DeadlineExceeded(d) if d == deadline));
+
+    let opt = MetadataOptions { deadline: Some(deadline), ..Default::default() };
+    let err = client.get_metadata(SKYLINK, opt).await.unwrap_err();
+    assert!(matches!(err, DeadlineExceeded(_)));
+  }
+
+  #[tokio::test]
+  async fn test_auto_portal() {
+    let dead = "http://127.0.0.1:1";
+    let err = SkynetClient::auto_with_portals(&[dead], SkynetClientOptions::default()).await.unwrap_err();
+    assert!(matches!(err, NoHealthyPortal(portals) if portals == vec![dead.to_string()]));
+
+    let first = crate::MockPortal::start().await;
+    let second = crate::MockPortal::start().await;
+    let second_url = second.url().to_string();
+    let opt = SkynetClientOptions { retries: 0, ..Default::default() };
+    let client = SkynetClient::auto_with_portals(&[dead, first.url(), &second_url], opt).await.unwrap();
+    assert_eq!(client.get_portal_url(), first.url());
+    assert_eq!(client.get_portal_candidates().unwrap().len(), 3);
+
+    // Repeated connection failures move the client to the next healthy portal, once however
+    // many fail at the same time.
+    let first_url = first.url().to_string();
+    drop(first);
+    let mut events = client.events();
+    let failures = (0..6).map(|_| client.download_data(SKYLINK, DownloadOptions::default()));
+    futures_util::future::join_all(failures).await;
+    assert_eq!(client.get_portal_url(), second_url);
+    assert_eq!(events.try_recv().unwrap(), Event::PortalSwitched { from: first_url.clone(), to: second_url.clone() });
+    assert!(events.try_recv().is_err());
+
+    // A failover decided on a portal the client already left doesn't move it back.
+    client.replace_portal_url(&first_url, dead);
+    assert_eq!(client.get_portal_url(), second_url);
+  }
+
+  #[tokio::test]
+  async fn test_health_probe() {
+    let dead = "http://127.0.0.1:1";
+    let first = crate::MockPortal::start().await;
+    let second = crate::MockPortal::start().await;
+    let second_url = second.url().to_string();
+    let opt = SkynetClientOptions {
+      health_probe_interval: Some(Duration::from_millis(20)),
+      ..Default::default()
+    };
+    let client = SkynetClient::auto_with_portals(&[dead, first.url(), &second_url], opt).await.unwrap();
+    assert_eq!(client.get_portal_url(), first.url());
+
+    let mut attempts = 0;
+    while client.get_portal_ranking().unwrap().last().map(String::as_str) != Some(dead) {
+      attempts += 1;
+      assert!(attempts < 100, "the dead portal was never ranked last");
+      tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    // The prober moves the client off a portal that went down without waiting for requests to fail.
+    drop(first);
+    let mut attempts = 0;
+    while client.get_portal_url() != second_url {
+      attempts += 1;
+      assert!(attempts < 100, "the client never left the dead portal");
+      tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+  }
+
+  #[tokio::test]
+  async fn test_shutdown() {
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::new(hyper::StatusCode::OK, "hello"));
+    let client = transport.client("https://siasky.net");
+    let clone = client.clone();
+
+    // A response whose body hasn't been read keeps shutdown waiting.
+    let res = client.request_raw(Method::GET, "skynet/stats", QueryParams::new(), HeaderMap::new(), Bytes::new())
+      .await
+      .unwrap();
+    assert!(tokio::time::timeout(Duration::from_millis(20), clone.shutdown()).await.is_err());
+    assert_eq!(hyper::body::to_bytes(res.into_body()).await.unwrap(), "hello");
+    clone.shutdown().await;
+
+    let err = client.download_data(SKYLINK, DownloadOptions::default()).await.unwrap_err();
+    assert!(matches!(err, ClientShutdown));
+  }
+}
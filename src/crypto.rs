@@ -1,31 +1,133 @@
+use crate::{SkynetError::*, SkynetResult};
 use crypto::{
   blake2b::Blake2b,
+  chacha20::ChaCha20,
+  curve25519::{curve25519, curve25519_base, Fe, GeP3},
   digest::Digest,
   ed25519,
   hmac::Hmac,
+  mac::Mac,
   pbkdf2::pbkdf2,
-  sha2::Sha256,
+  poly1305::Poly1305,
+  sha2::{Sha256, Sha512},
+  symmetriccipher::SynchronousStreamCipher,
+  util::fixed_time_eq,
 };
+use std::fmt;
+use hex::{FromHex, ToHex};
 use rand::prelude::*;
+use zeroize::Zeroize;
+#[cfg(any(feature = "skydb", feature = "keystore"))]
+use crypto::{
+  aead::{AeadDecryptor, AeadEncryptor},
+  chacha20poly1305::ChaCha20Poly1305,
+};
+
+/// An ed25519 private key: the 32-byte secret followed by the public key. Wiped from memory
+/// when dropped and redacted in `Debug`.
+#[derive(Clone)]
+pub struct PrivateKey([u8; 64]);
+
+impl PrivateKey {
+  pub fn from_bytes(bytes: [u8; 64]) -> Self {
+    Self(bytes)
+  }
+
+  pub fn as_bytes(&self) -> &[u8; 64] {
+    &self.0
+  }
+}
+
+impl Zeroize for PrivateKey {
+  fn zeroize(&mut self) {
+    self.0.zeroize();
+  }
+}
+
+impl Drop for PrivateKey {
+  fn drop(&mut self) {
+    self.zeroize();
+  }
+}
+
+impl fmt::Debug for PrivateKey {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_tuple("PrivateKey").field(&"..").finish()
+  }
+}
+
+/// The seed a keypair is derived from. Wiped from memory when dropped and redacted in `Debug`.
+#[derive(Clone)]
+pub struct Seed(Vec<u8>);
+
+impl Seed {
+  pub fn as_bytes(&self) -> &[u8] {
+    &self.0
+  }
+}
+
+impl From<Vec<u8>> for Seed {
+  fn from(bytes: Vec<u8>) -> Self {
+    Self(bytes)
+  }
+}
+
+impl Zeroize for Seed {
+  fn zeroize(&mut self) {
+    self.0.zeroize();
+  }
+}
+
+impl Drop for Seed {
+  fn drop(&mut self) {
+    self.zeroize();
+  }
+}
+
+impl fmt::Debug for Seed {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_tuple("Seed").field(&"..").finish()
+  }
+}
 
 #[derive(Debug)]
 pub struct KeyPair {
   pub public_key: [u8; 32],
-  pub private_key: [u8; 64],
+  pub private_key: PrivateKey,
+}
+
+/// X25519 keys for `seal` and `open_sealed`. The secret key is wiped from memory when dropped
+/// and redacted in `Debug`.
+pub struct BoxKeyPair {
+  pub public_key: [u8; 32],
+  pub secret_key: [u8; 32],
 }
 
-fn make_seed(length: u32) -> Vec<u8> {
-  let mut rng = rand::thread_rng();
-  let mut seed: Vec<u8> = Vec::new();
-  for _ in 0..length {
-  	seed.push(rng.gen());
+impl Drop for BoxKeyPair {
+  fn drop(&mut self) {
+    self.secret_key.zeroize();
   }
-  seed
 }
 
-pub fn gen_keypair_and_seed(length: u32) -> (KeyPair, Vec<u8>) {
+impl fmt::Debug for BoxKeyPair {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("BoxKeyPair")
+      .field("public_key", &self.public_key)
+      .field("secret_key", &"..")
+      .finish()
+  }
+}
+
+fn make_seed(length: u32) -> Seed {
+  // Filled in place, so growing the vector doesn't leave copies behind.
+  let mut seed = vec![0; length as usize];
+  rand::thread_rng().fill(&mut seed[..]);
+  Seed(seed)
+}
+
+pub fn gen_keypair_and_seed(length: u32) -> (KeyPair, Seed) {
   let seed = make_seed(length);
-  (gen_keypair_from_seed(&seed), seed)
+  (gen_keypair_from_seed(seed.as_bytes()), seed)
 }
 
 pub fn gen_keypair_from_seed(seed: &[u8]) -> KeyPair {
@@ -34,20 +136,407 @@ pub fn gen_keypair_from_seed(seed: &[u8]) -> KeyPair {
   pbkdf2(&mut mac, &[], 1000, &mut derived_key);
 
   let (private_key, public_key) = ed25519::keypair(&derived_key);
+  derived_key.zeroize();
 
   KeyPair {
   	public_key,
-  	private_key,
+  	private_key: PrivateKey(private_key),
+  }
+}
+
+/// The bytes actually signed: `data` as is, or prefixed with the length-prefixed `domain` so a
+/// signature made for one purpose can't be replayed for another.
+fn signed_message(data: &[u8], domain: Option<&str>) -> Vec<u8> {
+  match domain {
+    Some(domain) => {
+      let mut message = (domain.len() as u64).to_le_bytes().to_vec();
+      message.extend_from_slice(domain.as_bytes());
+      message.extend_from_slice(data);
+      message
+    }
+    None => data.to_vec(),
   }
 }
 
-pub fn derive_child_seed(master: &[u8], seed: &[u8]) -> Vec<u8> {
+/// Signs `data` with an ed25519 private key such as `KeyPair::private_key`, the same keys used
+/// for registry entries. Pass a `domain` naming the purpose of the signature, and the same one
+/// to `verify`.
+pub fn sign(data: &[u8], private_key: &PrivateKey, domain: Option<&str>) -> [u8; 64] {
+  ed25519::signature(&signed_message(data, domain), private_key.as_bytes())
+}
+
+/// Checks a signature made by `sign` with the same `domain`. Keys and signatures of the wrong
+/// length don't verify.
+pub fn verify(data: &[u8], signature: &[u8], public_key: &[u8], domain: Option<&str>) -> bool {
+  if signature.len() != 64 || public_key.len() != 32 {
+    return false;
+  }
+  ed25519::verify(&signed_message(data, domain), public_key, signature)
+}
+
+pub fn gen_box_keypair() -> BoxKeyPair {
+  let mut secret_key = [0; 32];
+  rand::thread_rng().fill(&mut secret_key);
+  BoxKeyPair {
+    public_key: curve25519_base(&secret_key),
+    secret_key,
+  }
+}
+
+/// The X25519 keys for an ed25519 identity, so a registry keypair can open sealed boxes and agree
+/// on shared secrets.
+impl From<&KeyPair> for BoxKeyPair {
+  fn from(keypair: &KeyPair) -> Self {
+    let secret_key = x25519_secret_key(&keypair.private_key);
+    BoxKeyPair {
+      public_key: curve25519_base(&secret_key),
+      secret_key,
+    }
+  }
+}
+
+/// The X25519 secret key for an ed25519 private key, the scalar ed25519 signs with.
+pub fn x25519_secret_key(private_key: &PrivateKey) -> [u8; 32] {
+  let mut hash = sha512(&private_key.as_bytes()[..32]);
+  let mut secret_key = [0; 32];
+  secret_key.copy_from_slice(&hash[..32]);
+  hash.zeroize();
+  secret_key[0] &= 248;
+  secret_key[31] &= 127;
+  secret_key[31] |= 64;
+  secret_key
+}
+
+/// The X25519 public key for an ed25519 public key, such as a registry entry's or a MySky user
+/// ID, mapping the Edwards `y` to the Montgomery `u = (1 + y) / (1 - y)`.
+pub fn x25519_public_key(public_key: &[u8]) -> SkynetResult<[u8; 32]> {
+  if public_key.len() != 32 || GeP3::from_bytes_negate_vartime(public_key).is_none() {
+    return Err(InvalidPublicKey(public_key.encode_hex()));
+  }
+
+  let mut one = [0; 32];
+  one[0] = 1;
+  let one = Fe::from_bytes(&one);
+  let y = Fe::from_bytes(public_key);
+  let u = (one + y) * (one - y).invert();
+  Ok(u.to_bytes())
+}
+
+/// Diffie-Hellman between our ed25519 identity and theirs. Both sides get the same 32-byte key,
+/// which suits `SkyDbEncryption::Local` for data only the two of them can read.
+pub fn shared_secret(keypair: &KeyPair, their_public_key: &[u8]) -> SkynetResult<[u8; 32]> {
+  let ours = BoxKeyPair::from(keypair);
+  let theirs = x25519_public_key(their_public_key)?;
+  let shared = curve25519(&ours.secret_key, &theirs);
+  // Low-order points give an all-zero secret that anyone could compute.
+  if shared == [0; 32] {
+    return Err(InvalidPublicKey(their_public_key.encode_hex()));
+  }
+
+  let (first, second) = if ours.public_key <= theirs { (ours.public_key, theirs) } else { (theirs, ours.public_key) };
+  Ok(blake2b_256(&[&shared, &first, &second]))
+}
+
+const SEAL_KEY_SIZE: usize = 32;
+const SEAL_TAG_SIZE: usize = 16;
+/// Bytes `seal` adds to the plaintext.
+pub const SEAL_OVERHEAD: usize = SEAL_KEY_SIZE + SEAL_TAG_SIZE;
+
+/// The XChaCha20-Poly1305 key and nonce of a sealed box, bound to both public keys.
+fn seal_key(shared: &[u8; 32], ephemeral_public_key: &[u8; 32], public_key: &[u8; 32]) -> ([u8; 32], [u8; 24]) {
+  let key = blake2b_256(&[shared, ephemeral_public_key, public_key]);
+  let mut nonce = [0; 24];
+  nonce.copy_from_slice(&blake2b_256(&[ephemeral_public_key, public_key])[..24]);
+  (key, nonce)
+}
+
+/// XChaCha20 positioned after the first block, and the Poly1305 key taken from that block.
+fn xchacha20(key: &[u8; 32], nonce: &[u8; 24]) -> (ChaCha20, [u8; 32]) {
+  let mut cipher = ChaCha20::new_xchacha20(key, nonce);
+  let mut block = [0; 64];
+  cipher.process(&[0; 64], &mut block);
+  let mut poly_key = [0; 32];
+  poly_key.copy_from_slice(&block[..32]);
+  (cipher, poly_key)
+}
+
+/// The AEAD tag over `aad` and `ciphertext`. Sealed boxes have no associated data.
+fn poly1305_tag(poly_key: &[u8; 32], aad: &[u8], ciphertext: &[u8]) -> [u8; 16] {
+  let mut mac = Poly1305::new(poly_key);
+  for data in &[aad, ciphertext] {
+    mac.input(data);
+    mac.input(&[0; 16][..(16 - data.len() % 16) % 16]);
+  }
+  mac.input(&(aad.len() as u64).to_le_bytes());
+  mac.input(&(ciphertext.len() as u64).to_le_bytes());
+  let mut tag = [0; 16];
+  mac.raw_result(&mut tag);
+  tag
+}
+
+/// Encrypts `plaintext` so only the holder of the secret key for `public_key` can read it. A
+/// fresh ephemeral X25519 key is agreed with `public_key` and the data is encrypted with
+/// XChaCha20-Poly1305. The output is `ephemeral public key || ciphertext || tag`, and reveals
+/// nothing about the sender.
+pub fn seal(plaintext: &[u8], public_key: &[u8; 32]) -> Vec<u8> {
+  let ephemeral = gen_box_keypair();
+  let shared = curve25519(&ephemeral.secret_key, public_key);
+  let (key, nonce) = seal_key(&shared, &ephemeral.public_key, public_key);
+  let (mut cipher, poly_key) = xchacha20(&key, &nonce);
+
+  let mut out = vec![0; SEAL_KEY_SIZE + plaintext.len() + SEAL_TAG_SIZE];
+  out[..SEAL_KEY_SIZE].copy_from_slice(&ephemeral.public_key);
+  let (ciphertext, tag) = out[SEAL_KEY_SIZE..].split_at_mut(plaintext.len());
+  cipher.process(plaintext, ciphertext);
+  tag.copy_from_slice(&poly1305_tag(&poly_key, &[], ciphertext));
+  out
+}
+
+/// Decrypts a box made by `seal` for `keypair`. Fails with `DecryptionError` if it wasn't sealed
+/// for this key or was tampered with.
+pub fn open_sealed(data: &[u8], keypair: &BoxKeyPair) -> SkynetResult<Vec<u8>> {
+  if data.len() < SEAL_OVERHEAD {
+    return Err(DecryptionError);
+  }
+
+  let (ephemeral_public_key, rest) = data.split_at(SEAL_KEY_SIZE);
+  let (ciphertext, tag) = rest.split_at(rest.len() - SEAL_TAG_SIZE);
+  let mut epk = [0; 32];
+  epk.copy_from_slice(ephemeral_public_key);
+
+  let shared = curve25519(&keypair.secret_key, &epk);
+  let (key, nonce) = seal_key(&shared, &epk, &keypair.public_key);
+  let (mut cipher, poly_key) = xchacha20(&key, &nonce);
+  if !fixed_time_eq(&poly1305_tag(&poly_key, &[], ciphertext), tag) {
+    return Err(DecryptionError);
+  }
+
+  let mut plaintext = vec![0; ciphertext.len()];
+  cipher.process(ciphertext, &mut plaintext);
+  Ok(plaintext)
+}
+
+pub(crate) fn blake2b_256(parts: &[&[u8]]) -> [u8; 32] {
+  let mut hash = [0; 32];
+  let mut hasher = Blake2b::new(32);
+  for part in parts {
+    Digest::input(&mut hasher, part);
+  }
+  Digest::result(&mut hasher, &mut hash);
+  hash
+}
+
+#[cfg(any(feature = "skydb", feature = "keystore"))]
+const NONCE_SIZE: usize = 24;
+#[cfg(any(feature = "skydb", feature = "keystore"))]
+const TAG_SIZE: usize = 16;
+/// Bytes `encrypt` adds to the plaintext.
+#[cfg(feature = "skydb")]
+pub(crate) const ENCRYPTION_OVERHEAD: usize = NONCE_SIZE + TAG_SIZE;
+
+/// Encrypts with ChaCha20-Poly1305 under a subkey derived from `key` and a random 24-byte nonce,
+/// so nonces never repeat for a key. The output is `nonce || ciphertext || tag`.
+#[cfg(any(feature = "skydb", feature = "keystore"))]
+pub(crate) fn encrypt(key: &[u8; 32], plaintext: &[u8]) -> Vec<u8> {
+  let mut nonce = [0; NONCE_SIZE];
+  rand::thread_rng().fill(&mut nonce);
+  let subkey = blake2b_256(&[key, &nonce]);
+
+  let mut out = vec![0; NONCE_SIZE + plaintext.len() + TAG_SIZE];
+  out[..NONCE_SIZE].copy_from_slice(&nonce);
+  let (ciphertext, tag) = out[NONCE_SIZE..].split_at_mut(plaintext.len());
+  ChaCha20Poly1305::new(&subkey, &[0; 8], &[]).encrypt(plaintext, ciphertext, tag);
+  out
+}
+
+#[cfg(any(feature = "skydb", feature = "keystore"))]
+pub(crate) fn decrypt(key: &[u8; 32], data: &[u8]) -> SkynetResult<Vec<u8>> {
+  if data.len() < NONCE_SIZE + TAG_SIZE {
+    return Err(DecryptionError);
+  }
+
+  let (nonce, rest) = data.split_at(NONCE_SIZE);
+  let (ciphertext, tag) = rest.split_at(rest.len() - TAG_SIZE);
+  let subkey = blake2b_256(&[key, nonce]);
+
+  let mut plaintext = vec![0; ciphertext.len()];
+  if !ChaCha20Poly1305::new(&subkey, &[0; 8], &[]).decrypt(ciphertext, &mut plaintext, tag) {
+    return Err(DecryptionError);
+  }
+  Ok(plaintext)
+}
+
+/// A 32-byte key stretched from `passphrase` with PBKDF2-HMAC-SHA256.
+#[cfg(feature = "keystore")]
+pub(crate) fn derive_passphrase_key(passphrase: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
+  let mut mac = Hmac::new(Sha256::new(), passphrase.as_bytes());
+  let mut key = [0; 32];
+  pbkdf2(&mut mac, salt, iterations, &mut key);
+  key
+}
+
+/// Sia merkle root over 64-byte segments, with `0x00`-prefixed leaves and `0x01`-prefixed nodes.
+pub fn merkle_root(data: &[u8]) -> [u8; 32] {
+  let mut stack: Vec<(u32, [u8; 32])> = Vec::new();
+
+  for segment in data.chunks(64) {
+    let mut node = (0, blake2b_256(&[&[0], segment]));
+    while let Some(&(height, left)) = stack.last() {
+      if height != node.0 {
+        break;
+      }
+      stack.pop();
+      node = (height + 1, blake2b_256(&[&[1], &left, &node.1]));
+    }
+    stack.push(node);
+  }
+
+  let mut root = match stack.pop() {
+    Some((_, root)) => root,
+    None => return [0; 32],
+  };
+  while let Some((_, left)) = stack.pop() {
+    root = blake2b_256(&[&[1], &left, &root]);
+  }
+  root
+}
+
+const DISCOVERABLE_BUCKET_TWEAK_VERSION: u8 = 1;
+
+/// Registry data key tweak of a MySky discoverable file: the hash of the version byte followed
+/// by the hash of each `/`-separated path component.
+pub fn derive_discoverable_file_tweak(path: &str) -> [u8; 32] {
+  let mut encoded = vec![DISCOVERABLE_BUCKET_TWEAK_VERSION];
+  for component in path.split('/') {
+    encoded.extend_from_slice(&blake2b_256(&[component.as_bytes()]));
+  }
+  blake2b_256(&[&encoded])
+}
+
+const KIB: u64 = 1 << 10;
+
+/// Padding block MySky uses for encrypted files of `size` bytes: 4 KiB up to 80 KiB, then
+/// doubling along with the size bracket, so padding never exceeds 5%.
+fn padding_block(size: u64) -> Option<u64> {
+  let mut block = 4 * KIB;
+  while size > block.checked_mul(20)? {
+    block = block.checked_mul(2)?;
+  }
+  Some(block)
+}
+
+/// Rounds `size` up to its padding block so encrypted files don't leak their exact size.
+/// Returns `None` if the padded size would overflow.
+pub fn pad_file_size(size: u64) -> Option<u64> {
+  let block = padding_block(size)?;
+  match size % block {
+    0 => Some(size),
+    rem => size.checked_add(block - rem),
+  }
+}
+
+/// Whether `size` is a valid padded size as produced by `pad_file_size`.
+pub fn check_padded_block(size: u64) -> bool {
+  pad_file_size(size) == Some(size)
+}
+
+const SALT_ENCRYPTED_TWEAK: &str = "encrypted filesystem tweak";
+const SALT_ENCRYPTION: &str = "encryption";
+const SALT_ENCRYPTED_CHILD: &str = "encrypted filesystem child";
+const SALT_ROOT_DISCOVERABLE_KEY: &str = "root discoverable key";
+const SALT_ROOT_PATH_SEED: &str = "root path seed";
+/// Encrypted path seeds of files are cut to this many bytes; directories keep all 64.
+const ENCRYPTED_FILE_PATH_SEED_LENGTH: usize = 32;
+
+fn sha512(data: &[u8]) -> [u8; 64] {
+  let mut hash = [0; 64];
+  let mut hasher = Sha512::new();
+  hasher.input(data);
+  hasher.result(&mut hash);
+  hash
+}
+
+/// `sha512(sha512(salt) || sha512(seed))`, as MySky derives it.
+fn derive_from_seed(salt: &str, seed: &[u8]) -> [u8; 64] {
+  let mut bytes = sha512(salt.as_bytes()).to_vec();
+  bytes.extend_from_slice(&sha512(seed));
+  sha512(&bytes)
+}
+
+/// First 32 bytes of `derive_from_seed`.
+fn derive_from_path_seed(salt: &str, path_seed: &str) -> [u8; 32] {
+  let mut derived = [0; 32];
+  derived.copy_from_slice(&derive_from_seed(salt, path_seed.as_bytes())[..32]);
+  derived
+}
+
+/// The keypair of the MySky user with `seed`. Its public key is the user ID.
+pub fn gen_mysky_keypair(seed: &[u8]) -> KeyPair {
+  let mut hash = derive_from_seed(SALT_ROOT_DISCOVERABLE_KEY, seed);
+  let (private_key, public_key) = ed25519::keypair(&hash[..32]);
+  hash.zeroize();
+
+  KeyPair {
+    public_key,
+    private_key: PrivateKey(private_key),
+  }
+}
+
+/// Hex path seed of the root of the hidden files of the MySky user with `seed`.
+pub fn derive_root_path_seed(seed: &[u8]) -> Seed {
+  let mut hash = derive_from_seed(SALT_ROOT_PATH_SEED, seed);
+  let path_seed = Seed(hash.encode_hex::<String>().into_bytes());
+  hash.zeroize();
+  path_seed
+}
+
+/// Hex path seed of the hidden file or directory at `sub_path` below the directory with the
+/// hex `path_seed`. Each path component is hashed in turn with the seed of its parent.
+pub fn derive_encrypted_path_seed(path_seed: &str, sub_path: &str, is_directory: bool) -> SkynetResult<String> {
+  let mut seed = Vec::from_hex(path_seed).map_err(|_| InvalidPath(sub_path.to_string()))?;
+  let names: Vec<&str> = sub_path.split('/').filter(|name| !name.is_empty()).collect();
+  if names.is_empty() {
+    return Err(InvalidPath(sub_path.to_string()));
+  }
+
+  for (i, name) in names.iter().enumerate() {
+    let directory = i + 1 < names.len() || is_directory;
+    let mut derivation_path = seed.clone();
+    derivation_path.push(directory as u8);
+    derivation_path.extend_from_slice(name.as_bytes());
+
+    let mut bytes = sha512(SALT_ENCRYPTED_CHILD.as_bytes()).to_vec();
+    bytes.extend_from_slice(&sha512(&derivation_path));
+    seed.zeroize();
+    derivation_path.zeroize();
+    seed = sha512(&bytes).to_vec();
+  }
+
+  if !is_directory {
+    seed.truncate(ENCRYPTED_FILE_PATH_SEED_LENGTH);
+  }
+  let path_seed = seed.encode_hex::<String>();
+  seed.zeroize();
+  Ok(path_seed)
+}
+
+/// Registry data key tweak of a MySky hidden (encrypted) file with the given hex path seed.
+pub fn derive_encrypted_file_tweak(path_seed: &str) -> [u8; 32] {
+  derive_from_path_seed(SALT_ENCRYPTED_TWEAK, path_seed)
+}
+
+/// Key entropy used to encrypt the contents of a MySky hidden file with the given hex path seed.
+pub fn derive_encrypted_file_key_entropy(path_seed: &str) -> [u8; 32] {
+  derive_from_path_seed(SALT_ENCRYPTION, path_seed)
+}
+
+pub fn derive_child_seed(master: &[u8], seed: &[u8]) -> Seed {
   let mut child_seed = vec![0; master.len()];
   let mut hasher = Blake2b::new(master.len());
   Digest::input(&mut hasher, master);
   Digest::input(&mut hasher, seed);
   Digest::result(&mut hasher, &mut child_seed);
-  child_seed
+  Seed(child_seed)
 }
 
 #[cfg(test)]
@@ -57,10 +546,185 @@ mod tests {
   #[test]
   fn test_keypair() {
     let (keypair, seed) = gen_keypair_and_seed(64);
-    let keypair2 = gen_keypair_from_seed(&seed);
+    let keypair2 = gen_keypair_from_seed(seed.as_bytes());
     assert_eq!(keypair.public_key, keypair2.public_key);
-    assert_eq!(keypair.private_key, keypair2.private_key);
-    let child_seed = derive_child_seed(&seed, b"foo");
-    assert_eq!(child_seed.len(), seed.len());
+    assert_eq!(keypair.private_key.as_bytes(), keypair2.private_key.as_bytes());
+    let child_seed = derive_child_seed(seed.as_bytes(), b"foo");
+    assert_eq!(child_seed.as_bytes().len(), seed.as_bytes().len());
+
+    assert_eq!(format!("{:?}", seed), "Seed(\"..\")");
+    assert_eq!(format!("{:?}", keypair.private_key), "PrivateKey(\"..\")");
+
+    let mut private_key = keypair.private_key.clone();
+    private_key.zeroize();
+    assert_eq!(private_key.as_bytes(), &[0; 64]);
+  }
+
+  #[test]
+  fn test_sign_verify() {
+    let (keypair, _) = gen_keypair_and_seed(64);
+    let (other, _) = gen_keypair_and_seed(64);
+
+    let signature = sign(b"hello", &keypair.private_key, None);
+    assert!(verify(b"hello", &signature, &keypair.public_key, None));
+    assert!(!verify(b"hellO", &signature, &keypair.public_key, None));
+    assert!(!verify(b"hello", &signature, &other.public_key, None));
+    assert!(!verify(b"hello", &signature[..63], &keypair.public_key, None));
+    assert!(!verify(b"hello", &signature, &keypair.public_key[..31], None));
+
+    let signature = sign(b"hello", &keypair.private_key, Some("app.example/v1"));
+    assert!(verify(b"hello", &signature, &keypair.public_key, Some("app.example/v1")));
+    assert!(!verify(b"hello", &signature, &keypair.public_key, Some("app.example/v2")));
+    assert!(!verify(b"hello", &signature, &keypair.public_key, None));
+  }
+
+  #[test]
+  fn test_seal() {
+    let keypair = gen_box_keypair();
+    let sealed = seal(b"hello world", &keypair.public_key);
+    assert_eq!(sealed.len(), 11 + SEAL_OVERHEAD);
+    assert_ne!(seal(b"hello world", &keypair.public_key), sealed);
+    assert_eq!(open_sealed(&sealed, &keypair).unwrap(), b"hello world");
+    assert_eq!(open_sealed(&seal(b"", &keypair.public_key), &keypair).unwrap(), b"");
+
+    assert!(matches!(open_sealed(&sealed, &gen_box_keypair()), Err(DecryptionError)));
+    let mut tampered = sealed.clone();
+    tampered[SEAL_KEY_SIZE] ^= 1;
+    assert!(matches!(open_sealed(&tampered, &keypair), Err(DecryptionError)));
+    assert!(matches!(open_sealed(&sealed[..SEAL_OVERHEAD - 1], &keypair), Err(DecryptionError)));
+  }
+
+  #[test]
+  fn test_x25519() {
+    let (alice, _) = gen_keypair_and_seed(64);
+    let (bob, _) = gen_keypair_and_seed(64);
+
+    let alice_box = BoxKeyPair::from(&alice);
+    assert_eq!(x25519_public_key(&alice.public_key).unwrap(), alice_box.public_key);
+    let sealed = seal(b"hello", &x25519_public_key(&alice.public_key).unwrap());
+    assert_eq!(open_sealed(&sealed, &alice_box).unwrap(), b"hello");
+
+    let secret = shared_secret(&alice, &bob.public_key).unwrap();
+    assert_eq!(shared_secret(&bob, &alice.public_key).unwrap(), secret);
+    assert_ne!(shared_secret(&alice, &alice.public_key).unwrap(), secret);
+
+    assert!(matches!(x25519_public_key(&[1; 31]), Err(InvalidPublicKey(_))));
+    // The identity point is valid ed25519 encoding but of low order.
+    let mut identity = [0; 32];
+    identity[0] = 1;
+    assert!(matches!(shared_secret(&alice, &identity), Err(InvalidPublicKey(_))));
+  }
+
+  #[test]
+  fn test_xchacha20_poly1305() {
+    // draft-irtf-cfrg-xchacha-03, appendix A.3.1.
+    let (mut key, mut nonce) = ([0; 32], [0; 24]);
+    key.iter_mut().zip(0x80..).for_each(|(b, i)| *b = i);
+    nonce.iter_mut().zip(0x40..).for_each(|(b, i)| *b = i);
+    let plaintext = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+    let aad = hex::decode("50515253c0c1c2c3c4c5c6c7").unwrap();
+
+    let (mut cipher, poly_key) = xchacha20(&key, &nonce);
+    assert_eq!(
+      hex::encode(poly_key),
+      "7b191f80f361f099094f6f4b8fb97df847cc6873a8f2b190dd73807183f907d5");
+    let mut ciphertext = vec![0; plaintext.len()];
+    cipher.process(plaintext, &mut ciphertext);
+    assert_eq!(
+      hex::encode(&ciphertext),
+      concat!(
+        "bd6d179d3e83d43b9576579493c0e939572a1700252bfaccbed2902c21396cbb",
+        "731c7f1b0b4aa6440bf3a82f4eda7e39ae64c6708c54c216cb96b72e1213b452",
+        "2f8c9ba40db5d945b11b69b982c1bb9e3f3fac2bc369488f76b2383565d3fff9",
+        "21f9664c97637da9768812f615c68b13b52e"));
+    assert_eq!(hex::encode(poly1305_tag(&poly_key, &aad, &ciphertext)), "c0875924c1c7987947deafd8780acf49");
+  }
+
+  #[cfg(feature = "skydb")]
+  #[test]
+  fn test_encrypt_decrypt() {
+    let key = [7; 32];
+    let encrypted = encrypt(&key, b"hello world");
+    assert_eq!(encrypted.len(), NONCE_SIZE + 11 + TAG_SIZE);
+    assert_ne!(encrypt(&key, b"hello world"), encrypted);
+    assert_eq!(decrypt(&key, &encrypted).unwrap(), b"hello world");
+    assert!(decrypt(&[8; 32], &encrypted).is_err());
+    assert!(decrypt(&key, &encrypted[..20]).is_err());
+  }
+
+  #[test]
+  fn test_derive_discoverable_file_tweak() {
+    let tweak = derive_discoverable_file_tweak("profile-dac.hns/profileIndex.json");
+    let mut encoded = vec![1];
+    encoded.extend_from_slice(&blake2b_256(&[b"profile-dac.hns"]));
+    encoded.extend_from_slice(&blake2b_256(&[b"profileIndex.json"]));
+    assert_eq!(tweak, blake2b_256(&[&encoded]));
+    assert_ne!(tweak, derive_discoverable_file_tweak("profile-dac.hns/other.json"));
+  }
+
+  #[test]
+  fn test_derive_encrypted_file() {
+    let seed = "a".repeat(64);
+    let tweak = derive_encrypted_file_tweak(&seed);
+    let entropy = derive_encrypted_file_key_entropy(&seed);
+
+    let mut bytes = sha512(b"encrypted filesystem tweak").to_vec();
+    bytes.extend_from_slice(&sha512(seed.as_bytes()));
+    assert_eq!(tweak[..], sha512(&bytes)[..32]);
+    assert_ne!(tweak, entropy);
+    assert_ne!(tweak, derive_encrypted_file_tweak(&"b".repeat(64)));
+  }
+
+  #[test]
+  fn test_mysky_derivation() {
+    let keypair = gen_mysky_keypair(b"seed");
+    assert_eq!(keypair.public_key, gen_mysky_keypair(b"seed").public_key);
+    assert_ne!(keypair.public_key, gen_keypair_from_seed(b"seed").public_key);
+    assert_ne!(keypair.public_key, gen_mysky_keypair(b"other").public_key);
+
+    let root = derive_root_path_seed(b"seed");
+    let root = std::str::from_utf8(root.as_bytes()).unwrap();
+    assert_eq!(root.len(), 128);
+
+    let dir = derive_encrypted_path_seed(root, "app.hns/photos", true).unwrap();
+    let file = derive_encrypted_path_seed(root, "app.hns/photos/cat.jpg", false).unwrap();
+    assert_eq!(dir.len(), 128);
+    assert_eq!(file.len(), 64);
+    assert_eq!(derive_encrypted_path_seed(&dir, "cat.jpg", false).unwrap(), file);
+    assert_ne!(derive_encrypted_path_seed(root, "app.hns/photos/cat.jpg", true).unwrap()[..64], file);
+
+    assert!(matches!(derive_encrypted_path_seed(root, "/", false), Err(InvalidPath(_))));
+    assert!(matches!(derive_encrypted_path_seed("xyz", "app.hns", false), Err(InvalidPath(_))));
+  }
+
+  #[test]
+  fn test_pad_file_size() {
+    assert_eq!(pad_file_size(0), Some(0));
+    assert_eq!(pad_file_size(1), Some(4 * KIB));
+    assert_eq!(pad_file_size(4 * KIB), Some(4 * KIB));
+    assert_eq!(pad_file_size(80 * KIB - 1), Some(80 * KIB));
+    assert_eq!(pad_file_size(80 * KIB + 1), Some(88 * KIB));
+    assert_eq!(pad_file_size(200 * KIB), Some(208 * KIB));
+    assert_eq!(pad_file_size(u64::MAX), None);
+
+    assert!(check_padded_block(4 * KIB));
+    assert!(check_padded_block(88 * KIB));
+    assert!(!check_padded_block(84 * KIB));
+    assert!(!check_padded_block(100));
+    assert!(!check_padded_block(u64::MAX));
+  }
+
+  #[test]
+  fn test_merkle_root() {
+    let leaf = |data: &[u8]| blake2b_256(&[&[0], data]);
+    let node = |left: &[u8; 32], right: &[u8; 32]| blake2b_256(&[&[1], left, right]);
+
+    assert_eq!(merkle_root(&[1; 64]), leaf(&[1; 64]));
+    assert_eq!(merkle_root(&[1; 128]), node(&leaf(&[1; 64]), &leaf(&[1; 64])));
+
+    let mut data = vec![1; 128];
+    data.extend_from_slice(&[2; 10]);
+    let left = node(&leaf(&[1; 64]), &leaf(&[1; 64]));
+    assert_eq!(merkle_root(&data), node(&left, &leaf(&[2; 10])));
   }
 }
@@ -0,0 +1,308 @@
+use crate::{skydb, SkyDbOptions, SkynetClient, SkynetResult};
+use futures_util::stream::{self, Stream};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+pub const PROFILE_DAC_DOMAIN: &str = "profile-dac.hns";
+pub const SOCIAL_DAC_DOMAIN: &str = "social-dac.hns";
+pub const FEED_DAC_DOMAIN: &str = "feed-dac.hns";
+
+/// `index.json` of a paginated DAC directory. Pages are `page_0.json` up to the current page.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PageIndex {
+  curr_page_number: u32,
+}
+
+#[derive(Deserialize)]
+struct Page<T> {
+  #[serde(default = "Vec::new")]
+  items: Vec<T>,
+}
+
+async fn get_page_count(
+  client: &SkynetClient,
+  user_id: &str,
+  dir: &str,
+  opt: &SkyDbOptions,
+) -> SkynetResult<u32> {
+  let path = format!("{}/index.json", dir);
+  let index = skydb::get_discoverable_json::<PageIndex>(client, user_id, &path, opt).await?;
+  Ok(index.map(|index| index.data.curr_page_number + 1).unwrap_or(0))
+}
+
+async fn get_page<T: DeserializeOwned>(
+  client: &SkynetClient,
+  user_id: &str,
+  dir: &str,
+  page: u32,
+  opt: &SkyDbOptions,
+) -> SkynetResult<Vec<T>> {
+  let path = format!("{}/page_{}.json", dir, page);
+  let page = skydb::get_discoverable_json::<Page<T>>(client, user_id, &path, opt).await?;
+  Ok(page.map(|page| page.data.items).unwrap_or_default())
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Avatar {
+  pub ext: String,
+  pub w: u32,
+  pub h: u32,
+  pub url: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Profile {
+  pub username: String,
+  pub first_name: Option<String>,
+  pub last_name: Option<String>,
+  pub about_me: Option<String>,
+  pub location: Option<String>,
+  pub topics: Vec<String>,
+  pub avatar: Vec<Avatar>,
+}
+
+impl Profile {
+  /// First and last name when set, otherwise the username.
+  pub fn display_name(&self) -> String {
+    let name = [&self.first_name, &self.last_name]
+      .iter()
+      .filter_map(|part| part.as_deref())
+      .collect::<Vec<_>>()
+      .join(" ");
+
+    if name.is_empty() {
+      self.username.clone()
+    } else {
+      name
+    }
+  }
+
+  pub fn avatar_skylink(&self) -> Option<&str> {
+    self.avatar.first().map(|avatar| avatar.url.as_str())
+  }
+
+  pub fn bio(&self) -> Option<&str> {
+    self.about_me.as_deref()
+  }
+}
+
+#[derive(Deserialize)]
+struct ProfileIndex {
+  profile: Profile,
+}
+
+/// Reads the Profile DAC profile of `user_id`, or `None` if the user never set one.
+pub async fn get_profile(
+  client: &SkynetClient,
+  user_id: &str,
+  opt: &SkyDbOptions,
+) -> SkynetResult<Option<Profile>> {
+  let path = format!("{}/profileIndex.json", PROFILE_DAC_DOMAIN);
+  let index = skydb::get_discoverable_json::<ProfileIndex>(client, user_id, &path, opt).await?;
+  Ok(index.map(|index| index.data.profile))
+}
+
+/// Number of pages in the Social DAC following list of `user_id`.
+pub async fn get_following_page_count(
+  client: &SkynetClient,
+  user_id: &str,
+  opt: &SkyDbOptions,
+) -> SkynetResult<u32> {
+  get_page_count(client, user_id, &format!("{}/following", SOCIAL_DAC_DOMAIN), opt).await
+}
+
+/// User IDs on one page of the Social DAC following list of `user_id`.
+pub async fn get_following_page(
+  client: &SkynetClient,
+  user_id: &str,
+  page: u32,
+  opt: &SkyDbOptions,
+) -> SkynetResult<Vec<String>> {
+  get_page(client, user_id, &format!("{}/following", SOCIAL_DAC_DOMAIN), page, opt).await
+}
+
+/// Every user ID `user_id` follows, oldest page first.
+pub async fn get_following(
+  client: &SkynetClient,
+  user_id: &str,
+  opt: &SkyDbOptions,
+) -> SkynetResult<Vec<String>> {
+  let mut following = Vec::new();
+  for page in 0..get_following_page_count(client, user_id, opt).await? {
+    following.extend(get_following_page(client, user_id, page, opt).await?);
+  }
+  Ok(following)
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PostContent {
+  pub text: Option<String>,
+  pub title: Option<String>,
+  pub link: Option<String>,
+  pub topics: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Post {
+  pub id: u64,
+  #[serde(default)]
+  pub content: PostContent,
+  pub ts: u64,
+  #[serde(default)]
+  pub is_deleted: bool,
+}
+
+enum FeedState<T> {
+  Start,
+  Page { items: Vec<T>, next: Option<u32> },
+  Done,
+}
+
+/// Posts `user_id` made from `skapp` through the Feed DAC, newest first. Pages are fetched as
+/// the stream is consumed; the stream ends after the first error. Use `Post` for the standard
+/// post schema or your own type for extended posts.
+pub fn get_feed_posts<'a, T: DeserializeOwned + 'a>(
+  client: &'a SkynetClient,
+  user_id: &'a str,
+  skapp: &'a str,
+  opt: &'a SkyDbOptions,
+) -> impl Stream<Item = SkynetResult<T>> + 'a {
+  let dir = format!("{}/{}/posts", FEED_DAC_DOMAIN, skapp);
+
+  stream::unfold((FeedState::Start, dir), move |(mut state, dir)| async move {
+    loop {
+      state = match state {
+        FeedState::Start => match get_page_count(client, user_id, &dir, opt).await {
+          Ok(count) => FeedState::Page { items: Vec::new(), next: count.checked_sub(1) },
+          Err(err) => return Some((Err(err), (FeedState::Done, dir))),
+        },
+        FeedState::Page { mut items, next } => {
+          if let Some(item) = items.pop() {
+            return Some((Ok(item), (FeedState::Page { items, next }, dir)));
+          }
+          match next {
+            Some(page) => match get_page(client, user_id, &dir, page, opt).await {
+              Ok(items) => FeedState::Page { items, next: page.checked_sub(1) },
+              Err(err) => return Some((Err(err), (FeedState::Done, dir))),
+            },
+            None => return None,
+          }
+        }
+        FeedState::Done => return None,
+      };
+    }
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{derive_discoverable_file_tweak, gen_keypair_and_seed, EntryOptions, MockPortal};
+  use futures_util::StreamExt;
+  use hex::ToHex;
+  use serde_json::{json, Value as Json};
+
+  async fn set_discoverable(client: &SkynetClient, keypair: &crate::KeyPair, path: &str, data: &Json) {
+    let data_key = derive_discoverable_file_tweak(path).encode_hex::<String>();
+    let opt = SkyDbOptions {
+      entry: EntryOptions { hashed_data_key_hex: true, ..Default::default() },
+      ..Default::default()
+    };
+    skydb::db_set(client, keypair, &data_key, data, &opt).await.unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_get_profile() {
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let (keypair, _) = gen_keypair_and_seed(64);
+    let user_id = keypair.public_key.encode_hex::<String>();
+
+    assert_eq!(get_profile(&client, &user_id, &SkyDbOptions::default()).await.unwrap(), None);
+
+    let index = json!({
+      "version": 1,
+      "profile": {
+        "version": 1,
+        "username": "alice",
+        "firstName": "Alice",
+        "aboutMe": "hello",
+        "avatar": [{ "ext": "png", "w": 64, "h": 64, "url": "sia://AAA" }],
+      },
+      "lastUpdatedBy": "skyprofile.hns",
+    });
+    set_discoverable(&client, &keypair, "profile-dac.hns/profileIndex.json", &index).await;
+
+    let res = get_profile(&client, &user_id, &SkyDbOptions::default()).await;
+    println!("{:?}", res);
+    let profile = res.unwrap().unwrap();
+    assert_eq!(profile.display_name(), "Alice");
+    assert_eq!(profile.avatar_skylink(), Some("sia://AAA"));
+    assert_eq!(profile.bio(), Some("hello"));
+    assert_eq!(Profile { username: "bob".into(), ..Default::default() }.display_name(), "bob");
+  }
+
+  #[tokio::test]
+  async fn test_get_following() {
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let (keypair, _) = gen_keypair_and_seed(64);
+    let user_id = keypair.public_key.encode_hex::<String>();
+    let opt = SkyDbOptions::default();
+
+    assert!(get_following(&client, &user_id, &opt).await.unwrap().is_empty());
+
+    let dir = "social-dac.hns/following";
+    set_discoverable(&client, &keypair, &format!("{}/index.json", dir), &json!({
+      "version": 1,
+      "currPageNumber": 1,
+      "currPageNumEntries": 1,
+      "pageSize": 2,
+    })).await;
+    set_discoverable(&client, &keypair, &format!("{}/page_0.json", dir), &json!({ "items": ["a", "b"] })).await;
+    set_discoverable(&client, &keypair, &format!("{}/page_1.json", dir), &json!({ "items": ["c"] })).await;
+
+    assert_eq!(get_following_page_count(&client, &user_id, &opt).await.unwrap(), 2);
+    assert_eq!(get_following_page(&client, &user_id, 1, &opt).await.unwrap(), vec!["c".to_string()]);
+    let res = get_following(&client, &user_id, &opt).await;
+    println!("{:?}", res);
+    assert_eq!(res.unwrap(), vec!["a".to_string(), "b".to_string(), "c".to_string()]);
+  }
+
+  #[tokio::test]
+  async fn test_get_feed_posts() {
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let (keypair, _) = gen_keypair_and_seed(64);
+    let user_id = keypair.public_key.encode_hex::<String>();
+    let opt = SkyDbOptions::default();
+
+    let posts: Vec<_> = get_feed_posts::<Post>(&client, &user_id, "app.hns", &opt).collect().await;
+    assert!(posts.is_empty());
+
+    let dir = "feed-dac.hns/app.hns/posts";
+    set_discoverable(&client, &keypair, &format!("{}/index.json", dir), &json!({ "currPageNumber": 1 })).await;
+    set_discoverable(&client, &keypair, &format!("{}/page_0.json", dir), &json!({ "items": [
+      { "id": 0, "content": { "text": "first" }, "ts": 1 },
+      { "id": 1, "content": { "text": "second" }, "ts": 2 },
+    ] })).await;
+    set_discoverable(&client, &keypair, &format!("{}/page_1.json", dir), &json!({ "items": [
+      { "id": 0, "content": { "text": "third", "topics": ["rust"] }, "ts": 3 },
+    ] })).await;
+
+    let posts: Vec<_> = get_feed_posts::<Post>(&client, &user_id, "app.hns", &opt).collect().await;
+    println!("{:?}", posts);
+    let texts: Vec<_> = posts
+      .into_iter()
+      .map(|post| post.unwrap().content.text.unwrap())
+      .collect();
+    assert_eq!(texts, vec!["third", "second", "first"]);
+
+    let mut posts = Box::pin(get_feed_posts::<Post>(&client, "xyz", "app.hns", &opt));
+    assert!(posts.next().await.unwrap().is_err());
+    assert!(posts.next().await.is_none());
+  }
+}
@@ -0,0 +1,134 @@
+use std::{
+  collections::HashMap,
+  fmt::Debug,
+  future::Future,
+  io,
+  net::{IpAddr, SocketAddr},
+  pin::Pin,
+  str::FromStr,
+  sync::Arc,
+  task::{Context, Poll},
+};
+use hyper::{
+  client::connect::dns::{GaiResolver, Name},
+  service::Service,
+};
+
+pub type ResolveFuture = Pin<Box<dyn Future<Output = io::Result<Vec<SocketAddr>>> + Send>>;
+
+/// Resolves portal hostnames for both the hyper and the tus (reqwest) connections. Ports on
+/// the returned addresses are ignored; the one from the request URL is used instead.
+pub trait Resolve: Debug + Send + Sync {
+  fn resolve(&self, host: &str) -> ResolveFuture;
+}
+
+/// The operating system's resolver (`getaddrinfo`).
+#[derive(Debug, Clone, Default)]
+pub struct SystemResolver;
+
+impl Resolve for SystemResolver {
+  fn resolve(&self, host: &str) -> ResolveFuture {
+    let name = Name::from_str(host);
+    Box::pin(async move {
+      let name = name.map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+      Ok(GaiResolver::new().call(name).await?.collect())
+    })
+  }
+}
+
+/// Answers fixed addresses for some hosts, e.g. to pin a portal's IP, and hands every other
+/// host to a fallback resolver.
+#[derive(Debug, Clone)]
+pub struct StaticResolver {
+  overrides: HashMap<String, Vec<IpAddr>>,
+  fallback: Arc<dyn Resolve>,
+}
+
+impl StaticResolver {
+  pub fn new() -> Self {
+    Self::with_fallback(Arc::new(SystemResolver))
+  }
+
+  pub fn with_fallback(fallback: Arc<dyn Resolve>) -> Self {
+    Self {
+      overrides: HashMap::new(),
+      fallback,
+    }
+  }
+
+  /// Resolves `host` to `ip`. Calling this again for the same host adds another address.
+  pub fn add(mut self, host: &str, ip: IpAddr) -> Self {
+    self.overrides.entry(host.to_lowercase()).or_default().push(ip);
+    self
+  }
+}
+
+impl Default for StaticResolver {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Resolve for StaticResolver {
+  fn resolve(&self, host: &str) -> ResolveFuture {
+    match self.overrides.get(&host.to_lowercase()) {
+      Some(ips) => {
+        let addrs = ips.iter().map(|ip| SocketAddr::new(*ip, 0)).collect();
+        Box::pin(async move { Ok(addrs) })
+      }
+      None => self.fallback.resolve(host),
+    }
+  }
+}
+
+/// Plugs a [`Resolve`] into hyper's `HttpConnector`.
+#[derive(Debug, Clone)]
+pub(crate) struct HyperResolver(pub(crate) Arc<dyn Resolve>);
+
+impl Service<Name> for HyperResolver {
+  type Response = std::vec::IntoIter<SocketAddr>;
+  type Error = io::Error;
+  type Future = Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send>>;
+
+  fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    Poll::Ready(Ok(()))
+  }
+
+  fn call(&mut self, name: Name) -> Self::Future {
+    let addrs = self.0.resolve(name.as_str());
+    Box::pin(async move { Ok(addrs.await?.into_iter()) })
+  }
+}
+
+/// Plugs a [`Resolve`] into reqwest's client builder.
+#[cfg(feature = "tus")]
+pub(crate) struct ReqwestResolver(pub(crate) Arc<dyn Resolve>);
+
+#[cfg(feature = "tus")]
+impl reqwest::dns::Resolve for ReqwestResolver {
+  fn resolve(&self, name: Name) -> reqwest::dns::Resolving {
+    let addrs = self.0.resolve(name.as_str());
+    Box::pin(async move {
+      let addrs: reqwest::dns::Addrs = Box::new(addrs.await?.into_iter());
+      Ok(addrs)
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::net::Ipv4Addr;
+
+  #[tokio::test]
+  async fn test_static_resolver() {
+    let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    let resolver = StaticResolver::new().add("Portal.example", localhost);
+
+    let addrs = resolver.resolve("portal.example").await.unwrap();
+    assert_eq!(addrs, vec![SocketAddr::new(localhost, 0)]);
+
+    let addrs = resolver.resolve("localhost").await.unwrap();
+    assert!(addrs.iter().any(|addr| addr.ip().is_loopback()));
+  }
+}
@@ -1,21 +1,86 @@
-use crate::{SkynetClient, SkynetError::*, SkynetResult, util::make_uri, URI_SKYNET_PREFIX};
+use crate::{
+  client::CallAuth,
+  events::Event,
+  skylink::{normalize_skylink, parse_skylink, Skylink, SkylinkVersion},
+  util::{
+    allocate, available_space, content_disposition_filename, in_context, joined, make_uri, parse_response,
+    read_body, status_error, with_deadline, QueryParam, QueryParams,
+  },
+  AuthScheme, SkynetClient, SkynetError, SkynetError::*, SkynetResult,
+};
 use std::{
-  collections::HashMap,
-  fs,
-  path::Path,
+  collections::{HashMap, HashSet},
+  fmt,
+  fs::{self, File, OpenOptions},
+  future::Future,
+  io::{self, Read, Write},
+  path::{Component, Path, PathBuf},
+  pin::Pin,
   str,
+  task::{Context, Poll},
+  time::Duration,
 };
-use hyper::{body, Body, Request};
+use bytes::Bytes;
+use futures_util::{future::try_join_all, ready, stream, Stream, StreamExt};
+#[cfg(feature = "directory")]
+use futures_util::TryStreamExt;
+use hex::FromHex;
+use hyper::{body::HttpBody, http::{response::Parts, Extensions}, Body, HeaderMap, Request, Response, StatusCode, Uri};
 use mime::Mime;
-use serde_json::Value as Json;
+use serde::{de::{DeserializeSeed, Error as _, IgnoredAny, MapAccess, Visitor}, Deserialize, Deserializer};
+use tokio::{runtime::Handle, sync::{mpsc, oneshot, OwnedSemaphorePermit}, task::JoinHandle};
+#[cfg(feature = "directory")]
+use crate::{sparse, upload::{FileAttributes, DIRECTORY_METADATA_FILENAME}};
+#[cfg(feature = "directory")]
+use std::collections::BTreeMap;
+#[cfg(feature = "checksum")]
+use crate::{checksum, util::read_response};
+#[cfg(feature = "gzip")]
+use crate::gzip;
+#[cfg(feature = "encryption")]
+use crate::Skykey;
+#[cfg(feature = "archive")]
+use crate::{archive, ArchiveFormat};
 
-#[derive(Debug)]
+const DEFAULT_MAX_RESOLVER_DEPTH: usize = 8;
+
+#[derive(Debug, Clone)]
 pub struct DownloadOptions {
   pub endpoint_path: String,
   pub api_key: Option<String>,
+  pub auth: Option<AuthScheme>,
   pub custom_user_agent: Option<String>,
   pub skykey_name: Option<String>,
   pub skykey_id: Option<String>,
+  /// Decrypts with this key, e.g. one from the client's skykey cache. Takes precedence over
+  /// `skykey_name` and `skykey_id`.
+  #[cfg(feature = "encryption")]
+  pub skykey: Option<Skykey>,
+  /// Transparently gunzips responses served with a gzip content-encoding or content type.
+  #[cfg(feature = "gzip")]
+  pub decompress: bool,
+  /// How many subfiles `download_directory` fetches at once.
+  #[cfg(feature = "directory")]
+  pub concurrency: usize,
+  /// Has `download_directory` fetch the whole directory as one archive in this format and
+  /// extract it as it streams in, instead of requesting each subfile on its own.
+  #[cfg(feature = "archive")]
+  pub archive_format: Option<ArchiveFormat>,
+  /// Wall-clock budget for the whole download, including retries and every subfile.
+  pub deadline: Option<Duration>,
+  /// When `download_file` is given a directory, saves into it under the name the portal reports
+  /// for the skyfile. When given a path without an extension, adds one from that name or the
+  /// content type.
+  pub derive_filename: bool,
+  /// Fails with `ChecksumMismatch` unless the downloaded data has this checksum, as returned by
+  /// `skynet::checksum`. `download_directory` ignores it and checks the checksums recorded at
+  /// upload instead.
+  #[cfg(feature = "checksum")]
+  pub expected_checksum: Option<String>,
+  /// Before `download_file` downloads, HEADs the skylink for its size and fails with
+  /// `InsufficientSpace` if the destination's filesystem has no room for it. A new file is
+  /// also allocated at that size up front. Skipped if the portal doesn't report a size.
+  pub preallocate: bool,
 }
 
 impl Default for DownloadOptions {
@@ -23,18 +88,52 @@ impl Default for DownloadOptions {
     Self {
       endpoint_path: "/".to_string(),
       api_key: None,
+      auth: None,
       custom_user_agent: None,
       skykey_name: None,
       skykey_id: None,
+      #[cfg(feature = "encryption")]
+      skykey: None,
+      #[cfg(feature = "gzip")]
+      decompress: false,
+      #[cfg(feature = "directory")]
+      concurrency: 8,
+      #[cfg(feature = "archive")]
+      archive_format: None,
+      deadline: None,
+      derive_filename: false,
+      #[cfg(feature = "checksum")]
+      expected_checksum: None,
+      preallocate: false,
     }
   }
 }
 
-#[derive(Debug)]
+/// Where `get_metadata` reads skyfile metadata from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetadataSource {
+  /// The `skynet-file-metadata` header of a HEAD request on the skylink.
+  Head,
+  /// The JSON metadata endpoint, `metadata_endpoint_path`.
+  Endpoint,
+}
+
+#[derive(Debug, Clone)]
 pub struct MetadataOptions {
   pub endpoint_path: String,
   pub api_key: Option<String>,
+  pub auth: Option<AuthScheme>,
   pub custom_user_agent: Option<String>,
+  pub metadata_endpoint_path: String,
+  /// Sources `get_metadata` tries in order until one succeeds. Older portals don't have the
+  /// metadata endpoint, while some newer ones don't send the metadata header.
+  pub sources: Vec<MetadataSource>,
+  /// Wall-clock budget for the whole call, including retries and fallbacks between sources.
+  pub deadline: Option<Duration>,
+  /// How many resolver skylinks `resolve_skylink_chain` follows before giving up.
+  pub max_resolver_depth: usize,
+  /// How many skylinks `get_metadata_many` fetches at once.
+  pub concurrency: usize,
 }
 
 impl Default for MetadataOptions {
@@ -42,66 +141,670 @@ impl Default for MetadataOptions {
     Self {
       endpoint_path: "/".to_string(),
       api_key: None,
+      auth: None,
       custom_user_agent: None,
+      metadata_endpoint_path: "/skynet/metadata".to_string(),
+      sources: vec![MetadataSource::Head, MetadataSource::Endpoint],
+      deadline: None,
+      max_resolver_depth: DEFAULT_MAX_RESOLVER_DEPTH,
+      concurrency: 8,
     }
   }
 }
 
+/// Response details returned alongside downloaded data.
+#[derive(Debug, Clone)]
+pub struct ResponseInfo {
+  pub status: StatusCode,
+  pub headers: HeaderMap,
+  pub url: Uri,
+  /// The skylink the portal resolved the request to, from the `skynet-skylink` header.
+  pub skylink: Option<String>,
+}
+
+/// Answers from the client's `local_store` if it has `skylink`, and otherwise saves a successful
+/// download to it. Only v1 skylinks are kept, as a v2 skylink can be repointed.
 pub async fn download_data(
   client: &SkynetClient,
   skylink: &str,
   opt: DownloadOptions,
-) -> SkynetResult<Vec<u8>> {
-  let req = Request::builder().method("GET");
+) -> SkynetResult<Bytes> {
+  let _reservation = reserve_download(client, skylink, &opt).await;
+  fetch_data(client, skylink, opt).await
+}
 
-  let mut query = HashMap::new();
+/// `download_data` without holding the memory budget, for callers that already do.
+async fn fetch_data(
+  client: &SkynetClient,
+  skylink: &str,
+  opt: DownloadOptions,
+) -> SkynetResult<Bytes> {
+  #[cfg(feature = "blocklist")]
+  check_blocklist(client, skylink)?;
 
-  let skylink = if skylink.starts_with(URI_SKYNET_PREFIX) {
-    &skylink[URI_SKYNET_PREFIX.len()..]
-  } else {
-    skylink
+  let store = match (&client.get_options().local_store, store_key(skylink, &opt)?) {
+    (Some(store), Some(key)) => Some((store.clone(), key)),
+    _ => None,
   };
 
+  if let Some((store, key)) = &store {
+    if let Some(data) = store.get(key).await? {
+      #[cfg(feature = "checksum")]
+      if let Some(expected) = &opt.expected_checksum {
+        checksum::verify(expected, &data)?;
+      }
+      #[cfg(feature = "offline")]
+      cache_offline(client, skylink, &data, StatusCode::OK)?;
+      return Ok(data);
+    }
+  }
+
+  let (data, info) = fetch_data_with_response(client, skylink, opt).await?;
+  #[cfg(feature = "offline")]
+  cache_offline(client, skylink, &data, info.status)?;
+  if let (Some((store, key)), true) = (store, info.status.is_success()) {
+    // The download already succeeded, so a store that can't keep it doesn't fail it.
+    if let Err(_err) = store.put(&key, data.clone()).await {
+      #[cfg(feature = "log")]
+      log::warn!("failed to save {} to the local store: {:?}", key, _err);
+    }
+  }
+  Ok(data)
+}
+
+/// The `local_store` key of a download: the normalized skylink, followed by the options that
+/// change the bytes returned. `None` unless it is a v1 skylink.
+fn store_key(skylink: &str, opt: &DownloadOptions) -> SkynetResult<Option<String>> {
+  if parse_skylink(skylink)?.0.version()? != SkylinkVersion::V1 {
+    return Ok(None);
+  }
+
+  let mut key = normalize_skylink(skylink)?;
+  let query = skykey_query(opt);
+  if !query.is_empty() {
+    key = format!("{}?{}", key, query.encode());
+  }
+  #[cfg(feature = "gzip")]
+  if opt.decompress {
+    key.push_str("#decompress");
+  }
+  Ok(Some(key))
+}
+
+/// The query parameters selecting the skykey `opt` decrypts with.
+fn skykey_query(opt: &DownloadOptions) -> QueryParams {
+  let mut query = QueryParams::new();
+
   if let Some(ref skykey_name) = opt.skykey_name {
-    query.insert("skykeyname".into(), skykey_name.clone());
+    query.set(QueryParam::SkykeyName, skykey_name.clone());
   }
 
   if let Some(ref skykey_id) = opt.skykey_id {
-    query.insert("skykeyid".into(), skykey_id.clone());
+    query.set(QueryParam::SkykeyId, skykey_id.clone());
+  }
+
+  #[cfg(feature = "encryption")]
+  if let Some(ref skykey) = opt.skykey {
+    query.set(QueryParam::SkykeyId, skykey.id());
+  }
+
+  query
+}
+
+pub async fn download_data_with_response(
+  client: &SkynetClient,
+  skylink: &str,
+  opt: DownloadOptions,
+) -> SkynetResult<(Bytes, ResponseInfo)> {
+  let _reservation = reserve_download(client, skylink, &opt).await;
+  fetch_data_with_response(client, skylink, opt).await
+}
+
+/// Holds the client's memory budget for a download of `skylink` before it is requested. The
+/// size is asked for with a HEAD request, only made when the client has a budget; a download
+/// whose size the portal doesn't report holds the whole budget.
+async fn reserve_download(client: &SkynetClient, skylink: &str, opt: &DownloadOptions) -> Option<OwnedSemaphorePermit> {
+  if !client.has_memory_budget() {
+    return None;
   }
+  // Errors are left for the download itself to report.
+  let size = content_length(client, skylink, opt).await.unwrap_or(None);
+  client.reserve_memory(size).await
+}
+
+/// `download_data_with_response` without holding the memory budget, for callers that already do.
+async fn fetch_data_with_response(
+  client: &SkynetClient,
+  skylink: &str,
+  opt: DownloadOptions,
+) -> SkynetResult<(Bytes, ResponseInfo)> {
+  let req = Request::builder().method("GET");
+
+  let skylink = normalize_skylink(skylink)?;
+  #[cfg(feature = "blocklist")]
+  check_blocklist(client, &skylink)?;
+
+  let query = skykey_query(&opt);
 
   let uri = make_uri(
-    client.get_portal_url(),
-    opt.endpoint_path,
-    opt.api_key,
-    Some(skylink.to_string()),
+    &client.get_portal_url(),
+    opt.endpoint_path.clone(),
+    opt.api_key.clone(),
+    Some(skylink.clone()),
     query);
 
-  let mut req = req.uri(uri);
+  let mut req = req.uri(uri.clone());
 
-  if let Some(custom_user_agent) = opt.custom_user_agent {
-    req = req.header("User-Agent", custom_user_agent);
+  if let Some(custom_user_agent) = &opt.custom_user_agent {
+    req = req.header("User-Agent", custom_user_agent.clone());
   }
 
+  req = req.extension(CallAuth(opt.auth.clone()));
+
   let req = req.body(Body::from("")).map_err(HttpError)?;
-  let res = client.http.request(req).await.map_err(HyperError)?;
-  let body = body::to_bytes(res.into_body()).await.map_err(HyperError)?;
+  let res = client.request(req).await?;
+  let (parts, body) = res.into_parts();
+  // A resolver skylink can point at a blocked one, which only the response tells.
+  #[cfg(feature = "blocklist")]
+  if let Some(resolved) = parts.headers.get("skynet-skylink").and_then(|skylink| skylink.to_str().ok()) {
+    check_blocklist(client, resolved)?;
+  }
+  let body = read_body(&parts, body).await?;
+  if parts.status.is_success() {
+    client.emit(Event::DownloadCompleted { skylink: skylink.clone(), size: body.len() as u64 });
+  }
+
+  // A checksum recorded at upload is of the bytes as sent, so it is checked before decompressing.
+  #[cfg(feature = "checksum")]
+  if let (true, None) = (parts.status.is_success(), &opt.expected_checksum) {
+    if let Some(recorded) = recorded_checksum(client, &skylink, &parts.headers, &opt).await? {
+      checksum::verify(&recorded, &body)?;
+    }
+  }
+
+  #[cfg(feature = "gzip")]
+  let body = if opt.decompress && gzip::is_gzipped(&parts.headers) {
+    gzip::decompress(&body)?
+  } else {
+    body
+  };
+
+  #[cfg(feature = "checksum")]
+  if let (true, Some(expected)) = (parts.status.is_success(), &opt.expected_checksum) {
+    checksum::verify(expected, &body)?;
+  }
+
+  let info = ResponseInfo {
+    status: parts.status,
+    skylink: parts
+      .headers
+      .get("skynet-skylink")
+      .and_then(|skylink| skylink.to_str().ok())
+      .map(|skylink| skylink.to_string()),
+    headers: parts.headers,
+    url: uri,
+  };
+
+  Ok((body, info))
+}
+
+/// The checksum `UploadOptions::checksum` recorded for the file a download of `skylink` served,
+/// read from the `DIRECTORY_METADATA_FILENAME` subfile next to it. `None` if nothing was recorded.
+#[cfg(feature = "checksum")]
+async fn recorded_checksum(
+  client: &SkynetClient,
+  skylink: &str,
+  headers: &HeaderMap,
+  opt: &DownloadOptions,
+) -> SkynetResult<Option<String>> {
+  let metadata = headers
+    .get("skynet-file-metadata")
+    .and_then(|metadata| metadata.to_str().ok())
+    .and_then(|metadata| serde_json::from_str::<FileMetadata>(metadata).ok());
+  let metadata = match metadata {
+    Some(metadata) if metadata.subfiles.as_ref().is_some_and(|subfiles| subfiles.contains_key(DIRECTORY_METADATA_FILENAME)) => metadata,
+    _ => return Ok(None),
+  };
+
+  let (base, filename) = match skylink.split_once('/') {
+    Some((base, path)) => (base, path.trim_matches('/').to_string()),
+    None => match metadata.default_path {
+      Some(default_path) => (skylink, default_path.trim_matches('/').to_string()),
+      None => return Ok(None),
+    },
+  };
+  if filename.is_empty() || filename == DIRECTORY_METADATA_FILENAME {
+    return Ok(None);
+  }
+
+  let uri = make_uri(
+    &client.get_portal_url(),
+    opt.endpoint_path.clone(),
+    opt.api_key.clone(),
+    Some(format!("{}/{}", base, DIRECTORY_METADATA_FILENAME)),
+    skykey_query(opt));
+
+  let mut req = Request::builder().method("GET").uri(uri.clone());
+
+  if let Some(custom_user_agent) = &opt.custom_user_agent {
+    req = req.header("User-Agent", custom_user_agent.clone());
+  }
+
+  req = req.extension(CallAuth(opt.auth.clone()));
+
+  let res = client.request(req.body(Body::empty()).map_err(HttpError)?).await?;
+  let status = res.status();
+  let body = read_response(res).await?;
+  let files: BTreeMap<String, FileAttributes> = parse_response(&uri, status, &String::from_utf8_lossy(&body))?;
+  Ok(files.get(&filename).and_then(|attributes| attributes.checksum.clone()))
+}
 
-  Ok(body.to_vec())
+#[cfg(feature = "blocklist")]
+pub(crate) fn check_blocklist(client: &SkynetClient, skylink: &str) -> SkynetResult<()> {
+  match &client.get_options().blocklist {
+    Some(blocklist) => blocklist.check(skylink),
+    None => Ok(()),
+  }
+}
+
+/// Keeps a successful download in the client's offline store, to be served while it is offline.
+#[cfg(feature = "offline")]
+fn cache_offline(client: &SkynetClient, skylink: &str, data: &[u8], status: StatusCode) -> SkynetResult<()> {
+  match &client.get_options().offline {
+    Some(store) if status.is_success() => store.cache_put(skylink, data),
+    _ => Ok(()),
+  }
 }
 
+/// Saves a skyfile to `path` and returns where it was written, which differs from `path` when
+/// `opt.derive_filename` is set.
 pub async fn download_file<P: AsRef<Path>>(
   client: &SkynetClient,
   path: P,
   skylink: &str,
   opt: DownloadOptions,
+) -> SkynetResult<PathBuf> {
+  let path = path.as_ref();
+  // Held until the file is written, so that `shutdown` doesn't return before it is.
+  let _transfer = client.begin_transfer()?;
+
+  let (preallocated, _reservation) = match opt.preallocate {
+    true => {
+      let size = content_length(client, skylink, &opt).await?;
+      let preallocated = match size {
+        Some(size) => reserve_space(path, size, !opt.derive_filename)?,
+        None => None,
+      };
+      (preallocated, client.reserve_memory(size).await)
+    }
+    false => (None, reserve_download(client, skylink, &opt).await),
+  };
+
+  if !opt.derive_filename {
+    let res = fetch_data(client, skylink, opt).await;
+    return match (res, preallocated) {
+      (Ok(data), Some(mut file)) => {
+        file.write_all(&data).and_then(|_| file.set_len(data.len() as u64)).map_err(FileError)?;
+        Ok(path.to_path_buf())
+      }
+      (Ok(data), None) => {
+        fs::write(path, data).map_err(FileError)?;
+        Ok(path.to_path_buf())
+      }
+      (Err(err), preallocated) => {
+        if preallocated.is_some() {
+          let _ = fs::remove_file(path);
+        }
+        Err(err)
+      }
+    };
+  }
+
+  // Deriving the name needs the response headers, which the local store doesn't keep.
+  let (data, info) = fetch_data_with_response(client, skylink, opt).await?;
+  #[cfg(feature = "offline")]
+  cache_offline(client, skylink, &data, info.status)?;
+  let path = derived_path(path, skylink, &info);
+  fs::write(&path, data).map_err(FileError)?;
+
+  Ok(path)
+}
+
+/// The size of `skylink` from a HEAD request, if the portal reports it. Errors from the
+/// portal are left for the download itself to report.
+async fn content_length(client: &SkynetClient, skylink: &str, opt: &DownloadOptions) -> SkynetResult<Option<u64>> {
+  let (_, res) = head(client, skylink, MetadataOptions {
+    endpoint_path: opt.endpoint_path.clone(),
+    api_key: opt.api_key.clone(),
+    auth: opt.auth.clone(),
+    custom_user_agent: opt.custom_user_agent.clone(),
+    ..Default::default()
+  }).await?;
+
+  if !res.status().is_success() {
+    return Ok(None);
+  }
+  Ok(res
+    .headers()
+    .get(hyper::header::CONTENT_LENGTH)
+    .and_then(|length| length.to_str().ok())
+    .and_then(|length| length.parse().ok()))
+}
+
+/// Fails with `InsufficientSpace` unless the filesystem at `path` has room for `size` bytes.
+/// With `allocate`, a file that doesn't exist yet is created at that size and returned.
+fn reserve_space(path: &Path, size: u64, allocate_file: bool) -> SkynetResult<Option<File>> {
+  let dir = match path.parent() {
+    _ if path.is_dir() => path,
+    Some(parent) if !parent.as_os_str().is_empty() => parent,
+    _ => Path::new("."),
+  };
+
+  if let Some(available) = available_space(dir).map_err(FileError)? {
+    // Overwriting a file frees its space first.
+    let existing = fs::metadata(path).ok().filter(|metadata| metadata.is_file()).map_or(0, |metadata| metadata.len());
+    if size > available.saturating_add(existing) {
+      return Err(InsufficientSpace(size, available));
+    }
+  }
+
+  if !allocate_file || path.exists() {
+    return Ok(None);
+  }
+  let file = OpenOptions::new().write(true).create_new(true).open(path).map_err(FileError)?;
+  if let Err(err) = allocate(&file, size) {
+    let _ = fs::remove_file(path);
+    return Err(FileError(err));
+  }
+  Ok(Some(file))
+}
+
+/// The name the portal gives a download: from `Content-Disposition`, the skyfile metadata or
+/// the last component of the skylink path. Only its final path component is kept so that it
+/// can't escape the directory it is saved in.
+fn response_filename(skylink: &str, info: &ResponseInfo) -> Option<String> {
+  let header = |name: &str| info.headers.get(name).and_then(|value| value.to_str().ok());
+
+  let filename = header("content-disposition")
+    .and_then(content_disposition_filename)
+    .or_else(|| {
+      header("skynet-file-metadata")
+        .and_then(|metadata| serde_json::from_str::<FileMetadata>(metadata).ok())
+        .and_then(|metadata| metadata.filename)
+    })
+    .or_else(|| {
+      let (_, path) = parse_skylink(skylink).ok()?;
+      path.and_then(|path| path.rsplit('/').next().map(str::to_string))
+    })?;
+
+  match Path::new(&filename).components().next_back() {
+    Some(Component::Normal(name)) => name.to_str().map(str::to_string),
+    _ => None,
+  }
+}
+
+/// A file extension for a content type, preferring one named after its subtype.
+fn mime_extension(mime: &Mime) -> Option<&'static str> {
+  if *mime == mime::TEXT_PLAIN {
+    return Some("txt");
+  }
+  let extensions = mime_guess::get_mime_extensions(mime)?;
+  extensions
+    .iter()
+    .find(|extension| **extension == mime.subtype().as_str())
+    .or_else(|| extensions.first())
+    .copied()
+}
+
+fn derived_path(path: &Path, skylink: &str, info: &ResponseInfo) -> PathBuf {
+  let filename = response_filename(skylink, info);
+  let extension = filename
+    .as_deref()
+    .and_then(|filename| Path::new(filename).extension())
+    .and_then(|extension| extension.to_str())
+    .map(str::to_string)
+    .or_else(|| {
+      let content_type = info.headers.get("content-type")?.to_str().ok()?;
+      mime_extension(&content_type.parse().ok()?).map(str::to_string)
+    });
+
+  if path.is_dir() {
+    let filename = filename.unwrap_or_else(|| {
+      let name = parse_skylink(skylink)
+        .map(|(skylink, _)| skylink.to_string())
+        .unwrap_or_else(|_| "download".to_string());
+      match extension {
+        Some(extension) => format!("{}.{}", name, extension),
+        None => name,
+      }
+    });
+    path.join(filename)
+  } else if let (None, Some(extension)) = (path.extension(), extension) {
+    path.with_extension(extension)
+  } else {
+    path.to_path_buf()
+  }
+}
+
+/// Downloads a directory skylink into `path`, fetching up to `opt.concurrency` subfiles at once.
+/// Directories uploaded with `upload_directory` get each file's mode and modification time
+/// restored; others are listed from their metadata.
+#[cfg(feature = "directory")]
+pub async fn download_directory<P: AsRef<Path>>(
+  client: &SkynetClient,
+  path: P,
+  skylink: &str,
+  opt: DownloadOptions,
+) -> SkynetResult<()> {
+  let skylink = normalize_skylink(skylink)?;
+  #[cfg(feature = "archive")]
+  if let Some(format) = opt.archive_format {
+    return download_directory_archive(client, path.as_ref(), &skylink, format, opt).await;
+  }
+
+  let metadata_skylink = format!("{}/{}", skylink, DIRECTORY_METADATA_FILENAME);
+  #[cfg(feature = "checksum")]
+  let opt = DownloadOptions { expected_checksum: None, ..opt };
+
+  let files: BTreeMap<String, FileAttributes> =
+    match download_data_with_response(client, &metadata_skylink, opt.clone()).await? {
+      (data, info) if info.status.is_success() => {
+        parse_response(&info.url, info.status, &String::from_utf8_lossy(&data))?
+      }
+      _ => {
+        let metadata = get_metadata(client, &skylink, MetadataOptions {
+          endpoint_path: opt.endpoint_path.clone(),
+          api_key: opt.api_key.clone(),
+          auth: opt.auth.clone(),
+          custom_user_agent: opt.custom_user_agent.clone(),
+          ..Default::default()
+        }).await?;
+        metadata
+          .subfiles
+          .unwrap_or_default()
+          .into_keys()
+          .map(|filename| (filename, FileAttributes::default()))
+          .collect()
+      }
+    };
+
+  let mut downloads = Vec::with_capacity(files.len());
+  for (filename, attributes) in files {
+    let relative = Path::new(&filename);
+    if !relative.components().all(|component| matches!(component, Component::Normal(_))) {
+      return Err(PortalResponse(format!("refusing to write subfile outside the directory: {}", filename)));
+    }
+
+    let target = path.as_ref().join(relative);
+    if let Some(parent) = target.parent() {
+      fs::create_dir_all(parent).map_err(FileError)?;
+    }
+
+    let subfile = format!("{}/{}", skylink, filename);
+    let opt = opt.clone();
+    #[cfg(feature = "checksum")]
+    let opt = DownloadOptions { expected_checksum: attributes.checksum.clone(), ..opt };
+    downloads.push(async move {
+      // Held until the subfile is written.
+      let _reservation = reserve_download(client, &subfile, &opt).await;
+      let data = fetch_data(client, &subfile, opt).await?;
+      match &attributes.data_ranges {
+        Some(ranges) => sparse::write_sparse(&target, &data, ranges).map_err(FileError)?,
+        None => fs::write(&target, data).map_err(FileError)?,
+      }
+      attributes.restore(&target)
+    });
+  }
+
+  stream::iter(downloads)
+    .buffer_unordered(opt.concurrency.max(1))
+    .try_collect::<()>()
+    .await?;
+
+  Ok(())
+}
+
+/// `download_directory` in a single request for the directory as an archive. File attributes
+/// recorded at upload come along in the archive, and are applied once it is extracted.
+#[cfg(feature = "archive")]
+async fn download_directory_archive(
+  client: &SkynetClient,
+  path: &Path,
+  skylink: &str,
+  format: ArchiveFormat,
+  opt: DownloadOptions,
 ) -> SkynetResult<()> {
-  let data = download_data(client, skylink, opt).await?;
-  fs::write(path, data).map_err(FileError)?;
+  #[cfg(feature = "blocklist")]
+  check_blocklist(client, skylink)?;
+
+  let mut query = QueryParams::new();
+  query.set(QueryParam::Format, format.as_str().to_string());
+
+  if let Some(skykey_name) = opt.skykey_name {
+    query.set(QueryParam::SkykeyName, skykey_name);
+  }
+
+  if let Some(skykey_id) = opt.skykey_id {
+    query.set(QueryParam::SkykeyId, skykey_id);
+  }
+
+  #[cfg(feature = "encryption")]
+  if let Some(skykey) = opt.skykey {
+    query.set(QueryParam::SkykeyId, skykey.id());
+  }
+
+  let uri = make_uri(
+    &client.get_portal_url(),
+    opt.endpoint_path,
+    opt.api_key,
+    Some(skylink.to_string()),
+    query);
+
+  let mut req = Request::builder().method("GET").uri(uri.clone());
+
+  if let Some(custom_user_agent) = opt.custom_user_agent {
+    req = req.header("User-Agent", custom_user_agent);
+  }
+
+  req = req.extension(CallAuth(opt.auth.clone()));
+
+  let req = req.body(Body::empty()).map_err(HttpError)?;
+  let res = client.request(req).await?;
+  let (mut parts, body) = res.into_parts();
+
+  if !parts.status.is_success() {
+    let body = read_body(&parts, body).await?;
+    return Err(status_error(&parts.extensions, &uri, parts.status, &body));
+  }
+
+  let mut reader = BodyReader::new(&mut parts, body);
+  let dir = path.to_path_buf();
+  let extracted = tokio::task::spawn_blocking(move || {
+    let extracted = archive::extract(&mut reader, format, &dir);
+    (extracted, reader.error)
+  });
+  match joined(extracted.await)? {
+    (_, Some(err)) => return Err(err),
+    (extracted, None) => extracted.map_err(FileError)?,
+  }
+
+  let metadata_path = path.join(DIRECTORY_METADATA_FILENAME);
+  if !is_plain_path(path, Path::new(DIRECTORY_METADATA_FILENAME)) {
+    return Ok(());
+  }
+  let metadata = match fs::read(&metadata_path) {
+    Ok(metadata) => metadata,
+    // Directories uploaded without attributes have nothing more to apply.
+    Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+    Err(err) => return Err(FileError(err)),
+  };
+  fs::remove_file(&metadata_path).map_err(FileError)?;
+  let files: BTreeMap<String, FileAttributes> = serde_json::from_slice(&metadata)
+    .map_err(|err| PortalResponse(format!("{} has invalid metadata: {}", DIRECTORY_METADATA_FILENAME, err)))?;
+
+  for (filename, attributes) in files {
+    let relative = Path::new(&filename);
+    if !is_plain_path(path, relative) {
+      continue;
+    }
+    let target = path.join(relative);
+
+    // Sparse files are archived with their holes left out, like any other download of them.
+    #[cfg(feature = "checksum")]
+    let verify = attributes.checksum.is_some();
+    #[cfg(not(feature = "checksum"))]
+    let verify = false;
+    if verify || attributes.data_ranges.is_some() {
+      let data = fs::read(&target).map_err(FileError)?;
+      #[cfg(feature = "checksum")]
+      if let Some(expected) = &attributes.checksum {
+        checksum::verify(expected, &data)?;
+      }
+      if let Some(ranges) = &attributes.data_ranges {
+        sparse::write_sparse(&target, &data, ranges).map_err(FileError)?;
+      }
+    }
+    attributes.restore(&target)?;
+  }
 
   Ok(())
 }
 
+/// Whether `relative` names a file under `root` that can be touched without leaving it: only
+/// normal components, none of them an existing symlink. Missing components are fine.
+#[cfg(feature = "archive")]
+fn is_plain_path(root: &Path, relative: &Path) -> bool {
+  let mut path = root.to_path_buf();
+  for component in relative.components() {
+    match component {
+      Component::Normal(name) => path.push(name),
+      _ => return false,
+    }
+    if fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+      return false;
+    }
+  }
+  true
+}
+
+/// Downloads several subfiles of a directory skylink at once. The requests are issued
+/// concurrently, so over HTTP/2 they share a single connection.
+pub async fn download_subfiles(
+  client: &SkynetClient,
+  skylink: &str,
+  paths: &[&str],
+  opt: DownloadOptions,
+) -> SkynetResult<Vec<Bytes>> {
+  let skylink = skylink.trim_end_matches('/');
+  let downloads = paths.iter().map(|path| {
+    let skylink = format!("{}/{}", skylink, path.trim_start_matches('/'));
+    let opt = opt.clone();
+    async move { download_data(client, &skylink, opt).await }
+  });
+
+  try_join_all(downloads).await
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Subfile {
   pub filename: String,
@@ -119,25 +822,22 @@ pub struct Metadata {
   pub subfiles: Option<HashMap<String, Subfile>>,
 }
 
-pub async fn get_metadata(
+/// Sends a HEAD request for `skylink`, returning its normalized form and the response.
+async fn head(
   client: &SkynetClient,
   skylink: &str,
   opt: MetadataOptions,
-) -> SkynetResult<Metadata> {
+) -> SkynetResult<(String, Response<Body>)> {
   let req = Request::builder().method("HEAD");
 
-  let skylink = if skylink.starts_with(URI_SKYNET_PREFIX) {
-    &skylink[URI_SKYNET_PREFIX.len()..]
-  } else {
-    skylink
-  };
+  let skylink = normalize_skylink(skylink)?;
 
   let uri = make_uri(
-    client.get_portal_url(),
+    &client.get_portal_url(),
     opt.endpoint_path,
     opt.api_key,
-    Some(skylink.to_string()),
-    HashMap::new());
+    Some(skylink.clone()),
+    QueryParams::new());
 
   let mut req = req.uri(uri);
 
@@ -145,103 +845,1078 @@ pub async fn get_metadata(
     req = req.header("User-Agent", custom_user_agent);
   }
 
+  req = req.extension(CallAuth(opt.auth.clone()));
+
   let req = req.body(Body::from("")).map_err(HttpError)?;
-  let res = client.http.request(req).await.map_err(HyperError)?;
-  let headers = res.headers();
+  let res = client.request(req).await?;
+  Ok((skylink, res))
+}
 
-  let skylink = if let Some(skylink) = headers.get("skynet-skylink") {
-    skylink.to_str().unwrap().to_string()
-  } else {
-    skylink.to_string()
-  };
+/// Checks whether the portal can serve `skylink` without downloading it. A 404 means it
+/// doesn't exist; a 401 or 403 is reported as `AccessDenied`.
+pub async fn skylink_exists(
+  client: &SkynetClient,
+  skylink: &str,
+  opt: MetadataOptions,
+) -> SkynetResult<bool> {
+  let (skylink, res) = head(client, skylink, opt).await?;
 
-  let portal_url = if let Some(portal_url) = headers.get("skynet-portal-api") {
-    portal_url.to_str().unwrap().to_string()
-  } else {
-    client.get_portal_url().to_string()
-  };
+  match res.status() {
+    status if status.is_success() => Ok(true),
+    StatusCode::NOT_FOUND => Ok(false),
+    StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(AccessDenied(skylink)),
+    status => Err(PortalResponse(status.to_string())),
+  }
+}
 
-  let content_type = if let Some(content_type) = headers.get("content-type") {
-    Some(content_type.to_str().unwrap().parse().unwrap())
-  } else {
-    None
-  };
+/// The v1 skylink a v2 skylink currently points to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedSkylink {
+  pub skylink: String,
+  /// Revision of the registry entry the v2 skylink resolved through, or `None` if the portal
+  /// didn't include a registry proof.
+  pub revision: Option<u64>,
+}
 
-  let (filename, length, subfiles) = if let Some(metadata) = headers.get("skynet-file-metadata") {
-    let metadata: Json = serde_json::from_str(metadata.to_str().unwrap()).unwrap();
-    let filename = if let Some(filename) = metadata.get("filename") {
-      Some(filename.as_str().unwrap().to_string())
-    } else {
-      None
-    };
-    let length = if let Some(length) = metadata.get("length") {
-      Some(length.as_u64().unwrap() as u32)
-    } else {
-      None
-    };
-    let subfiles = if let Some(subfiles) = metadata.get("subfiles") {
-      let mut map = HashMap::new();
-
-      for (filename, subfile) in subfiles.as_object().unwrap() {
-        let subfile = Subfile {
-          filename: subfile["filename"].as_str().unwrap().to_string(),
-          length: subfile["len"].as_u64().unwrap() as u32,
-          content_type: subfile["contenttype"].as_str().unwrap().parse().unwrap(),
-        };
-        map.insert(filename.into(), subfile);
-      }
+/// One resolver skylink on the way to a v1 skylink and what its registry entry pointed at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolutionHop {
+  pub resolver: String,
+  pub target: String,
+  pub revision: Option<u64>,
+}
 
-      Some(map)
-    } else {
-      None
-    };
+/// The v1 skylink a resolver chain ends at, with every hop taken to reach it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolutionChain {
+  pub skylink: String,
+  pub hops: Vec<ResolutionHop>,
+}
 
-    (filename, length, subfiles)
-  } else {
-    (None, None, None)
+#[derive(Deserialize)]
+struct ProofEntry {
+  #[serde(default)]
+  data: String,
+  revision: u64,
+}
+
+/// What the portal resolved `skylink` to and the registry entries it went through, in order.
+async fn head_resolution(
+  client: &SkynetClient,
+  skylink: &str,
+  opt: MetadataOptions,
+) -> SkynetResult<(String, Vec<ProofEntry>)> {
+  let (skylink, res) = head(client, skylink, opt).await?;
+  if !res.status().is_success() {
+    return Err(in_context(res.extensions(), PortalResponse(res.status().to_string())));
+  }
+
+  let header = |name| res.headers().get(name).and_then(|value| value.to_str().ok());
+
+  let resolved = header("skynet-skylink").ok_or_else(|| PortalResponse(format!("{} was not resolved", skylink)))?;
+  let proof = match header("skynet-proof") {
+    Some(proof) => serde_json::from_str(proof).map_err(|_| PortalResponse(proof.to_string()))?,
+    None => Vec::new(),
   };
 
-  Ok(Metadata {
-    skylink,
-    portal_url,
-    content_type,
-    filename,
-    length,
-    subfiles,
-  })
+  Ok((resolved.to_string(), proof))
 }
 
-#[cfg(test)]
-mod tests {
-  use super::*;
+/// Resolves a v2 skylink to its current v1 target, using the `skynet-skylink` and
+/// `skynet-proof` headers of a HEAD request. The target is immutable, so it can be cached
+/// for as long as the revision stays the same.
+pub async fn resolve_skylink_v2(
+  client: &SkynetClient,
+  skylink: &str,
+  opt: MetadataOptions,
+) -> SkynetResult<ResolvedSkylink> {
+  let (parsed, _) = parse_skylink(skylink)?;
+  if parsed.version()? != SkylinkVersion::V2 {
+    return Err(InvalidSkylink(format!("{} is not a v2 skylink", parsed)));
+  }
 
-  #[tokio::test]
-  async fn test_download_data() {
-    let client = SkynetClient::default();
-    let skylink = "sia://AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng";
-    let res = download_data(&client, skylink, DownloadOptions::default()).await;
-    println!("{:?}", res);
-    assert!(res.is_ok());
-    let data = res.unwrap();
+  let (resolved, proof) = head_resolution(client, skylink, opt).await?;
+
+  Ok(ResolvedSkylink {
+    skylink: resolved,
+    revision: proof.last().map(|entry| entry.revision),
+  })
+}
+
+/// Resolves a v2 skylink through any resolver skylinks it points at, down to a v1 skylink.
+/// Each hop is read from the portal's registry proof; if the portal stops at another resolver,
+/// that one is resolved in turn. Fails with `ResolverChainError` and the path so far when the
+/// chain loops or is longer than `max_resolver_depth`.
+pub async fn resolve_skylink_chain(
+  client: &SkynetClient,
+  skylink: &str,
+  opt: MetadataOptions,
+) -> SkynetResult<ResolutionChain> {
+  let (parsed, _) = parse_skylink(skylink)?;
+  if parsed.version()? != SkylinkVersion::V2 {
+    return Err(InvalidSkylink(format!("{} is not a v2 skylink", parsed)));
+  }
+
+  let mut current = parsed.to_string();
+  let mut seen = HashSet::new();
+  seen.insert(current.clone());
+  let mut hops = Vec::new();
+  let chain_error = |hops: Vec<ResolutionHop>, last: String| {
+    let mut path: Vec<_> = hops.into_iter().map(|hop| hop.resolver).collect();
+    path.push(last);
+    ResolverChainError(path)
+  };
+
+  loop {
+    let (resolved, proof) = head_resolution(client, &current, opt.clone()).await?;
+
+    // Fall back to a single hop when the proof doesn't spell out the chain.
+    let targets = proof
+      .iter()
+      .map(|entry| {
+        let target = Vec::from_hex(&entry.data).ok().and_then(|data| Skylink::from_bytes(&data).ok())?;
+        Some((target.to_string(), Some(entry.revision)))
+      })
+      .collect::<Option<Vec<_>>>()
+      .filter(|targets| targets.last().map(|(target, _)| target) == Some(&resolved))
+      .unwrap_or_else(|| vec![(resolved.clone(), proof.last().map(|entry| entry.revision))]);
+
+    for (target, revision) in targets {
+      if hops.len() >= opt.max_resolver_depth {
+        return Err(chain_error(hops, current));
+      }
+      hops.push(ResolutionHop {
+        resolver: current,
+        target: target.clone(),
+        revision,
+      });
+      if !seen.insert(target.clone()) {
+        return Err(chain_error(hops, target));
+      }
+      current = target;
+    }
+
+    if current.parse::<Skylink>()?.version()? == SkylinkVersion::V1 {
+      return Ok(ResolutionChain { skylink: current, hops });
+    }
+  }
+}
+
+#[derive(Deserialize)]
+struct FileMetadata {
+  filename: Option<String>,
+  length: Option<u64>,
+  subfiles: Option<HashMap<String, SubfileMetadata>>,
+  #[cfg(feature = "checksum")]
+  #[serde(default, rename = "defaultpath")]
+  default_path: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SubfileMetadata {
+  filename: String,
+  len: u64,
+  contenttype: String,
+}
+
+impl FileMetadata {
+  fn into_metadata(self, skylink: String, portal_url: String, content_type: Option<Mime>) -> Metadata {
+    let subfiles = self.subfiles.map(|subfiles| {
+      subfiles
+        .into_iter()
+        .map(|(name, subfile)| (name, subfile.into()))
+        .collect::<HashMap<_, _>>()
+    });
+
+    Metadata {
+      skylink,
+      portal_url,
+      content_type,
+      filename: self.filename,
+      length: self.length.map(|length| length as u32),
+      subfiles,
+    }
+  }
+}
+
+impl From<SubfileMetadata> for Subfile {
+  fn from(subfile: SubfileMetadata) -> Self {
+    Self {
+      filename: subfile.filename,
+      length: subfile.len as u32,
+      content_type: subfile.contenttype.parse().unwrap_or(mime::APPLICATION_OCTET_STREAM),
+    }
+  }
+}
+
+/// Metadata of a skyfile whose subfiles are parsed one at a time as the response streams in,
+/// without holding the whole JSON document or a map of subfiles. For directories with tens of
+/// thousands of files. `filename` and `length` are those the portal lists before the subfiles,
+/// as skyd does.
+#[derive(Debug)]
+pub struct StreamedMetadata {
+  pub skylink: String,
+  pub portal_url: String,
+  pub filename: Option<String>,
+  pub length: Option<u64>,
+  pub subfiles: Subfiles,
+}
+
+/// How many parsed subfiles wait for the caller before parsing pauses.
+const SUBFILES_BUFFER: usize = 256;
+
+/// The subfiles of a `StreamedMetadata`, paired with their paths, in the order the portal
+/// listed them. Each is yielded as soon as it is parsed, and the stream ends with an error if
+/// the rest of the response can't be read or parsed.
+#[derive(Debug)]
+pub struct Subfiles {
+  entries: mpsc::Receiver<(String, Subfile)>,
+  parser: Option<JoinHandle<SkynetResult<()>>>,
+}
+
+impl Stream for Subfiles {
+  type Item = SkynetResult<(String, Subfile)>;
+
+  fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+    if let Some(entry) = ready!(self.entries.poll_recv(cx)) {
+      return Poll::Ready(Some(Ok(entry)));
+    }
+
+    // The parser dropped its sender, so it is done; its result says whether it got to the end.
+    let parser = match self.parser.as_mut() {
+      Some(parser) => parser,
+      None => return Poll::Ready(None),
+    };
+    let res = ready!(Pin::new(parser).poll(cx));
+    self.parser = None;
+    Poll::Ready(joined(res).and_then(|res| res).err().map(Err))
+  }
+}
+
+/// The top level of a streamed metadata document. Hands `filename` and `length` to `header` when
+/// the subfiles start, or at the end if there are none, and sends each subfile as it is parsed.
+struct StreamedFileMetadata<'a> {
+  header: &'a mut Option<oneshot::Sender<(Option<String>, Option<u64>)>>,
+  subfiles: &'a mpsc::Sender<(String, Subfile)>,
+}
+
+impl<'de, 'a> DeserializeSeed<'de> for StreamedFileMetadata<'a> {
+  type Value = ();
+
+  fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+    deserializer.deserialize_map(self)
+  }
+}
+
+impl<'de, 'a> Visitor<'de> for StreamedFileMetadata<'a> {
+  type Value = ();
+
+  fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("skyfile metadata")
+  }
+
+  fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+    let (mut filename, mut length) = (None, None);
+    while let Some(key) = map.next_key::<String>()? {
+      match key.as_str() {
+        "filename" => filename = map.next_value()?,
+        "length" => length = map.next_value()?,
+        "subfiles" => {
+          if let Some(header) = self.header.take() {
+            let _ = header.send((filename.take(), length.take()));
+          }
+          map.next_value_seed(SubfileEntries(self.subfiles))?;
+        }
+        _ => {
+          map.next_value::<IgnoredAny>()?;
+        }
+      }
+    }
+    if let Some(header) = self.header.take() {
+      let _ = header.send((filename, length));
+    }
+    Ok(())
+  }
+}
+
+/// Sends each subfile on as it is parsed instead of building a map first.
+struct SubfileEntries<'a>(&'a mpsc::Sender<(String, Subfile)>);
+
+impl<'de, 'a> DeserializeSeed<'de> for SubfileEntries<'a> {
+  type Value = ();
+
+  fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+    deserializer.deserialize_any(self)
+  }
+}
+
+impl<'de, 'a> Visitor<'de> for SubfileEntries<'a> {
+  type Value = ();
+
+  fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("a map of subfiles")
+  }
+
+  fn visit_unit<E>(self) -> Result<(), E> {
+    Ok(())
+  }
+
+  fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+    while let Some((path, subfile)) = map.next_entry::<String, SubfileMetadata>()? {
+      // Nobody is left to read the rest once the `Subfiles` is dropped.
+      self.0.blocking_send((path, subfile.into())).map_err(|_| A::Error::custom("subfiles dropped"))?;
+    }
+    Ok(())
+  }
+}
+
+/// Reads a response body chunk by chunk as it arrives. Only for use off the executor, such as
+/// in `spawn_blocking`, since each read waits on the runtime.
+struct BodyReader {
+  body: Body,
+  chunk: Bytes,
+  handle: Handle,
+  extensions: Extensions,
+  /// What a failed read of the body failed with, in the context of its request, which the
+  /// `io::Error` it returns can only carry as text.
+  error: Option<SkynetError>,
+}
+
+impl BodyReader {
+  fn new(parts: &mut Parts, body: Body) -> Self {
+    let extensions = std::mem::take(&mut parts.extensions);
+    Self { body, chunk: Bytes::new(), handle: Handle::current(), extensions, error: None }
+  }
+}
+
+impl Read for BodyReader {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    while self.chunk.is_empty() {
+      match self.handle.block_on(self.body.data()) {
+        Some(Ok(chunk)) => self.chunk = chunk,
+        Some(Err(err)) => {
+          let err = in_context(&self.extensions, HyperError(err));
+          let io_err = io::Error::other(format!("{:?}", err));
+          self.error = Some(err);
+          return Err(io_err);
+        }
+        None => return Ok(0),
+      }
+    }
+
+    let n = buf.len().min(self.chunk.len());
+    buf[..n].copy_from_slice(&self.chunk.split_to(n));
+    Ok(n)
+  }
+}
+
+/// The resolved skylink and portal URL from a portal response's headers.
+fn skylink_and_portal(client: &SkynetClient, skylink: String, headers: &HeaderMap) -> (String, String) {
+  let header = |name| headers.get(name).and_then(|value| value.to_str().ok()).map(str::to_string);
+
+  (
+    header("skynet-skylink").unwrap_or(skylink),
+    header("skynet-portal-api").unwrap_or_else(|| client.get_portal_url()),
+  )
+}
+
+/// Metadata from the headers of a HEAD request. Fails if the portal didn't send the
+/// `skynet-file-metadata` header.
+async fn get_metadata_from_head(
+  client: &SkynetClient,
+  skylink: &str,
+  opt: &MetadataOptions,
+) -> SkynetResult<Metadata> {
+  let (skylink, res) = head(client, skylink, opt.clone()).await?;
+  if !res.status().is_success() {
+    return Err(in_context(res.extensions(), PortalResponse(res.status().to_string())));
+  }
+
+  let headers = res.headers();
+  let (skylink, portal_url) = skylink_and_portal(client, skylink, headers);
+  let content_type = headers
+    .get("content-type")
+    .and_then(|content_type| content_type.to_str().ok())
+    .and_then(|content_type| content_type.parse().ok());
+
+  let metadata = headers
+    .get("skynet-file-metadata")
+    .ok_or_else(|| PortalResponse(format!("no skynet-file-metadata header for {}", skylink)))?;
+  let metadata = metadata.to_str().map_err(|_| PortalResponse(format!("{:?}", metadata)))?;
+  let metadata: FileMetadata = serde_json::from_str(metadata).map_err(|_| PortalResponse(metadata.to_string()))?;
+
+  Ok(metadata.into_metadata(skylink, portal_url, content_type))
+}
+
+/// Metadata from the portal's JSON metadata endpoint. The content type is only known for
+/// skyfiles with a single subfile.
+async fn get_metadata_from_endpoint(
+  client: &SkynetClient,
+  skylink: &str,
+  opt: &MetadataOptions,
+) -> SkynetResult<Metadata> {
+  let skylink = normalize_skylink(skylink)?;
+
+  let uri = make_uri(
+    &client.get_portal_url(),
+    opt.metadata_endpoint_path.clone(),
+    opt.api_key.clone(),
+    Some(skylink.clone()),
+    QueryParams::new());
+
+  let mut req = Request::builder().method("GET").uri(uri.clone());
+
+  if let Some(custom_user_agent) = &opt.custom_user_agent {
+    req = req.header("User-Agent", custom_user_agent.clone());
+  }
+
+  req = req.extension(CallAuth(opt.auth.clone()));
+
+  let req = req.body(Body::from("")).map_err(HttpError)?;
+  let res = client.request(req).await?;
+  let (parts, body) = res.into_parts();
+  let body = read_body(&parts, body).await?;
+
+  if !parts.status.is_success() {
+    return Err(status_error(&parts.extensions, &uri, parts.status, &body));
+  }
+
+  let body_str = str::from_utf8(&body).map_err(Utf8Error)?;
+
+  let (skylink, portal_url) = skylink_and_portal(client, skylink, &parts.headers);
+  let metadata: FileMetadata = parse_response(&uri, parts.status, body_str)?;
+  let content_type = match &metadata.subfiles {
+    Some(subfiles) if subfiles.len() == 1 => subfiles.values().next().unwrap().contenttype.parse().ok(),
+    _ => None,
+  };
+
+  Ok(metadata.into_metadata(skylink, portal_url, content_type))
+}
+
+/// Fetches the metadata of `skylink` from the metadata endpoint, parsing it as it streams in.
+/// Unlike `get_metadata`, memory stays proportional to the subfiles rather than to the JSON.
+pub async fn get_streamed_metadata(
+  client: &SkynetClient,
+  skylink: &str,
+  opt: MetadataOptions,
+) -> SkynetResult<StreamedMetadata> {
+  let skylink = normalize_skylink(skylink)?;
+
+  let uri = make_uri(
+    &client.get_portal_url(),
+    opt.metadata_endpoint_path,
+    opt.api_key,
+    Some(skylink.clone()),
+    QueryParams::new());
+
+  let mut req = Request::builder().method("GET").uri(uri.clone());
+
+  if let Some(custom_user_agent) = opt.custom_user_agent {
+    req = req.header("User-Agent", custom_user_agent);
+  }
+
+  req = req.extension(CallAuth(opt.auth.clone()));
+
+  let req = req.body(Body::empty()).map_err(HttpError)?;
+  let res = client.request(req).await?;
+  let (mut parts, body) = res.into_parts();
+
+  if !parts.status.is_success() {
+    let body = read_body(&parts, body).await?;
+    return Err(status_error(&parts.extensions, &uri, parts.status, &body));
+  }
+
+  let (skylink, portal_url) = skylink_and_portal(client, skylink, &parts.headers);
+  let mut reader = BodyReader::new(&mut parts, body);
+  let (header_tx, header_rx) = oneshot::channel();
+  let (entries_tx, entries) = mpsc::channel(SUBFILES_BUFFER);
+  let parser = tokio::task::spawn_blocking(move || {
+    let mut header = Some(header_tx);
+    let mut deserializer = serde_json::Deserializer::from_reader(&mut reader);
+    let seed = StreamedFileMetadata { header: &mut header, subfiles: &entries_tx };
+    let parsed = seed.deserialize(&mut deserializer).and_then(|()| deserializer.end());
+    match (parsed, reader.error) {
+      (_, Some(err)) => Err(err),
+      (Err(err), None) => Err(PortalResponse(format!("{} returned invalid metadata: {}", uri.path(), err))),
+      (Ok(()), None) => Ok(()),
+    }
+  });
+
+  // The header is only dropped unsent if parsing failed before the subfiles.
+  let (filename, length) = match header_rx.await {
+    Ok(header) => header,
+    Err(_) => {
+      joined(parser.await)??;
+      unreachable!("metadata parsed without sending its header");
+    }
+  };
+
+  Ok(StreamedMetadata {
+    skylink,
+    portal_url,
+    filename,
+    length,
+    subfiles: Subfiles { entries, parser: Some(parser) },
+  })
+}
+
+/// Fetches the skyfile metadata of `skylink` from each of `opt.sources` in turn, returning the
+/// first that succeeds or the last error.
+pub async fn get_metadata(
+  client: &SkynetClient,
+  skylink: &str,
+  opt: MetadataOptions,
+) -> SkynetResult<Metadata> {
+  let mut last_err = PortalResponse("no metadata sources configured".into());
+
+  for source in &opt.sources {
+    let res = match source {
+      MetadataSource::Head => get_metadata_from_head(client, skylink, &opt).await,
+      MetadataSource::Endpoint => get_metadata_from_endpoint(client, skylink, &opt).await,
+    };
+
+    match res {
+      Ok(metadata) => return Ok(metadata),
+      Err(err) => last_err = err,
+    }
+  }
+
+  Err(last_err)
+}
+
+/// Fetches the metadata of every skylink, up to `opt.concurrency` at a time, returning the
+/// results in the order given. One skylink failing doesn't stop the others, and `opt.deadline`
+/// applies to each skylink separately.
+pub async fn get_metadata_many(
+  client: &SkynetClient,
+  skylinks: &[&str],
+  opt: MetadataOptions,
+) -> Vec<SkynetResult<Metadata>> {
+  let fetches = skylinks.iter().map(|skylink| {
+    let opt = opt.clone();
+    with_deadline(opt.deadline, get_metadata(client, skylink, opt))
+  });
+
+  stream::iter(fetches)
+    .buffered(opt.concurrency.max(1))
+    .collect()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{MockPortal, MockResponse, MockTransport, SkynetClientOptions, UploadOptions, URI_SKYNET_PREFIX};
+  use std::sync::Arc;
+
+  async fn upload_hello(client: &SkynetClient) -> String {
+    let mut data = HashMap::new();
+    data.insert("hello.txt".into(), (mime::TEXT_PLAIN, Bytes::from("hello world")));
+    let opt = UploadOptions { skylink_format: crate::SkylinkFormat::Raw, ..Default::default() };
+    client.upload_data(data, opt).await.unwrap()
+  }
+
+  #[tokio::test]
+  async fn test_download_data() {
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let skylink = format!("{}{}", URI_SKYNET_PREFIX, upload_hello(&client).await);
+    let res = download_data(&client, &skylink, DownloadOptions::default()).await;
+    println!("{:?}", res);
+    assert!(res.is_ok());
+    let data = res.unwrap();
     assert_eq!(str::from_utf8(&data).unwrap(), "hello world");
   }
 
+  #[tokio::test]
+  async fn test_download_local_store() {
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::new(StatusCode::NOT_FOUND, "not found"));
+    transport.push_response(MockResponse::new(StatusCode::OK, "hello world"));
+    let store = crate::MemoryStore::new();
+    let client = SkynetClient::with_transport("https://siasky.net", SkynetClientOptions {
+      local_store: Some(Arc::new(store.clone())),
+      ..Default::default()
+    }, Arc::new(transport.clone()));
+
+    let skylink = "AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng";
+    download_data(&client, skylink, DownloadOptions::default()).await.unwrap();
+    assert!(store.is_empty());
+
+    let data = download_data(&client, skylink, DownloadOptions::default()).await.unwrap();
+    assert_eq!(data, "hello world");
+    let data = download_data(&client, &format!("{}{}", URI_SKYNET_PREFIX, skylink), DownloadOptions::default()).await.unwrap();
+    assert_eq!(data, "hello world");
+    assert_eq!(store.len(), 1);
+    assert_eq!(transport.requests().len(), 2);
+
+    transport.push_response(MockResponse::new(StatusCode::OK, "encrypted"));
+    let opt = DownloadOptions { skykey_name: Some("key".into()), ..Default::default() };
+    assert_eq!(download_data(&client, skylink, opt).await.unwrap(), "encrypted");
+    assert_eq!(store.len(), 2);
+
+    let v2 = "AQAZ1R-KcL4NO_xIVf0q8B1ngPVd6ec-Pu54O0Cto387Nw";
+    transport.push_response(MockResponse::new(StatusCode::OK, "v1"));
+    transport.push_response(MockResponse::new(StatusCode::OK, "v2"));
+    assert_eq!(download_data(&client, v2, DownloadOptions::default()).await.unwrap(), "v1");
+    assert_eq!(download_data(&client, v2, DownloadOptions::default()).await.unwrap(), "v2");
+    assert_eq!(store.len(), 2);
+  }
+
+  #[tokio::test]
+  async fn test_download_data_with_response() {
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let skylink = upload_hello(&client).await;
+    let res = download_data_with_response(&client, &skylink, DownloadOptions::default()).await;
+    println!("{:?}", res);
+    assert!(res.is_ok());
+    let (data, info) = res.unwrap();
+    assert_eq!(data, "hello world");
+    assert_eq!(info.status, StatusCode::OK);
+    assert_eq!(info.skylink, Some(skylink.clone()));
+    assert!(info.url.to_string().ends_with(&skylink));
+    assert_eq!(info.headers["content-type"], "text/plain");
+  }
+
   #[tokio::test]
   async fn test_download_file() {
-    let client = SkynetClient::default();
-    let skylink = "sia://AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng";
-    let res = download_file(&client, "tmp2.txt", skylink, DownloadOptions::default()).await;
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let skylink = upload_hello(&client).await;
+    let res = download_file(&client, "tmp2.txt", &skylink, DownloadOptions::default()).await;
     println!("{:?}", res);
     assert!(res.is_ok());
     assert_eq!(fs::read_to_string("tmp2.txt").unwrap(), "hello world");
     fs::remove_file("tmp2.txt").unwrap();
   }
 
+  #[tokio::test]
+  async fn test_download_file_memory_budget() {
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::new(StatusCode::OK, "").with_header("content-length", "5"));
+    transport.push_response(MockResponse::new(StatusCode::OK, "hello"));
+    let client = SkynetClient::with_transport("https://siasky.net", SkynetClientOptions {
+      memory_budget: Some(8),
+      ..Default::default()
+    }, Arc::new(transport.clone()));
+
+    let res = download_file(&client, "tmp_budget.txt", "AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng", DownloadOptions::default()).await;
+    let contents = fs::read_to_string("tmp_budget.txt");
+    let _ = fs::remove_file("tmp_budget.txt");
+    res.unwrap();
+    assert_eq!(contents.unwrap(), "hello");
+    // The size is asked for before the download is requested.
+    let methods: Vec<_> = transport.requests().into_iter().map(|req| req.method).collect();
+    assert_eq!(methods, [hyper::Method::HEAD, hyper::Method::GET]);
+  }
+
+  // Directory transfers hold the budget while the calls they make run, without waiting on
+  // themselves.
+  #[cfg(feature = "directory")]
+  #[tokio::test]
+  async fn test_directory_memory_budget() {
+    let portal = MockPortal::start().await;
+    let client = SkynetClient::new(portal.url(), SkynetClientOptions { memory_budget: Some(8), ..Default::default() });
+    fs::create_dir_all("tmp_budget_dir").unwrap();
+    fs::write("tmp_budget_dir/a.txt", "hello a").unwrap();
+    fs::write("tmp_budget_dir/b.txt", "hello b").unwrap();
+    let skylink = client.upload_directory("tmp_budget_dir", UploadOptions::default()).await;
+    fs::remove_dir_all("tmp_budget_dir").unwrap();
+    let skylink = skylink.unwrap();
+    let res = download_directory(&client, "tmp_budget_dir", &skylink, DownloadOptions::default()).await;
+    let contents = fs::read_to_string("tmp_budget_dir/b.txt");
+    let _ = fs::remove_dir_all("tmp_budget_dir");
+    res.unwrap();
+    assert_eq!(contents.unwrap(), "hello b");
+  }
+
+  #[tokio::test]
+  async fn test_download_file_preallocate() {
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let skylink = upload_hello(&client).await;
+    let opt = DownloadOptions { preallocate: true, ..Default::default() };
+    let res = download_file(&client, "tmp_prealloc.txt", &skylink, opt).await;
+    println!("{:?}", res);
+    assert!(res.is_ok());
+    assert_eq!(fs::read_to_string("tmp_prealloc.txt").unwrap(), "hello world");
+    fs::remove_file("tmp_prealloc.txt").unwrap();
+
+    let res = reserve_space(Path::new("tmp_prealloc_huge.bin"), u64::MAX, true);
+    #[cfg(unix)]
+    assert!(matches!(res, Err(InsufficientSpace(u64::MAX, _))));
+    assert!(!Path::new("tmp_prealloc_huge.bin").exists());
+  }
+
+  #[tokio::test]
+  async fn test_download_file_derive_filename() {
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let skylink = upload_hello(&client).await;
+    let opt = DownloadOptions { derive_filename: true, ..Default::default() };
+
+    fs::create_dir("tmp_derive").unwrap();
+    let saved = download_file(&client, "tmp_derive", &skylink, opt.clone()).await;
+    let extensionless = download_file(&client, "tmp_derive/hello", &skylink, opt.clone()).await;
+    let explicit = download_file(&client, "tmp_derive/hello.md", &skylink, opt).await;
+    let contents = fs::read_to_string("tmp_derive/hello.txt");
+    fs::remove_dir_all("tmp_derive").unwrap();
+
+    assert_eq!(saved.unwrap(), Path::new("tmp_derive/hello.txt"));
+    assert_eq!(extensionless.unwrap(), Path::new("tmp_derive/hello.txt"));
+    assert_eq!(explicit.unwrap(), Path::new("tmp_derive/hello.md"));
+    assert_eq!(contents.unwrap(), "hello world");
+
+    let mut info = ResponseInfo {
+      status: StatusCode::OK,
+      headers: HeaderMap::new(),
+      url: "https://siasky.net/".parse().unwrap(),
+      skylink: None,
+    };
+    info.headers.insert("content-type", "image/png".parse().unwrap());
+    assert_eq!(derived_path(Path::new("image"), &skylink, &info), Path::new("image.png"));
+    info.headers.insert("content-disposition", "attachment; filename=\"../../etc/passwd\"".parse().unwrap());
+    assert_eq!(derived_path(Path::new("."), &skylink, &info), Path::new("./passwd"));
+  }
+
+  #[tokio::test]
+  async fn test_download_subfiles() {
+    let portal = MockPortal::start().await;
+    let client = SkynetClient::new(portal.url(), SkynetClientOptions {
+      http2: true,
+      ..Default::default()
+    });
+    let mut data = HashMap::new();
+    data.insert("1.txt".into(), (mime::TEXT_PLAIN, Bytes::from("hello 1")));
+    data.insert("2.txt".into(), (mime::TEXT_PLAIN, Bytes::from("hello 2")));
+    let skylink = client.upload_data(data, UploadOptions {
+      custom_dirname: Some("dir".into()),
+      ..Default::default()
+    }).await.unwrap();
+    let res = download_subfiles(&client, &skylink, &["2.txt", "1.txt"], DownloadOptions::default()).await;
+    println!("{:?}", res);
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap(), vec![Bytes::from("hello 2"), Bytes::from("hello 1")]);
+  }
+
+  #[cfg(feature = "directory")]
+  #[tokio::test]
+  async fn test_download_directory() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    fs::create_dir_all("tmpdldir/src/sub").unwrap();
+    fs::write("tmpdldir/src/1.txt", "hello 1").unwrap();
+    for i in 0..20 {
+      fs::write(format!("tmpdldir/src/sub/{}.txt", i), i.to_string()).unwrap();
+    }
+    fs::write("tmpdldir/src/sub/2.sh", "echo hello 2").unwrap();
+    let mtime = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    fs::File::options().write(true).open("tmpdldir/src/1.txt").unwrap().set_modified(mtime).unwrap();
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      fs::set_permissions("tmpdldir/src/sub/2.sh", fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    let opt = UploadOptions { preserve_attributes: true, ..Default::default() };
+    let skylink = client.upload_directory("tmpdldir/src", opt).await.unwrap();
+    let res = download_directory(&client, "tmpdldir/dst", &skylink, DownloadOptions {
+      concurrency: 4,
+      ..Default::default()
+    }).await;
+    println!("{:?}", res);
+    assert!(res.is_ok());
+
+    assert_eq!(fs::read_to_string("tmpdldir/dst/1.txt").unwrap(), "hello 1");
+    assert_eq!(fs::read_to_string("tmpdldir/dst/sub/2.sh").unwrap(), "echo hello 2");
+    for i in 0..20 {
+      assert_eq!(fs::read_to_string(format!("tmpdldir/dst/sub/{}.txt", i)).unwrap(), i.to_string());
+    }
+    assert!(!Path::new("tmpdldir/dst").join(DIRECTORY_METADATA_FILENAME).exists());
+    assert_eq!(fs::metadata("tmpdldir/dst/1.txt").unwrap().modified().unwrap(), mtime);
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      assert_eq!(fs::metadata("tmpdldir/dst/sub/2.sh").unwrap().permissions().mode() & 0o777, 0o755);
+    }
+    fs::remove_dir_all("tmpdldir").unwrap();
+  }
+
+  #[cfg(feature = "archive")]
+  #[tokio::test]
+  async fn test_download_directory_archive() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let mut builder = tar::Builder::new(Vec::new());
+    let metadata = r#"{"sub/1.txt":{"mtime":1600000000}}"#;
+    for (name, contents) in &[("sub/1.txt", "hello 1"), (DIRECTORY_METADATA_FILENAME, metadata)] {
+      let mut header = tar::Header::new_gnu();
+      header.set_size(contents.len() as u64);
+      header.set_mode(0o644);
+      header.set_cksum();
+      builder.append_data(&mut header, name, contents.as_bytes()).unwrap();
+    }
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::new(StatusCode::OK, builder.into_inner().unwrap()));
+    let client = transport.client("https://siasky.net");
+
+    let skylink = "AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng";
+    let opt = DownloadOptions { archive_format: Some(ArchiveFormat::Tar), ..Default::default() };
+    let res = download_directory(&client, "tmp_archive_dl", skylink, opt).await;
+    println!("{:?}", res);
+    assert!(res.is_ok());
+
+    let requests = transport.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].uri.query(), Some("format=tar"));
+    assert_eq!(fs::read_to_string("tmp_archive_dl/sub/1.txt").unwrap(), "hello 1");
+    assert!(!Path::new("tmp_archive_dl").join(DIRECTORY_METADATA_FILENAME).exists());
+    let mtime = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+    assert_eq!(fs::metadata("tmp_archive_dl/sub/1.txt").unwrap().modified().unwrap(), mtime);
+    fs::remove_dir_all("tmp_archive_dl").unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_get_streamed_metadata() {
+    use futures_util::TryStreamExt;
+
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let data = (0..500)
+      .map(|i| (format!("{}.txt", i), (mime::TEXT_PLAIN, Bytes::from(format!("hello {}", i)))))
+      .collect::<HashMap<_, _>>();
+    let skylink = client.upload_data(data, UploadOptions {
+      custom_dirname: Some("dir".into()),
+      ..Default::default()
+    }).await.unwrap();
+
+    let metadata = get_streamed_metadata(&client, &skylink, MetadataOptions::default()).await.unwrap();
+    assert_eq!(metadata.filename, Some("dir".into()));
+    let subfiles = metadata.subfiles.try_collect::<HashMap<_, _>>().await.unwrap();
+    assert_eq!(subfiles.len(), 500);
+    assert_eq!(subfiles["42.txt"].length, 8);
+    assert_eq!(subfiles["42.txt"].content_type, mime::TEXT_PLAIN);
+
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::json(serde_json::json!({ "filename": "a.txt", "subfiles": null })));
+    transport.push_response(MockResponse::new(
+      StatusCode::OK,
+      "{\"length\": 1, \"subfiles\": {\"a.txt\": {\"filename\": \"a.txt\", \"len\": 1, \"contenttype\": \"text/plain\"}, \"b.txt\": 1}}",
+    ));
+    transport.push_response(MockResponse::new(StatusCode::OK, "{\"length\": \"one\"}"));
+    transport.push_response(MockResponse::new(StatusCode::NOT_FOUND, "not found"));
+    let client = transport.client("https://siasky.net");
+
+    let metadata = get_streamed_metadata(&client, &skylink, MetadataOptions::default()).await.unwrap();
+    assert_eq!(metadata.filename, Some("a.txt".into()));
+    assert_eq!(metadata.subfiles.count().await, 0);
+    // Subfiles parsed before a malformed one are still yielded.
+    let mut subfiles = get_streamed_metadata(&client, &skylink, MetadataOptions::default()).await.unwrap().subfiles;
+    assert_eq!(subfiles.next().await.unwrap().unwrap().0, "a.txt");
+    assert!(matches!(subfiles.next().await, Some(Err(PortalResponse(_)))));
+    assert!(subfiles.next().await.is_none());
+    let res = get_streamed_metadata(&client, &skylink, MetadataOptions::default()).await;
+    assert!(matches!(res, Err(PortalResponse(_))));
+    let res = get_streamed_metadata(&client, &skylink, MetadataOptions::default()).await;
+    assert!(matches!(res.map_err(crate::SkynetError::into_inner), Err(PortalResponse(message)) if message.contains("404")));
+  }
+
+  #[tokio::test]
+  async fn test_get_metadata_fallback() {
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let mut data = HashMap::new();
+    data.insert("1.txt".into(), (mime::TEXT_PLAIN, Bytes::from("hello 1")));
+    data.insert("2.txt".into(), (mime::TEXT_PLAIN, Bytes::from("hello 2")));
+    let skylink = client.upload_data(data, UploadOptions {
+      custom_dirname: Some("dir".into()),
+      ..Default::default()
+    }).await.unwrap();
+
+    // Without a default path the HEAD request fails, so the endpoint is used.
+    let res = get_metadata(&client, &skylink, MetadataOptions::default()).await;
+    println!("{:?}", res);
+    let metadata = res.unwrap();
+    assert_eq!(metadata.filename, Some("dir".into()));
+    assert_eq!(metadata.subfiles.unwrap().len(), 2);
+
+    let res = get_metadata(&client, &skylink, MetadataOptions {
+      sources: vec![MetadataSource::Head],
+      ..Default::default()
+    }).await;
+    assert!(res.is_err());
+
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::json(serde_json::json!({
+      "filename": "hello.txt",
+      "length": 11,
+      "subfiles": { "hello.txt": { "filename": "hello.txt", "contenttype": "text/plain", "len": 11 } },
+    })));
+    let client = transport.client("https://siasky.net");
+    let res = get_metadata(&client, &skylink, MetadataOptions {
+      sources: vec![MetadataSource::Endpoint, MetadataSource::Head],
+      ..Default::default()
+    }).await;
+    let metadata = res.unwrap();
+    assert_eq!(metadata.content_type, Some(mime::TEXT_PLAIN));
+    assert_eq!(metadata.length, Some(11));
+    let requests = transport.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].uri.path(), format!("/skynet/metadata/{}", normalize_skylink(&skylink).unwrap()));
+  }
+
+  #[tokio::test]
+  async fn test_skylink_exists() {
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let skylink = upload_hello(&client).await;
+    let res = skylink_exists(&client, &skylink, MetadataOptions::default()).await;
+    println!("{:?}", res);
+    assert!(res.unwrap());
+
+    let missing = "AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng";
+    assert!(!skylink_exists(&client, missing, MetadataOptions::default()).await.unwrap());
+
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::new(StatusCode::FORBIDDEN, ""));
+    let client = transport.client("https://siasky.net");
+    let res = skylink_exists(&client, missing, MetadataOptions::default()).await;
+    assert!(matches!(res, Err(AccessDenied(_))));
+  }
+
+  #[tokio::test]
+  async fn test_resolve_skylink_v2() {
+    let v1 = "AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng";
+    let v2 = "AQAZ1R-KcL4NO_xIVf0q8B1ngPVd6ec-Pu54O0Cto387Nw";
+
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::new(StatusCode::OK, "")
+      .with_header("skynet-skylink", v1)
+      .with_header("skynet-proof", r#"[{"data":"00","revision":7,"datakey":"00","type":1}]"#));
+    let client = transport.client("https://siasky.net");
+
+    let res = resolve_skylink_v2(&client, &format!("sia://{}", v2), MetadataOptions::default()).await;
+    println!("{:?}", res);
+    assert_eq!(res.unwrap(), ResolvedSkylink { skylink: v1.into(), revision: Some(7) });
+    assert_eq!(transport.last_request().unwrap().method, hyper::Method::HEAD);
+
+    let res = resolve_skylink_v2(&client, v1, MetadataOptions::default()).await;
+    assert!(matches!(res, Err(InvalidSkylink(_))));
+  }
+
+  #[cfg(feature = "checksum")]
+  #[tokio::test]
+  async fn test_download_checksums() {
+    use crate::{checksum, ChecksumAlgorithm};
+
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    fs::create_dir_all("tmpchecksum/src").unwrap();
+    fs::write("tmpchecksum/src/1.txt", "hello 1").unwrap();
+
+    let opt = UploadOptions { checksum: Some(ChecksumAlgorithm::Sha256), ..Default::default() };
+    let skylink = client.upload_directory("tmpchecksum/src", opt).await.unwrap();
+    let metadata = download_data(&client, &format!("{}/{}", skylink, DIRECTORY_METADATA_FILENAME), Default::default())
+      .await
+      .unwrap();
+    let expected = checksum(ChecksumAlgorithm::Sha256, b"hello 1");
+    assert!(String::from_utf8_lossy(&metadata).contains(&expected));
+    download_directory(&client, "tmpchecksum/dst", &skylink, DownloadOptions::default()).await.unwrap();
+    assert_eq!(fs::read_to_string("tmpchecksum/dst/1.txt").unwrap(), "hello 1");
+    fs::remove_dir_all("tmpchecksum").unwrap();
+
+    let subfile = format!("{}/1.txt", skylink);
+    let opt = DownloadOptions { expected_checksum: Some(expected), ..Default::default() };
+    assert_eq!(download_data(&client, &subfile, opt).await.unwrap(), "hello 1");
+    let opt = DownloadOptions {
+      expected_checksum: Some(checksum(ChecksumAlgorithm::Sha256, b"tampered")),
+      ..Default::default()
+    };
+    assert!(matches!(download_data(&client, &subfile, opt).await, Err(ChecksumMismatch(_, _))));
+
+    let mut data = HashMap::new();
+    data.insert("hello.txt".to_string(), (mime::TEXT_PLAIN, Bytes::from("hello")));
+    let opt = UploadOptions { checksum: Some(ChecksumAlgorithm::Sha256), ..Default::default() };
+    let skylink = client.upload_data(data, opt).await.unwrap();
+    assert_eq!(download_data(&client, &skylink, Default::default()).await.unwrap(), "hello");
+
+    let transport = MockTransport::new();
+    let metadata = serde_json::json!({
+      "filename": "hello.txt",
+      "subfiles": {
+        "hello.txt": { "filename": "hello.txt", "contenttype": "text/plain", "len": 5 },
+        DIRECTORY_METADATA_FILENAME: {
+          "filename": DIRECTORY_METADATA_FILENAME,
+          "contenttype": "application/json",
+          "len": 64,
+        },
+      },
+      "defaultpath": "/hello.txt",
+    });
+    transport.push_response(MockResponse::new(StatusCode::OK, "hello")
+      .with_header("skynet-file-metadata", &metadata.to_string()));
+    transport.push_response(MockResponse::json(serde_json::json!({
+      "hello.txt": { "checksum": checksum(ChecksumAlgorithm::Sha256, b"tampered") },
+    })));
+    let client = transport.client("https://siasky.net");
+    let res = download_data(&client, "AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng", Default::default()).await;
+    assert!(matches!(res, Err(ChecksumMismatch(_, _))));
+    assert!(transport.last_request().unwrap().uri.path().ends_with(DIRECTORY_METADATA_FILENAME));
+  }
+
+  #[tokio::test]
+  async fn test_resolve_skylink_chain() {
+    use hex::ToHex;
+
+    let v1 = "AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng";
+    let resolver = |n| Skylink { bitfield: 1, merkle_root: [n; 32] }.to_string();
+    let (a, b) = (resolver(1), resolver(2));
+    let proof_entry = |target: &str, revision| {
+      let data = target.parse::<Skylink>().unwrap().to_bytes().encode_hex::<String>();
+      serde_json::json!({ "data": data, "revision": revision, "datakey": "00", "type": 1 })
+    };
+
+    let transport = MockTransport::new();
+    let proof = serde_json::json!([proof_entry(&b, 3), proof_entry(v1, 5)]).to_string();
+    for _ in 0..2 {
+      transport.push_response(MockResponse::new(StatusCode::OK, "")
+        .with_header("skynet-skylink", v1)
+        .with_header("skynet-proof", &proof));
+    }
+    let client = transport.client("https://siasky.net");
+
+    let chain = resolve_skylink_chain(&client, &a, MetadataOptions::default()).await.unwrap();
+    assert_eq!(chain, ResolutionChain {
+      skylink: v1.into(),
+      hops: vec![
+        ResolutionHop { resolver: a.clone(), target: b.clone(), revision: Some(3) },
+        ResolutionHop { resolver: b.clone(), target: v1.into(), revision: Some(5) },
+      ],
+    });
+
+    let opt = MetadataOptions { max_resolver_depth: 1, ..Default::default() };
+    let res = resolve_skylink_chain(&client, &a, opt).await;
+    assert!(matches!(res, Err(ResolverChainError(path)) if path == vec![a.clone(), b.clone()]));
+
+    // A portal that stops after one hop each time, with the second resolver pointing back.
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::new(StatusCode::OK, "").with_header("skynet-skylink", &b));
+    transport.push_response(MockResponse::new(StatusCode::OK, "").with_header("skynet-skylink", &a));
+    let client = transport.client("https://siasky.net");
+
+    let res = resolve_skylink_chain(&client, &a, MetadataOptions::default()).await;
+    assert!(matches!(res, Err(ResolverChainError(path)) if path == vec![a.clone(), b.clone(), a.clone()]));
+  }
+
   #[tokio::test]
   async fn test_get_metadata() {
-    let client = SkynetClient::default();
-    let skylink = "sia://AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng";
-    let res = get_metadata(&client, skylink, MetadataOptions::default()).await;
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let skylink = upload_hello(&client).await;
+    let res = get_metadata(&client, &skylink, MetadataOptions::default()).await;
     println!("{:?}", res);
     assert!(res.is_ok());
 
@@ -253,12 +1928,34 @@ mod tests {
       content_type: mime::TEXT_PLAIN,
     });
     assert_eq!(metadata, Metadata {
-      skylink: "AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng".into(),
-      portal_url: "https://siasky.net".into(),
+      skylink,
+      portal_url: portal.url().into(),
       content_type: Some(mime::TEXT_PLAIN),
       filename: Some("hello.txt".into()),
       length: Some(11),
       subfiles: Some(subfiles),
     });
   }
+
+  #[tokio::test]
+  async fn test_get_metadata_many() {
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let mut skylinks = Vec::new();
+    for i in 0..5 {
+      let mut data = HashMap::new();
+      data.insert(format!("{}.txt", i), (mime::TEXT_PLAIN, Bytes::from("x".repeat(i + 1))));
+      skylinks.push(client.upload_data(data, UploadOptions::default()).await.unwrap());
+    }
+    let missing = "AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng";
+    let mut requested: Vec<&str> = skylinks.iter().map(String::as_str).collect();
+    requested.insert(2, missing);
+
+    let opt = MetadataOptions { concurrency: 2, ..Default::default() };
+    let res = client.get_metadata_many(&requested, opt).await;
+    assert_eq!(res.len(), 6);
+    assert!(res[2].is_err());
+    let lengths: Vec<_> = res.iter().filter_map(|metadata| metadata.as_ref().ok()?.length).collect();
+    assert_eq!(lengths, vec![1, 2, 3, 4, 5]);
+  }
 }
@@ -0,0 +1,153 @@
+//! Sia's binary encoding, as skyd uses it for registry entries and skyfile layouts. Integers
+//! are little-endian `u64`s and byte strings are prefixed with their length as one.
+
+#[cfg(feature = "crypto")]
+use crypto::{blake2b::Blake2b, digest::Digest};
+
+/// Size of an encoded signature algorithm specifier.
+pub const SPECIFIER_SIZE: usize = 16;
+
+pub fn encode_u64(n: u64) -> [u8; 8] {
+  n.to_le_bytes()
+}
+
+/// `data` prefixed with its length.
+pub fn encode_bytes(data: &[u8]) -> Vec<u8> {
+  let mut encoded = Vec::with_capacity(8 + data.len());
+  encoded.extend_from_slice(&encode_u64(data.len() as u64));
+  encoded.extend_from_slice(data);
+  encoded
+}
+
+/// An algorithm name such as `ed25519`, zero-padded to `SPECIFIER_SIZE` bytes. Returns `None` if
+/// the name is longer than that.
+pub fn encode_specifier(name: &str) -> Option<[u8; SPECIFIER_SIZE]> {
+  let mut specifier = [0; SPECIFIER_SIZE];
+  specifier.get_mut(..name.len())?.copy_from_slice(name.as_bytes());
+  Some(specifier)
+}
+
+/// A public key as skyd encodes it: the algorithm specifier followed by the length-prefixed key.
+pub fn encode_public_key(algorithm: &str, key: &[u8]) -> Option<Vec<u8>> {
+  let mut encoded = encode_specifier(algorithm)?.to_vec();
+  encoded.extend_from_slice(&encode_bytes(key));
+  Some(encoded)
+}
+
+/// Reads a `u64` off the front of `data`, returning it and the rest.
+pub fn decode_u64(data: &[u8]) -> Option<(u64, &[u8])> {
+  if data.len() < 8 {
+    return None;
+  }
+  let (n, rest) = data.split_at(8);
+  let mut bytes = [0; 8];
+  bytes.copy_from_slice(n);
+  Some((u64::from_le_bytes(bytes), rest))
+}
+
+/// Reads a length-prefixed byte string off the front of `data`, returning it and the rest.
+pub fn decode_bytes(data: &[u8]) -> Option<(&[u8], &[u8])> {
+  let (len, rest) = decode_u64(data)?;
+  if (rest.len() as u64) < len {
+    return None;
+  }
+  Some(rest.split_at(len as usize))
+}
+
+#[cfg(feature = "crypto")]
+fn blake2b_256(data: &[u8]) -> [u8; 32] {
+  let mut hash = [0; 32];
+  let mut hasher = Blake2b::new(32);
+  hasher.input(data);
+  hasher.result(&mut hash);
+  hash
+}
+
+/// The tweak a registry entry is stored under for a plain-text data key: the hash of the
+/// length-prefixed key.
+#[cfg(feature = "crypto")]
+pub fn data_key_tweak(data_key: &str) -> [u8; 32] {
+  blake2b_256(&encode_bytes(data_key.as_bytes()))
+}
+
+/// The ID hosts store a registry entry under: the hash of the encoded public key and the tweak.
+/// It is also the merkle root of the entry's v2 skylink. Returns `None` if the algorithm name
+/// doesn't fit a specifier.
+#[cfg(feature = "crypto")]
+pub fn registry_entry_id(algorithm: &str, key: &[u8], tweak: &[u8; 32]) -> Option<[u8; 32]> {
+  let mut encoded = encode_public_key(algorithm, key)?;
+  encoded.extend_from_slice(tweak);
+  Some(blake2b_256(&encoded))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use proptest::prelude::*;
+
+  #[test]
+  fn test_encoding() {
+    assert_eq!(encode_u64(0x0102), [2, 1, 0, 0, 0, 0, 0, 0]);
+    assert_eq!(encode_bytes(b"abc"), [3, 0, 0, 0, 0, 0, 0, 0, b'a', b'b', b'c']);
+    assert_eq!(&encode_specifier("ed25519").unwrap()[..8], b"ed25519\0");
+    assert_eq!(encode_specifier("a-much-too-long-name"), None);
+    assert_eq!(decode_bytes(&[4, 0, 0, 0, 0, 0, 0, 0, 1, 2]), None);
+    assert_eq!(decode_u64(&[1, 2, 3]), None);
+  }
+
+  #[cfg(feature = "crypto")]
+  #[test]
+  fn test_registry_entry_id() {
+    let key = [0xab; 32];
+    let tweak = data_key_tweak("app");
+    assert_eq!(tweak, blake2b_256(&[3, 0, 0, 0, 0, 0, 0, 0, b'a', b'p', b'p']));
+
+    let mut preimage = b"ed25519\0\0\0\0\0\0\0\0\0".to_vec();
+    preimage.extend_from_slice(&[32, 0, 0, 0, 0, 0, 0, 0]);
+    preimage.extend_from_slice(&key);
+    preimage.extend_from_slice(&tweak);
+    assert_eq!(registry_entry_id("ed25519", &key, &tweak), Some(blake2b_256(&preimage)));
+    assert_eq!(registry_entry_id("a-much-too-long-name", &key, &tweak), None);
+
+    // Vectors from skynet-js's `hashDataKey` and `getEntryLink` tests.
+    assert_eq!(hex::encode(tweak), "7c96a0537ab2aaac9cfe0eca217732f4e10791625b4ab4c17e4d91c8078713b9");
+    let key = hex::decode("a1790331b8b41a94644d01a7b482564e7049047812364bcabc32d399ad23f7e2").unwrap();
+    let mut tweak = [0; 32];
+    hex::decode_to_slice("d321b3c31337047493c9b5a99675e9bdaea44218a31aad2fd7738209e7a5aca1", &mut tweak).unwrap();
+    assert_eq!(
+      registry_entry_id("ed25519", &key, &tweak).map(hex::encode),
+      Some("7bcc7543b43f8f8a4a000ffd33cc56d658f718f882682511d7149730a14f96bd".to_string()),
+    );
+  }
+
+  proptest! {
+    #[test]
+    fn prop_u64_roundtrip(n: u64, rest: Vec<u8>) {
+      let mut encoded = encode_u64(n).to_vec();
+      encoded.extend_from_slice(&rest);
+      prop_assert_eq!(decode_u64(&encoded), Some((n, &rest[..])));
+    }
+
+    #[test]
+    fn prop_bytes_roundtrip(data: Vec<u8>, rest: Vec<u8>) {
+      let mut encoded = encode_bytes(&data);
+      prop_assert_eq!(encoded.len(), 8 + data.len());
+      encoded.extend_from_slice(&rest);
+      prop_assert_eq!(decode_bytes(&encoded), Some((&data[..], &rest[..])));
+    }
+
+    #[test]
+    fn prop_truncated_bytes_rejected(data in proptest::collection::vec(any::<u8>(), 1..64), cut in 1usize..64) {
+      let encoded = encode_bytes(&data);
+      let cut = cut.min(data.len());
+      prop_assert_eq!(decode_bytes(&encoded[..encoded.len() - cut]), None);
+    }
+
+    #[test]
+    fn prop_specifier(name in "[a-z0-9]{0,16}") {
+      let specifier = encode_specifier(&name).unwrap();
+      prop_assert_eq!(&specifier[..name.len()], name.as_bytes());
+      prop_assert!(specifier[name.len()..].iter().all(|&b| b == 0));
+    }
+  }
+}
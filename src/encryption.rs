@@ -1,9 +1,9 @@
-use crate::{SkynetClient, SkynetError::*, SkynetResult, util::make_uri};
-use std::{collections::HashMap, str};
-use hyper::{body, Body, Request};
+use crate::{client::CallAuth, AuthScheme, SkynetClient, SkynetError::*, SkynetResult, util::{make_uri, parse_response, read_response, QueryParam, QueryParams}};
+use std::{str, sync::{Arc, RwLock}};
+use hyper::{Body, Request};
 use serde::Deserialize;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
 pub struct Skykey {
   skykey: String,
   name: String,
@@ -11,23 +11,90 @@ pub struct Skykey {
   r#type: String,
 }
 
-#[derive(Debug)]
+impl Skykey {
+  /// The key itself, encoded as the portal shares it.
+  pub fn skykey(&self) -> &str {
+    &self.skykey
+  }
+
+  pub fn name(&self) -> &str {
+    &self.name
+  }
+
+  pub fn id(&self) -> &str {
+    &self.id
+  }
+
+  /// The cipher the key is for, e.g. `private-id`.
+  pub fn key_type(&self) -> &str {
+    &self.r#type
+  }
+}
+
+/// Skykeys this client and its clones have fetched or created, by the portal they came from,
+/// as each portal holds keys of its own. Lookups by name or ID are answered from here without
+/// asking the portal, so `invalidate` a key that may have changed there.
+#[derive(Debug, Clone, Default)]
+pub struct SkykeyCache {
+  skykeys: Arc<RwLock<Vec<(String, Skykey)>>>,
+}
+
+/// Portal URLs as the cache keys them, so `https://siasky.net/` finds the keys of `https://siasky.net`.
+fn portal_key(portal_url: &str) -> &str {
+  portal_url.trim_end_matches('/')
+}
+
+impl SkykeyCache {
+  pub fn get_by_name(&self, portal_url: &str, name: &str) -> Option<Skykey> {
+    self.find(portal_url, |skykey| skykey.name == name)
+  }
+
+  pub fn get_by_id(&self, portal_url: &str, id: &str) -> Option<Skykey> {
+    self.find(portal_url, |skykey| skykey.id == id)
+  }
+
+  fn find(&self, portal_url: &str, matches: impl Fn(&Skykey) -> bool) -> Option<Skykey> {
+    let portal_url = portal_key(portal_url);
+    let skykeys = self.skykeys.read().unwrap();
+    skykeys.iter().find(|(portal, skykey)| portal == portal_url && matches(skykey)).map(|(_, skykey)| skykey.clone())
+  }
+
+  /// Replaces any key of the portal with the same name or ID.
+  pub(crate) fn insert(&self, portal_url: &str, skykey: Skykey) {
+    let portal_url = portal_key(portal_url);
+    let mut skykeys = self.skykeys.write().unwrap();
+    skykeys.retain(|(portal, cached)| portal != portal_url || (cached.name != skykey.name && cached.id != skykey.id));
+    skykeys.push((portal_url.to_string(), skykey));
+  }
+
+  /// Replaces every key of the portal with `skykeys`, as listed by it.
+  pub(crate) fn replace(&self, portal_url: &str, skykeys: &[Skykey]) {
+    let portal_url = portal_key(portal_url);
+    let mut cached = self.skykeys.write().unwrap();
+    cached.retain(|(portal, _)| portal != portal_url);
+    cached.extend(skykeys.iter().map(|skykey| (portal_url.to_string(), skykey.clone())));
+  }
+
+  /// Drops the portal's key with this name or ID, so the next lookup asks the portal.
+  pub fn invalidate(&self, portal_url: &str, name_or_id: &str) {
+    let portal_url = portal_key(portal_url);
+    let mut skykeys = self.skykeys.write().unwrap();
+    skykeys.retain(|(portal, skykey)| portal != portal_url || (skykey.name != name_or_id && skykey.id != name_or_id));
+  }
+
+  pub fn clear(&self) {
+    self.skykeys.write().unwrap().clear();
+  }
+}
+
+#[derive(Debug, Default)]
 pub struct SkykeyOptions {
   pub endpoint_path: Option<String>,
   pub api_key: Option<String>,
+  pub auth: Option<AuthScheme>,
   pub custom_user_agent: Option<String>,
 }
 
-impl Default for SkykeyOptions {
-  fn default() -> Self {
-    Self {
-      endpoint_path: None,
-      api_key: None,
-      custom_user_agent: None,
-    }
-  }
-}
-
 pub async fn add_skykey(
   client: &SkynetClient,
   skykey: &str,
@@ -35,7 +102,7 @@ pub async fn add_skykey(
 ) -> SkynetResult<()> {
   let req = Request::builder().method("POST");
 
-  let mut query = HashMap::new();
+  let mut query = QueryParams::new();
 
   let endpoint_path = if let Some(endpoint_path) = opt.endpoint_path {
     endpoint_path
@@ -43,10 +110,10 @@ pub async fn add_skykey(
     "/skynet/addskykey".into()
   };
 
-  query.insert("skykey".into(), skykey.into());
+  query.set(QueryParam::Skykey, skykey);
 
   let uri = make_uri(
-    client.get_portal_url(),
+    &client.get_portal_url(),
     endpoint_path,
     opt.api_key,
     None,
@@ -58,8 +125,10 @@ pub async fn add_skykey(
     req = req.header("User-Agent", custom_user_agent);
   }
 
+  req = req.extension(CallAuth(opt.auth.clone()));
+
   let req = req.body(Body::from("")).map_err(HttpError)?;
-  client.http.request(req).await.map_err(HyperError)?;
+  client.request(req).await?;
 
   Ok(())
 }
@@ -72,7 +141,7 @@ pub async fn create_skykey(
 ) -> SkynetResult<Skykey> {
   let req = Request::builder().method("POST");
 
-  let mut query = HashMap::new();
+  let mut query = QueryParams::new();
 
   let endpoint_path = if let Some(endpoint_path) = opt.endpoint_path {
     endpoint_path
@@ -80,40 +149,50 @@ pub async fn create_skykey(
     "/skynet/createskykey".into()
   };
 
-  query.insert("name".into(), name.into());
-  query.insert("type".into(), skykey_type.into());
+  query.set(QueryParam::Name, name);
+  query.set(QueryParam::Type, skykey_type);
 
+  let portal_url = client.get_portal_url();
   let uri = make_uri(
-    client.get_portal_url(),
+    &portal_url,
     endpoint_path,
     opt.api_key,
     None,
     query);
 
-  let mut req = req.uri(uri);
+  let mut req = req.uri(uri.clone());
 
   if let Some(custom_user_agent) = opt.custom_user_agent {
     req = req.header("User-Agent", custom_user_agent);
   }
 
+  req = req.extension(CallAuth(opt.auth.clone()));
+
   let req = req.body(Body::from("")).map_err(HttpError)?;
-  let res = client.http.request(req).await.map_err(HyperError)?;
-  let body = body::to_bytes(res.into_body()).await.map_err(HyperError)?;
+  let res = client.request(req).await?;
+  let status = res.status();
+  let body = read_response(res).await?;
   let body_str = str::from_utf8(&body).map_err(Utf8Error)?;
-  let skykey: Skykey = serde_json::from_str(body_str)
-    .map_err(|_| PortalResponse(body_str.to_string()))?;
+  let skykey: Skykey = parse_response(&uri, status, body_str)?;
+  client.skykeys().insert(&portal_url, skykey.clone());
 
   Ok(skykey)
 }
 
+/// Answers from the client's skykey cache if it has the name.
 pub async fn get_skykey_by_name(
   client: &SkynetClient,
   name: &str,
   opt: SkykeyOptions,
 ) -> SkynetResult<Skykey> {
+  let portal_url = client.get_portal_url();
+  if let Some(skykey) = client.skykeys().get_by_name(&portal_url, name) {
+    return Ok(skykey);
+  }
+
   let req = Request::builder().method("GET");
 
-  let mut query = HashMap::new();
+  let mut query = QueryParams::new();
 
   let endpoint_path = if let Some(endpoint_path) = opt.endpoint_path {
     endpoint_path
@@ -121,39 +200,48 @@ pub async fn get_skykey_by_name(
     "/skynet/skykey".into()
   };
 
-  query.insert("name".into(), name.into());
+  query.set(QueryParam::Name, name);
 
   let uri = make_uri(
-    client.get_portal_url(),
+    &portal_url,
     endpoint_path,
     opt.api_key,
     None,
     query);
 
-  let mut req = req.uri(uri);
+  let mut req = req.uri(uri.clone());
 
   if let Some(custom_user_agent) = opt.custom_user_agent {
     req = req.header("User-Agent", custom_user_agent);
   }
 
+  req = req.extension(CallAuth(opt.auth.clone()));
+
   let req = req.body(Body::from("")).map_err(HttpError)?;
-  let res = client.http.request(req).await.map_err(HyperError)?;
-  let body = body::to_bytes(res.into_body()).await.map_err(HyperError)?;
+  let res = client.request(req).await?;
+  let status = res.status();
+  let body = read_response(res).await?;
   let body_str = str::from_utf8(&body).map_err(Utf8Error)?;
-  let skykey: Skykey = serde_json::from_str(body_str)
-    .map_err(|_| PortalResponse(body_str.to_string()))?;
+  let skykey: Skykey = parse_response(&uri, status, body_str)?;
+  client.skykeys().insert(&portal_url, skykey.clone());
 
   Ok(skykey)
 }
 
+/// Answers from the client's skykey cache if it has the ID.
 pub async fn get_skykey_by_id(
   client: &SkynetClient,
   id: &str,
   opt: SkykeyOptions,
 ) -> SkynetResult<Skykey> {
+  let portal_url = client.get_portal_url();
+  if let Some(skykey) = client.skykeys().get_by_id(&portal_url, id) {
+    return Ok(skykey);
+  }
+
   let req = Request::builder().method("GET");
 
-  let mut query = HashMap::new();
+  let mut query = QueryParams::new();
 
   let endpoint_path = if let Some(endpoint_path) = opt.endpoint_path {
     endpoint_path
@@ -161,38 +249,42 @@ pub async fn get_skykey_by_id(
     "/skynet/skykey".into()
   };
 
-  query.insert("id".into(), id.into());
+  query.set(QueryParam::Id, id);
 
   let uri = make_uri(
-    client.get_portal_url(),
+    &portal_url,
     endpoint_path,
     opt.api_key,
     None,
     query);
 
-  let mut req = req.uri(uri);
+  let mut req = req.uri(uri.clone());
 
   if let Some(custom_user_agent) = opt.custom_user_agent {
     req = req.header("User-Agent", custom_user_agent);
   }
 
+  req = req.extension(CallAuth(opt.auth.clone()));
+
   let req = req.body(Body::from("")).map_err(HttpError)?;
-  let res = client.http.request(req).await.map_err(HyperError)?;
-  let body = body::to_bytes(res.into_body()).await.map_err(HyperError)?;
+  let res = client.request(req).await?;
+  let status = res.status();
+  let body = read_response(res).await?;
   let body_str = str::from_utf8(&body).map_err(Utf8Error)?;
-  let skykey: Skykey = serde_json::from_str(body_str)
-    .map_err(|_| PortalResponse(body_str.to_string()))?;
+  let skykey: Skykey = parse_response(&uri, status, body_str)?;
+  client.skykeys().insert(&portal_url, skykey.clone());
 
   Ok(skykey)
 }
 
+/// Lists the portal's skykeys, replacing the client's cached keys of that portal with them.
 pub async fn get_skykeys(
   client: &SkynetClient,
   opt: SkykeyOptions,
 ) -> SkynetResult<Vec<Skykey>> {
   let req = Request::builder().method("GET");
 
-  let query = HashMap::new();
+  let query = QueryParams::new();
 
   let endpoint_path = if let Some(endpoint_path) = opt.endpoint_path {
     endpoint_path
@@ -200,25 +292,66 @@ pub async fn get_skykeys(
     "/skynet/skykeys".into()
   };
 
+  let portal_url = client.get_portal_url();
   let uri = make_uri(
-    client.get_portal_url(),
+    &portal_url,
     endpoint_path,
     opt.api_key,
     None,
     query);
 
-  let mut req = req.uri(uri);
+  let mut req = req.uri(uri.clone());
 
   if let Some(custom_user_agent) = opt.custom_user_agent {
     req = req.header("User-Agent", custom_user_agent);
   }
 
+  req = req.extension(CallAuth(opt.auth.clone()));
+
   let req = req.body(Body::from("")).map_err(HttpError)?;
-  let res = client.http.request(req).await.map_err(HyperError)?;
-  let body = body::to_bytes(res.into_body()).await.map_err(HyperError)?;
+  let res = client.request(req).await?;
+  let status = res.status();
+  let body = read_response(res).await?;
   let body_str = str::from_utf8(&body).map_err(Utf8Error)?;
-  let skykey: Vec<Skykey> = serde_json::from_str(body_str)
-    .map_err(|_| PortalResponse(body_str.to_string()))?;
+  let skykeys: Vec<Skykey> = parse_response(&uri, status, body_str)?;
+  client.skykeys().replace(&portal_url, &skykeys);
 
-  Ok(skykey)
+  Ok(skykeys)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{MockResponse, MockTransport};
+  use serde_json::json;
+
+  #[tokio::test]
+  async fn test_skykey_cache() {
+    let transport = MockTransport::new();
+    let skykey = json!({ "skykey": "skykey:AbC", "name": "photos", "id": "pJAPPfWkWXpss3BvMDCJCw==", "type": "private-id" });
+    transport.push_response(MockResponse::json(skykey.clone()));
+    transport.push_response(MockResponse::json(skykey));
+    let client = transport.client("https://siasky.net");
+
+    let first = get_skykey_by_name(&client, "photos", SkykeyOptions::default()).await.unwrap();
+    let second = get_skykey_by_name(&client, "photos", SkykeyOptions::default()).await.unwrap();
+    let by_id = get_skykey_by_id(&client, "pJAPPfWkWXpss3BvMDCJCw==", SkykeyOptions::default()).await.unwrap();
+    assert_eq!(first, second);
+    assert_eq!(first, by_id);
+    assert_eq!(first.key_type(), "private-id");
+    assert_eq!(transport.requests().len(), 1);
+
+    assert_eq!(client.skykeys().get_by_id("https://siasky.net/", first.id()), Some(first.clone()));
+    assert_eq!(client.skykeys().get_by_id("https://skyportal.xyz", first.id()), None);
+
+    client.skykeys().invalidate("https://siasky.net", "photos");
+    assert_eq!(client.skykeys().get_by_id("https://siasky.net", first.id()), None);
+    get_skykey_by_name(&client, "photos", SkykeyOptions::default()).await.unwrap();
+    assert_eq!(transport.requests().len(), 2);
+
+    // Another portal's key of the same name isn't answered from the cache.
+    client.set_portal_url("https://skyportal.xyz");
+    assert!(get_skykey_by_name(&client, "photos", SkykeyOptions::default()).await.is_err());
+    assert_eq!(transport.requests().len(), 3);
+  }
 }
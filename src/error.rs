@@ -1,3 +1,5 @@
+use std::fmt;
+
 #[derive(Debug)]
 pub enum SkynetError {
   NoCustomDirname,
@@ -6,13 +8,199 @@ pub enum SkynetError {
   TextNonceError(String),
   WriteError(std::io::Error),
   FileError(std::io::Error),
+  #[cfg(feature = "tus")]
   TUSError(tus_async_client::Error),
   HttpError(http::Error),
   HyperError(hyper::Error),
+  /// The TLS backend couldn't be set up for a transport.
+  TlsError(native_tls::Error),
+  #[cfg(feature = "tus")]
   ReqwestError(reqwest::Error),
   Utf8Error(std::str::Utf8Error),
   PortalResponse(String),
   InvalidSignature,
+  DecryptionError,
+  JsonError(serde_json::Error),
+  InvalidSkylink(String),
+  InvalidUserId(String),
+  /// A MySky path, or a path seed to derive from, that is empty or malformed.
+  InvalidPath(String),
+  /// A public key, in hex, that isn't a valid ed25519 point.
+  InvalidPublicKey(String),
+  NotCached(String),
+  /// The portal refused to serve a skylink, e.g. because it is blocked or requires an account.
+  AccessDenied(String),
+  /// The client's blocklist has the skylink, so it wasn't served.
+  Blocked(String),
+  /// A keystore file that isn't in the expected format.
+  InvalidKeystore(String),
+  /// A hash that isn't 32 bytes of hex.
+  InvalidHash(String),
+  /// An upload was refused before sending: its size and the limit it exceeds.
+  UploadTooLarge(u64, u64),
+  /// An `UploadHook` refused a file, with its reason.
+  UploadRejected(String),
+  /// Registry entry data was refused before sending: its size and the most an entry holds.
+  EntryDataTooLarge(usize, usize),
+  /// Downloaded data didn't match its checksum: the expected and the actual checksum.
+  ChecksumMismatch(String, String),
+  /// Stored data didn't match the type or schema version it was read as.
+  SchemaError(String),
+  /// The portal rejected a registry update because its revision wasn't higher than the stored one.
+  RevisionConflict(String),
+  /// A resolver skylink chain looped or was longer than allowed. Carries the resolvers visited,
+  /// ending with the one that wasn't followed.
+  ResolverChainError(Vec<String>),
+  /// A portal request failed, sending it or reading its response. Carries where it was going.
+  RequestError(RequestContext, Box<SkynetError>),
+  /// `init_global_client` was called after the global client was already set or used.
+  GlobalClientInitialized,
+  /// None of the candidate portals passed its health check.
+  NoHealthyPortal(Vec<String>),
+  /// A download wouldn't fit on disk: the bytes it needs and those available.
+  InsufficientSpace(u64, u64),
+  /// A call didn't finish within the deadline set in its options.
+  DeadlineExceeded(std::time::Duration),
+  /// The client was shut down with `SkynetClient::shutdown`.
+  ClientShutdown,
+  /// Work handed to tokio's blocking pool was cancelled, as happens when the runtime shuts down.
+  TaskCancelled,
+  #[cfg(feature = "ledger")]
+  LedgerError(sled::Error),
+  #[cfg(feature = "gzip")]
+  GzipError(std::io::Error),
+}
+
+impl SkynetError {
+  /// The ID of the portal request that failed, for correlating with portal-side logs.
+  pub fn request_id(&self) -> Option<&str> {
+    match self {
+      Self::RequestError(context, _) => context.request_id.as_deref(),
+      _ => None,
+    }
+  }
+
+  /// The portal, endpoint and operation of the request that failed.
+  pub fn context(&self) -> Option<&RequestContext> {
+    match self {
+      Self::RequestError(context, _) => Some(context),
+      _ => None,
+    }
+  }
+
+  /// The error a `RequestError` carries, or this one, for matching on what went wrong whichever
+  /// request it came from.
+  pub fn into_inner(self) -> SkynetError {
+    match self {
+      Self::RequestError(_, err) => err.into_inner(),
+      err => err,
+    }
+  }
+
+  /// Whether the failure was a transient connection problem (reset, broken pipe or a failed
+  /// connect, which includes temporary DNS failures) that an idempotent request can retry.
+  pub fn is_retryable(&self) -> bool {
+    match self {
+      Self::HyperError(err) => {
+        err.is_connect() || err.is_incomplete_message() || is_transient_io_error(err)
+      }
+      #[cfg(feature = "tus")]
+      Self::ReqwestError(err) => err.is_connect() || is_transient_io_error(err),
+      Self::RequestError(_, err) => err.is_retryable(),
+      _ => false,
+    }
+  }
+}
+
+/// The kind of call a portal request was made for, told from its endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Operation {
+  Upload,
+  Download,
+  Registry,
+  /// Accounts, skykeys, pinning and health checks.
+  Other,
+}
+
+impl Operation {
+  pub(crate) fn from_path(path: &str) -> Self {
+    let under = |prefix: &str| path == prefix || path.starts_with(&format!("{}/", prefix));
+    if under("/skynet/skyfile") || under("/skynet/tus") {
+      Self::Upload
+    } else if under("/skynet/registry") {
+      Self::Registry
+    } else if under("/skynet/metadata") {
+      Self::Download
+    } else if under("/skynet") || under("/user") || under("/health-check") {
+      Self::Other
+    } else {
+      // Skylinks and HNS names are fetched from the portal root.
+      Self::Download
+    }
+  }
+}
+
+impl fmt::Display for Operation {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.write_str(match self {
+      Self::Upload => "upload",
+      Self::Download => "download",
+      Self::Registry => "registry",
+      Self::Other => "request",
+    })
+  }
+}
+
+/// Where a failed portal request was going, so that an error surfacing far from the call that
+/// made it still says which portal and endpoint it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequestContext {
+  pub operation: Operation,
+  /// The scheme and host of the portal, e.g. `https://siasky.net`.
+  pub portal_url: String,
+  /// The endpoint path. The query is left out, as it can carry an API key.
+  pub endpoint: String,
+  /// The ID sent in the request's `X-Request-ID` header. TUS uploads don't send one.
+  pub request_id: Option<String>,
+}
+
+impl RequestContext {
+  pub(crate) fn new(uri: &http::Uri, request_id: Option<&str>) -> Self {
+    let portal_url = match (uri.scheme_str(), uri.authority()) {
+      (Some(scheme), Some(authority)) => format!("{}://{}", scheme, authority),
+      _ => String::new(),
+    };
+    Self {
+      operation: Operation::from_path(uri.path()),
+      portal_url,
+      endpoint: uri.path().to_string(),
+      request_id: request_id.map(str::to_string),
+    }
+  }
+}
+
+impl fmt::Display for RequestContext {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{} {}{}", self.operation, self.portal_url, self.endpoint)?;
+    if let Some(request_id) = &self.request_id {
+      write!(f, " (request {})", request_id)?;
+    }
+    Ok(())
+  }
+}
+
+fn is_transient_io_error(err: &(dyn std::error::Error + 'static)) -> bool {
+  use std::io::ErrorKind::*;
+
+  let mut source = err.source();
+  while let Some(err) = source {
+    if let Some(err) = err.downcast_ref::<std::io::Error>() {
+      return matches!(err.kind(), ConnectionReset | ConnectionAborted | BrokenPipe);
+    }
+    source = err.source();
+  }
+  false
 }
 
 pub type SkynetResult<T> = Result<T, SkynetError>;
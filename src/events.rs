@@ -0,0 +1,20 @@
+/// How many events a subscriber can fall behind by before it misses the oldest ones.
+pub(crate) const EVENT_CAPACITY: usize = 256;
+
+/// Progress of the client's operations, as seen by subscribers of `SkynetClient::events`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Event {
+  /// A multipart or tus upload is about to be sent.
+  UploadStarted { files: usize, size: u64 },
+  /// A part of a multipart upload body was handed to the connection. `sent` counts from the
+  /// start of the body, and starts over if the upload is retried.
+  ChunkSent { sent: u64, total: u64 },
+  UploadCompleted { skylink: String, size: u64 },
+  /// A download was read in full. `size` is before any decompression.
+  DownloadCompleted { skylink: String, size: u64 },
+  /// A request failed with a transient error and is about to be sent again.
+  RetryScheduled { request_id: String, attempt: u32, error: String },
+  /// An `auto` client moved to another portal after failures or a health probe.
+  PortalSwitched { from: String, to: String },
+}
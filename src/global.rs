@@ -0,0 +1,95 @@
+use crate::{
+  DownloadOptions, Metadata, MetadataOptions, SkynetClient, SkynetError::*, SkynetResult, UploadOptions,
+};
+use std::{
+  collections::HashMap,
+  path::{Path, PathBuf},
+  sync::OnceLock,
+};
+use bytes::Bytes;
+use mime::Mime;
+#[cfg(feature = "directory")]
+use crate::DirectoryUploadResult;
+
+static GLOBAL_CLIENT: OnceLock<SkynetClient> = OnceLock::new();
+
+/// Sets the client used by the crate-level functions such as `skynet::upload_file`. This only
+/// works once, and only before any of them has run: they fall back to `SkynetClient::default()`
+/// on first use.
+pub fn init_global_client(client: SkynetClient) -> SkynetResult<()> {
+  GLOBAL_CLIENT.set(client).map_err(|_| GlobalClientInitialized)
+}
+
+/// The client used by the crate-level functions, created with defaults if it wasn't set.
+pub fn global_client() -> &'static SkynetClient {
+  GLOBAL_CLIENT.get_or_init(SkynetClient::default)
+}
+
+pub async fn upload_data<B: Into<Bytes>>(
+  data: HashMap<String, (Mime, B)>,
+  opt: UploadOptions,
+) -> SkynetResult<String> {
+  global_client().upload_data(data, opt).await
+}
+
+pub async fn upload_file<P: AsRef<Path>>(path: P, opt: UploadOptions) -> SkynetResult<String> {
+  global_client().upload_file(path, opt).await
+}
+
+#[cfg(feature = "directory")]
+pub async fn upload_directory<P: AsRef<Path>>(path: P, opt: UploadOptions) -> SkynetResult<String> {
+  global_client().upload_directory(path, opt).await
+}
+
+#[cfg(feature = "directory")]
+pub async fn upload_directory_manifest<P: AsRef<Path>>(
+  path: P,
+  opt: UploadOptions,
+) -> SkynetResult<DirectoryUploadResult> {
+  global_client().upload_directory_manifest(path, opt).await
+}
+
+pub async fn download_data(skylink: &str, opt: DownloadOptions) -> SkynetResult<Bytes> {
+  global_client().download_data(skylink, opt).await
+}
+
+pub async fn download_file<P: AsRef<Path>>(
+  path: P,
+  skylink: &str,
+  opt: DownloadOptions,
+) -> SkynetResult<PathBuf> {
+  global_client().download_file(path, skylink, opt).await
+}
+
+#[cfg(feature = "directory")]
+pub async fn download_directory<P: AsRef<Path>>(
+  path: P,
+  skylink: &str,
+  opt: DownloadOptions,
+) -> SkynetResult<()> {
+  global_client().download_directory(path, skylink, opt).await
+}
+
+pub async fn get_metadata(skylink: &str, opt: MetadataOptions) -> SkynetResult<Metadata> {
+  global_client().get_metadata(skylink, opt).await
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::MockPortal;
+
+  #[tokio::test]
+  async fn test_global_client() {
+    let portal = MockPortal::start().await;
+    init_global_client(portal.client()).unwrap();
+    assert!(matches!(init_global_client(SkynetClient::default()), Err(GlobalClientInitialized)));
+    assert_eq!(global_client().get_portal_url(), portal.url());
+
+    let mut data = HashMap::new();
+    data.insert("hello.txt".to_string(), (mime::TEXT_PLAIN, "hello world"));
+    let skylink = upload_data(data, UploadOptions::default()).await.unwrap();
+    let data = download_data(&skylink, DownloadOptions::default()).await.unwrap();
+    assert_eq!(data, "hello world");
+  }
+}
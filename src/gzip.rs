@@ -0,0 +1,51 @@
+use crate::{SkynetError::*, SkynetResult};
+use std::io::{Read, Write};
+use bytes::Bytes;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use hyper::HeaderMap;
+use mime::Mime;
+
+pub(crate) const GZIP_EXTENSION: &str = ".gz";
+
+pub(crate) fn gzip_mime() -> Mime {
+  "application/gzip".parse().unwrap()
+}
+
+pub(crate) fn compress(data: &[u8]) -> SkynetResult<Bytes> {
+  let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+  encoder.write_all(data).map_err(WriteError)?;
+  Ok(encoder.finish().map_err(WriteError)?.into())
+}
+
+pub(crate) fn decompress(data: &[u8]) -> SkynetResult<Bytes> {
+  let mut decoded = Vec::new();
+  GzDecoder::new(data).read_to_end(&mut decoded).map_err(GzipError)?;
+  Ok(decoded.into())
+}
+
+/// Whether a portal response carries gzip data, either as a content-encoding or as a `.gz` skyfile.
+pub(crate) fn is_gzipped(headers: &HeaderMap) -> bool {
+  let header_is = |name: &str, values: &[&str]| {
+    headers
+      .get(name)
+      .and_then(|value| value.to_str().ok())
+      .map(|value| values.iter().any(|v| value.eq_ignore_ascii_case(v)))
+      .unwrap_or(false)
+  };
+
+  header_is("content-encoding", &["gzip"])
+    || header_is("content-type", &["application/gzip", "application/x-gzip"])
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_gzip_roundtrip() {
+    let data = "hello world ".repeat(100);
+    let compressed = compress(data.as_bytes()).unwrap();
+    assert!(compressed.len() < data.len());
+    assert_eq!(decompress(&compressed).unwrap(), data.as_bytes());
+  }
+}
@@ -0,0 +1,374 @@
+use crate::{
+  client::CallAuth,
+  download,
+  skylink::normalize_skylink,
+  util::{make_uri, parse_response, read_body, read_response, status_error, QueryParams},
+  AuthScheme, DownloadOptions, SkynetClient, SkynetError::*, SkynetResult,
+};
+use std::{path::Path, str};
+use bytes::Bytes;
+use hyper::{Body, Method, Request, Response, Uri};
+use serde::Deserialize;
+#[cfg(feature = "registry")]
+use crate::{registry, EntryOptions, PublicKey};
+#[cfg(feature = "registry")]
+use hex::FromHex;
+
+const HNS_URI_PREFIX: &str = "hns://";
+
+/// Splits `hns://<domain>/<path>`, with or without the scheme, into the domain and the path.
+fn split_hns_uri(uri: &str) -> (&str, &str) {
+  let uri = uri.trim_start_matches(HNS_URI_PREFIX).trim_start_matches('/');
+  uri.split_once('/').unwrap_or((uri, ""))
+}
+
+/// How `download_hns` addresses a domain on the portal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HnsAddressing {
+  /// `https://<portal>/hns/<domain>/<path>`, under `download_endpoint_path`.
+  Path,
+  /// `https://<domain>.hns.<portal>/<path>`, for portals that block the path form.
+  Subdomain,
+}
+
+#[derive(Debug, Clone)]
+pub struct HnsOptions {
+  pub endpoint_path: String,
+  pub download_endpoint_path: String,
+  pub addressing: HnsAddressing,
+  pub api_key: Option<String>,
+  pub auth: Option<AuthScheme>,
+  pub custom_user_agent: Option<String>,
+}
+
+impl Default for HnsOptions {
+  fn default() -> Self {
+    Self {
+      endpoint_path: "/hnsres".into(),
+      download_endpoint_path: "/hns".into(),
+      addressing: HnsAddressing::Path,
+      api_key: None,
+      auth: None,
+      custom_user_agent: None,
+    }
+  }
+}
+
+/// What a Handshake domain's skylink TXT record points at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HnsResolution {
+  /// A fixed skylink.
+  Skylink(String),
+  /// A registry entry holding the skylink, so the content can change without touching the
+  /// domain. `public_key` is hex and `data_key` is the hashed data key in hex.
+  Registry { public_key: String, data_key: String },
+}
+
+impl HnsResolution {
+  /// The skylink the domain currently serves, reading the registry entry if there is one.
+  #[cfg(feature = "registry")]
+  pub async fn resolve_skylink(&self, client: &SkynetClient, opt: EntryOptions) -> SkynetResult<String> {
+    match self {
+      Self::Skylink(skylink) => Ok(skylink.clone()),
+      Self::Registry { public_key, data_key } => {
+        let public_key = <[u8; 32]>::from_hex(public_key)
+          .map(PublicKey::Ed25519)
+          .map_err(|_| PortalResponse(format!("invalid HNS registry public key {}", public_key)))?;
+        let opt = EntryOptions { hashed_data_key_hex: true, ..opt };
+        let signed = registry::get_registry_entry(client, &public_key, data_key, opt).await?;
+        registry::parse_pointer(&signed.entry.data)
+      }
+    }
+  }
+}
+
+#[derive(Deserialize)]
+struct HnsResponse {
+  skylink: Option<String>,
+  registry: Option<HnsRegistryResponse>,
+}
+
+#[derive(Deserialize)]
+struct HnsRegistryResponse {
+  publickey: String,
+  datakey: String,
+}
+
+pub async fn resolve_hns(
+  client: &SkynetClient,
+  domain: &str,
+  opt: HnsOptions,
+) -> SkynetResult<HnsResolution> {
+  let domain = domain.trim_start_matches(HNS_URI_PREFIX).trim_end_matches('/');
+  let uri = make_uri(
+    &client.get_portal_url(),
+    opt.endpoint_path,
+    opt.api_key,
+    Some(domain.to_string()),
+    QueryParams::new());
+
+  let mut req = Request::builder().method("GET").uri(uri.clone());
+
+  if let Some(custom_user_agent) = opt.custom_user_agent {
+    req = req.header("User-Agent", custom_user_agent);
+  }
+
+  req = req.extension(CallAuth(opt.auth.clone()));
+
+  let req = req.body(Body::empty()).map_err(HttpError)?;
+  let res = client.request(req).await?;
+  let status = res.status();
+  let body = read_response(res).await?;
+  let body_str = str::from_utf8(&body).map_err(Utf8Error)?;
+  let res: HnsResponse = parse_response(&uri, status, body_str)?;
+
+  match (res.skylink, res.registry) {
+    (Some(skylink), _) => Ok(HnsResolution::Skylink(normalize_skylink(&skylink)?)),
+    (None, Some(registry)) => Ok(HnsResolution::Registry {
+      public_key: registry.publickey.trim_start_matches("ed25519:").to_string(),
+      data_key: registry.datakey,
+    }),
+    (None, None) => Err(PortalResponse(format!("{} resolves to neither a skylink nor a registry entry", domain))),
+  }
+}
+
+/// Downloads `path` from the content a Handshake domain serves, through the portal's HNS
+/// gateway. An empty `path` fetches the domain's default file.
+pub async fn download_hns(
+  client: &SkynetClient,
+  domain: &str,
+  path: &str,
+  opt: HnsOptions,
+) -> SkynetResult<Bytes> {
+  let (uri, res) = gateway_request(client, Method::GET, domain, path, opt).await?;
+  let (parts, body) = res.into_parts();
+  // The domain can point at a blocked skylink, which only the response tells.
+  #[cfg(feature = "blocklist")]
+  if let Some(skylink) = parts.headers.get("skynet-skylink").and_then(|skylink| skylink.to_str().ok()) {
+    download::check_blocklist(client, skylink)?;
+  }
+  let body = read_body(&parts, body).await?;
+
+  if !parts.status.is_success() {
+    return Err(status_error(&parts.extensions, &uri, parts.status, &body));
+  }
+
+  Ok(body)
+}
+
+/// Sends a request for `path` under a domain to the portal's HNS gateway.
+async fn gateway_request(
+  client: &SkynetClient,
+  method: Method,
+  domain: &str,
+  path: &str,
+  opt: HnsOptions,
+) -> SkynetResult<(Uri, Response<Body>)> {
+  let domain = domain.trim_matches('/');
+  let path = path.trim_start_matches('/');
+
+  // In subdomain mode the domain goes in the authority, which hyper also sends as the Host.
+  let uri = match opt.addressing {
+    HnsAddressing::Path => {
+      let extra_path = if path.is_empty() { domain.to_string() } else { format!("{}/{}", domain, path) };
+      make_uri(&client.get_portal_url(), opt.download_endpoint_path, opt.api_key, Some(extra_path), QueryParams::new())
+    }
+    HnsAddressing::Subdomain => {
+      let portal_url = client.get_portal_url();
+      let (scheme, host) = portal_url.split_once("://").unwrap_or(("https", portal_url.as_str()));
+      let portal_url = format!("{}://{}.hns.{}", scheme, domain, host);
+      make_uri(&portal_url, String::new(), opt.api_key, Some(path.to_string()), QueryParams::new())
+    }
+  };
+
+  let mut req = Request::builder().method(method).uri(uri.clone());
+
+  if let Some(custom_user_agent) = opt.custom_user_agent {
+    req = req.header("User-Agent", custom_user_agent);
+  }
+
+  req = req.extension(CallAuth(opt.auth));
+
+  let req = req.body(Body::empty()).map_err(HttpError)?;
+  let res = client.request(req).await?;
+  Ok((uri, res))
+}
+
+/// `download_hns` for a `hns://<domain>/<path>` URI. The scheme and path are optional.
+pub async fn download_data_hns(client: &SkynetClient, uri: &str, opt: HnsOptions) -> SkynetResult<Bytes> {
+  let (domain, path) = split_hns_uri(uri);
+  download_hns(client, domain, path, opt).await
+}
+
+/// Saves the file an `hns://` URI names to `path`. The gateway is only asked which skylink the
+/// domain serves; the file itself comes through `download_file`.
+pub async fn download_file_hns<P: AsRef<Path>>(
+  client: &SkynetClient,
+  path: P,
+  uri: &str,
+  opt: HnsOptions,
+) -> SkynetResult<()> {
+  let (domain, subpath) = split_hns_uri(uri);
+  let download_opt = DownloadOptions {
+    api_key: opt.api_key.clone(),
+    auth: opt.auth.clone(),
+    custom_user_agent: opt.custom_user_agent.clone(),
+    ..Default::default()
+  };
+
+  // The gateway names the skylink it serves, which `download_file` then fetches like any other.
+  let (gateway_uri, res) = gateway_request(client, Method::HEAD, domain, "", opt).await?;
+  if !res.status().is_success() {
+    return Err(status_error(res.extensions(), &gateway_uri, res.status(), b""));
+  }
+  let skylink = res
+    .headers()
+    .get("skynet-skylink")
+    .and_then(|skylink| skylink.to_str().ok())
+    .ok_or_else(|| PortalResponse(format!("{} was not resolved to a skylink", domain)))?;
+  let skylink = match subpath.trim_matches('/') {
+    "" => skylink.to_string(),
+    subpath => format!("{}/{}", skylink, subpath),
+  };
+
+  download::download_file(client, path, &skylink, download_opt).await.map(|_| ())
+}
+
+/// Resolves `domain` all the way to a skylink.
+#[cfg(feature = "registry")]
+pub async fn resolve_hns_skylink(
+  client: &SkynetClient,
+  domain: &str,
+  opt: HnsOptions,
+) -> SkynetResult<String> {
+  let entry_opt = EntryOptions {
+    api_key: opt.api_key.clone(),
+    auth: opt.auth.clone(),
+    custom_user_agent: opt.custom_user_agent.clone(),
+    ..Default::default()
+  };
+  resolve_hns(client, domain, opt).await?.resolve_skylink(client, entry_opt).await
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{MockResponse, MockTransport};
+  use serde_json::json;
+
+  const SKYLINK: &str = "AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng";
+
+  #[tokio::test]
+  async fn test_resolve_hns() {
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::json(json!({ "skylink": format!("sia://{}", SKYLINK) })));
+    transport.push_response(MockResponse::json(json!({
+      "registry": { "publickey": format!("ed25519:{}", "ab".repeat(32)), "datakey": "cd".repeat(32) },
+    })));
+    transport.push_response(MockResponse::json(json!({ "message": "no records" })));
+    let client = transport.client("https://siasky.net");
+
+    let res = resolve_hns(&client, "skyapp", HnsOptions::default()).await.unwrap();
+    assert_eq!(res, HnsResolution::Skylink(SKYLINK.into()));
+    assert_eq!(transport.last_request().unwrap().uri.path(), "/hnsres/skyapp");
+
+    let res = resolve_hns(&client, "skyapp", HnsOptions::default()).await.unwrap();
+    assert_eq!(res, HnsResolution::Registry { public_key: "ab".repeat(32), data_key: "cd".repeat(32) });
+
+    assert!(matches!(resolve_hns(&client, "skyapp", HnsOptions::default()).await, Err(PortalResponse(_))));
+  }
+
+  #[tokio::test]
+  async fn test_download_hns() {
+    let transport = MockTransport::new();
+    for _ in 0..3 {
+      transport.push_response(MockResponse::new(hyper::StatusCode::OK, "hello"));
+    }
+    transport.push_response(MockResponse::new(hyper::StatusCode::FORBIDDEN, "blocked"));
+    let client = transport.client("https://siasky.net");
+
+    let data = download_hns(&client, "skyapp", "", HnsOptions::default()).await.unwrap();
+    assert_eq!(data, "hello");
+    assert_eq!(transport.last_request().unwrap().uri.to_string(), "https://siasky.net/hns/skyapp");
+
+    download_hns(&client, "skyapp", "/docs/index.html", HnsOptions::default()).await.unwrap();
+    assert_eq!(transport.last_request().unwrap().uri.to_string(), "https://siasky.net/hns/skyapp/docs/index.html");
+
+    let opt = HnsOptions { addressing: HnsAddressing::Subdomain, ..Default::default() };
+    download_hns(&client, "skyapp", "docs/index.html", opt.clone()).await.unwrap();
+    assert_eq!(transport.last_request().unwrap().uri.to_string(), "https://skyapp.hns.siasky.net/docs/index.html");
+
+    assert!(matches!(download_hns(&client, "skyapp", "", opt).await.map_err(crate::SkynetError::into_inner), Err(PortalResponse(_))));
+  }
+
+  #[tokio::test]
+  async fn test_download_hns_uri() {
+    let transport = MockTransport::new();
+    for _ in 0..2 {
+      transport.push_response(MockResponse::new(hyper::StatusCode::OK, "hello"));
+    }
+    let client = transport.client("https://siasky.net");
+
+    download_data_hns(&client, "hns://skyapp/docs/index.html", HnsOptions::default()).await.unwrap();
+    assert_eq!(transport.last_request().unwrap().uri.to_string(), "https://siasky.net/hns/skyapp/docs/index.html");
+    download_data_hns(&client, "skyapp", HnsOptions::default()).await.unwrap();
+    assert_eq!(transport.last_request().unwrap().uri.to_string(), "https://siasky.net/hns/skyapp");
+
+  }
+
+  #[tokio::test]
+  async fn test_download_file_hns() {
+    use std::fs;
+
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::new(hyper::StatusCode::OK, "").with_header("skynet-skylink", SKYLINK));
+    transport.push_response(MockResponse::new(hyper::StatusCode::OK, "hello"));
+    let client = transport.client("https://siasky.net");
+
+    download_file_hns(&client, "tmp_hns.txt", "hns://skyapp/docs/", HnsOptions::default()).await.unwrap();
+    let data = fs::read_to_string("tmp_hns.txt").unwrap();
+    fs::remove_file("tmp_hns.txt").unwrap();
+    assert_eq!(data, "hello");
+    let requests = transport.requests();
+    assert_eq!(requests[0].method, Method::HEAD);
+    assert_eq!(requests[0].uri.to_string(), "https://siasky.net/hns/skyapp");
+    assert!(requests[1].uri.path().ends_with(&format!("{}/docs", SKYLINK)));
+  }
+
+  #[cfg(feature = "blocklist")]
+  #[tokio::test]
+  async fn test_download_hns_blocked() {
+    use crate::{Blocklist, SkynetClientOptions};
+    use std::sync::Arc;
+
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::new(hyper::StatusCode::OK, "hello").with_header("skynet-skylink", SKYLINK));
+    let blocklist = Blocklist::new();
+    blocklist.insert_skylink(SKYLINK).unwrap();
+    let client = SkynetClient::with_transport("https://siasky.net", SkynetClientOptions {
+      blocklist: Some(blocklist),
+      ..Default::default()
+    }, Arc::new(transport.clone()));
+
+    let res = download_hns(&client, "skyapp", "", HnsOptions::default()).await;
+    assert!(matches!(res, Err(Blocked(_))));
+  }
+
+  #[cfg(feature = "registry")]
+  #[tokio::test]
+  async fn test_resolve_skylink_from_registry() {
+    use crate::Skylink;
+    use hex::ToHex;
+
+    let transport = MockTransport::new();
+    let data = SKYLINK.parse::<Skylink>().unwrap().to_bytes().encode_hex::<String>();
+    transport.push_response(MockResponse::json(json!({ "data": data, "revision": 3, "signature": "00".repeat(64) })));
+    let client = transport.client("https://siasky.net");
+
+    let resolution = HnsResolution::Registry { public_key: "ab".repeat(32), data_key: "cd".repeat(32) };
+    let opt = EntryOptions { verify_signature: false, ..Default::default() };
+    assert_eq!(resolution.resolve_skylink(&client, opt).await.unwrap(), SKYLINK);
+    let uri = transport.last_request().unwrap().uri.to_string();
+    assert!(uri.contains(&format!("datakey={}", "cd".repeat(32))));
+  }
+}
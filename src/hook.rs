@@ -0,0 +1,170 @@
+use crate::{SkynetError::*, SkynetResult};
+use std::{collections::HashMap, fmt, sync::Arc};
+use bytes::Bytes;
+use mime::Mime;
+
+/// A file about to be uploaded, as `UploadHook`s see it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadPart {
+  /// The file's name, or its `/`-separated path within the directory for directory uploads.
+  pub filename: String,
+  pub content_type: Mime,
+  pub data: Bytes,
+}
+
+/// Looks at every file of an upload before it is sent, over multipart or tus. A hook can change
+/// the file in place, e.g. to strip EXIF data or compress it, or refuse the whole upload by
+/// returning an error such as `UploadRejected`.
+pub trait UploadHook: fmt::Debug + Send + Sync {
+  fn before_upload(&self, part: &mut UploadPart) -> SkynetResult<()>;
+}
+
+/// An `UploadHook` that calls a closure.
+pub struct FnHook<F>(pub F);
+
+impl<F> fmt::Debug for FnHook<F> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.write_str("FnHook(..)")
+  }
+}
+
+impl<F> UploadHook for FnHook<F>
+where
+  F: Fn(&mut UploadPart) -> SkynetResult<()> + Send + Sync,
+{
+  fn before_upload(&self, part: &mut UploadPart) -> SkynetResult<()> {
+    (self.0)(part)
+  }
+}
+
+/// An `UploadHook` that replaces the content type guessed from a file's name, which the portal
+/// serves the file with as is. A file's own entry wins over its extension's.
+#[derive(Debug, Clone, Default)]
+pub struct ContentTypeOverride {
+  files: HashMap<String, Mime>,
+  extensions: HashMap<String, Mime>,
+}
+
+impl ContentTypeOverride {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Sets the content type of the file named `filename`, or at that path in a directory upload.
+  pub fn file(mut self, filename: &str, content_type: Mime) -> Self {
+    self.files.insert(filename.to_string(), content_type);
+    self
+  }
+
+  /// Sets the content type of files ending in `.extension`, compared case-insensitively.
+  pub fn extension(mut self, extension: &str, content_type: Mime) -> Self {
+    self.extensions.insert(extension.trim_start_matches('.').to_lowercase(), content_type);
+    self
+  }
+}
+
+impl UploadHook for ContentTypeOverride {
+  fn before_upload(&self, part: &mut UploadPart) -> SkynetResult<()> {
+    let extension = part.filename.rsplit_once('.').map(|(_, extension)| extension.to_lowercase());
+    let content_type = self.files
+      .get(&part.filename)
+      .or_else(|| extension.and_then(|extension| self.extensions.get(&extension)));
+    if let Some(content_type) = content_type {
+      part.content_type = content_type.clone();
+    }
+    Ok(())
+  }
+}
+
+/// An `UploadHook` that refuses the upload with `UploadRejected` unless every file's content
+/// type is allowed. Place it after any hook that changes content types. Parameters such as
+/// `charset` are ignored, and a `*` subtype allows the whole type, e.g. `image/*`.
+#[derive(Debug, Clone)]
+pub struct ContentTypeFilter {
+  allowed: Vec<Mime>,
+}
+
+impl ContentTypeFilter {
+  pub fn new(allowed: Vec<Mime>) -> Self {
+    Self { allowed }
+  }
+
+  pub fn allows(&self, content_type: &Mime) -> bool {
+    self.allowed.iter().any(|allowed| {
+      allowed.type_() == content_type.type_()
+        && (allowed.subtype() == mime::STAR || allowed.subtype() == content_type.subtype())
+    })
+  }
+}
+
+impl UploadHook for ContentTypeFilter {
+  fn before_upload(&self, part: &mut UploadPart) -> SkynetResult<()> {
+    if self.allows(&part.content_type) {
+      Ok(())
+    } else {
+      Err(UploadRejected(format!("{} has disallowed content type {}", part.filename, part.content_type)))
+    }
+  }
+}
+
+/// Runs `hooks` on a file in order, each seeing what the one before left.
+pub(crate) fn run_hooks(
+  hooks: &[Arc<dyn UploadHook>],
+  filename: String,
+  content_type: Mime,
+  data: Bytes,
+) -> SkynetResult<UploadPart> {
+  let mut part = UploadPart { filename, content_type, data };
+  for hook in hooks {
+    hook.before_upload(&mut part)?;
+  }
+  Ok(part)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_run_hooks() {
+    let hooks: Vec<Arc<dyn UploadHook>> = vec![
+      Arc::new(FnHook(|part: &mut UploadPart| {
+        part.data = part.data.to_ascii_uppercase().into();
+        Ok(())
+      })),
+      Arc::new(FnHook(|part: &mut UploadPart| {
+        part.filename = format!("{}.upper", part.filename);
+        Ok(())
+      })),
+    ];
+    let part = run_hooks(&hooks, "a.txt".into(), mime::TEXT_PLAIN, Bytes::from("abc")).unwrap();
+    assert_eq!(part.filename, "a.txt.upper");
+    assert_eq!(part.data, "ABC");
+
+    let reject: Vec<Arc<dyn UploadHook>> = vec![Arc::new(FnHook(|part: &mut UploadPart| {
+      Err(UploadRejected(format!("{} is infected", part.filename)))
+    }))];
+    let res = run_hooks(&reject, "a.exe".into(), mime::APPLICATION_OCTET_STREAM, Bytes::new());
+    assert!(matches!(res, Err(UploadRejected(reason)) if reason == "a.exe is infected"));
+  }
+
+  #[test]
+  fn test_content_type_hooks() {
+    let hooks: Vec<Arc<dyn UploadHook>> = vec![
+      Arc::new(ContentTypeOverride::new()
+        .extension(".HEIC", "image/heic".parse().unwrap())
+        .file("photos/cover.heic", mime::IMAGE_JPEG)),
+      Arc::new(ContentTypeFilter::new(vec!["image/*".parse().unwrap()])),
+    ];
+
+    let part = run_hooks(&hooks, "photos/a.heic".into(), mime::APPLICATION_OCTET_STREAM, Bytes::new()).unwrap();
+    assert_eq!(part.content_type.essence_str(), "image/heic");
+    let part = run_hooks(&hooks, "photos/cover.heic".into(), mime::APPLICATION_OCTET_STREAM, Bytes::new()).unwrap();
+    assert_eq!(part.content_type, mime::IMAGE_JPEG);
+    assert!(run_hooks(&hooks, "a.png".into(), mime::IMAGE_PNG, Bytes::new()).is_ok());
+
+    let res = run_hooks(&hooks, "index.html".into(), mime::TEXT_HTML_UTF_8, Bytes::new());
+    assert!(matches!(res, Err(UploadRejected(reason)) if reason.starts_with("index.html")));
+    assert!(ContentTypeFilter::new(vec![mime::TEXT_HTML]).allows(&mime::TEXT_HTML_UTF_8));
+  }
+}
@@ -0,0 +1,123 @@
+use crate::{
+  skydb::{self, SkyDbOptions},
+  skylink::normalize_skylink,
+  KeyPair, PublicKey, SkynetClient, SkynetResult,
+};
+use std::collections::BTreeMap;
+
+/// A mutable directory of names to skylinks: a JSON manifest skyfile behind a SkyDB registry
+/// entry. Changes go through `db_update`, so each one is a read-modify-write of the whole
+/// manifest that is retried when another writer gets in first, and no writer's entries are lost.
+pub type Index = BTreeMap<String, String>;
+
+/// Every name in the index under `data_key`, or an empty index if it was never written.
+pub async fn index_list(
+  client: &SkynetClient,
+  public_key: &PublicKey,
+  data_key: &str,
+  opt: &SkyDbOptions,
+) -> SkynetResult<Index> {
+  let entry = skydb::db_get::<Index>(client, public_key, data_key, opt).await?;
+  Ok(entry.map(|entry| entry.data).unwrap_or_default())
+}
+
+pub async fn index_get(
+  client: &SkynetClient,
+  public_key: &PublicKey,
+  data_key: &str,
+  name: &str,
+  opt: &SkyDbOptions,
+) -> SkynetResult<Option<String>> {
+  Ok(index_list(client, public_key, data_key, opt).await?.remove(name))
+}
+
+/// Points `name` at `skylink`, returning the skylink it pointed at before.
+pub async fn index_insert(
+  client: &SkynetClient,
+  keypair: &KeyPair,
+  data_key: &str,
+  name: &str,
+  skylink: &str,
+  opt: &SkyDbOptions,
+) -> SkynetResult<Option<String>> {
+  let skylink = normalize_skylink(skylink)?;
+  let mut previous = None;
+  index_update(client, keypair, data_key, |index| {
+    previous = index.insert(name.to_string(), skylink.clone());
+  }, opt).await?;
+  Ok(previous)
+}
+
+/// Drops `name` from the index, returning the skylink it pointed at.
+pub async fn index_remove(
+  client: &SkynetClient,
+  keypair: &KeyPair,
+  data_key: &str,
+  name: &str,
+  opt: &SkyDbOptions,
+) -> SkynetResult<Option<String>> {
+  let mut removed = None;
+  index_update(client, keypair, data_key, |index| {
+    removed = index.remove(name);
+  }, opt).await?;
+  Ok(removed)
+}
+
+/// Applies several changes to the index as one manifest update. `update` may run more than once
+/// if another writer changes the index in between.
+pub async fn index_update<F>(
+  client: &SkynetClient,
+  keypair: &KeyPair,
+  data_key: &str,
+  mut update: F,
+  opt: &SkyDbOptions,
+) -> SkynetResult<Index>
+where
+  F: FnMut(&mut Index),
+{
+  let entry = skydb::db_update(client, keypair, data_key, |current: Option<Index>| {
+    let mut index = current.unwrap_or_default();
+    update(&mut index);
+    index
+  }, opt).await?;
+  Ok(entry.data)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{gen_keypair_and_seed, MockPortal, SkynetError::*};
+
+  const SKYLINK: &str = "AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng";
+  const OTHER_SKYLINK: &str = "AABWKm9W4atTb-lmN7mAswXyYj9xfSaaLkQ1DCq_7y0bZA";
+
+  #[tokio::test]
+  async fn test_index() {
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let (keypair, _) = gen_keypair_and_seed(64);
+    let public_key = PublicKey::from(&keypair);
+    let opt = SkyDbOptions::default();
+
+    assert!(index_list(&client, &public_key, "site", &opt).await.unwrap().is_empty());
+
+    let (first, second) = futures_util::future::join(
+      index_insert(&client, &keypair, "site", "a.txt", &format!("sia://{}", SKYLINK), &opt),
+      index_insert(&client, &keypair, "site", "b.txt", SKYLINK, &opt),
+    ).await;
+    assert_eq!(first.unwrap(), None);
+    assert_eq!(second.unwrap(), None);
+
+    let previous = index_insert(&client, &keypair, "site", "a.txt", OTHER_SKYLINK, &opt).await;
+    assert_eq!(previous.unwrap(), Some(SKYLINK.to_string()));
+    assert_eq!(index_get(&client, &public_key, "site", "a.txt", &opt).await.unwrap(), Some(OTHER_SKYLINK.into()));
+
+    let removed = index_remove(&client, &keypair, "site", "b.txt", &opt).await;
+    assert_eq!(removed.unwrap(), Some(SKYLINK.to_string()));
+    let index = index_list(&client, &public_key, "site", &opt).await.unwrap();
+    assert_eq!(index.into_iter().collect::<Vec<_>>(), vec![("a.txt".to_string(), OTHER_SKYLINK.to_string())]);
+
+    let res = index_insert(&client, &keypair, "site", "c.txt", "not a skylink", &opt).await;
+    assert!(matches!(res, Err(InvalidSkylink(_))));
+  }
+}
@@ -0,0 +1,307 @@
+use crate::{
+  crypto::{decrypt, derive_passphrase_key, encrypt},
+  KeyPair, PrivateKey, Seed, SkynetError::*, SkynetResult,
+};
+use std::{
+  collections::BTreeMap,
+  fmt, fs,
+  io::Write,
+  path::{Path, PathBuf},
+};
+use hex::{FromHex, ToHex};
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+const KEYSTORE_VERSION: u32 = 1;
+const SALT_SIZE: usize = 16;
+
+/// PBKDF2 rounds for new keystores and rotated passphrases.
+pub const DEFAULT_KEYSTORE_ITERATIONS: u32 = 100_000;
+/// Keystores claiming fewer or more PBKDF2 rounds than this aren't opened: too few would make
+/// the passphrase cheap to guess, too many would hang `load`.
+const MIN_KEYSTORE_ITERATIONS: u32 = 10_000;
+const MAX_KEYSTORE_ITERATIONS: u32 = 10_000_000;
+
+/// A secret kept in a `Keystore`.
+#[derive(Clone)]
+pub enum StoredKey {
+  /// A seed, e.g. a MySky seed or one from `gen_keypair_and_seed`.
+  Seed(Seed),
+  /// A registry signing key, holding its public key too.
+  PrivateKey(PrivateKey),
+  /// A symmetric key, e.g. for `SkyDbEncryption`.
+  EncryptionKey([u8; 32]),
+}
+
+impl StoredKey {
+  /// The keypair of a seed or private key, derived the way `gen_keypair_from_seed` does for a seed.
+  pub fn keypair(&self) -> Option<KeyPair> {
+    match self {
+      Self::Seed(seed) => Some(crate::gen_keypair_from_seed(seed.as_bytes())),
+      Self::PrivateKey(private_key) => {
+        let mut public_key = [0; 32];
+        public_key.copy_from_slice(&private_key.as_bytes()[32..]);
+        Some(KeyPair { public_key, private_key: private_key.clone() })
+      }
+      Self::EncryptionKey(_) => None,
+    }
+  }
+}
+
+impl Drop for StoredKey {
+  fn drop(&mut self) {
+    // Seeds and private keys wipe themselves.
+    if let Self::EncryptionKey(key) = self {
+      key.zeroize();
+    }
+  }
+}
+
+impl fmt::Debug for StoredKey {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Seed(_) => f.debug_tuple("Seed").field(&"..").finish(),
+      Self::PrivateKey(_) => f.debug_tuple("PrivateKey").field(&"..").finish(),
+      Self::EncryptionKey(_) => f.debug_tuple("EncryptionKey").field(&"..").finish(),
+    }
+  }
+}
+
+/// The keystore file: everything but the format and key derivation parameters is encrypted.
+#[derive(Serialize, Deserialize)]
+struct KeystoreFile {
+  version: u32,
+  salt: String,
+  iterations: u32,
+  data: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", content = "key", rename_all = "camelCase")]
+enum EncodedKey {
+  Seed(String),
+  PrivateKey(String),
+  EncryptionKey(String),
+}
+
+impl Drop for EncodedKey {
+  fn drop(&mut self) {
+    match self {
+      Self::Seed(key) | Self::PrivateKey(key) | Self::EncryptionKey(key) => key.zeroize(),
+    }
+  }
+}
+
+/// Seeds, signing keys and encryption keys kept in one file encrypted under a passphrase, with
+/// XChaCha20-Poly1305 and a key stretched by PBKDF2. Changes stay in memory until `save`.
+pub struct Keystore {
+  path: PathBuf,
+  key: [u8; 32],
+  salt: [u8; SALT_SIZE],
+  iterations: u32,
+  keys: BTreeMap<String, StoredKey>,
+}
+
+impl Keystore {
+  /// An empty keystore to be saved at `path`, which is only written by `save`.
+  pub fn create<P: AsRef<Path>>(path: P, passphrase: &str) -> Self {
+    let mut salt = [0; SALT_SIZE];
+    rand::thread_rng().fill(&mut salt);
+
+    Self {
+      path: path.as_ref().to_path_buf(),
+      key: derive_passphrase_key(passphrase, &salt, DEFAULT_KEYSTORE_ITERATIONS),
+      salt,
+      iterations: DEFAULT_KEYSTORE_ITERATIONS,
+      keys: BTreeMap::new(),
+    }
+  }
+
+  /// Opens the keystore saved at `path`. A wrong passphrase fails with `DecryptionError`.
+  pub fn load<P: AsRef<Path>>(path: P, passphrase: &str) -> SkynetResult<Self> {
+    let path = path.as_ref();
+    let file: KeystoreFile = serde_json::from_slice(&fs::read(path).map_err(FileError)?).map_err(JsonError)?;
+    if file.version != KEYSTORE_VERSION {
+      return Err(InvalidKeystore(format!("unsupported version {}", file.version)));
+    }
+    if !(MIN_KEYSTORE_ITERATIONS..=MAX_KEYSTORE_ITERATIONS).contains(&file.iterations) {
+      return Err(InvalidKeystore(format!("unsupported iteration count {}", file.iterations)));
+    }
+    let salt = <[u8; SALT_SIZE]>::from_hex(&file.salt).map_err(|_| InvalidKeystore("invalid salt".into()))?;
+    let data = Vec::from_hex(&file.data).map_err(|_| InvalidKeystore("invalid data".into()))?;
+
+    let key = derive_passphrase_key(passphrase, &salt, file.iterations);
+    let mut plaintext = decrypt(&key, &data)?;
+    let encoded = serde_json::from_slice::<BTreeMap<String, EncodedKey>>(&plaintext);
+    plaintext.zeroize();
+
+    let mut keys = BTreeMap::new();
+    for (name, encoded) in encoded.map_err(JsonError)? {
+      let invalid = || InvalidKeystore(format!("invalid key {}", name));
+      let key = match &encoded {
+        EncodedKey::Seed(seed) => StoredKey::Seed(Vec::from_hex(seed).map_err(|_| invalid())?.into()),
+        EncodedKey::PrivateKey(private_key) => {
+          StoredKey::PrivateKey(PrivateKey::from_bytes(<[u8; 64]>::from_hex(private_key).map_err(|_| invalid())?))
+        }
+        EncodedKey::EncryptionKey(key) => StoredKey::EncryptionKey(<[u8; 32]>::from_hex(key).map_err(|_| invalid())?),
+      };
+      keys.insert(name, key);
+    }
+
+    Ok(Self { path: path.to_path_buf(), key, salt, iterations: file.iterations, keys })
+  }
+
+  /// Writes the keystore to its path, replacing the saved one in a single step so a crash
+  /// can't leave it half written. On Unix the file is only readable by its owner.
+  pub fn save(&self) -> SkynetResult<()> {
+    let encoded: BTreeMap<&str, EncodedKey> = self
+      .keys
+      .iter()
+      .map(|(name, key)| {
+        let encoded = match key {
+          StoredKey::Seed(seed) => EncodedKey::Seed(seed.as_bytes().encode_hex()),
+          StoredKey::PrivateKey(private_key) => EncodedKey::PrivateKey(private_key.as_bytes().encode_hex()),
+          StoredKey::EncryptionKey(key) => EncodedKey::EncryptionKey(key.encode_hex()),
+        };
+        (name.as_str(), encoded)
+      })
+      .collect();
+    let mut plaintext = serde_json::to_vec(&encoded).map_err(JsonError)?;
+    let data = encrypt(&self.key, &plaintext);
+    plaintext.zeroize();
+
+    let file = KeystoreFile {
+      version: KEYSTORE_VERSION,
+      salt: self.salt.encode_hex(),
+      iterations: self.iterations,
+      data: data.encode_hex(),
+    };
+    let contents = serde_json::to_vec(&file).map_err(JsonError)?;
+
+    let mut tmp_path = self.path.clone().into_os_string();
+    tmp_path.push(".tmp");
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::OpenOptionsExt;
+      options.mode(0o600);
+    }
+    let mut tmp = options.open(&tmp_path).map_err(FileError)?;
+    // The mode above only applies to a new file, not one left behind by an earlier save.
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      tmp.set_permissions(fs::Permissions::from_mode(0o600)).map_err(FileError)?;
+    }
+    tmp.write_all(&contents).and_then(|_| tmp.sync_all()).map_err(FileError)?;
+    fs::rename(&tmp_path, &self.path).map_err(FileError)
+  }
+
+  /// Re-encrypts the keystore under `new_passphrase` with a fresh salt and saves it.
+  pub fn rotate(&mut self, new_passphrase: &str) -> SkynetResult<()> {
+    let mut salt = [0; SALT_SIZE];
+    rand::thread_rng().fill(&mut salt);
+    let key = derive_passphrase_key(new_passphrase, &salt, DEFAULT_KEYSTORE_ITERATIONS);
+
+    let mut old_key = std::mem::replace(&mut self.key, key);
+    let old_salt = std::mem::replace(&mut self.salt, salt);
+    let old_iterations = std::mem::replace(&mut self.iterations, DEFAULT_KEYSTORE_ITERATIONS);
+    let res = self.save();
+    if res.is_err() {
+      // The saved keystore still opens with the old passphrase, so keep using it.
+      self.key = old_key;
+      self.salt = old_salt;
+      self.iterations = old_iterations;
+    }
+    old_key.zeroize();
+    res
+  }
+
+  pub fn path(&self) -> &Path {
+    &self.path
+  }
+
+  pub fn get(&self, name: &str) -> Option<&StoredKey> {
+    self.keys.get(name)
+  }
+
+  /// The keypair of the seed or private key stored as `name`.
+  pub fn keypair(&self, name: &str) -> Option<KeyPair> {
+    self.get(name).and_then(StoredKey::keypair)
+  }
+
+  /// Stores `key` as `name`, returning the key it replaces.
+  pub fn insert(&mut self, name: &str, key: StoredKey) -> Option<StoredKey> {
+    self.keys.insert(name.to_string(), key)
+  }
+
+  pub fn remove(&mut self, name: &str) -> Option<StoredKey> {
+    self.keys.remove(name)
+  }
+
+  pub fn names(&self) -> impl Iterator<Item = &str> {
+    self.keys.keys().map(String::as_str)
+  }
+}
+
+impl Drop for Keystore {
+  fn drop(&mut self) {
+    self.key.zeroize();
+  }
+}
+
+impl fmt::Debug for Keystore {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Keystore")
+      .field("path", &self.path)
+      .field("keys", &self.keys)
+      .finish()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::gen_keypair_and_seed;
+
+  #[test]
+  fn test_keystore() {
+    let path = "tmp_keystore.json";
+    let (keypair, seed) = gen_keypair_and_seed(32);
+    let mut keystore = Keystore::create(path, "correct horse");
+    keystore.insert("mysky", StoredKey::Seed(seed.clone()));
+    keystore.insert("registry", StoredKey::PrivateKey(keypair.private_key.clone()));
+    keystore.insert("skydb", StoredKey::EncryptionKey([7; 32]));
+    keystore.save().unwrap();
+    assert!(!fs::read_to_string(path).unwrap().contains(&seed.as_bytes().encode_hex::<String>()));
+    assert!(!format!("{:?}", keystore).contains(&seed.as_bytes().encode_hex::<String>()));
+
+    assert!(matches!(Keystore::load(path, "wrong"), Err(DecryptionError)));
+    let loaded = Keystore::load(path, "correct horse").unwrap();
+    assert_eq!(loaded.names().collect::<Vec<_>>(), vec!["mysky", "registry", "skydb"]);
+    assert_eq!(loaded.keypair("mysky").unwrap().public_key, keypair.public_key);
+    assert_eq!(loaded.keypair("registry").unwrap().public_key, keypair.public_key);
+    assert!(matches!(loaded.get("skydb"), Some(StoredKey::EncryptionKey(key)) if *key == [7; 32]));
+    assert!(loaded.keypair("skydb").is_none());
+
+    let mut loaded = loaded;
+    loaded.remove("skydb");
+    loaded.rotate("battery staple").unwrap();
+    assert!(matches!(Keystore::load(path, "correct horse"), Err(DecryptionError)));
+    let rotated = Keystore::load(path, "battery staple").unwrap();
+    assert_eq!(rotated.names().collect::<Vec<_>>(), vec!["mysky", "registry"]);
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::PermissionsExt;
+      assert_eq!(fs::metadata(path).unwrap().permissions().mode() & 0o777, 0o600);
+    }
+
+    let mut file: KeystoreFile = serde_json::from_slice(&fs::read(path).unwrap()).unwrap();
+    file.iterations = 0;
+    fs::write(path, serde_json::to_vec(&file).unwrap()).unwrap();
+    assert!(matches!(Keystore::load(path, "battery staple"), Err(InvalidKeystore(_))));
+    fs::remove_file(path).unwrap();
+  }
+}
@@ -0,0 +1,115 @@
+use crate::{SkynetError::*, SkynetResult};
+use std::{
+  path::Path,
+  time::{SystemTime, UNIX_EPOCH},
+};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LedgerRecord {
+  pub path: String,
+  pub skylink: String,
+  pub size: u64,
+  pub timestamp: u64,
+  pub portal_url: String,
+}
+
+impl LedgerRecord {
+  pub fn new(path: &str, skylink: &str, size: u64, portal_url: &str) -> Self {
+    let timestamp = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .map(|d| d.as_secs())
+      .unwrap_or(0);
+
+    Self {
+      path: path.to_string(),
+      skylink: skylink.to_string(),
+      size,
+      timestamp,
+      portal_url: portal_url.to_string(),
+    }
+  }
+}
+
+/// Persistent record of every upload made through a client, keyed in insertion order.
+#[derive(Debug, Clone)]
+pub struct UploadLedger {
+  db: sled::Db,
+}
+
+impl UploadLedger {
+  pub fn open<P: AsRef<Path>>(path: P) -> SkynetResult<Self> {
+    let db = sled::open(path).map_err(LedgerError)?;
+    Ok(Self { db })
+  }
+
+  pub fn temporary() -> SkynetResult<Self> {
+    let db = sled::Config::new().temporary(true).open().map_err(LedgerError)?;
+    Ok(Self { db })
+  }
+
+  pub fn record(&self, record: &LedgerRecord) -> SkynetResult<()> {
+    let id = self.db.generate_id().map_err(LedgerError)?;
+    let value = serde_json::to_vec(record).map_err(JsonError)?;
+    self.db.insert(id.to_be_bytes(), value).map_err(LedgerError)?;
+    self.db.flush().map_err(LedgerError)?;
+    Ok(())
+  }
+
+  pub fn records(&self) -> SkynetResult<Vec<LedgerRecord>> {
+    self.filter(|_| true)
+  }
+
+  pub fn find_by_path(&self, path: &str) -> SkynetResult<Vec<LedgerRecord>> {
+    self.filter(|record| record.path == path)
+  }
+
+  pub fn find_by_skylink(&self, skylink: &str) -> SkynetResult<Vec<LedgerRecord>> {
+    self.filter(|record| record.skylink == skylink)
+  }
+
+  pub fn latest_for_path(&self, path: &str) -> SkynetResult<Option<LedgerRecord>> {
+    Ok(self.find_by_path(path)?.pop())
+  }
+
+  pub fn since(&self, timestamp: u64) -> SkynetResult<Vec<LedgerRecord>> {
+    self.filter(|record| record.timestamp >= timestamp)
+  }
+
+  pub fn contains_path(&self, path: &str) -> SkynetResult<bool> {
+    Ok(self.latest_for_path(path)?.is_some())
+  }
+
+  fn filter<F: Fn(&LedgerRecord) -> bool>(&self, f: F) -> SkynetResult<Vec<LedgerRecord>> {
+    let mut records = Vec::new();
+
+    for item in self.db.iter() {
+      let (_, value) = item.map_err(LedgerError)?;
+      let record: LedgerRecord = serde_json::from_slice(&value).map_err(JsonError)?;
+      if f(&record) {
+        records.push(record);
+      }
+    }
+
+    Ok(records)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_ledger() {
+    let ledger = UploadLedger::temporary().unwrap();
+    ledger.record(&LedgerRecord::new("a.txt", "AAA", 1, "https://siasky.net")).unwrap();
+    ledger.record(&LedgerRecord::new("b.txt", "BBB", 2, "https://siasky.net")).unwrap();
+    ledger.record(&LedgerRecord::new("a.txt", "CCC", 3, "https://siasky.net")).unwrap();
+
+    assert_eq!(ledger.records().unwrap().len(), 3);
+    assert_eq!(ledger.find_by_path("a.txt").unwrap().len(), 2);
+    assert_eq!(ledger.latest_for_path("a.txt").unwrap().unwrap().skylink, "CCC");
+    assert_eq!(ledger.find_by_skylink("BBB").unwrap()[0].size, 2);
+    assert!(!ledger.contains_path("c.txt").unwrap());
+  }
+}
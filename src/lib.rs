@@ -1,16 +1,137 @@
+#[cfg(feature = "account")]
+mod account;
+#[cfg(feature = "archive")]
+mod archive;
+mod auth;
+#[cfg(feature = "backup")]
+mod backup;
+#[cfg(feature = "blocklist")]
+mod blocklist;
+mod budget;
+#[cfg(feature = "checksum")]
+mod checksum;
 mod client;
+#[cfg(feature = "crypto")]
 mod crypto;
+#[cfg(feature = "dac")]
+mod dac;
+mod dns;
 mod download;
+pub mod encoding;
+#[cfg(feature = "encryption")]
 mod encryption;
 mod error;
+mod events;
+mod global;
+#[cfg(feature = "gzip")]
+mod gzip;
+mod hns;
+mod hook;
+#[cfg(feature = "skydb")]
+mod index;
+#[cfg(feature = "keystore")]
+mod keystore;
+#[cfg(feature = "ledger")]
+mod ledger;
+mod lifecycle;
+#[cfg(any(test, feature = "test-util"))]
+mod mock;
+#[cfg(feature = "mysky")]
+mod mysky;
+#[cfg(feature = "offline")]
+mod offline;
+#[cfg(feature = "account")]
+mod pin;
+mod pool;
+mod portal;
+#[cfg(feature = "registry")]
 mod registry;
+#[cfg(feature = "skydb")]
+mod skydb;
+mod skylink;
+mod sparse;
+mod store;
+mod transport;
+#[cfg(feature = "tus")]
+mod tus;
 mod upload;
 mod util;
 
+#[cfg(feature = "account")]
+pub use account::{
+  AccountOptions, ApiKey, ApiKeyScope, DownloadStats, PinInfo, ShareUrl, SkylinkHealth, UserInfo, UserLimits,
+  JWT_COOKIE_NAME,
+};
+#[cfg(feature = "archive")]
+pub use archive::ArchiveFormat;
+pub use auth::{AuthScheme, Session};
+#[cfg(feature = "backup")]
+pub use backup::{BackupFile, BackupOptions, Snapshot};
+#[cfg(feature = "blocklist")]
+pub use blocklist::Blocklist;
+#[cfg(feature = "checksum")]
+pub use checksum::{checksum, ChecksumAlgorithm};
 pub use client::{SkynetClientOptions, SkynetClient};
-pub use crate::crypto::{gen_keypair_and_seed, gen_keypair_from_seed, derive_child_seed, KeyPair};
-pub use download::{DownloadOptions, MetadataOptions, Metadata, Subfile};
-pub use encryption::{Skykey, SkykeyOptions};
-pub use error::{SkynetError, SkynetResult};
-pub use upload::{UploadOptions};
-pub use util::{DEFAULT_PORTAL_URL, URI_SKYNET_PREFIX};
+#[cfg(feature = "crypto")]
+pub use crate::crypto::{
+  gen_box_keypair, gen_keypair_and_seed, gen_keypair_from_seed, gen_mysky_keypair, check_padded_block,
+  derive_child_seed, derive_discoverable_file_tweak, derive_encrypted_file_key_entropy, derive_encrypted_file_tweak,
+  derive_encrypted_path_seed, derive_root_path_seed, merkle_root, open_sealed, pad_file_size, seal, shared_secret,
+  sign, verify, x25519_public_key, x25519_secret_key, BoxKeyPair, KeyPair, PrivateKey, Seed, SEAL_OVERHEAD,
+};
+#[cfg(feature = "dac")]
+pub use dac::{
+  Avatar, Post, PostContent, Profile, FEED_DAC_DOMAIN, PROFILE_DAC_DOMAIN, SOCIAL_DAC_DOMAIN,
+};
+pub use dns::{Resolve, ResolveFuture, StaticResolver, SystemResolver};
+pub use download::{
+  DownloadOptions, MetadataOptions, Metadata, MetadataSource, ResolutionChain, ResolutionHop, ResolvedSkylink,
+  ResponseInfo, StreamedMetadata, Subfile, Subfiles,
+};
+#[cfg(feature = "encryption")]
+pub use encryption::{Skykey, SkykeyCache, SkykeyOptions};
+pub use error::{Operation, RequestContext, SkynetError, SkynetResult};
+pub use events::Event;
+pub use store::{FsStore, LocalStore, MemoryStore, StoreFuture};
+pub use hook::{ContentTypeFilter, ContentTypeOverride, FnHook, UploadHook, UploadPart};
+pub use global::{
+  download_data, download_file, get_metadata, global_client, init_global_client, upload_data, upload_file,
+};
+#[cfg(feature = "directory")]
+pub use global::{download_directory, upload_directory, upload_directory_manifest};
+pub use hns::{HnsAddressing, HnsOptions, HnsResolution};
+#[cfg(feature = "skydb")]
+pub use index::Index;
+#[cfg(feature = "keystore")]
+pub use keystore::{Keystore, StoredKey, DEFAULT_KEYSTORE_ITERATIONS};
+#[cfg(feature = "ledger")]
+pub use ledger::{LedgerRecord, UploadLedger};
+#[cfg(any(test, feature = "test-util"))]
+pub use mock::{MockPortal, MockResponse, MockTransport, RecordedRequest};
+#[cfg(feature = "mysky")]
+pub use mysky::{
+  get_path_domain, is_own_domain, is_path_contained, sanitize_path, MySky, PermCategory, PermType, Permission,
+};
+#[cfg(feature = "offline")]
+pub use offline::{FlushedUpload, OfflineStore, QueuedFile, QueuedOptions, QueuedUpload};
+#[cfg(feature = "account")]
+pub use pin::{PinAction, PinFailure, PinFailureReason, PinMaintainer, PinMaintainerOptions, PinOptions};
+pub use pool::{TransferJob, TransferOutput, TransferPool, TransferPoolOptions, TransferResult};
+pub use portal::PUBLIC_PORTALS;
+#[cfg(feature = "registry")]
+pub use registry::{
+  get_entry_link, get_registry_entry_multi, EntryOptions, PublicKey, RegistryEntry, RevisionCache,
+  SignedRegistryEntry, MAX_ENTRY_DATA_SIZE,
+};
+#[cfg(feature = "skydb")]
+pub use skydb::{get_discoverable_json, DbEntry, SkyDbEncryption, SkyDbOptions};
+pub use skylink::{normalize_skylink, parse_skylink, Skylink, SkylinkFormat, SkylinkVersion, SECTOR_SIZE};
+pub use transport::{HyperTransport, Transport, TransportFuture};
+#[cfg(all(unix, feature = "uds"))]
+pub use transport::{UnixTransport, UNIX_SOCKET_PORTAL_URL, UNIX_SOCKET_PREFIX};
+pub use upload::{estimate_file_upload, estimate_upload, UploadEstimate, UploadOptions, UploadResult};
+#[cfg(feature = "directory")]
+pub use upload::{DirectoryUploadResult, UploadedFile, DIRECTORY_METADATA_FILENAME};
+#[cfg(all(feature = "directory", feature = "crypto"))]
+pub use upload::{DedupedUploadResult, DirectoryManifest, ManifestFile};
+pub use util::{QueryParam, QueryParams, DEFAULT_PORTAL_URL, REQUEST_ID_HEADER, URI_SKYNET_PREFIX};
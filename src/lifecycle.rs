@@ -0,0 +1,96 @@
+use std::sync::Arc;
+use tokio::sync::{watch, OwnedSemaphorePermit, Semaphore};
+use crate::{SkynetError::*, SkynetResult};
+
+/// Most transfers a client and its clones can have in flight at once.
+const MAX_TRANSFERS: u32 = 1 << 20;
+
+/// Shared by a client and its clones, so that `SkynetClient::shutdown` can stop their
+/// background tasks and wait for their transfers in flight.
+#[derive(Debug, Clone)]
+pub(crate) struct Lifecycle {
+  shutdown: Arc<watch::Sender<bool>>,
+  closed: watch::Receiver<bool>,
+  transfers: Arc<Semaphore>,
+}
+
+/// Marks a transfer in flight until it is dropped.
+#[derive(Debug)]
+pub(crate) struct TransferGuard {
+  _permit: OwnedSemaphorePermit,
+}
+
+impl Default for Lifecycle {
+  fn default() -> Self {
+    let (shutdown, closed) = watch::channel(false);
+    Self {
+      shutdown: Arc::new(shutdown),
+      closed,
+      transfers: Arc::new(Semaphore::new(MAX_TRANSFERS as usize)),
+    }
+  }
+}
+
+impl Lifecycle {
+  pub(crate) fn is_shut_down(&self) -> bool {
+    *self.closed.borrow()
+  }
+
+  /// Resolves once shutdown has begun. Background tasks stop when it does.
+  pub(crate) async fn closed(&self) {
+    let mut closed = self.closed.clone();
+    loop {
+      let shut_down = *closed.borrow();
+      // `self` holds the sender, so the channel can't close under us.
+      if shut_down || closed.changed().await.is_err() {
+        return;
+      }
+    }
+  }
+
+  /// Fails with `ClientShutdown` once shutdown has begun.
+  pub(crate) fn begin_transfer(&self) -> SkynetResult<TransferGuard> {
+    if self.is_shut_down() {
+      return Err(ClientShutdown);
+    }
+    self
+      .transfers
+      .clone()
+      .try_acquire_owned()
+      .map(|permit| TransferGuard { _permit: permit })
+      .map_err(|_| ClientShutdown)
+  }
+
+  /// Signals the background tasks to stop, then waits until no transfer is in flight.
+  pub(crate) async fn shut_down(&self) {
+    let _ = self.shutdown.send(true);
+    // Each transfer holds a permit, so holding all of them means none is left. Closing the
+    // semaphore lets a second call return straight away.
+    if let Ok(_all) = self.transfers.acquire_many(MAX_TRANSFERS).await {
+      self.transfers.close();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::time::Duration;
+  use tokio::time::timeout;
+
+  #[tokio::test]
+  async fn test_shut_down_waits_for_transfers() {
+    let lifecycle = Lifecycle::default();
+    let transfer = lifecycle.begin_transfer().unwrap();
+    assert!(timeout(Duration::from_millis(20), lifecycle.closed()).await.is_err());
+
+    assert!(timeout(Duration::from_millis(20), lifecycle.shut_down()).await.is_err());
+    assert!(lifecycle.is_shut_down());
+    lifecycle.closed().await;
+    assert!(matches!(lifecycle.begin_transfer(), Err(ClientShutdown)));
+
+    drop(transfer);
+    lifecycle.shut_down().await;
+    lifecycle.shut_down().await;
+  }
+}
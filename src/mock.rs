@@ -0,0 +1,617 @@
+use crate::{
+  util::{content_disposition_filename, percent_decode},
+  SkynetClient, SkynetClientOptions, Transport, TransportFuture,
+};
+use std::{
+  collections::{hash_map::DefaultHasher, HashMap, VecDeque},
+  convert::Infallible,
+  hash::{Hash, Hasher},
+  net::SocketAddr,
+  str,
+  sync::{Arc, Mutex},
+};
+use hex::ToHex;
+use hyper::{
+  body,
+  service::{make_service_fn, service_fn},
+  Body, HeaderMap, Method, Request, Response, Server, StatusCode, Uri,
+};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::sync::oneshot;
+
+#[derive(Debug, Clone)]
+struct MockSubfile {
+  filename: String,
+  content_type: String,
+  data: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+struct MockSkyfile {
+  filename: String,
+  subfiles: Vec<MockSubfile>,
+  /// The ID of the skykey the skyfile was uploaded with.
+  skykey_id: Option<String>,
+  /// The subfile served without a path, from the `defaultpath` upload parameter.
+  default_path: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct MockSkykey {
+  name: String,
+  id: String,
+  key_type: String,
+}
+
+impl MockSkykey {
+  fn to_json(&self) -> String {
+    json!({
+      "skykey": format!("skykey:{}", self.id),
+      "name": self.name,
+      "id": self.id,
+      "type": self.key_type,
+    }).to_string()
+  }
+}
+
+#[derive(Debug, Clone)]
+struct MockEntry {
+  data: Vec<u8>,
+  revision: u64,
+  signature: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+  skyfiles: HashMap<String, MockSkyfile>,
+  registry: HashMap<(String, String), MockEntry>,
+  skykeys: Vec<MockSkykey>,
+}
+
+/// In-process portal implementing the upload, download, metadata, registry and skykey endpoints.
+/// Uploads with a skykey are stored as they were sent and served back decrypted, as a portal
+/// holding the key would.
+///
+/// The server runs on the current tokio runtime and is shut down when the portal is dropped.
+#[derive(Debug)]
+pub struct MockPortal {
+  url: String,
+  state: Arc<Mutex<MockState>>,
+  shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl MockPortal {
+  pub async fn start() -> Self {
+    let state = Arc::new(Mutex::new(MockState::default()));
+    let service_state = state.clone();
+
+    let make_svc = make_service_fn(move |_| {
+      let state = service_state.clone();
+      async move {
+        Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req)))
+      }
+    });
+
+    let addr: SocketAddr = ([127, 0, 0, 1], 0).into();
+    let server = Server::bind(&addr).serve(make_svc);
+    let url = format!("http://{}", server.local_addr());
+    let (tx, rx) = oneshot::channel::<()>();
+
+    tokio::spawn(server.with_graceful_shutdown(async {
+      rx.await.ok();
+    }));
+
+    Self {
+      url,
+      state,
+      shutdown: Some(tx),
+    }
+  }
+
+  pub fn url(&self) -> &str {
+    self.url.as_str()
+  }
+
+  pub fn client(&self) -> SkynetClient {
+    SkynetClient::new(self.url(), SkynetClientOptions::default())
+  }
+
+  pub fn skyfile_count(&self) -> usize {
+    self.state.lock().unwrap().skyfiles.len()
+  }
+
+  pub fn registry_entry_count(&self) -> usize {
+    self.state.lock().unwrap().registry.len()
+  }
+
+  /// The ID of the skykey a skylink was uploaded with, if it was encrypted.
+  pub fn skyfile_skykey_id(&self, skylink: &str) -> Option<String> {
+    let skylink = skylink.trim_start_matches(crate::URI_SKYNET_PREFIX);
+    self.state.lock().unwrap().skyfiles.get(skylink)?.skykey_id.clone()
+  }
+}
+
+impl Drop for MockPortal {
+  fn drop(&mut self) {
+    if let Some(tx) = self.shutdown.take() {
+      tx.send(()).ok();
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+  pub method: Method,
+  pub uri: Uri,
+  pub headers: HeaderMap,
+  pub body: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MockResponse {
+  pub status: StatusCode,
+  pub headers: Vec<(String, String)>,
+  pub body: Vec<u8>,
+}
+
+impl MockResponse {
+  pub fn new(status: StatusCode, body: impl Into<Vec<u8>>) -> Self {
+    Self {
+      status,
+      headers: Vec::new(),
+      body: body.into(),
+    }
+  }
+
+  pub fn json(value: serde_json::Value) -> Self {
+    Self::new(StatusCode::OK, value.to_string())
+      .with_header("content-type", mime::APPLICATION_JSON.as_ref())
+  }
+
+  pub fn with_header(mut self, name: &str, value: &str) -> Self {
+    self.headers.push((name.to_string(), value.to_string()));
+    self
+  }
+}
+
+/// Transport that records every request and replies with queued canned responses.
+///
+/// Clones share the same queues, so keep one clone for assertions and give another to the client.
+/// Once the queue is empty, requests are answered with `404 Not Found`.
+#[derive(Debug, Clone, Default)]
+pub struct MockTransport {
+  requests: Arc<Mutex<Vec<RecordedRequest>>>,
+  responses: Arc<Mutex<VecDeque<MockResponse>>>,
+}
+
+impl MockTransport {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn client(&self, portal_url: &str) -> SkynetClient {
+    SkynetClient::with_transport(portal_url, SkynetClientOptions::default(), Arc::new(self.clone()))
+  }
+
+  pub fn push_response(&self, res: MockResponse) {
+    self.responses.lock().unwrap().push_back(res);
+  }
+
+  pub fn requests(&self) -> Vec<RecordedRequest> {
+    self.requests.lock().unwrap().clone()
+  }
+
+  pub fn last_request(&self) -> Option<RecordedRequest> {
+    self.requests.lock().unwrap().last().cloned()
+  }
+}
+
+impl Transport for MockTransport {
+  fn request(&self, req: Request<Body>) -> TransportFuture {
+    let requests = self.requests.clone();
+    let res = self.responses.lock().unwrap().pop_front();
+
+    Box::pin(async move {
+      let (parts, body) = req.into_parts();
+      let body = body::to_bytes(body).await.map_err(crate::SkynetError::HyperError)?;
+      requests.lock().unwrap().push(RecordedRequest {
+        method: parts.method,
+        uri: parts.uri,
+        headers: parts.headers,
+        body: body.to_vec(),
+      });
+
+      let res = res.unwrap_or_else(|| MockResponse::new(StatusCode::NOT_FOUND, "no canned response"));
+      let mut builder = Response::builder().status(res.status);
+      for (name, value) in &res.headers {
+        builder = builder.header(name.as_str(), value.as_str());
+      }
+      Ok(builder.body(Body::from(res.body)).unwrap())
+    })
+  }
+}
+
+fn parse_query(req: &Request<Body>) -> HashMap<String, String> {
+  req
+    .uri()
+    .query()
+    .unwrap_or("")
+    .split('&')
+    .filter_map(|pair| {
+      let mut parts = pair.splitn(2, '=');
+      Some((percent_decode(parts.next()?), percent_decode(parts.next().unwrap_or(""))))
+    })
+    .collect()
+}
+
+fn respond(status: StatusCode, body: Body) -> Response<Body> {
+  Response::builder().status(status).body(body).unwrap()
+}
+
+fn error(status: StatusCode, message: &str) -> Response<Body> {
+  respond(status, Body::from(json!({ "message": message }).to_string()))
+}
+
+fn make_skylink(data: &[u8]) -> (String, Vec<u8>) {
+  let mut root = Vec::with_capacity(32);
+  for seed in 0..4u8 {
+    let mut hasher = DefaultHasher::new();
+    seed.hash(&mut hasher);
+    data.hash(&mut hasher);
+    root.extend_from_slice(&hasher.finish().to_le_bytes());
+  }
+
+  let mut raw = vec![0, 0];
+  raw.extend_from_slice(&root);
+  (base64::encode_config(&raw, base64::URL_SAFE_NO_PAD), root.to_vec())
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+  haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn parse_multipart(content_type: &str, body: &[u8]) -> Option<Vec<MockSubfile>> {
+  let boundary = content_type
+    .split("boundary=")
+    .nth(1)?
+    .trim_matches('"');
+  let delimiter = format!("--{}", boundary).into_bytes();
+
+  let mut subfiles = Vec::new();
+  let mut rest = &body[find(body, &delimiter)? + delimiter.len()..];
+
+  while !rest.starts_with(b"--") {
+    let rest_of_part = rest.strip_prefix(b"\r\n")?;
+    let header_end = find(rest_of_part, b"\r\n\r\n")?;
+    let headers = str::from_utf8(&rest_of_part[..header_end]).ok()?;
+    let content = &rest_of_part[header_end + 4..];
+    let content_end = find(content, &delimiter)?;
+
+    let mut filename = String::new();
+    let mut content_type = mime::APPLICATION_OCTET_STREAM.to_string();
+    for line in headers.split("\r\n") {
+      let lower = line.to_ascii_lowercase();
+      if lower.starts_with("content-disposition:") {
+        if let Some(name) = content_disposition_filename(&line["content-disposition:".len()..]) {
+          filename = name;
+        }
+      } else if lower.starts_with("content-type:") {
+        content_type = line["content-type:".len()..].trim().to_string();
+      }
+    }
+
+    subfiles.push(MockSubfile {
+      filename,
+      content_type,
+      data: content[..content_end - 2].to_vec(),
+    });
+    rest = &content[content_end + delimiter.len()..];
+  }
+
+  Some(subfiles)
+}
+
+fn skyfile_metadata(skyfile: &MockSkyfile) -> String {
+  let mut offset = 0;
+  let mut subfiles = serde_json::Map::new();
+
+  for subfile in &skyfile.subfiles {
+    subfiles.insert(subfile.filename.clone(), json!({
+      "filename": subfile.filename,
+      "contenttype": subfile.content_type,
+      "offset": offset,
+      "len": subfile.data.len(),
+    }));
+    offset += subfile.data.len();
+  }
+
+  let mut metadata = json!({
+    "filename": skyfile.filename,
+    "length": offset,
+    "subfiles": subfiles,
+  });
+  if let Some(ref default_path) = skyfile.default_path {
+    metadata["defaultpath"] = json!(format!("/{}", default_path));
+  }
+  metadata.to_string()
+}
+
+async fn handle(state: Arc<Mutex<MockState>>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+  let path = req.uri().path().to_string();
+
+  let res = match (req.method().clone(), path.as_str()) {
+    (Method::POST, path) if path == "/skynet/skyfile" || path.starts_with("/skynet/skyfile/") => {
+      handle_upload(state, req).await
+    }
+    (Method::GET, "/skynet/registry") => handle_get_entry(state, req),
+    (Method::POST, "/skynet/registry") => handle_set_entry(state, req).await,
+    (Method::POST, "/skynet/createskykey") => handle_create_skykey(state, req),
+    (Method::GET, "/skynet/skykey") => handle_get_skykey(state, req),
+    (Method::GET, "/health-check") => respond(StatusCode::OK, Body::from(json!({ "up": true }).to_string())),
+    (Method::GET, path) if path.starts_with("/skynet/metadata/") => handle_metadata(state, path),
+    (Method::GET, _) | (Method::HEAD, _) => handle_download(state, req),
+    _ => error(StatusCode::NOT_FOUND, "unknown endpoint"),
+  };
+
+  Ok(res)
+}
+
+async fn handle_upload(state: Arc<Mutex<MockState>>, req: Request<Body>) -> Response<Body> {
+  let query = parse_query(&req);
+  let content_type = req
+    .headers()
+    .get("content-type")
+    .and_then(|value| value.to_str().ok())
+    .unwrap_or("")
+    .to_string();
+  let body = match body::to_bytes(req.into_body()).await {
+    Ok(body) => body,
+    Err(_) => return error(StatusCode::BAD_REQUEST, "failed to read body"),
+  };
+
+  let subfiles = match parse_multipart(&content_type, &body) {
+    Some(subfiles) if !subfiles.is_empty() => subfiles,
+    _ => return error(StatusCode::BAD_REQUEST, "invalid multipart body"),
+  };
+
+  let filename = if let Some(filename) = query.get("filename") {
+    filename.clone()
+  } else {
+    subfiles[0].filename.clone()
+  };
+
+  let skykey_id = match (query.get("skykeyname"), query.get("skykeyid")) {
+    (Some(_), Some(_)) => return error(StatusCode::BAD_REQUEST, "cannot set both skykeyname and skykeyid"),
+    (Some(name), None) => find_skykey(&state, |skykey| &skykey.name == name),
+    (None, Some(id)) => find_skykey(&state, |skykey| &skykey.id == id),
+    (None, None) => None,
+  };
+  let skykey_id = match skykey_id {
+    Some(skykey) => Some(skykey.id),
+    None if query.contains_key("skykeyname") || query.contains_key("skykeyid") => {
+      return error(StatusCode::BAD_REQUEST, "unable to get skykey");
+    }
+    None => None,
+  };
+
+  let default_path = query.get("defaultpath").map(|path| path.trim_start_matches('/').to_string());
+  let skyfile = MockSkyfile { filename, subfiles, skykey_id, default_path };
+  let mut hashed = skyfile_metadata(&skyfile).into_bytes();
+  if let Some(ref skykey_id) = skyfile.skykey_id {
+    hashed.extend_from_slice(skykey_id.as_bytes());
+  }
+  for subfile in &skyfile.subfiles {
+    hashed.extend_from_slice(&subfile.data);
+  }
+  let (skylink, merkleroot) = make_skylink(&hashed);
+
+  state.lock().unwrap().skyfiles.insert(skylink.clone(), skyfile);
+
+  let res = json!({
+    "skylink": skylink,
+    "merkleroot": merkleroot.encode_hex::<String>(),
+    "bitfield": 0,
+  });
+  respond(StatusCode::OK, Body::from(res.to_string()))
+}
+
+fn find_skykey(
+  state: &Arc<Mutex<MockState>>,
+  matches: impl Fn(&MockSkykey) -> bool,
+) -> Option<MockSkykey> {
+  state.lock().unwrap().skykeys.iter().find(|skykey| matches(skykey)).cloned()
+}
+
+fn handle_create_skykey(state: Arc<Mutex<MockState>>, req: Request<Body>) -> Response<Body> {
+  let query = parse_query(&req);
+  let name = query.get("name").cloned().unwrap_or_default();
+  if name.is_empty() {
+    return error(StatusCode::BAD_REQUEST, "a skykey name is required");
+  }
+
+  let mut state = state.lock().unwrap();
+  if state.skykeys.iter().any(|skykey| skykey.name == name) {
+    return error(StatusCode::BAD_REQUEST, "skykey name already used");
+  }
+
+  let (id, _) = make_skylink(name.as_bytes());
+  let skykey = MockSkykey {
+    name,
+    id: id[..22].to_string(),
+    key_type: query.get("type").cloned().unwrap_or_else(|| "private-id".into()),
+  };
+  let res = skykey.to_json();
+  state.skykeys.push(skykey);
+  respond(StatusCode::OK, Body::from(res))
+}
+
+fn handle_get_skykey(state: Arc<Mutex<MockState>>, req: Request<Body>) -> Response<Body> {
+  let query = parse_query(&req);
+  let skykey = match (query.get("name"), query.get("id")) {
+    (Some(name), _) => find_skykey(&state, |skykey| &skykey.name == name),
+    (None, Some(id)) => find_skykey(&state, |skykey| &skykey.id == id),
+    (None, None) => return error(StatusCode::BAD_REQUEST, "a skykey name or id is required"),
+  };
+
+  match skykey {
+    Some(skykey) => respond(StatusCode::OK, Body::from(skykey.to_json())),
+    None => error(StatusCode::BAD_REQUEST, "unable to get skykey"),
+  }
+}
+
+fn handle_metadata(state: Arc<Mutex<MockState>>, path: &str) -> Response<Body> {
+  let skylink = path.trim_start_matches("/skynet/metadata/");
+
+  match state.lock().unwrap().skyfiles.get(skylink) {
+    Some(skyfile) => Response::builder()
+      .status(StatusCode::OK)
+      .header("content-type", "application/json")
+      .header("skynet-skylink", skylink)
+      .body(Body::from(skyfile_metadata(skyfile)))
+      .unwrap(),
+    None => error(StatusCode::NOT_FOUND, "skylink not found"),
+  }
+}
+
+fn handle_download(state: Arc<Mutex<MockState>>, req: Request<Body>) -> Response<Body> {
+  let mut parts = req.uri().path().trim_start_matches('/').splitn(2, '/');
+  let skylink = parts.next().unwrap_or("").to_string();
+  let subpath = percent_decode(parts.next().unwrap_or(""));
+
+  let skyfile = match state.lock().unwrap().skyfiles.get(&skylink) {
+    Some(skyfile) => skyfile.clone(),
+    None => return error(StatusCode::NOT_FOUND, "skylink not found"),
+  };
+
+  let subfile = if !subpath.is_empty() {
+    skyfile.subfiles.iter().find(|subfile| subfile.filename == subpath)
+  } else if let Some(ref default_path) = skyfile.default_path {
+    skyfile.subfiles.iter().find(|subfile| &subfile.filename == default_path)
+  } else if skyfile.subfiles.len() == 1 {
+    skyfile.subfiles.first()
+  } else {
+    skyfile.subfiles.iter().find(|subfile| subfile.filename == "index.html")
+  };
+
+  let subfile = match subfile {
+    Some(subfile) => subfile.clone(),
+    None => return error(StatusCode::BAD_REQUEST, "skyfile has no default path"),
+  };
+
+  let body = if req.method() == Method::HEAD {
+    Body::empty()
+  } else {
+    Body::from(subfile.data.clone())
+  };
+
+  Response::builder()
+    .status(StatusCode::OK)
+    .header("content-type", subfile.content_type.as_str())
+    .header("content-length", subfile.data.len())
+    .header("content-disposition", format!("inline; filename=\"{}\"", subfile.filename))
+    .header("skynet-skylink", skylink.as_str())
+    .header("skynet-file-metadata", skyfile_metadata(&skyfile))
+    .body(body)
+    .unwrap()
+}
+
+fn handle_get_entry(state: Arc<Mutex<MockState>>, req: Request<Body>) -> Response<Body> {
+  let query = parse_query(&req);
+  let key = (
+    query.get("publickey").cloned().unwrap_or_default(),
+    query.get("datakey").cloned().unwrap_or_default(),
+  );
+
+  match state.lock().unwrap().registry.get(&key) {
+    Some(entry) => {
+      let res = json!({
+        "data": entry.data.encode_hex::<String>(),
+        "revision": entry.revision,
+        "signature": entry.signature.encode_hex::<String>(),
+      });
+      respond(StatusCode::OK, Body::from(res.to_string()))
+    }
+    None => error(StatusCode::NOT_FOUND, "registry entry not found"),
+  }
+}
+
+#[derive(Deserialize)]
+struct MockPublicKey {
+  key: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct MockSetEntry {
+  publickey: MockPublicKey,
+  datakey: String,
+  revision: u64,
+  data: Vec<u8>,
+  signature: Vec<u8>,
+}
+
+async fn handle_set_entry(state: Arc<Mutex<MockState>>, req: Request<Body>) -> Response<Body> {
+  let body = match body::to_bytes(req.into_body()).await {
+    Ok(body) => body,
+    Err(_) => return error(StatusCode::BAD_REQUEST, "failed to read body"),
+  };
+
+  let entry: MockSetEntry = match serde_json::from_slice(&body) {
+    Ok(entry) => entry,
+    Err(_) => return error(StatusCode::BAD_REQUEST, "invalid registry entry"),
+  };
+
+  let key = (
+    format!("ed25519:{}", entry.publickey.key.encode_hex::<String>()),
+    entry.datakey,
+  );
+
+  let mut state = state.lock().unwrap();
+  if let Some(existing) = state.registry.get(&key) {
+    if existing.revision >= entry.revision {
+      return error(StatusCode::BAD_REQUEST, "revision number is too low");
+    }
+  }
+
+  state.registry.insert(key, MockEntry {
+    data: entry.data,
+    revision: entry.revision,
+    signature: entry.signature,
+  });
+
+  respond(StatusCode::NO_CONTENT, Body::empty())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use bytes::Bytes;
+
+  #[test]
+  fn test_parse_multipart() {
+    let body = b"--xyz\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\nContent-Type: text/plain\r\n\r\nhello\r\n--xyz--\r\n";
+    let subfiles = parse_multipart("multipart/form-data; boundary=\"xyz\"", body).unwrap();
+    assert_eq!(subfiles.len(), 1);
+    assert_eq!(subfiles[0].filename, "a.txt");
+    assert_eq!(subfiles[0].content_type, "text/plain");
+    assert_eq!(subfiles[0].data, b"hello".to_vec());
+  }
+
+  #[tokio::test]
+  async fn test_mock_transport() {
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::json(json!({ "skylink": "AAA" })));
+    let client = transport.client("https://siasky.net");
+
+    let mut data = HashMap::new();
+    data.insert("hello.txt".into(), (mime::TEXT_PLAIN, Bytes::from("hello world")));
+    let res = client.upload_data(data, crate::UploadOptions::default()).await;
+    assert_eq!(res.unwrap(), "sia://AAA");
+
+    let req = transport.last_request().unwrap();
+    assert_eq!(req.method, Method::POST);
+    assert_eq!(req.uri.path(), "/skynet/skyfile");
+    assert!(str::from_utf8(&req.body).unwrap().contains("hello world"));
+    assert_eq!(transport.requests().len(), 1);
+  }
+}
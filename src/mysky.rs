@@ -0,0 +1,210 @@
+use crate::{
+  crypto, skydb, DbEntry, EntryOptions, KeyPair, PublicKey, Seed, SkyDbOptions, SkynetClient, SkynetError::*,
+  SkynetResult,
+};
+use std::str;
+use hex::ToHex;
+use serde::{de::DeserializeOwned, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PermCategory {
+  Discoverable = 1,
+  Hidden = 2,
+  LegacySkyID = 3,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PermType {
+  Read = 1,
+  Write = 2,
+}
+
+/// Access granted to the app at `requestor` (a domain such as `app.hns`) for `path` and
+/// everything below it.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Permission {
+  pub requestor: String,
+  pub path: String,
+  pub category: PermCategory,
+  pub perm_type: PermType,
+}
+
+impl Permission {
+  pub fn new(requestor: &str, path: &str, category: PermCategory, perm_type: PermType) -> Self {
+    Self {
+      requestor: requestor.to_string(),
+      path: path.to_string(),
+      category,
+      perm_type,
+    }
+  }
+
+  /// Whether this permission lets `requestor` access `path` in the given way.
+  pub fn allows(&self, requestor: &str, path: &str, category: PermCategory, perm_type: PermType) -> bool {
+    let (granted, requested) = match (sanitize_path(&self.path), sanitize_path(path)) {
+      (Some(granted), Some(requested)) => (granted, requested),
+      _ => return false,
+    };
+
+    self.requestor.eq_ignore_ascii_case(requestor)
+      && self.category == category
+      && self.perm_type == perm_type
+      && is_path_contained(&granted, &requested)
+  }
+}
+
+/// Normalizes a MySky path: collapses repeated slashes, drops trailing ones and lowercases the
+/// domain. Returns `None` for empty or absolute paths.
+pub fn sanitize_path(path: &str) -> Option<String> {
+  let path = path.trim();
+  if path.is_empty() || path.starts_with('/') {
+    return None;
+  }
+
+  let mut components = path.split('/').filter(|component| !component.is_empty());
+  let domain = components.next()?.to_lowercase();
+
+  Some(std::iter::once(domain).chain(components.map(str::to_string)).collect::<Vec<_>>().join("/"))
+}
+
+/// The domain a path belongs to, i.e. its first component.
+pub fn get_path_domain(path: &str) -> Option<String> {
+  sanitize_path(path).map(|path| path.split('/').next().unwrap().to_string())
+}
+
+/// Whether `child` is `parent` or lies below it. Both are compared component by component, so
+/// `app.hns/foo` does not contain `app.hns/foobar`.
+pub fn is_path_contained(parent: &str, child: &str) -> bool {
+  let (parent, child) = match (sanitize_path(parent), sanitize_path(child)) {
+    (Some(parent), Some(child)) => (parent, child),
+    _ => return false,
+  };
+
+  let mut child_components = child.split('/');
+  parent.split('/').all(|component| child_components.next() == Some(component))
+}
+
+/// Apps always have access to paths under their own domain.
+pub fn is_own_domain(requestor: &str, path: &str) -> bool {
+  get_path_domain(path) == Some(requestor.to_lowercase())
+}
+
+/// A MySky identity acting straight from its seed, for server-side apps that can't go through
+/// the MySky UI. Paths are sanitized the way MySky does before keys are derived from them.
+#[derive(Debug)]
+pub struct MySky {
+  keypair: KeyPair,
+  root_path_seed: Seed,
+}
+
+impl MySky {
+  pub fn from_seed(seed: &[u8]) -> Self {
+    Self {
+      keypair: crypto::gen_mysky_keypair(seed),
+      root_path_seed: crypto::derive_root_path_seed(seed),
+    }
+  }
+
+  /// The hex public key identifying the user, e.g. for `get_discoverable_json`.
+  pub fn user_id(&self) -> String {
+    self.keypair.public_key.encode_hex()
+  }
+
+  pub fn public_key(&self) -> PublicKey {
+    PublicKey::from(&self.keypair)
+  }
+
+  /// Path seed of the hidden file or directory at `path`. A file's registry data key and
+  /// encryption key follow from it with `derive_encrypted_file_tweak` and
+  /// `derive_encrypted_file_key_entropy`.
+  pub fn encrypted_path_seed(&self, path: &str, is_directory: bool) -> SkynetResult<String> {
+    let path = sanitize_path(path).ok_or_else(|| InvalidPath(path.to_string()))?;
+    // The root path seed is hex, so it is always valid UTF-8.
+    let root_path_seed = str::from_utf8(self.root_path_seed.as_bytes()).unwrap();
+    crypto::derive_encrypted_path_seed(root_path_seed, &path, is_directory)
+  }
+
+  /// Reads this user's discoverable file at `path`.
+  pub async fn get_json<T: DeserializeOwned>(
+    &self,
+    client: &SkynetClient,
+    path: &str,
+    opt: &SkyDbOptions,
+  ) -> SkynetResult<Option<DbEntry<T>>> {
+    let path = sanitize_path(path).ok_or_else(|| InvalidPath(path.to_string()))?;
+    skydb::get_discoverable_json(client, &self.user_id(), &path, opt).await
+  }
+
+  /// Writes this user's discoverable file at `path`, returning the skylink of the new data.
+  pub async fn set_json<T: Serialize>(
+    &self,
+    client: &SkynetClient,
+    path: &str,
+    data: &T,
+    opt: &SkyDbOptions,
+  ) -> SkynetResult<String> {
+    let path = sanitize_path(path).ok_or_else(|| InvalidPath(path.to_string()))?;
+    let data_key = crypto::derive_discoverable_file_tweak(&path).encode_hex::<String>();
+    let opt = SkyDbOptions {
+      entry: EntryOptions { hashed_data_key_hex: true, ..opt.entry.clone() },
+      ..opt.clone()
+    };
+    skydb::db_set(client, &self.keypair, &data_key, data, &opt).await
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::MockPortal;
+
+  #[test]
+  fn test_path_helpers() {
+    assert_eq!(sanitize_path("App.hns//foo/bar/"), Some("app.hns/foo/bar".into()));
+    assert_eq!(sanitize_path("/app.hns"), None);
+    assert_eq!(sanitize_path("  "), None);
+    assert_eq!(get_path_domain("App.hns/foo"), Some("app.hns".into()));
+
+    assert!(is_path_contained("app.hns/foo", "app.hns/foo"));
+    assert!(is_path_contained("app.hns/foo", "app.hns/foo/bar.json"));
+    assert!(is_path_contained("app.hns", "APP.hns/foo"));
+    assert!(!is_path_contained("app.hns/foo", "app.hns/foobar"));
+    assert!(!is_path_contained("app.hns/foo/bar", "app.hns/foo"));
+
+    assert!(is_own_domain("app.hns", "app.hns/data.json"));
+    assert!(!is_own_domain("app.hns", "other.hns/data.json"));
+  }
+
+  #[test]
+  fn test_permission_allows() {
+    let perm = Permission::new("app.hns", "feed-dac.hns/app.hns", PermCategory::Discoverable, PermType::Write);
+    assert!(perm.allows("app.hns", "feed-dac.hns/app.hns/posts/index.json", PermCategory::Discoverable, PermType::Write));
+    assert!(!perm.allows("other.hns", "feed-dac.hns/app.hns/posts", PermCategory::Discoverable, PermType::Write));
+    assert!(!perm.allows("app.hns", "feed-dac.hns/app.hns", PermCategory::Hidden, PermType::Write));
+    assert!(!perm.allows("app.hns", "feed-dac.hns/app.hns", PermCategory::Discoverable, PermType::Read));
+    assert!(!perm.allows("app.hns", "feed-dac.hns/other.hns", PermCategory::Discoverable, PermType::Write));
+  }
+
+  #[tokio::test]
+  async fn test_mysky_discoverable_json() {
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let mysky = MySky::from_seed(b"correct horse battery staple");
+    let opt = SkyDbOptions::default();
+    assert_eq!(mysky.user_id(), MySky::from_seed(b"correct horse battery staple").user_id());
+
+    assert!(mysky.get_json::<u32>(&client, "app.hns/count.json", &opt).await.unwrap().is_none());
+    let skylink = mysky.set_json(&client, "App.hns/count.json", &7, &opt).await.unwrap();
+
+    let res = mysky.get_json::<u32>(&client, "app.hns/count.json", &opt).await;
+    println!("{:?}", res);
+    let entry = res.unwrap().unwrap();
+    assert_eq!((entry.data, entry.skylink), (7, skylink));
+
+    let res = skydb::get_discoverable_json::<u32>(&client, &mysky.user_id(), "app.hns/count.json", &opt).await;
+    assert_eq!(res.unwrap().unwrap().data, 7);
+
+    assert!(matches!(mysky.set_json(&client, "/count.json", &7, &opt).await, Err(InvalidPath(_))));
+    assert_eq!(mysky.encrypted_path_seed("App.hns//a", false).unwrap(), mysky.encrypted_path_seed("app.hns/a", false).unwrap());
+  }
+}
@@ -0,0 +1,386 @@
+use crate::{
+  crypto::merkle_root,
+  skylink::{parse_skylink, Skylink, SECTOR_SIZE},
+  upload, SkylinkFormat, SkynetClient, SkynetError::*, SkynetResult, UploadOptions,
+};
+#[cfg(feature = "checksum")]
+use crate::ChecksumAlgorithm;
+use std::{
+  collections::{BTreeMap, HashMap},
+  fs,
+  path::{Path, PathBuf},
+  sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+  },
+};
+use bytes::Bytes;
+use hex::ToHex;
+use mime::Mime;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueuedFile {
+  pub filename: String,
+  pub content_type: String,
+}
+
+/// The `UploadOptions` a queued upload is sent with, so that it gets the skylink it would have
+/// online. Credentials, hooks and the deadline aren't kept: the flushing client's credentials
+/// apply, the hooks ran when the upload was queued and the deadline was for that call.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueuedOptions {
+  pub endpoint_path: String,
+  pub custom_user_agent: Option<String>,
+  pub portal_file_fieldname: String,
+  pub portal_directory_file_fieldname: String,
+  pub custom_filename: Option<String>,
+  pub custom_dirname: Option<String>,
+  pub siapath: Option<String>,
+  pub force: bool,
+  pub root: bool,
+  pub skylink_format: SkylinkFormat,
+  pub skykey_name: Option<String>,
+  /// Also set from `UploadOptions::skykey`, which is sent by its ID.
+  pub skykey_id: Option<String>,
+  #[cfg(feature = "gzip")]
+  #[serde(default)]
+  pub gzip: bool,
+  pub max_size: Option<u64>,
+  #[cfg(feature = "account")]
+  #[serde(default)]
+  pub check_account_limits: bool,
+  #[cfg(feature = "directory")]
+  #[serde(default)]
+  pub preserve_attributes: bool,
+  #[cfg(feature = "checksum")]
+  #[serde(default)]
+  pub checksum: Option<ChecksumAlgorithm>,
+}
+
+impl QueuedOptions {
+  fn new(opt: &UploadOptions) -> Self {
+    #[cfg(feature = "encryption")]
+    let skykey_id = opt.skykey.as_ref().map(|skykey| skykey.id().to_string()).or_else(|| opt.skykey_id.clone());
+    #[cfg(not(feature = "encryption"))]
+    let skykey_id = opt.skykey_id.clone();
+
+    Self {
+      endpoint_path: opt.endpoint_path.clone(),
+      custom_user_agent: opt.custom_user_agent.clone(),
+      portal_file_fieldname: opt.portal_file_fieldname.clone(),
+      portal_directory_file_fieldname: opt.portal_directory_file_fieldname.clone(),
+      custom_filename: opt.custom_filename.clone(),
+      custom_dirname: opt.custom_dirname.clone(),
+      siapath: opt.siapath.clone(),
+      force: opt.force,
+      root: opt.root,
+      skylink_format: opt.skylink_format,
+      skykey_name: opt.skykey_name.clone(),
+      skykey_id,
+      #[cfg(feature = "gzip")]
+      gzip: opt.gzip,
+      max_size: opt.max_size,
+      #[cfg(feature = "account")]
+      check_account_limits: opt.check_account_limits,
+      #[cfg(feature = "directory")]
+      preserve_attributes: opt.preserve_attributes,
+      #[cfg(feature = "checksum")]
+      checksum: opt.checksum,
+    }
+  }
+
+  fn upload_options(&self) -> UploadOptions {
+    UploadOptions {
+      endpoint_path: self.endpoint_path.clone(),
+      custom_user_agent: self.custom_user_agent.clone(),
+      portal_file_fieldname: self.portal_file_fieldname.clone(),
+      portal_directory_file_fieldname: self.portal_directory_file_fieldname.clone(),
+      custom_filename: self.custom_filename.clone(),
+      custom_dirname: self.custom_dirname.clone(),
+      siapath: self.siapath.clone(),
+      force: self.force,
+      root: self.root,
+      skylink_format: self.skylink_format,
+      skykey_name: self.skykey_name.clone(),
+      skykey_id: self.skykey_id.clone(),
+      #[cfg(feature = "gzip")]
+      gzip: self.gzip,
+      max_size: self.max_size,
+      #[cfg(feature = "account")]
+      check_account_limits: self.check_account_limits,
+      #[cfg(feature = "directory")]
+      preserve_attributes: self.preserve_attributes,
+      #[cfg(feature = "checksum")]
+      checksum: self.checksum,
+      ..UploadOptions::default()
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueuedUpload {
+  pub local_skylink: String,
+  pub options: QueuedOptions,
+  pub files: Vec<QueuedFile>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlushedUpload {
+  pub local_skylink: String,
+  pub skylink: String,
+  pub path: String,
+  pub size: u64,
+}
+
+/// On-disk upload queue and download cache used while the client is offline.
+///
+/// Queued uploads get a skylink computed from a locally built base sector. Portals may lay the
+/// sector out differently, so `flush` reports the skylink each queued upload actually received.
+#[derive(Debug, Clone)]
+pub struct OfflineStore {
+  dir: PathBuf,
+  offline: Arc<AtomicBool>,
+}
+
+impl OfflineStore {
+  pub fn open<P: AsRef<Path>>(dir: P) -> SkynetResult<Self> {
+    let dir = dir.as_ref().to_path_buf();
+    fs::create_dir_all(dir.join("queue")).map_err(FileError)?;
+    fs::create_dir_all(dir.join("cache")).map_err(FileError)?;
+
+    Ok(Self {
+      dir,
+      offline: Arc::new(AtomicBool::new(true)),
+    })
+  }
+
+  pub fn is_offline(&self) -> bool {
+    self.offline.load(Ordering::SeqCst)
+  }
+
+  pub fn set_offline(&self, offline: bool) {
+    self.offline.store(offline, Ordering::SeqCst);
+  }
+
+  /// Keyed on the canonical base64 skylink, so that every form of a skylink finds the same
+  /// entry. A path is appended in hex, which can't escape the cache directory.
+  fn cache_path(&self, skylink: &str) -> SkynetResult<PathBuf> {
+    let name = match parse_skylink(skylink)? {
+      (skylink, Some(path)) => format!("{}.{}", skylink, path.encode_hex::<String>()),
+      (skylink, None) => skylink.to_string(),
+    };
+    Ok(self.dir.join("cache").join(name))
+  }
+
+  pub fn cache_get(&self, skylink: &str) -> SkynetResult<Option<Vec<u8>>> {
+    let path = self.cache_path(skylink)?;
+    if path.is_file() {
+      Ok(Some(fs::read(path).map_err(FileError)?))
+    } else {
+      Ok(None)
+    }
+  }
+
+  pub fn cache_put(&self, skylink: &str, data: &[u8]) -> SkynetResult<()> {
+    fs::write(self.cache_path(skylink)?, data).map_err(FileError)
+  }
+
+  /// Queues `data` to be uploaded on the next flush, after running the upload hooks on it.
+  pub fn queue_upload(
+    &self,
+    data: HashMap<String, (Mime, Bytes)>,
+    opt: &UploadOptions,
+  ) -> SkynetResult<String> {
+    let data: BTreeMap<_, _> = upload::apply_hooks(data, &opt.hooks)?.into_iter().collect();
+    let local_skylink = compute_skylink(&data, opt.custom_dirname.as_deref())?.to_string();
+
+    let dir = self.dir.join("queue").join(&local_skylink);
+    fs::create_dir_all(&dir).map_err(FileError)?;
+
+    let mut files = Vec::new();
+    for (i, (filename, (mime, bytes))) in data.iter().enumerate() {
+      fs::write(dir.join(i.to_string()), bytes).map_err(FileError)?;
+      files.push(QueuedFile {
+        filename: filename.clone(),
+        content_type: mime.to_string(),
+      });
+    }
+
+    let queued = QueuedUpload {
+      local_skylink: local_skylink.clone(),
+      options: QueuedOptions::new(opt),
+      files,
+    };
+    let manifest = serde_json::to_vec(&queued).map_err(JsonError)?;
+    fs::write(dir.join("upload.json"), manifest).map_err(FileError)?;
+
+    let default_file = if data.len() == 1 {
+      data.values().next()
+    } else {
+      data.get("index.html")
+    };
+    if let Some((_, bytes)) = default_file {
+      self.cache_put(&local_skylink, bytes)?;
+    }
+
+    Ok(local_skylink)
+  }
+
+  pub fn queued(&self) -> SkynetResult<Vec<QueuedUpload>> {
+    let mut queued = Vec::new();
+
+    for entry in fs::read_dir(self.dir.join("queue")).map_err(FileError)? {
+      let manifest = entry.map_err(FileError)?.path().join("upload.json");
+      if manifest.is_file() {
+        let bytes = fs::read(manifest).map_err(FileError)?;
+        queued.push(serde_json::from_slice(&bytes).map_err(JsonError)?);
+      }
+    }
+
+    Ok(queued)
+  }
+
+  fn load_queued(&self, queued: &QueuedUpload) -> SkynetResult<HashMap<String, (Mime, Bytes)>> {
+    let dir = self.dir.join("queue").join(&queued.local_skylink);
+    let mut data = HashMap::new();
+
+    for (i, file) in queued.files.iter().enumerate() {
+      let bytes = fs::read(dir.join(i.to_string())).map_err(FileError)?;
+      let mime = file.content_type.parse().unwrap_or(mime::APPLICATION_OCTET_STREAM);
+      data.insert(file.filename.clone(), (mime, bytes.into()));
+    }
+
+    Ok(data)
+  }
+
+  fn remove_queued(&self, queued: &QueuedUpload) -> SkynetResult<()> {
+    fs::remove_dir_all(self.dir.join("queue").join(&queued.local_skylink)).map_err(FileError)
+  }
+}
+
+fn compute_skylink(data: &BTreeMap<String, (Mime, Bytes)>, custom_dirname: Option<&str>) -> SkynetResult<Skylink> {
+  let mut offset = 0;
+  let mut subfiles = serde_json::Map::new();
+  let mut content = Vec::new();
+
+  for (filename, (mime, bytes)) in data {
+    subfiles.insert(filename.clone(), json!({
+      "filename": filename,
+      "contenttype": mime.to_string(),
+      "offset": offset,
+      "len": bytes.len(),
+    }));
+    offset += bytes.len();
+    content.extend_from_slice(bytes);
+  }
+
+  let filename = custom_dirname
+    .map(|dirname| dirname.to_string())
+    .or_else(|| data.keys().next().cloned())
+    .unwrap_or_default();
+  let metadata = json!({
+    "filename": filename,
+    "length": offset,
+    "subfiles": subfiles,
+  }).to_string().into_bytes();
+
+  let mut sector = Vec::with_capacity(99 + metadata.len() + content.len());
+  sector.push(1);
+  sector.extend_from_slice(&(content.len() as u64).to_le_bytes());
+  sector.extend_from_slice(&(metadata.len() as u64).to_le_bytes());
+  sector.extend_from_slice(&0u64.to_le_bytes());
+  sector.extend_from_slice(&[0; 2 + 8 + 64]);
+  sector.extend_from_slice(&metadata);
+  sector.extend_from_slice(&content);
+
+  let fetch_size = (sector.len() as u64).min(SECTOR_SIZE);
+  if sector.len() as u64 <= SECTOR_SIZE {
+    sector.resize(SECTOR_SIZE as usize, 0);
+  }
+
+  Skylink::new_v1(merkle_root(&sector), 0, fetch_size)
+}
+
+pub async fn flush(client: &SkynetClient, store: &OfflineStore) -> SkynetResult<Vec<FlushedUpload>> {
+  let mut flushed = Vec::new();
+
+  for queued in store.queued()? {
+    let data = store.load_queued(&queued)?;
+    let path = queued
+      .options
+      .custom_dirname
+      .clone()
+      .or_else(|| data.keys().next().cloned())
+      .unwrap_or_default();
+    let size = data.values().map(|(_, bytes)| bytes.len() as u64).sum();
+
+    let skylink = upload::upload_data(client, data, queued.options.upload_options()).await?;
+
+    if let Some(bytes) = store.cache_get(&queued.local_skylink)? {
+      store.cache_put(&skylink, &bytes)?;
+    }
+    store.remove_queued(&queued)?;
+
+    flushed.push(FlushedUpload {
+      local_skylink: queued.local_skylink,
+      skylink,
+      path,
+      size,
+    });
+  }
+
+  Ok(flushed)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{DownloadOptions, MockPortal, SkynetClientOptions};
+
+  #[tokio::test]
+  async fn test_offline_queue_and_flush() {
+    let portal = MockPortal::start().await;
+    let dir = std::env::temp_dir().join(format!("skynet-rs-offline-{}", std::process::id()));
+    let store = OfflineStore::open(&dir).unwrap();
+    let client = SkynetClient::new(portal.url(), SkynetClientOptions {
+      offline: Some(store.clone()),
+      ..Default::default()
+    });
+
+    let mut data = HashMap::new();
+    data.insert("hello.txt".into(), (mime::TEXT_PLAIN, Bytes::from("hello world")));
+    let opt = UploadOptions { skylink_format: SkylinkFormat::Raw, ..Default::default() };
+    let local_skylink = client.upload_data(data, opt).await.unwrap();
+    assert_eq!(local_skylink.len(), 46);
+    assert_eq!(portal.skyfile_count(), 0);
+    assert_eq!(store.queued().unwrap()[0].options.skylink_format, SkylinkFormat::Raw);
+
+    let data = client.download_data(&local_skylink, DownloadOptions::default()).await.unwrap();
+    assert_eq!(data, b"hello world".to_vec());
+    let data = client.download_data(&format!("sia://{}/", local_skylink), DownloadOptions::default()).await.unwrap();
+    assert_eq!(data, b"hello world".to_vec());
+    assert!(store.cache_get(&format!("{}/../../escaped", local_skylink)).unwrap().is_none());
+    assert!(store.cache_get("../escaped").is_err());
+
+    store.set_offline(false);
+    let flushed = client.flush().await.unwrap();
+    assert_eq!(flushed.len(), 1);
+    assert_eq!(flushed[0].local_skylink, local_skylink);
+    assert_eq!(flushed[0].skylink.len(), 46);
+    assert_eq!(portal.skyfile_count(), 1);
+    assert!(store.queued().unwrap().is_empty());
+
+    // Error pages aren't cached as content.
+    let missing = "AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng";
+    client.download_data(missing, DownloadOptions::default()).await.unwrap();
+    client.download_file(dir.join("missing"), missing, DownloadOptions::default()).await.unwrap();
+    assert!(store.cache_get(missing).unwrap().is_none());
+
+    store.set_offline(true);
+    let data = client.download_data(&flushed[0].skylink, DownloadOptions::default()).await.unwrap();
+    assert_eq!(data, b"hello world".to_vec());
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
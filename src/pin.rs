@@ -0,0 +1,305 @@
+use crate::{
+  account, client::CallAuth, skylink::parse_skylink, util::{make_uri, read_body, status_error, QueryParams},
+  AccountOptions, AuthScheme, SkylinkHealth, SkynetClient, SkynetError, SkynetError::*, SkynetResult,
+};
+use std::time::Duration;
+use futures_util::future::join_all;
+use hyper::{Body, Request};
+use tokio::{
+  sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+  task::JoinHandle,
+};
+
+#[derive(Debug, Clone)]
+pub struct PinOptions {
+  /// Portal to pin on instead of the client's, e.g. the paid portal an account belongs to.
+  pub portal_url: Option<String>,
+  pub endpoint_path: String,
+  pub unpin_endpoint_path: String,
+  pub api_key: Option<String>,
+  pub auth: Option<AuthScheme>,
+  pub custom_user_agent: Option<String>,
+}
+
+impl Default for PinOptions {
+  fn default() -> Self {
+    Self {
+      portal_url: None,
+      endpoint_path: "/skynet/pin".into(),
+      unpin_endpoint_path: "/skynet/unpin".into(),
+      api_key: None,
+      auth: None,
+      custom_user_agent: None,
+    }
+  }
+}
+
+/// What `PinMaintainer` does with each skylink on every round.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinAction {
+  /// Pins the skylink again, which renews it on portals that expire unpinned content.
+  Repin,
+  /// Only checks that the skylink's base sector is still stored redundantly enough.
+  HealthCheck,
+}
+
+#[derive(Debug, Clone)]
+pub struct PinMaintainerOptions {
+  pub interval: Duration,
+  pub action: PinAction,
+  pub pin_endpoint_path: String,
+  /// Lowest base sector redundancy `PinAction::HealthCheck` accepts.
+  pub min_redundancy: u64,
+  pub api_key: Option<String>,
+  pub auth: Option<AuthScheme>,
+  pub custom_user_agent: Option<String>,
+}
+
+impl Default for PinMaintainerOptions {
+  fn default() -> Self {
+    Self {
+      interval: Duration::from_secs(24 * 60 * 60),
+      action: PinAction::Repin,
+      pin_endpoint_path: "/skynet/pin".into(),
+      min_redundancy: 1,
+      api_key: None,
+      auth: None,
+      custom_user_agent: None,
+    }
+  }
+}
+
+#[derive(Debug)]
+pub enum PinFailureReason {
+  /// The pin or health request failed.
+  Error(SkynetError),
+  /// The portal answered, but the skylink is stored less redundantly than `min_redundancy`.
+  LowRedundancy(SkylinkHealth),
+}
+
+/// A skylink that failed its round on one portal.
+#[derive(Debug)]
+pub struct PinFailure {
+  pub portal_url: String,
+  pub skylink: String,
+  pub reason: PinFailureReason,
+}
+
+/// Background task that keeps a set of skylinks alive on several portals, re-pinning or
+/// health-checking each one every `interval`, starting right away. Failures are sent to the
+/// receiver returned by `start`. The task stops when the maintainer is stopped or dropped, or
+/// once all its clients are shut down.
+#[derive(Debug)]
+pub struct PinMaintainer {
+  handle: JoinHandle<()>,
+}
+
+impl PinMaintainer {
+  /// Spawns the task on the current tokio runtime.
+  pub fn start(
+    portals: Vec<SkynetClient>,
+    skylinks: Vec<String>,
+    opt: PinMaintainerOptions,
+  ) -> (Self, UnboundedReceiver<PinFailure>) {
+    let (failures, receiver) = unbounded_channel();
+    let handle = tokio::spawn(async move {
+      let maintained = async {
+        let mut interval = tokio::time::interval(opt.interval);
+        loop {
+          interval.tick().await;
+          // Clients that were shut down are left out rather than failing every round.
+          let rounds = portals
+            .iter()
+            .filter(|client| !client.is_shut_down())
+            .flat_map(|client| skylinks.iter().map(move |skylink| (client, skylink)))
+            .map(|(client, skylink)| maintain(client, skylink, &opt, &failures));
+          join_all(rounds).await;
+        }
+      };
+      tokio::select! {
+        _ = maintained => {}
+        _ = join_all(portals.iter().map(|client| client.closed())) => {}
+      }
+    });
+
+    (Self { handle }, receiver)
+  }
+
+  pub fn stop(self) {
+    self.handle.abort();
+  }
+}
+
+impl Drop for PinMaintainer {
+  fn drop(&mut self) {
+    self.handle.abort();
+  }
+}
+
+async fn maintain(
+  client: &SkynetClient,
+  skylink: &str,
+  opt: &PinMaintainerOptions,
+  failures: &UnboundedSender<PinFailure>,
+) {
+  let reason = match opt.action {
+    PinAction::Repin => repin(client, skylink, opt).await.err().map(PinFailureReason::Error),
+    PinAction::HealthCheck => {
+      let account_opt = AccountOptions {
+        api_key: opt.api_key.clone(),
+        auth: opt.auth.clone(),
+        custom_user_agent: opt.custom_user_agent.clone(),
+        ..Default::default()
+      };
+      match account::get_skylink_health(client, skylink, account_opt).await {
+        Ok(health) if health.base_sector_redundancy < opt.min_redundancy => {
+          Some(PinFailureReason::LowRedundancy(health))
+        }
+        Ok(_) => None,
+        Err(err) => Some(PinFailureReason::Error(err)),
+      }
+    }
+  };
+
+  if let Some(reason) = reason {
+    // Nobody listening isn't a reason to stop maintaining the pins.
+    let _ = failures.send(PinFailure {
+      portal_url: client.get_portal_url(),
+      skylink: skylink.to_string(),
+      reason,
+    });
+  }
+}
+
+async fn repin(client: &SkynetClient, skylink: &str, opt: &PinMaintainerOptions) -> SkynetResult<()> {
+  pin_skylink(client, skylink, PinOptions {
+    endpoint_path: opt.pin_endpoint_path.clone(),
+    api_key: opt.api_key.clone(),
+    auth: opt.auth.clone(),
+    custom_user_agent: opt.custom_user_agent.clone(),
+    ..Default::default()
+  }).await
+}
+
+/// Pins `skylink` on the portal, so that it keeps the data alive and, on paid portals, counts
+/// it against the account's storage.
+pub async fn pin_skylink(client: &SkynetClient, skylink: &str, opt: PinOptions) -> SkynetResult<()> {
+  let endpoint_path = opt.endpoint_path.clone();
+  send_pin(client, endpoint_path, skylink, &opt).await
+}
+
+/// Unpins `skylink` from the portal, which may then let its data expire.
+pub async fn unpin_skylink(client: &SkynetClient, skylink: &str, opt: PinOptions) -> SkynetResult<()> {
+  let endpoint_path = opt.unpin_endpoint_path.clone();
+  send_pin(client, endpoint_path, skylink, &opt).await
+}
+
+async fn send_pin(client: &SkynetClient, endpoint_path: String, skylink: &str, opt: &PinOptions) -> SkynetResult<()> {
+  // Only the skylink itself is pinned, whatever path it comes with.
+  let (skylink, _) = parse_skylink(skylink)?;
+  let portal_url = opt.portal_url.clone().unwrap_or_else(|| client.get_portal_url());
+  let uri = make_uri(&portal_url, endpoint_path, opt.api_key.clone(), Some(skylink.to_string()), QueryParams::new());
+
+  let mut req = Request::builder().method("POST").uri(uri.clone());
+
+  if let Some(apikey) = &opt.api_key {
+    req = req.header("Skynet-Api-Key", apikey.clone());
+  }
+
+  if let Some(custom_user_agent) = &opt.custom_user_agent {
+    req = req.header("User-Agent", custom_user_agent);
+  }
+
+  req = req.extension(CallAuth(opt.auth.clone()));
+
+  let req = req.body(Body::empty()).map_err(HttpError)?;
+  let res = client.request(req).await?;
+  let (parts, body) = res.into_parts();
+
+  if !parts.status.is_success() {
+    let body = read_body(&parts, body).await?;
+    return Err(status_error(&parts.extensions, &uri, parts.status, &body));
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{MockResponse, MockTransport};
+  use hyper::{Method, StatusCode};
+  use serde_json::json;
+
+  const SKYLINK: &str = "AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng";
+
+  #[tokio::test]
+  async fn test_pin_maintainer_repin() {
+    let healthy = MockTransport::new();
+    healthy.push_response(MockResponse::new(StatusCode::NO_CONTENT, ""));
+    let broken = MockTransport::new();
+    broken.push_response(MockResponse::new(StatusCode::INTERNAL_SERVER_ERROR, "down"));
+    let portals = vec![healthy.client("https://siasky.net"), broken.client("https://skynetfree.net")];
+
+    let (maintainer, mut failures) = PinMaintainer::start(
+      portals,
+      vec![format!("sia://{}", SKYLINK)],
+      PinMaintainerOptions::default(),
+    );
+    let failure = failures.recv().await.unwrap();
+    maintainer.stop();
+
+    assert_eq!(failure.portal_url, "https://skynetfree.net");
+    assert_eq!(failure.skylink, format!("sia://{}", SKYLINK));
+    assert!(matches!(failure.reason, PinFailureReason::Error(RequestError(_, err)) if matches!(*err, PortalResponse(_))));
+
+    let req = healthy.last_request().unwrap();
+    assert_eq!(req.method, Method::POST);
+    assert_eq!(req.uri.path(), format!("/skynet/pin/{}", SKYLINK));
+  }
+
+  #[tokio::test]
+  async fn test_pin_unpin_skylink() {
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::new(StatusCode::NO_CONTENT, ""));
+    transport.push_response(MockResponse::new(StatusCode::NO_CONTENT, ""));
+    transport.push_response(MockResponse::new(StatusCode::UNAUTHORIZED, "no account"));
+    let client = transport.client("https://siasky.net");
+
+    let res = pin_skylink(&client, &format!("sia://{}/index.html", SKYLINK), PinOptions::default()).await;
+    println!("{:?}", res);
+    assert!(res.is_ok());
+    let req = transport.last_request().unwrap();
+    assert_eq!(req.method, Method::POST);
+    assert_eq!(req.uri.path(), format!("/skynet/pin/{}", SKYLINK));
+
+    let opt = PinOptions {
+      portal_url: Some("https://account.siasky.net".into()),
+      api_key: Some("key".into()),
+      ..Default::default()
+    };
+    assert!(unpin_skylink(&client, SKYLINK, opt.clone()).await.is_ok());
+    let req = transport.last_request().unwrap();
+    assert_eq!(req.uri.host(), Some("account.siasky.net"));
+    assert_eq!(req.uri.path(), format!("/skynet/unpin/{}", SKYLINK));
+    assert_eq!(req.headers["Skynet-Api-Key"], "key");
+
+    assert!(matches!(pin_skylink(&client, SKYLINK, opt).await.map_err(crate::SkynetError::into_inner), Err(PortalResponse(_))));
+    assert!(matches!(pin_skylink(&client, "AAA", PinOptions::default()).await, Err(InvalidSkylink(_))));
+  }
+
+  #[tokio::test]
+  async fn test_pin_maintainer_health_check() {
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::json(json!({ "basesectorredundancy": 0, "fanoutredundancy": 0.0 })));
+    let opt = PinMaintainerOptions { action: PinAction::HealthCheck, ..Default::default() };
+
+    let (_maintainer, mut failures) = PinMaintainer::start(
+      vec![transport.client("https://siasky.net")],
+      vec![SKYLINK.into()],
+      opt,
+    );
+    let failure = failures.recv().await.unwrap();
+    assert!(matches!(failure.reason, PinFailureReason::LowRedundancy(health) if health.base_sector_redundancy == 0));
+  }
+}
@@ -0,0 +1,179 @@
+use crate::{DownloadOptions, SkynetClient, SkynetError, SkynetResult, UploadOptions};
+use std::{path::PathBuf, sync::Mutex, time::Duration};
+use bytes::Bytes;
+use futures_util::{stream::Stream, StreamExt};
+use tokio::time::Instant;
+
+/// One transfer for a `TransferPool` to run.
+#[derive(Debug, Clone)]
+pub enum TransferJob {
+  UploadFile { path: PathBuf, opt: UploadOptions },
+  DownloadFile { path: PathBuf, skylink: String, opt: DownloadOptions },
+  DownloadData { skylink: String, opt: DownloadOptions },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransferOutput {
+  /// The skylink of an uploaded file.
+  Uploaded(String),
+  /// Where a downloaded file was saved.
+  Saved(PathBuf),
+  Data(Bytes),
+}
+
+/// How a job went, after any retries.
+#[derive(Debug)]
+pub struct TransferResult {
+  pub job: TransferJob,
+  pub attempts: u32,
+  pub output: SkynetResult<TransferOutput>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TransferPoolOptions {
+  /// How many jobs run at once.
+  pub concurrency: usize,
+  /// How many times a failed job is run again when `retry_if` allows it. This is on top of the
+  /// client's own retries of single requests.
+  pub retries: u32,
+  /// Wait before the first retry of a job, doubling with each one after.
+  pub retry_delay: Duration,
+  pub retry_if: fn(&SkynetError) -> bool,
+  /// Most jobs started per second, retries included.
+  pub max_jobs_per_second: Option<u32>,
+}
+
+impl Default for TransferPoolOptions {
+  fn default() -> Self {
+    Self {
+      concurrency: 8,
+      retries: 2,
+      retry_delay: Duration::from_secs(1),
+      retry_if: SkynetError::is_retryable,
+      max_jobs_per_second: None,
+    }
+  }
+}
+
+/// Runs streams of uploads and downloads on a client with bounded parallelism, retries and a
+/// rate limit, the scaffolding bulk migrations otherwise build around the client themselves.
+#[derive(Debug)]
+pub struct TransferPool {
+  client: SkynetClient,
+  opt: TransferPoolOptions,
+  /// When the next job may start, for `max_jobs_per_second`.
+  next_start: Mutex<Option<Instant>>,
+}
+
+impl TransferPool {
+  pub fn new(client: SkynetClient, opt: TransferPoolOptions) -> Self {
+    Self { client, opt, next_start: Mutex::new(None) }
+  }
+
+  /// Runs `jobs` as they come, yielding each result as soon as its job is done, so results can
+  /// come out of order. Jobs are only taken from `jobs` when there is room to run them.
+  pub fn run<'a, S>(&'a self, jobs: S) -> impl Stream<Item = TransferResult> + 'a
+  where
+    S: Stream<Item = TransferJob> + 'a,
+  {
+    jobs.map(move |job| self.run_job(job)).buffer_unordered(self.opt.concurrency.max(1))
+  }
+
+  async fn run_job(&self, job: TransferJob) -> TransferResult {
+    let mut attempts = 0;
+    let mut delay = self.opt.retry_delay;
+
+    loop {
+      self.wait_for_rate_limit().await;
+      attempts += 1;
+      match self.execute(&job).await {
+        Err(err) if attempts <= self.opt.retries && (self.opt.retry_if)(&err) => {
+          tokio::time::sleep(delay).await;
+          delay *= 2;
+        }
+        output => return TransferResult { job, attempts, output },
+      }
+    }
+  }
+
+  async fn wait_for_rate_limit(&self) {
+    let per_second = match self.opt.max_jobs_per_second {
+      Some(per_second) => per_second.max(1),
+      None => return,
+    };
+
+    // Each start reserves the next slot, so concurrent jobs space out instead of bursting.
+    let start = {
+      let mut next_start = self.next_start.lock().unwrap();
+      let start = next_start.map_or_else(Instant::now, |next| next.max(Instant::now()));
+      *next_start = Some(start + Duration::from_secs(1) / per_second);
+      start
+    };
+    tokio::time::sleep_until(start).await;
+  }
+
+  async fn execute(&self, job: &TransferJob) -> SkynetResult<TransferOutput> {
+    match job {
+      TransferJob::UploadFile { path, opt } => {
+        self.client.upload_file(path, opt.clone()).await.map(TransferOutput::Uploaded)
+      }
+      TransferJob::DownloadFile { path, skylink, opt } => {
+        self.client.download_file(path, skylink, opt.clone()).await.map(TransferOutput::Saved)
+      }
+      TransferJob::DownloadData { skylink, opt } => {
+        self.client.download_data(skylink, opt.clone()).await.map(TransferOutput::Data)
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{MockPortal, SkynetError::*};
+  use std::{collections::HashMap, fs};
+  use futures_util::stream;
+
+  #[tokio::test]
+  async fn test_transfer_pool() {
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let mut data = HashMap::new();
+    data.insert("hello.txt".to_string(), (mime::TEXT_PLAIN, "hello world"));
+    let skylink = client.upload_data(data, UploadOptions::default()).await.unwrap();
+    fs::write("tmp_pool.txt", "pooled").unwrap();
+
+    let pool = TransferPool::new(client, TransferPoolOptions {
+      concurrency: 2,
+      retries: 1,
+      retry_delay: Duration::from_millis(1),
+      retry_if: |err| matches!(err, InvalidSkylink(_)),
+      max_jobs_per_second: Some(100),
+    });
+    let jobs = vec![
+      TransferJob::UploadFile { path: "tmp_pool.txt".into(), opt: UploadOptions::default() },
+      TransferJob::DownloadData { skylink: skylink.clone(), opt: DownloadOptions::default() },
+      TransferJob::DownloadData { skylink: "not a skylink".into(), opt: DownloadOptions::default() },
+    ];
+    let started = Instant::now();
+    let results: Vec<TransferResult> = pool.run(stream::iter(jobs)).collect().await;
+    fs::remove_file("tmp_pool.txt").unwrap();
+    println!("{:?}", results);
+
+    assert_eq!(results.len(), 3);
+    // Four starts at most 100 a second take at least 30ms.
+    assert!(started.elapsed() >= Duration::from_millis(30));
+    for result in results {
+      match result.job {
+        TransferJob::UploadFile { .. } => assert!(matches!(result.output, Ok(TransferOutput::Uploaded(_)))),
+        TransferJob::DownloadData { skylink: ref requested, .. } if *requested == skylink => {
+          assert_eq!(result.output.unwrap(), TransferOutput::Data(Bytes::from("hello world")));
+        }
+        _ => {
+          assert_eq!(result.attempts, 2);
+          assert!(matches!(result.output, Err(InvalidSkylink(_))));
+        }
+      }
+    }
+  }
+}
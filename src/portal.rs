@@ -0,0 +1,233 @@
+use crate::{
+  util::{make_uri, new_request_id, QueryParams},
+  SkynetClient, SkynetError::*, SkynetResult,
+};
+use std::{
+  cmp::Ordering as CmpOrdering,
+  collections::HashMap,
+  sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc, Mutex, RwLock,
+  },
+  time::{Duration, Instant},
+};
+use futures_util::{future::join_all, lock::Mutex as AsyncMutex};
+use hyper::{Body, Request, Response, StatusCode};
+use tokio::task::JoinHandle;
+
+/// Public portals `SkynetClient::auto` chooses from, in order of preference.
+pub const PUBLIC_PORTALS: &[&str] = &[
+  "https://siasky.net",
+  "https://skynetfree.net",
+  "https://skynetpro.net",
+  "https://web3portal.com",
+  "https://fileportal.org",
+];
+
+const HEALTH_CHECK_PATH: &str = "/health-check";
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many requests in a row have to fail before an automatically chosen portal is replaced.
+const FAILURE_THRESHOLD: u32 = 3;
+
+/// Weight of the newest probe in a portal's moving success rate and latency.
+const PROBE_WEIGHT: f64 = 0.3;
+
+/// How long `portal_url` took to answer its health check with a success status, or `None` if
+/// it didn't in time.
+pub(crate) async fn probe_portal(client: &SkynetClient, portal_url: &str) -> Option<Duration> {
+  let uri = make_uri(portal_url, HEALTH_CHECK_PATH.into(), None, None, QueryParams::new());
+  let req = Request::get(uri).body(Body::empty()).ok()?;
+  let request_id = new_request_id().ok()?;
+
+  let start = Instant::now();
+  match tokio::time::timeout(HEALTH_CHECK_TIMEOUT, client.send(req, &request_id)).await {
+    Ok(Ok(res)) if res.status().is_success() => Some(start.elapsed()),
+    _ => None,
+  }
+}
+
+/// Whether `portal_url` answers its health check with a success status in time.
+pub(crate) async fn check_portal_health(client: &SkynetClient, portal_url: &str) -> bool {
+  probe_portal(client, portal_url).await.is_some()
+}
+
+/// Checks every candidate at once and returns the first healthy one in `candidates` order.
+pub(crate) async fn select_portal(client: &SkynetClient, candidates: &[String]) -> SkynetResult<String> {
+  let health = join_all(candidates.iter().map(|portal_url| check_portal_health(client, portal_url))).await;
+
+  candidates
+    .iter()
+    .zip(health)
+    .find(|(_, healthy)| *healthy)
+    .map(|(portal_url, _)| portal_url.clone())
+    .ok_or_else(|| NoHealthyPortal(candidates.to_vec()))
+}
+
+/// Moving averages over a portal's background probes.
+#[derive(Debug, Clone, Copy)]
+struct PortalStats {
+  success_rate: f64,
+  latency: Option<Duration>,
+  healthy: bool,
+}
+
+impl PortalStats {
+  fn update(stats: Option<Self>, latency: Option<Duration>) -> Self {
+    let success = if latency.is_some() { 1.0 } else { 0.0 };
+    match stats {
+      None => Self { success_rate: success, latency, healthy: latency.is_some() },
+      Some(stats) => Self {
+        success_rate: stats.success_rate * (1.0 - PROBE_WEIGHT) + success * PROBE_WEIGHT,
+        latency: match (stats.latency, latency) {
+          (Some(average), Some(latency)) => Some(average.mul_f64(1.0 - PROBE_WEIGHT) + latency.mul_f64(PROBE_WEIGHT)),
+          (average, latency) => latency.or(average),
+        },
+        healthy: latency.is_some(),
+      },
+    }
+  }
+
+  /// Higher success rate first, then lower latency. Portals never probed come last.
+  fn compare(a: Option<&Self>, b: Option<&Self>) -> CmpOrdering {
+    match (a, b) {
+      (Some(a), Some(b)) => b
+        .success_rate
+        .partial_cmp(&a.success_rate)
+        .unwrap_or(CmpOrdering::Equal)
+        .then_with(|| match (a.latency, b.latency) {
+          (Some(a), Some(b)) => a.cmp(&b),
+          (Some(_), None) => CmpOrdering::Less,
+          (None, Some(_)) => CmpOrdering::Greater,
+          (None, None) => CmpOrdering::Equal,
+        }),
+      (Some(_), None) => CmpOrdering::Less,
+      (None, Some(_)) => CmpOrdering::Greater,
+      (None, None) => CmpOrdering::Equal,
+    }
+  }
+}
+
+/// Portal selection state of a client built with `SkynetClient::auto`.
+#[derive(Debug)]
+pub(crate) struct AutoPortal {
+  candidates: Vec<String>,
+  /// Candidates in the order failover tries them, best first.
+  ranking: RwLock<Vec<String>>,
+  stats: Mutex<HashMap<String, PortalStats>>,
+  failures: AtomicU32,
+  /// Held while a failover picks a new portal, so that requests failing meanwhile don't start
+  /// selections of their own.
+  selecting: AsyncMutex<()>,
+}
+
+impl AutoPortal {
+  pub(crate) fn new(candidates: Vec<String>) -> Self {
+    Self {
+      ranking: RwLock::new(candidates.clone()),
+      candidates,
+      stats: Mutex::new(HashMap::new()),
+      failures: AtomicU32::new(0),
+      selecting: AsyncMutex::new(()),
+    }
+  }
+
+  pub(crate) fn candidates(&self) -> &[String] {
+    &self.candidates
+  }
+
+  pub(crate) fn ranking(&self) -> Vec<String> {
+    self.ranking.read().unwrap().clone()
+  }
+
+  /// Counts consecutive failed requests and switches `client` to another healthy portal once
+  /// there are too many. Connection errors and gateway errors count as failures.
+  pub(crate) async fn record(&self, client: &SkynetClient, res: &SkynetResult<Response<Body>>) {
+    let failed = match res {
+      Ok(res) => matches!(
+        res.status(),
+        StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+      ),
+      Err(err) => err.is_retryable(),
+    };
+
+    if !failed {
+      self.failures.store(0, Ordering::SeqCst);
+      return;
+    }
+
+    if self.failures.fetch_add(1, Ordering::SeqCst) + 1 < FAILURE_THRESHOLD {
+      return;
+    }
+    let _selecting = match self.selecting.try_lock() {
+      Some(guard) => guard,
+      None => return,
+    };
+
+    let failing = client.get_portal_url();
+    if let Ok(portal_url) = select_portal(client, &self.ranking()).await {
+      client.replace_portal_url(&failing, &portal_url);
+    }
+    // Failures counted while selecting were of the portal just replaced.
+    self.failures.store(0, Ordering::SeqCst);
+  }
+
+  /// Probes every candidate, reorders the ranking by success rate and latency, and moves
+  /// `client` to the best healthy portal if its current one failed the probe.
+  pub(crate) async fn probe(&self, client: &SkynetClient) {
+    let probes = join_all(self.candidates.iter().map(|portal_url| probe_portal(client, portal_url))).await;
+
+    let switch_to = {
+      let mut stats = self.stats.lock().unwrap();
+      for (portal_url, latency) in self.candidates.iter().zip(probes) {
+        let updated = PortalStats::update(stats.get(portal_url).copied(), latency);
+        stats.insert(portal_url.clone(), updated);
+      }
+
+      let mut ranking = self.candidates.clone();
+      ranking.sort_by(|a, b| PortalStats::compare(stats.get(a), stats.get(b)));
+      *self.ranking.write().unwrap() = ranking.clone();
+
+      let current = client.get_portal_url();
+      let current_healthy = stats.get(&current).map(|stats| stats.healthy).unwrap_or(true);
+      let best = ranking.into_iter().find(|portal_url| stats.get(portal_url).map(|stats| stats.healthy) == Some(true));
+      if current_healthy { None } else { best.map(|best| (current, best)) }
+    };
+
+    if let Some((current, portal_url)) = switch_to {
+      self.failures.store(0, Ordering::SeqCst);
+      client.replace_portal_url(&current, &portal_url);
+    }
+  }
+}
+
+/// A background task that is aborted when its last handle is dropped.
+#[derive(Debug)]
+pub(crate) struct BackgroundTask(JoinHandle<()>);
+
+impl BackgroundTask {
+  /// Probes the portals of `client`, which must be an `auto` client, every `interval` until it
+  /// is shut down. The task only holds `client`, so `client` must not own the returned handle
+  /// itself.
+  pub(crate) fn probe_portals(client: SkynetClient, auto_portal: Arc<AutoPortal>, interval: Duration) -> Self {
+    Self(tokio::spawn(async move {
+      let probes = async {
+        let mut ticks = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+        loop {
+          ticks.tick().await;
+          auto_portal.probe(&client).await;
+        }
+      };
+      tokio::select! {
+        _ = probes => {}
+        _ = client.closed() => {}
+      }
+    }))
+  }
+}
+
+impl Drop for BackgroundTask {
+  fn drop(&mut self) {
+    self.0.abort();
+  }
+}
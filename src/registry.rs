@@ -1,36 +1,195 @@
-use crate::{SkynetClient, SkynetResult, SkynetError::*, util::make_uri};
-use std::{collections::HashMap, str};
+use crate::{
+  client::CallAuth, download, encoding, skylink::parse_skylink, AuthScheme, DownloadOptions, KeyPair, PrivateKey, Skylink, SkynetClient, SkynetError, SkynetResult,
+  SkynetError::*,
+  util::{blocking, describe_response, make_uri, read_body, status_error, QueryParam, QueryParams},
+};
+use std::{
+  collections::HashMap,
+  convert::TryFrom,
+  fmt,
+  str::{self, FromStr},
+  sync::{Arc, Mutex},
+};
 use crypto::{
   blake2b::Blake2b,
   digest::Digest,
   ed25519,
 };
+use futures_util::{
+  future::{join_all, try_join_all},
+  lock::{Mutex as AsyncMutex, OwnedMutexGuard},
+};
 use hex::{FromHex, ToHex};
-use hyper::{body, Body, Request};
-use serde::Deserialize;
-use serde_json::json;
+use bytes::Bytes;
+use hyper::{Body, Request, StatusCode};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{json, Value as Json};
 
 const DEFAULT_GET_ENTRY_TIMEOUT: u32 = 5;
 
-#[derive(Debug)]
+/// The most data a registry entry can hold.
+pub const MAX_ENTRY_DATA_SIZE: usize = 70;
+
+/// What `delete_entry_data` writes: as many zero bytes as a raw skylink, like skynet-js.
+const DELETION_ENTRY_DATA: [u8; 34] = [0; 34];
+
+/// A registry public key tagged with its signature algorithm. Displays and serializes as
+/// `<algorithm>:<hex key>`, the form portals address entries by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum PublicKey {
+  Ed25519([u8; 32]),
+}
+
+impl PublicKey {
+  pub fn algorithm(&self) -> &'static str {
+    match self {
+      Self::Ed25519(_) => "ed25519",
+    }
+  }
+
+  pub fn key(&self) -> &[u8] {
+    match self {
+      Self::Ed25519(key) => key,
+    }
+  }
+
+  fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+    match self {
+      Self::Ed25519(key) => signature.len() == 64 && ed25519::verify(message, key, signature),
+    }
+  }
+}
+
+impl From<&KeyPair> for PublicKey {
+  fn from(keypair: &KeyPair) -> Self {
+    Self::Ed25519(keypair.public_key)
+  }
+}
+
+/// Raw keys are taken as ed25519, the only algorithm portals support so far.
+impl TryFrom<&[u8]> for PublicKey {
+  type Error = SkynetError;
+
+  fn try_from(key: &[u8]) -> SkynetResult<Self> {
+    <[u8; 32]>::try_from(key)
+      .map(Self::Ed25519)
+      .map_err(|_| InvalidPublicKey(key.encode_hex()))
+  }
+}
+
+impl fmt::Display for PublicKey {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}:{}", self.algorithm(), self.key().encode_hex::<String>())
+  }
+}
+
+impl FromStr for PublicKey {
+  type Err = SkynetError;
+
+  fn from_str(s: &str) -> SkynetResult<Self> {
+    match s.split_once(':') {
+      Some(("ed25519", key)) => <[u8; 32]>::from_hex(key).map(Self::Ed25519).map_err(|_| InvalidPublicKey(s.into())),
+      _ => Err(InvalidPublicKey(s.into())),
+    }
+  }
+}
+
+impl Serialize for PublicKey {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(self)
+  }
+}
+
+impl<'de> Deserialize<'de> for PublicKey {
+  fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+    String::deserialize(deserializer)?
+      .parse()
+      .map_err(|_| de::Error::custom("expected an <algorithm>:<hex key> public key"))
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct RegistryEntry {
   pub data_key: String,
+  #[serde(with = "hex")]
   pub data: Vec<u8>,
   pub revision: u64,
 }
 
-#[derive(Debug)]
+impl RegistryEntry {
+  /// The hash the entry's signature is made over. `hashed_data_key_hex` is as in `EntryOptions`.
+  pub fn hash(&self, hashed_data_key_hex: bool) -> [u8; 32] {
+    let mut hash = [0; 32];
+    let mut hasher = Blake2b::new(32);
+    Digest::input(&mut hasher, hash_data_key(&self.data_key, hashed_data_key_hex).as_bytes());
+    Digest::input(&mut hasher, &self.data);
+    Digest::input(&mut hasher, self.revision.to_string().as_bytes());
+    Digest::result(&mut hasher, &mut hash);
+    hash
+  }
+
+  /// Signs the entry the way `set_registry_entry` does, without sending it.
+  pub fn sign(self, private_key: &PrivateKey, hashed_data_key_hex: bool) -> SignedRegistryEntry {
+    let signature = ed25519::signature(&self.hash(hashed_data_key_hex), private_key.as_bytes()).to_vec();
+    SignedRegistryEntry { entry: self, signature }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SignedRegistryEntry {
+  #[serde(flatten)]
   pub entry: RegistryEntry,
+  #[serde(with = "hex")]
   pub signature: Vec<u8>,
 }
 
-#[derive(Debug)]
+impl SignedRegistryEntry {
+  /// Whether the signature is `public_key`'s over the entry, as `get_registry_entry` checks.
+  pub fn verify(&self, public_key: &PublicKey, hashed_data_key_hex: bool) -> bool {
+    public_key.verify(&self.entry.hash(hashed_data_key_hex), &self.signature)
+  }
+
+  /// The request body the portal expects when setting this entry.
+  pub fn to_portal_json(&self, public_key: &PublicKey, hashed_data_key_hex: bool) -> Json {
+    json!({
+      "publickey": {
+        "algorithm": public_key.algorithm(),
+        "key": public_key.key(),
+      },
+      "datakey": hash_data_key(&self.entry.data_key, hashed_data_key_hex),
+      "revision": self.entry.revision,
+      "data": self.entry.data,
+      "signature": self.signature,
+    })
+  }
+
+  /// Parses a portal's registry read response. The portal only returns the hashed data key,
+  /// so the original `data_key` has to be supplied.
+  pub fn from_portal_json(data_key: &str, json: &str) -> SkynetResult<Self> {
+    let res: GetResponse = serde_json::from_str(json).map_err(|_| PortalResponse(json.to_string()))?;
+
+    Ok(Self {
+      entry: RegistryEntry {
+        data_key: data_key.into(),
+        data: FromHex::from_hex(res.data).map_err(|_| PortalResponse(json.to_string()))?,
+        revision: res.revision,
+      },
+      signature: FromHex::from_hex(res.signature).map_err(|_| PortalResponse(json.to_string()))?,
+    })
+  }
+}
+
+#[derive(Debug, Clone)]
 pub struct EntryOptions {
   pub endpoint_path: String,
   pub api_key: Option<String>,
+  pub auth: Option<AuthScheme>,
   pub custom_user_agent: Option<String>,
   pub hashed_data_key_hex: bool,
+  /// Reject entries whose signature doesn't match the public key. Only turn this off to
+  /// inspect raw entries, never to trust their contents.
+  pub verify_signature: bool,
 }
 
 impl Default for EntryOptions {
@@ -38,8 +197,10 @@ impl Default for EntryOptions {
     Self {
       endpoint_path: "/skynet/registry".into(),
       api_key: None,
+      auth: None,
       custom_user_agent: None,
       hashed_data_key_hex: false,
+      verify_signature: true,
     }
   }
 }
@@ -56,14 +217,80 @@ fn hash_data_key(data_key: &str, hashed_data_key_hex: bool) -> String {
   }
 }
 
-fn hash_registry_entry(entry: &RegistryEntry, hashed_data_key_hex: bool) -> Vec<u8> {
-  let mut hash = [0; 32];
-  let mut hasher = Blake2b::new(32);
-  Digest::input(&mut hasher, hash_data_key(&entry.data_key, hashed_data_key_hex).as_bytes());
-  Digest::input(&mut hasher, &entry.data);
-  Digest::input(&mut hasher, entry.revision.to_string().as_bytes());
-  Digest::result(&mut hasher, &mut hash);
-  hash.to_vec()
+/// The tweak an entry is stored under on the hosts: the hex data key itself when
+/// `hashed_data_key_hex` is set, otherwise the hash of the length-prefixed data key.
+fn data_key_tweak(data_key: &str, hashed_data_key_hex: bool) -> SkynetResult<[u8; 32]> {
+  if hashed_data_key_hex {
+    return <[u8; 32]>::from_hex(data_key).map_err(|_| PortalResponse(format!("invalid data key {}", data_key)));
+  }
+
+  Ok(encoding::data_key_tweak(data_key))
+}
+
+/// The v2 skylink resolving through the entry of `public_key` under `data_key`: its merkle
+/// root is the entry ID, the hash of the encoded public key and the data key tweak.
+pub(crate) fn entry_link(public_key: &PublicKey, data_key: &str, hashed_data_key_hex: bool) -> SkynetResult<Skylink> {
+  let tweak = data_key_tweak(data_key, hashed_data_key_hex)?;
+  // Every algorithm `PublicKey` has fits a specifier.
+  let merkle_root = encoding::registry_entry_id(public_key.algorithm(), public_key.key(), &tweak).unwrap();
+
+  Ok(Skylink {
+    bitfield: 1,
+    merkle_root,
+  })
+}
+
+/// The entry link of the registry entry of `public_key` under `data_key`, as skynet-js's
+/// `getEntryLink` computes it. Needs no portal: the link follows from the key alone, so it can
+/// be published before the entry is first written and keeps resolving to its latest data.
+pub fn get_entry_link(public_key: &PublicKey, data_key: &str, hashed_data_key_hex: bool) -> SkynetResult<String> {
+  Ok(entry_link(public_key, data_key, hashed_data_key_hex)?.to_string())
+}
+
+/// The highest registry revision this process has read or reserved for each entry. Clones of a
+/// client share one cache, and writes to the same entry take turns through it, so tasks get
+/// distinct, increasing revisions instead of racing each other to the same one.
+#[derive(Debug, Clone, Default)]
+pub struct RevisionCache {
+  revisions: Arc<Mutex<HashMap<EntryId, u64>>>,
+  writers: Arc<Mutex<HashMap<EntryId, Arc<AsyncMutex<()>>>>>,
+}
+
+/// Public key and data key of a registry entry.
+type EntryId = (PublicKey, String);
+
+impl RevisionCache {
+  pub fn get(&self, public_key: &PublicKey, data_key: &str) -> Option<u64> {
+    let revisions = self.revisions.lock().unwrap();
+    revisions.get(&(*public_key, data_key.to_string())).copied()
+  }
+
+  /// Records a revision seen on the portal.
+  pub(crate) fn observe(&self, public_key: &PublicKey, data_key: &str, revision: u64) {
+    let mut revisions = self.revisions.lock().unwrap();
+    let cached = revisions.entry((*public_key, data_key.to_string())).or_insert(revision);
+    *cached = (*cached).max(revision);
+  }
+
+  /// Waits for other writers of the entry in this process to finish. Hold the guard from reading
+  /// the latest revision until the write is done.
+  pub(crate) async fn lock_entry(&self, public_key: &PublicKey, data_key: &str) -> OwnedMutexGuard<()> {
+    let writer = {
+      let mut writers = self.writers.lock().unwrap();
+      writers.entry((*public_key, data_key.to_string())).or_default().clone()
+    };
+    writer.lock_owned().await
+  }
+
+  /// Reserves the revision for the next write: one past both the cached revision and `latest`,
+  /// the latest one on the portal, if any.
+  pub(crate) fn next_revision(&self, public_key: &PublicKey, data_key: &str, latest: Option<u64>) -> u64 {
+    let mut revisions = self.revisions.lock().unwrap();
+    let key = (*public_key, data_key.to_string());
+    let next = revisions.get(&key).copied().max(latest).map(|revision| revision + 1).unwrap_or(0);
+    revisions.insert(key, next);
+    next
+  }
 }
 
 #[derive(Deserialize)]
@@ -75,109 +302,315 @@ struct GetResponse {
 
 pub async fn get_registry_entry(
   client: &SkynetClient,
-  public_key: &[u8],
+  public_key: &PublicKey,
   data_key: &str,
   opt: EntryOptions,
 ) -> SkynetResult<SignedRegistryEntry> {
+  find_registry_entry(client, public_key, data_key, opt)
+    .await?
+    .ok_or_else(|| PortalResponse(format!("registry entry {} not found", data_key)))
+}
+
+/// Like `get_registry_entry`, but `None` when the portal answers 404, so that an entry that was
+/// never set can be told apart from a portal that failed.
+pub(crate) async fn find_registry_entry(
+  client: &SkynetClient,
+  public_key: &PublicKey,
+  data_key: &str,
+  opt: EntryOptions,
+) -> SkynetResult<Option<SignedRegistryEntry>> {
   let req = Request::builder().method("GET");
-  let mut query = HashMap::new();
+  let mut query = QueryParams::new();
   
-  query.insert("publickey".into(), format!("ed25519:{}", public_key.encode_hex::<String>()));
-  query.insert("datakey".into(), hash_data_key(data_key, opt.hashed_data_key_hex));
-  query.insert("timeout".into(), DEFAULT_GET_ENTRY_TIMEOUT.to_string());
+  query.set(QueryParam::PublicKey, public_key.to_string());
+  query.set(QueryParam::DataKey, hash_data_key(data_key, opt.hashed_data_key_hex));
+  query.set(QueryParam::Timeout, DEFAULT_GET_ENTRY_TIMEOUT.to_string());
 
   let uri = make_uri(
-    client.get_portal_url(),
+    &client.get_portal_url(),
     opt.endpoint_path,
     opt.api_key,
     None,
     query);
 
-  let mut req = req.uri(uri);
+  let mut req = req.uri(uri.clone());
 
   if let Some(custom_user_agent) = opt.custom_user_agent {
     req = req.header("User-Agent", custom_user_agent);
   }
 
+  req = req.extension(CallAuth(opt.auth.clone()));
+
   let req = req.body(Body::from("")).map_err(HttpError)?;
-  let res = client.http.request(req).await.map_err(HyperError)?;
-  let body = body::to_bytes(res.into_body()).await.map_err(HyperError)?;
+  let res = client.request(req).await?;
+  let (parts, body) = res.into_parts();
+  if parts.status == StatusCode::NOT_FOUND {
+    return Ok(None);
+  }
+  let body = read_body(&parts, body).await?;
+  if !parts.status.is_success() {
+    return Err(status_error(&parts.extensions, &uri, parts.status, &body));
+  }
   let body_str = str::from_utf8(&body).map_err(Utf8Error)?;
-  let res: GetResponse = serde_json::from_str(body_str)
-    .map_err(|_| PortalResponse(body_str.to_string()))?;
+  let entry = SignedRegistryEntry::from_portal_json(data_key, body_str)
+    .map_err(|_| PortalResponse(describe_response(&uri, parts.status, body_str)))?;
 
-  let entry = SignedRegistryEntry {
-  	entry: RegistryEntry {
-      data_key: data_key.into(),
-      data: FromHex::from_hex(res.data).unwrap(),
-      revision: res.revision,
-    },
-    signature: FromHex::from_hex(res.signature).unwrap(),
-  };
+  if !opt.verify_signature {
+    client.revisions().observe(public_key, data_key, entry.entry.revision);
+    return Ok(Some(entry));
+  }
 
-  let hash = hash_registry_entry(&entry.entry, opt.hashed_data_key_hex);
-  if !ed25519::verify(&hash, public_key, &entry.signature) {
+  let signer_key = *public_key;
+  let hashed_data_key_hex = opt.hashed_data_key_hex;
+  let (entry, valid) = blocking(move || {
+    let valid = entry.verify(&signer_key, hashed_data_key_hex);
+    Ok((entry, valid))
+  }).await?;
+
+  if !valid {
   	return Err(InvalidSignature);
   }
 
-  Ok(entry)
+  client.revisions().observe(public_key, data_key, entry.entry.revision);
+  Ok(Some(entry))
+}
+
+/// Reads several registry entries concurrently, returning them in the order requested.
+pub async fn get_registry_entries(
+  client: &SkynetClient,
+  entries: &[(&PublicKey, &str)],
+  opt: EntryOptions,
+) -> SkynetResult<Vec<SignedRegistryEntry>> {
+  let reads = entries
+    .iter()
+    .map(|(public_key, data_key)| get_registry_entry(client, public_key, data_key, opt.clone()));
+
+  try_join_all(reads).await
+}
+
+/// Reads the same entry from several portals concurrently and returns the verified entry with
+/// the highest revision, so a single portal serving a stale revision can't hide updates.
+/// Fails with the first portal's error only if no portal returns a valid entry.
+pub async fn get_registry_entry_multi(
+  portals: &[SkynetClient],
+  public_key: &PublicKey,
+  data_key: &str,
+  opt: EntryOptions,
+) -> SkynetResult<SignedRegistryEntry> {
+  let opt = EntryOptions {
+    verify_signature: true,
+    ..opt
+  };
+  let reads = portals
+    .iter()
+    .map(|client| get_registry_entry(client, public_key, data_key, opt.clone()));
+
+  let mut latest: Option<SignedRegistryEntry> = None;
+  let mut first_err = None;
+  for res in join_all(reads).await {
+    match res {
+      Ok(entry) => match &latest {
+        Some(latest) if latest.entry.revision >= entry.entry.revision => {}
+        _ => latest = Some(entry),
+      },
+      Err(err) => {
+        first_err.get_or_insert(err);
+      }
+    }
+  }
+
+  match (latest, first_err) {
+    (Some(entry), _) => Ok(entry),
+    (None, Some(err)) => Err(err),
+    (None, None) => Err(PortalResponse("no portals given".into())),
+  }
 }
 
 pub async fn set_registry_entry(
   client: &SkynetClient,
-  public_key: &[u8],
-  private_key: &[u8],
+  public_key: &PublicKey,
+  private_key: &PrivateKey,
   entry: RegistryEntry,
   opt: EntryOptions,
 ) -> SkynetResult<()> {
   let req = Request::builder().method("POST");
-  let query = HashMap::new();
+  let query = QueryParams::new();
   
   let uri = make_uri(
-    client.get_portal_url(),
+    &client.get_portal_url(),
     opt.endpoint_path,
     opt.api_key,
     None,
     query);
 
-  let mut req = req.uri(uri);
+  let mut req = req.uri(uri.clone());
 
   if let Some(custom_user_agent) = opt.custom_user_agent {
     req = req.header("User-Agent", custom_user_agent);
   }
 
-  let hash = hash_registry_entry(&entry, opt.hashed_data_key_hex);
-  let signature = ed25519::signature(&hash, private_key);
+  req = req.extension(CallAuth(opt.auth.clone()));
 
-  let data = json!({
-    "publickey": {
-      "algorithm": "ed25519",
-      "key": public_key,
-    },
-    "datakey": hash_data_key(&entry.data_key, opt.hashed_data_key_hex),
-    "revision": entry.revision,
-    "data": entry.data,
-    "signature": signature.to_vec(),
-  }).to_string();
+  let (data_key, revision) = (entry.data_key.clone(), entry.revision);
+  let (signer_key, private_key) = (*public_key, private_key.clone());
+  let hashed_data_key_hex = opt.hashed_data_key_hex;
+  let data = blocking(move || {
+    Ok(entry.sign(&private_key, hashed_data_key_hex).to_portal_json(&signer_key, hashed_data_key_hex))
+  }).await?.to_string();
 
   let req = req.body(Body::from(data)).map_err(HttpError)?;
-  client.http.request(req).await.map_err(HyperError)?;
+  let res = client.request(req).await?;
 
+  if !res.status().is_success() {
+    let (parts, body) = res.into_parts();
+    let body = read_body(&parts, body).await?;
+    let body_str = String::from_utf8_lossy(&body).into_owned();
+    if parts.status == StatusCode::BAD_REQUEST && body_str.contains("revision") {
+      return Err(RevisionConflict(body_str));
+    }
+    return Err(status_error(&parts.extensions, &uri, parts.status, &body));
+  }
+
+  client.revisions().observe(public_key, &data_key, revision);
   Ok(())
 }
 
+/// The skylink an entry points at. SkyDB entries hold it as a string, while MySky and
+/// `set_data_link` store the raw 34 bytes.
+pub(crate) fn parse_pointer(data: &[u8]) -> SkynetResult<String> {
+  match Skylink::from_bytes(data) {
+    Ok(skylink) => Ok(skylink.to_string()),
+    Err(_) => Ok(str::from_utf8(data).map_err(Utf8Error)?.to_string()),
+  }
+}
+
+/// Writes `data` to the entry under `data_key` with the next revision after the latest one.
+async fn write_entry_data(
+  client: &SkynetClient,
+  keypair: &KeyPair,
+  data_key: &str,
+  data: Vec<u8>,
+  opt: EntryOptions,
+) -> SkynetResult<()> {
+  let public_key = PublicKey::from(keypair);
+  let _writer = client.revisions().lock_entry(&public_key, data_key).await;
+  let latest = find_registry_entry(client, &public_key, data_key, opt.clone())
+    .await?
+    .map(|signed| signed.entry.revision);
+  let revision = client.revisions().next_revision(&public_key, data_key, latest);
+
+  set_registry_entry(
+    client,
+    &public_key,
+    &keypair.private_key,
+    RegistryEntry {
+      data_key: data_key.into(),
+      data,
+      revision,
+    },
+    opt,
+  ).await
+}
+
+/// Points the entry under `data_key` at `skylink`, stored as its raw 34 bytes the way MySky
+/// and skynet-js do, and returns the entry link. The entry link never changes, so it can be
+/// shared once and kept pointing at the latest deploy.
+pub async fn set_data_link(
+  client: &SkynetClient,
+  keypair: &KeyPair,
+  data_key: &str,
+  skylink: &str,
+  opt: EntryOptions,
+) -> SkynetResult<String> {
+  let (skylink, _) = parse_skylink(skylink)?;
+  let hashed_data_key_hex = opt.hashed_data_key_hex;
+  write_entry_data(client, keypair, data_key, skylink.to_bytes().to_vec(), opt).await?;
+
+  get_entry_link(&keypair.into(), data_key, hashed_data_key_hex)
+}
+
+/// The skylink stored in the entry under `data_key`, either as raw bytes by `set_data_link` or as
+/// a string.
+pub async fn get_data_link(
+  client: &SkynetClient,
+  public_key: &PublicKey,
+  data_key: &str,
+  opt: EntryOptions,
+) -> SkynetResult<Skylink> {
+  let signed = get_registry_entry(client, public_key, data_key, opt).await?;
+  if signed.entry.data == DELETION_ENTRY_DATA {
+    return Err(PortalResponse(format!("registry entry {} was deleted", data_key)));
+  }
+  parse_pointer(&signed.entry.data)?.parse()
+}
+
+/// Downloads the content the entry under `data_key` currently points at.
+pub async fn download_from_entry(
+  client: &SkynetClient,
+  public_key: &PublicKey,
+  data_key: &str,
+  entry_opt: EntryOptions,
+  download_opt: DownloadOptions,
+) -> SkynetResult<Bytes> {
+  let skylink = get_data_link(client, public_key, data_key, entry_opt).await?;
+  download::download_data(client, &skylink.to_string(), download_opt).await
+}
+
+/// The raw data of the entry under `data_key`, or `None` if it doesn't exist or was deleted
+/// with `delete_entry_data`.
+pub async fn get_entry_data(
+  client: &SkynetClient,
+  public_key: &PublicKey,
+  data_key: &str,
+  opt: EntryOptions,
+) -> SkynetResult<Option<Vec<u8>>> {
+  match find_registry_entry(client, public_key, data_key, opt).await? {
+    Some(signed) if signed.entry.data == DELETION_ENTRY_DATA => Ok(None),
+    Some(signed) => Ok(Some(signed.entry.data)),
+    None => Ok(None),
+  }
+}
+
+/// Stores `data` as is in the entry under `data_key`, bumping its revision. Fails with
+/// `EntryDataTooLarge` if it doesn't fit in an entry.
+pub async fn set_entry_data(
+  client: &SkynetClient,
+  keypair: &KeyPair,
+  data_key: &str,
+  data: &[u8],
+  opt: EntryOptions,
+) -> SkynetResult<()> {
+  if data.len() > MAX_ENTRY_DATA_SIZE {
+    return Err(EntryDataTooLarge(data.len(), MAX_ENTRY_DATA_SIZE));
+  }
+  write_entry_data(client, keypair, data_key, data.to_vec(), opt).await
+}
+
+/// Marks the entry under `data_key` as deleted. Registry entries can't be removed, so this
+/// overwrites it with the deletion sentinel that `get_entry_data` reads back as `None`.
+pub async fn delete_entry_data(
+  client: &SkynetClient,
+  keypair: &KeyPair,
+  data_key: &str,
+  opt: EntryOptions,
+) -> SkynetResult<()> {
+  write_entry_data(client, keypair, data_key, DELETION_ENTRY_DATA.to_vec(), opt).await
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
-  use crate::gen_keypair_and_seed;
+  use crate::{gen_keypair_and_seed, gen_keypair_from_seed, MockPortal, MockResponse, MockTransport};
+  use proptest::prelude::*;
 
   #[tokio::test]
   async fn test_registry_entry() {
 	  let (keypair, _) = gen_keypair_and_seed(64);
-    let client = SkynetClient::default();
+    let portal = MockPortal::start().await;
+    let client = portal.client();
     let res = set_registry_entry(
       &client,
-      &keypair.public_key,
+      &PublicKey::from(&keypair),
       &keypair.private_key,
       RegistryEntry {
         data_key: "data".into(),
@@ -190,7 +623,7 @@ mod tests {
     assert!(res.is_ok());
     let res = get_registry_entry(
       &client,
-      &keypair.public_key,
+      &PublicKey::from(&keypair),
       "data",
       EntryOptions::default(),
     ).await;
@@ -200,5 +633,323 @@ mod tests {
     assert_eq!(entry.data_key, "data".to_string());
     assert_eq!(entry.data, b"hello world".to_vec());
     assert_eq!(entry.revision, 0);
+    let res = set_registry_entry(
+      &client,
+      &PublicKey::from(&keypair),
+      &keypair.private_key,
+      RegistryEntry {
+        data_key: "data".into(),
+        data: b"stale".to_vec(),
+        revision: 0,
+      },
+      EntryOptions::default(),
+    ).await;
+    assert!(matches!(res, Err(RevisionConflict(_))));
+  }
+
+  #[tokio::test]
+  async fn test_get_registry_entry_multi() {
+    let (keypair, _) = gen_keypair_and_seed(64);
+    let stale = MockPortal::start().await;
+    let fresh = MockPortal::start().await;
+    for (portal, revision) in &[(&stale, 1), (&fresh, 2)] {
+      set_registry_entry(
+        &portal.client(),
+        &PublicKey::from(&keypair),
+        &keypair.private_key,
+        RegistryEntry {
+          data_key: "data".into(),
+          data: revision.to_string().into_bytes(),
+          revision: *revision,
+        },
+        EntryOptions::default(),
+      ).await.unwrap();
+    }
+
+    let portals = vec![stale.client(), MockTransport::new().client("https://siasky.net"), fresh.client()];
+    let res = get_registry_entry_multi(&portals, &PublicKey::from(&keypair), "data", EntryOptions::default()).await;
+    println!("{:?}", res);
+    assert!(res.is_ok());
+    assert_eq!(res.unwrap().entry.revision, 2);
+
+    let portals = vec![MockTransport::new().client("https://siasky.net")];
+    let res = get_registry_entry_multi(&portals, &PublicKey::from(&keypair), "data", EntryOptions::default()).await;
+    assert!(res.is_err());
+  }
+
+  #[tokio::test]
+  async fn test_skip_signature_verification() {
+    let transport = MockTransport::new();
+    let res = json!({ "data": "68656c6c6f", "revision": 0, "signature": "00".repeat(64) });
+    transport.push_response(MockResponse::json(res.clone()));
+    transport.push_response(MockResponse::json(res));
+    let client = transport.client("https://siasky.net");
+
+    let res = get_registry_entry(&client, &PublicKey::Ed25519([1; 32]), "data", EntryOptions::default()).await;
+    assert!(matches!(res, Err(InvalidSignature)));
+
+    let res = get_registry_entry(&client, &PublicKey::Ed25519([1; 32]), "data", EntryOptions {
+      verify_signature: false,
+      ..Default::default()
+    }).await;
+    println!("{:?}", res);
+    assert_eq!(res.unwrap().entry.data, b"hello".to_vec());
+  }
+
+  #[test]
+  fn test_signed_entry_serde() {
+    let entry = SignedRegistryEntry {
+      entry: RegistryEntry {
+        data_key: "data".into(),
+        data: b"hello".to_vec(),
+        revision: 3,
+      },
+      signature: vec![0xab; 64],
+    };
+    let json = serde_json::to_value(&entry).unwrap();
+    assert_eq!(json["data"], "68656c6c6f");
+    assert_eq!(json["signature"], "ab".repeat(64));
+    assert_eq!(serde_json::from_value::<SignedRegistryEntry>(json).unwrap(), entry);
+
+    let portal_json = entry.to_portal_json(&PublicKey::Ed25519([1; 32]), false);
+    assert_eq!(portal_json["datakey"], hash_data_key("data", false));
+    assert_eq!(portal_json["revision"], 3);
+    let res = json!({
+      "data": "68656c6c6f",
+      "revision": 3,
+      "signature": "ab".repeat(64),
+    }).to_string();
+    assert_eq!(SignedRegistryEntry::from_portal_json("data", &res).unwrap(), entry);
+    assert!(SignedRegistryEntry::from_portal_json("data", "{}").is_err());
+  }
+
+  #[test]
+  fn test_public_key() {
+    let public_key = PublicKey::Ed25519([0xab; 32]);
+    let encoded = format!("ed25519:{}", "ab".repeat(32));
+    assert_eq!(public_key.to_string(), encoded);
+    assert_eq!(encoded.parse::<PublicKey>().unwrap(), public_key);
+    assert_eq!(serde_json::to_value(public_key).unwrap(), json!(encoded));
+    assert_eq!(serde_json::from_value::<PublicKey>(json!(encoded)).unwrap(), public_key);
+
+    assert!(matches!("ab".repeat(32).parse::<PublicKey>(), Err(InvalidPublicKey(_))));
+    assert!(matches!(format!("secp256k1:{}", "ab".repeat(32)).parse::<PublicKey>(), Err(InvalidPublicKey(_))));
+    assert!(matches!("ed25519:abab".parse::<PublicKey>(), Err(InvalidPublicKey(_))));
+    assert!(serde_json::from_value::<PublicKey>(json!("ed25519:abab")).is_err());
+
+    assert_eq!(PublicKey::try_from(&[0xab; 32][..]).unwrap(), public_key);
+    assert!(matches!(PublicKey::try_from(&[0xab; 31][..]), Err(InvalidPublicKey(_))));
+  }
+
+  #[tokio::test]
+  async fn test_get_registry_entries() {
+    let (keypair, _) = gen_keypair_and_seed(64);
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    for data_key in &["a", "b"] {
+      set_registry_entry(
+        &client,
+        &PublicKey::from(&keypair),
+        &keypair.private_key,
+        RegistryEntry {
+          data_key: data_key.to_string(),
+          data: data_key.as_bytes().to_vec(),
+          revision: 0,
+        },
+        EntryOptions::default(),
+      ).await.unwrap();
+    }
+    let res = get_registry_entries(
+      &client,
+      &[(&PublicKey::from(&keypair), "b"), (&PublicKey::from(&keypair), "a")],
+      EntryOptions::default(),
+    ).await;
+    println!("{:?}", res);
+    assert!(res.is_ok());
+    let entries = res.unwrap();
+    assert_eq!(entries[0].entry.data, b"b".to_vec());
+    assert_eq!(entries[1].entry.data, b"a".to_vec());
+  }
+
+  #[test]
+  fn test_parse_pointer() {
+    let skylink = "AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng";
+    let raw = skylink.parse::<Skylink>().unwrap().to_bytes();
+    assert_eq!(parse_pointer(&raw).unwrap(), skylink);
+    assert_eq!(parse_pointer(skylink.as_bytes()).unwrap(), skylink);
+  }
+
+  #[tokio::test]
+  async fn test_set_data_link() {
+    let (keypair, _) = gen_keypair_and_seed(64);
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let v1 = "AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng";
+
+    let res = set_data_link(&client, &keypair, "deploy", &format!("sia://{}", v1), EntryOptions::default()).await;
+    println!("{:?}", res);
+    let link = res.unwrap();
+    let entry = get_registry_entry(&client, &PublicKey::from(&keypair), "deploy", EntryOptions::default()).await.unwrap();
+    assert_eq!(entry.entry.data, v1.parse::<Skylink>().unwrap().to_bytes().to_vec());
+
+    let link2 = set_data_link(&client, &keypair, "deploy", v1, EntryOptions::default()).await.unwrap();
+    assert_eq!(link, link2);
+    assert_eq!(link.parse::<Skylink>().unwrap().bitfield, 1);
+    let entry = get_registry_entry(&client, &PublicKey::from(&keypair), "deploy", EntryOptions::default()).await.unwrap();
+    assert_eq!(entry.entry.revision, 1);
+
+    assert_eq!(link, get_entry_link(&PublicKey::from(&keypair), "deploy", false).unwrap());
+    assert_ne!(link, get_entry_link(&PublicKey::from(&keypair), "other", false).unwrap());
+    assert!(set_data_link(&client, &keypair, "deploy", "AAA", EntryOptions::default()).await.is_err());
+  }
+
+  #[test]
+  fn test_get_entry_link() {
+    let (keypair, _) = gen_keypair_and_seed(64);
+    let public_key = PublicKey::from(&keypair);
+    let data_key = "d321b3c31337047493c9b5a99675e9bdaea44218a31aad2fd7738209e7a5aca1";
+
+    let link = get_entry_link(&public_key, data_key, true).unwrap();
+    assert_eq!(link, get_entry_link(&public_key, data_key, true).unwrap());
+    assert_ne!(link, get_entry_link(&public_key, data_key, false).unwrap());
+    let skylink = link.parse::<Skylink>().unwrap();
+    assert_eq!(skylink.bitfield, 1);
+    let tweak = <[u8; 32]>::from_hex(data_key).unwrap();
+    assert_eq!(Some(skylink.merkle_root), encoding::registry_entry_id("ed25519", public_key.key(), &tweak));
+
+    assert!(get_entry_link(&public_key, "not hex", true).is_err());
+
+    // skynet-js's `getEntryLink` test vector.
+    let public_key = "ed25519:a1790331b8b41a94644d01a7b482564e7049047812364bcabc32d399ad23f7e2".parse().unwrap();
+    let link = get_entry_link(&public_key, data_key, true).unwrap();
+    assert_eq!(link, "AQB7zHVDtD-PikoAD_0zzFbWWPcY-IJoJRHXFJcwoU-WvQ");
+  }
+
+  #[tokio::test]
+  async fn test_shared_revision_cache() {
+    let (keypair, _) = gen_keypair_and_seed(64);
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let clone = client.clone();
+    let skylink = "AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng";
+
+    // The clones take turns, so the second writer builds on the first instead of conflicting.
+    let (first, second) = futures_util::future::join(
+      set_data_link(&client, &keypair, "deploy", skylink, EntryOptions::default()),
+      set_data_link(&clone, &keypair, "deploy", skylink, EntryOptions::default()),
+    ).await;
+    first.unwrap();
+    second.unwrap();
+    assert_eq!(client.revisions().get(&PublicKey::from(&keypair), "deploy"), Some(1));
+
+    let entry = get_registry_entry(&clone, &PublicKey::from(&keypair), "deploy", EntryOptions::default()).await.unwrap();
+    assert_eq!(entry.entry.revision, 1);
+  }
+
+  #[tokio::test]
+  async fn test_entry_data() {
+    let (keypair, _) = gen_keypair_and_seed(64);
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+
+    let res = get_entry_data(&client, &PublicKey::from(&keypair), "raw", EntryOptions::default()).await;
+    assert_eq!(res.unwrap(), None);
+
+    set_entry_data(&client, &keypair, "raw", &[1, 2, 3], EntryOptions::default()).await.unwrap();
+    let res = get_entry_data(&client, &PublicKey::from(&keypair), "raw", EntryOptions::default()).await;
+    assert_eq!(res.unwrap(), Some(vec![1, 2, 3]));
+
+    let res = set_entry_data(&client, &keypair, "raw", &[0; MAX_ENTRY_DATA_SIZE + 1], EntryOptions::default()).await;
+    assert!(matches!(res, Err(EntryDataTooLarge(71, MAX_ENTRY_DATA_SIZE))));
+
+    delete_entry_data(&client, &keypair, "raw", EntryOptions::default()).await.unwrap();
+    let res = get_entry_data(&client, &PublicKey::from(&keypair), "raw", EntryOptions::default()).await;
+    assert_eq!(res.unwrap(), None);
+    let entry = get_registry_entry(&client, &PublicKey::from(&keypair), "raw", EntryOptions::default()).await.unwrap();
+    assert_eq!(entry.entry.revision, 1);
+
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::new(StatusCode::BAD_GATEWAY, "bad gateway"));
+    let client = transport.client("https://siasky.net");
+    let res = get_entry_data(&client, &PublicKey::from(&keypair), "raw", EntryOptions::default()).await;
+    assert!(matches!(res.map_err(crate::SkynetError::into_inner), Err(PortalResponse(_))));
+  }
+
+  #[tokio::test]
+  async fn test_download_from_entry() {
+    let (keypair, _) = gen_keypair_and_seed(64);
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+
+    let mut data = HashMap::new();
+    data.insert("hello.txt".to_string(), (mime::TEXT_PLAIN, "hello world"));
+    let skylink = client.upload_data(data, Default::default()).await.unwrap();
+    set_data_link(&client, &keypair, "deploy", &skylink, EntryOptions::default()).await.unwrap();
+
+    let link = get_data_link(&client, &PublicKey::from(&keypair), "deploy", EntryOptions::default()).await.unwrap();
+    assert_eq!(link, parse_skylink(&skylink).unwrap().0);
+    let data = download_from_entry(
+      &client,
+      &PublicKey::from(&keypair),
+      "deploy",
+      EntryOptions::default(),
+      DownloadOptions::default(),
+    ).await.unwrap();
+    assert_eq!(data, "hello world");
+
+    delete_entry_data(&client, &keypair, "deploy", EntryOptions::default()).await.unwrap();
+    let res = get_data_link(&client, &PublicKey::from(&keypair), "deploy", EntryOptions::default()).await;
+    assert!(matches!(res, Err(PortalResponse(_))));
+  }
+
+  fn entry_strategy() -> impl Strategy<Value = RegistryEntry> {
+    (".{0,32}", proptest::collection::vec(any::<u8>(), 0..=MAX_ENTRY_DATA_SIZE), any::<u64>())
+      .prop_map(|(data_key, data, revision)| RegistryEntry { data_key, data, revision })
+  }
+
+  proptest! {
+    #[test]
+    fn prop_sign_verify(seed in any::<[u8; 32]>(), entry in entry_strategy(), hashed_data_key_hex in any::<bool>()) {
+      let keypair = gen_keypair_from_seed(&seed);
+      let public_key = PublicKey::from(&keypair);
+      let signed = entry.clone().sign(&keypair.private_key, hashed_data_key_hex);
+      prop_assert_eq!(&signed, &entry.clone().sign(&keypair.private_key, hashed_data_key_hex));
+      prop_assert!(signed.verify(&public_key, hashed_data_key_hex));
+
+      let mut tampered = signed.clone();
+      tampered.entry.revision = entry.revision.wrapping_add(1);
+      prop_assert!(!tampered.verify(&public_key, hashed_data_key_hex));
+      let other = gen_keypair_from_seed(&[&seed[..], &b"other"[..]].concat());
+      prop_assert!(!signed.verify(&PublicKey::from(&other), hashed_data_key_hex));
+    }
+
+    #[test]
+    fn prop_signed_entry_serde(seed in any::<[u8; 32]>(), entry in entry_strategy()) {
+      let signed = entry.sign(&gen_keypair_from_seed(&seed).private_key, false);
+      let json = serde_json::to_string(&signed).unwrap();
+      prop_assert_eq!(serde_json::from_str::<SignedRegistryEntry>(&json).unwrap(), signed);
+    }
+  }
+
+  proptest! {
+    // Each case starts a mock portal, so keep the count down.
+    #![proptest_config(ProptestConfig::with_cases(16))]
+
+    #[test]
+    fn prop_mock_portal_roundtrip(seed in any::<[u8; 32]>(), entry in entry_strategy()) {
+      let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+      let keypair = gen_keypair_from_seed(&seed);
+      let public_key = PublicKey::from(&keypair);
+
+      let read = runtime.block_on(async {
+        let portal = MockPortal::start().await;
+        let client = portal.client();
+        set_registry_entry(&client, &public_key, &keypair.private_key, entry.clone(), EntryOptions::default()).await?;
+        get_registry_entry(&client, &public_key, &entry.data_key, EntryOptions::default()).await
+      });
+      let read = read.unwrap();
+      prop_assert_eq!(&read.entry, &entry);
+      prop_assert_eq!(read, entry.sign(&keypair.private_key, false));
+    }
   }
 }
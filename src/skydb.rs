@@ -0,0 +1,488 @@
+use crate::{
+  crypto, download, registry, upload,
+  DownloadOptions, KeyPair, SkylinkFormat, SkynetClient, SkynetError::*, SkynetResult, UploadOptions,
+  registry::{parse_pointer, EntryOptions, PublicKey, RegistryEntry},
+};
+use std::{collections::HashMap, fmt, str};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use hex::{FromHex, ToHex};
+use serde_json::Value as Json;
+
+/// Version of the stored envelope, matching skynet-js.
+const ENVELOPE_VERSION: u32 = 2;
+/// How many times `db_update` retries after losing a race to another writer.
+const MAX_UPDATE_ATTEMPTS: u32 = 5;
+
+/// Stored JSON wrapping the value. `_schema` is the application's own schema version, checked
+/// on read when `SkyDbOptions::schema_version` is set.
+#[derive(Serialize, Deserialize)]
+struct Envelope<T> {
+  #[serde(rename = "_data")]
+  data: T,
+  #[serde(rename = "_v")]
+  version: u32,
+  #[serde(rename = "_schema", default, skip_serializing_if = "Option::is_none")]
+  schema: Option<u32>,
+}
+
+#[derive(Clone)]
+pub enum SkyDbEncryption {
+  /// Encrypted by the portal with the skykey it knows under this name.
+  Skykey(String),
+  /// Encrypted client-side with a key that never leaves this process.
+  Local([u8; 32]),
+}
+
+impl fmt::Debug for SkyDbEncryption {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Skykey(name) => f.debug_tuple("Skykey").field(name).finish(),
+      Self::Local(_) => f.debug_tuple("Local").field(&"..").finish(),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SkyDbOptions {
+  pub schema_version: Option<u32>,
+  pub encryption: Option<SkyDbEncryption>,
+  pub upload: UploadOptions,
+  pub download: DownloadOptions,
+  pub entry: EntryOptions,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DbEntry<T> {
+  pub data: T,
+  pub skylink: String,
+  pub revision: u64,
+}
+
+/// Returns the skylink and revision stored under `data_key`, or `None` if it was never set.
+async fn get_pointer(
+  client: &SkynetClient,
+  public_key: &PublicKey,
+  data_key: &str,
+  opt: &SkyDbOptions,
+) -> SkynetResult<Option<(String, u64)>> {
+  get_pointer_with(client, public_key, data_key, opt.entry.clone()).await
+}
+
+async fn get_pointer_with(
+  client: &SkynetClient,
+  public_key: &PublicKey,
+  data_key: &str,
+  entry_opt: EntryOptions,
+) -> SkynetResult<Option<(String, u64)>> {
+  match registry::find_registry_entry(client, public_key, data_key, entry_opt).await? {
+    Some(signed) => Ok(Some((parse_pointer(&signed.entry.data)?, signed.entry.revision))),
+    None => Ok(None),
+  }
+}
+
+fn upload_options(opt: &SkyDbOptions) -> UploadOptions {
+  let skykey_name = match &opt.encryption {
+    Some(SkyDbEncryption::Skykey(name)) => Some(name.clone()),
+    _ => opt.upload.skykey_name.clone(),
+  };
+  // Registry entries keep the bare skylink, whatever format the caller asked for.
+  UploadOptions { custom_dirname: None, skykey_name, skylink_format: SkylinkFormat::Raw, ..opt.upload.clone() }
+}
+
+fn download_options(opt: &SkyDbOptions) -> DownloadOptions {
+  let skykey_name = match &opt.encryption {
+    Some(SkyDbEncryption::Skykey(name)) => Some(name.clone()),
+    _ => opt.download.skykey_name.clone(),
+  };
+  DownloadOptions { skykey_name, ..opt.download.clone() }
+}
+
+fn decode<T: DeserializeOwned>(data_key: &str, bytes: &[u8], opt: &SkyDbOptions) -> SkynetResult<T> {
+  let decrypted;
+  let bytes = match &opt.encryption {
+    Some(SkyDbEncryption::Local(key)) => {
+      decrypted = crypto::decrypt(key, bytes)?;
+      &decrypted[..]
+    }
+    _ => bytes,
+  };
+
+  let envelope: Envelope<Json> = serde_json::from_slice(bytes)
+    .map_err(|err| SchemaError(format!("{}: not a SkyDB entry: {}", data_key, err)))?;
+
+  if let Some(expected) = opt.schema_version {
+    if envelope.schema != Some(expected) {
+      return Err(SchemaError(format!(
+        "{}: expected schema version {}, found {:?}",
+        data_key, expected, envelope.schema)));
+    }
+  }
+
+  serde_json::from_value(envelope.data)
+    .map_err(|err| SchemaError(format!("{}: stored data doesn't match the expected type: {}", data_key, err)))
+}
+
+pub async fn db_get<T: DeserializeOwned>(
+  client: &SkynetClient,
+  public_key: &PublicKey,
+  data_key: &str,
+  opt: &SkyDbOptions,
+) -> SkynetResult<Option<DbEntry<T>>> {
+  let (skylink, revision) = match get_pointer(client, public_key, data_key, opt).await? {
+    Some(pointer) => pointer,
+    None => return Ok(None),
+  };
+
+  let bytes = download::download_data(client, &skylink, download_options(opt)).await?;
+  let data = decode(data_key, &bytes, opt)?;
+
+  Ok(Some(DbEntry { data, skylink, revision }))
+}
+
+/// Reads a MySky discoverable file, such as a DAC's data, for the hex-encoded `user_id`.
+pub async fn get_discoverable_json<T: DeserializeOwned>(
+  client: &SkynetClient,
+  user_id: &str,
+  path: &str,
+  opt: &SkyDbOptions,
+) -> SkynetResult<Option<DbEntry<T>>> {
+  let public_key = <[u8; 32]>::from_hex(user_id)
+    .map(PublicKey::Ed25519)
+    .map_err(|_| InvalidUserId(user_id.to_string()))?;
+  let data_key = crypto::derive_discoverable_file_tweak(path).encode_hex::<String>();
+  let entry_opt = EntryOptions {
+    hashed_data_key_hex: true,
+    ..opt.entry.clone()
+  };
+
+  let (skylink, revision) = match get_pointer_with(client, &public_key, &data_key, entry_opt).await? {
+    Some(pointer) => pointer,
+    None => return Ok(None),
+  };
+
+  let bytes = download::download_data(client, &skylink, download_options(opt)).await?;
+  let data = decode(path, &bytes, opt)?;
+
+  Ok(Some(DbEntry { data, skylink, revision }))
+}
+
+pub(crate) async fn db_set_revision<T: Serialize>(
+  client: &SkynetClient,
+  keypair: &KeyPair,
+  data_key: &str,
+  data: &T,
+  revision: u64,
+  opt: &SkyDbOptions,
+) -> SkynetResult<String> {
+  let envelope = Envelope {
+    data,
+    version: ENVELOPE_VERSION,
+    schema: opt.schema_version,
+  };
+
+  let mut json = serde_json::to_vec(&envelope).map_err(JsonError)?;
+  let file = match &opt.encryption {
+    Some(SkyDbEncryption::Local(key)) => {
+      // Pad with trailing whitespace, which JSON ignores, so the ciphertext hides the exact size.
+      let size = (json.len() + crypto::ENCRYPTION_OVERHEAD) as u64;
+      if let Some(padded) = crypto::pad_file_size(size) {
+        json.resize(padded as usize - crypto::ENCRYPTION_OVERHEAD, b' ');
+      }
+      ("skydb.bin".to_string(), (mime::APPLICATION_OCTET_STREAM, crypto::encrypt(key, &json).into()))
+    }
+    _ => ("skydb.json".to_string(), (mime::APPLICATION_JSON, json.into())),
+  };
+
+  let mut files = HashMap::new();
+  files.insert(file.0, file.1);
+  let skylink = upload::upload_data(client, files, upload_options(opt)).await?;
+
+  registry::set_registry_entry(
+    client,
+    &keypair.into(),
+    &keypair.private_key,
+    RegistryEntry {
+      data_key: data_key.into(),
+      data: skylink.clone().into_bytes(),
+      revision,
+    },
+    opt.entry.clone(),
+  ).await?;
+
+  Ok(skylink)
+}
+
+/// Writes `data` over the latest revision, returning the new skylink and revision.
+async fn db_set_latest<T: Serialize>(
+  client: &SkynetClient,
+  keypair: &KeyPair,
+  data_key: &str,
+  data: &T,
+  opt: &SkyDbOptions,
+) -> SkynetResult<(String, u64)> {
+  let public_key = PublicKey::from(keypair);
+  let _writer = client.revisions().lock_entry(&public_key, data_key).await;
+  let latest = get_pointer(client, &public_key, data_key, opt).await?.map(|(_, revision)| revision);
+  let revision = client.revisions().next_revision(&public_key, data_key, latest);
+
+  let skylink = db_set_revision(client, keypair, data_key, data, revision, opt).await?;
+  Ok((skylink, revision))
+}
+
+/// Uploads `data` and points the registry entry at it, returning the new skylink.
+pub async fn db_set<T: Serialize>(
+  client: &SkynetClient,
+  keypair: &KeyPair,
+  data_key: &str,
+  data: &T,
+  opt: &SkyDbOptions,
+) -> SkynetResult<String> {
+  db_set_latest(client, keypair, data_key, data, opt).await.map(|(skylink, _)| skylink)
+}
+
+/// Reads untyped JSON stored under `data_key`, like skynet-js's `getJSON`.
+pub async fn get_json(
+  client: &SkynetClient,
+  public_key: &PublicKey,
+  data_key: &str,
+  opt: &SkyDbOptions,
+) -> SkynetResult<Option<DbEntry<Json>>> {
+  db_get(client, public_key, data_key, opt).await
+}
+
+/// Stores untyped JSON under `data_key`, like skynet-js's `setJSON`. Returns the entry as
+/// written, with the skylink of the uploaded data.
+pub async fn set_json(
+  client: &SkynetClient,
+  keypair: &KeyPair,
+  data_key: &str,
+  data: Json,
+  opt: &SkyDbOptions,
+) -> SkynetResult<DbEntry<Json>> {
+  let (skylink, revision) = db_set_latest(client, keypair, data_key, &data, opt).await?;
+  Ok(DbEntry { data, skylink, revision })
+}
+
+/// Read-modify-write of the value under `data_key`. `update` receives the current value, if
+/// any, and returns the new one. When another writer bumps the revision in between, the value
+/// is re-read and `update` runs again.
+pub async fn db_update<T, F>(
+  client: &SkynetClient,
+  keypair: &KeyPair,
+  data_key: &str,
+  mut update: F,
+  opt: &SkyDbOptions,
+) -> SkynetResult<DbEntry<T>>
+where
+  T: Serialize + DeserializeOwned,
+  F: FnMut(Option<T>) -> T,
+{
+  let mut attempts = 0;
+
+  let public_key = PublicKey::from(keypair);
+  let _writer = client.revisions().lock_entry(&public_key, data_key).await;
+
+  loop {
+    let current = db_get::<T>(client, &public_key, data_key, opt).await?;
+    let revision = current.as_ref().map(|entry| entry.revision + 1).unwrap_or(0);
+    let data = update(current.map(|entry| entry.data));
+
+    match db_set_revision(client, keypair, data_key, &data, revision, opt).await {
+      Ok(skylink) => return Ok(DbEntry { data, skylink, revision }),
+      Err(RevisionConflict(_)) if attempts + 1 < MAX_UPDATE_ATTEMPTS => attempts += 1,
+      Err(err) => return Err(err),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{gen_keypair_and_seed, MockPortal, MockResponse, MockTransport};
+  use hyper::StatusCode;
+  use proptest::prelude::*;
+
+  #[derive(Debug, PartialEq, Serialize, Deserialize)]
+  struct Profile {
+    name: String,
+    age: u32,
+  }
+
+  #[tokio::test]
+  async fn test_db_get_set() {
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let (keypair, _) = gen_keypair_and_seed(64);
+    let opt = SkyDbOptions {
+      schema_version: Some(1),
+      ..Default::default()
+    };
+
+    let res = db_get::<Profile>(&client, &PublicKey::from(&keypair), "profile", &opt).await;
+    assert!(res.unwrap().is_none());
+
+    let profile = Profile { name: "alice".into(), age: 30 };
+    db_set(&client, &keypair, "profile", &profile, &opt).await.unwrap();
+    let profile = Profile { name: "alice".into(), age: 31 };
+    let skylink = db_set(&client, &keypair, "profile", &profile, &opt).await.unwrap();
+
+    let res = db_get::<Profile>(&client, &PublicKey::from(&keypair), "profile", &opt).await;
+    println!("{:?}", res);
+    assert_eq!(res.unwrap(), Some(DbEntry { data: profile, skylink, revision: 1 }));
+
+    let res = db_get::<Vec<String>>(&client, &PublicKey::from(&keypair), "profile", &opt).await;
+    assert!(matches!(res, Err(SchemaError(_))));
+
+    let opt = SkyDbOptions {
+      schema_version: Some(2),
+      ..Default::default()
+    };
+    let res = db_get::<Profile>(&client, &PublicKey::from(&keypair), "profile", &opt).await;
+    assert!(matches!(res, Err(SchemaError(_))));
+  }
+
+  #[tokio::test]
+  async fn test_db_get_portal_error() {
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::new(StatusCode::TOO_MANY_REQUESTS, "slow down"));
+    let client = transport.client("https://siasky.net");
+    let (keypair, _) = gen_keypair_and_seed(64);
+
+    let res = db_get::<Profile>(&client, &PublicKey::from(&keypair), "profile", &Default::default()).await;
+    assert!(matches!(res.map_err(crate::SkynetError::into_inner), Err(PortalResponse(_))));
+  }
+
+  #[tokio::test]
+  async fn test_get_set_json() {
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let (keypair, _) = gen_keypair_and_seed(64);
+    let public_key = PublicKey::from(&keypair);
+    let opt = SkyDbOptions::default();
+
+    assert!(get_json(&client, &public_key, "app", &opt).await.unwrap().is_none());
+
+    set_json(&client, &keypair, "app", serde_json::json!({ "theme": "dark" }), &opt).await.unwrap();
+    let written = set_json(&client, &keypair, "app", serde_json::json!({ "theme": "light" }), &opt).await.unwrap();
+    assert_eq!(written.revision, 1);
+
+    let res = get_json(&client, &public_key, "app", &opt).await;
+    println!("{:?}", res);
+    assert_eq!(res.unwrap(), Some(written));
+
+    let res = db_get::<HashMap<String, String>>(&client, &public_key, "app", &opt).await.unwrap().unwrap();
+    assert_eq!(res.data["theme"], "light");
+  }
+
+  #[tokio::test]
+  async fn test_db_local_encryption() {
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let (keypair, _) = gen_keypair_and_seed(64);
+    let opt = SkyDbOptions {
+      encryption: Some(SkyDbEncryption::Local([7; 32])),
+      ..Default::default()
+    };
+
+    let profile = Profile { name: "alice".into(), age: 30 };
+    let skylink = db_set(&client, &keypair, "profile", &profile, &opt).await.unwrap();
+    let raw = download::download_data(&client, &skylink, DownloadOptions::default()).await.unwrap();
+    assert!(!String::from_utf8_lossy(&raw).contains("alice"));
+    assert_eq!(raw.len(), 4096);
+    assert!(crypto::check_padded_block(raw.len() as u64));
+
+    let res = db_get::<Profile>(&client, &PublicKey::from(&keypair), "profile", &opt).await;
+    println!("{:?}", res);
+    assert_eq!(res.unwrap().unwrap().data, profile);
+
+    let res = db_get::<Profile>(&client, &PublicKey::from(&keypair), "profile", &SkyDbOptions {
+      encryption: Some(SkyDbEncryption::Local([8; 32])),
+      ..Default::default()
+    }).await;
+    assert!(matches!(res, Err(DecryptionError)));
+  }
+
+  #[test]
+  fn test_skykey_options() {
+    let opt = SkyDbOptions {
+      encryption: Some(SkyDbEncryption::Skykey("private".into())),
+      ..Default::default()
+    };
+    assert_eq!(upload_options(&opt).skykey_name, Some("private".into()));
+    assert_eq!(download_options(&opt).skykey_name, Some("private".into()));
+    assert_eq!(format!("{:?}", SkyDbEncryption::Local([7; 32])), "Local(\"..\")");
+  }
+
+  #[tokio::test]
+  async fn test_get_discoverable_json() {
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let (keypair, _) = gen_keypair_and_seed(64);
+    let user_id = keypair.public_key.encode_hex::<String>();
+    let path = "example.hns/data.json";
+    let opt = SkyDbOptions {
+      entry: EntryOptions { hashed_data_key_hex: true, ..Default::default() },
+      ..Default::default()
+    };
+
+    let res = get_discoverable_json::<u32>(&client, &user_id, path, &opt).await;
+    assert!(res.unwrap().is_none());
+
+    let data_key = crypto::derive_discoverable_file_tweak(path).encode_hex::<String>();
+    db_set_revision(&client, &keypair, &data_key, &42, 0, &opt).await.unwrap();
+    let res = get_discoverable_json::<u32>(&client, &user_id, path, &SkyDbOptions::default()).await;
+    println!("{:?}", res);
+    assert_eq!(res.unwrap().unwrap().data, 42);
+
+    assert!(matches!(
+      get_discoverable_json::<u32>(&client, "xyz", path, &opt).await,
+      Err(InvalidUserId(_))));
+  }
+
+  #[tokio::test]
+  async fn test_db_update() {
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let (keypair, _) = gen_keypair_and_seed(64);
+    let opt = SkyDbOptions::default();
+
+    let increment = |current: Option<u32>| current.unwrap_or(0) + 1;
+    let (first, second) = futures_util::future::join(
+      db_update(&client, &keypair, "counter", increment, &opt),
+      db_update(&client, &keypair, "counter", increment, &opt),
+    ).await;
+    println!("{:?} {:?}", first, second);
+    assert!(first.is_ok());
+    assert!(second.is_ok());
+
+    let entry = db_get::<u32>(&client, &PublicKey::from(&keypair), "counter", &opt).await.unwrap().unwrap();
+    assert_eq!(entry.data, 2);
+    assert_eq!(entry.revision, 1);
+  }
+
+  proptest! {
+    // Each case starts a mock portal, so keep the count down.
+    #![proptest_config(ProptestConfig::with_cases(8))]
+
+    #[test]
+    fn prop_db_roundtrip(name in ".{0,64}", age in any::<u32>(), key in proptest::option::of(any::<[u8; 32]>())) {
+      let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+      let profile = Profile { name, age };
+      let opt = SkyDbOptions {
+        encryption: key.map(SkyDbEncryption::Local),
+        ..Default::default()
+      };
+
+      let read = runtime.block_on(async {
+        let portal = MockPortal::start().await;
+        let client = portal.client();
+        let (keypair, _) = gen_keypair_and_seed(64);
+        let skylink = db_set(&client, &keypair, "profile", &profile, &opt).await?;
+        let entry = db_get::<Profile>(&client, &PublicKey::from(&keypair), "profile", &opt).await?;
+        Ok::<_, crate::SkynetError>((skylink, entry))
+      });
+      let (skylink, entry) = read.unwrap();
+      prop_assert_eq!(entry, Some(DbEntry { data: profile, skylink, revision: 0 }));
+    }
+  }
+}
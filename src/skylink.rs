@@ -0,0 +1,290 @@
+use crate::{SkynetError::*, SkynetResult, URI_SKYNET_PREFIX};
+use std::{fmt, str::FromStr};
+use hyper::Uri;
+use serde::{Deserialize, Serialize};
+
+pub const SECTOR_SIZE: u64 = 1 << 22;
+
+const RAW_SKYLINK_SIZE: usize = 34;
+const BASE64_SKYLINK_SIZE: usize = 46;
+const BASE32_SKYLINK_SIZE: usize = 55;
+/// RFC 4648 extended hex alphabet, lowercased, which portals use for skylink subdomains.
+const BASE32_ALPHABET: &[u8] = b"0123456789abcdefghijklmnopqrstuv";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SkylinkVersion {
+  /// Points directly at data in a sector.
+  V1,
+  /// Resolves through a registry entry whose ID is the merkle root.
+  V2,
+}
+
+/// How skylinks returned from uploads are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SkylinkFormat {
+  /// The bare base64 skylink.
+  Raw,
+  /// `sia://<skylink>`.
+  #[default]
+  SiaUri,
+  /// `<portal URL>/<skylink>`, ready to open in a browser.
+  PortalUrl,
+}
+
+impl SkylinkFormat {
+  /// Writes a skylink, with or without a `sia://` prefix, in this format.
+  pub fn format(self, skylink: &str, portal_url: &str) -> String {
+    let skylink = skylink.strip_prefix(URI_SKYNET_PREFIX).unwrap_or(skylink);
+    match self {
+      Self::Raw => skylink.to_string(),
+      Self::SiaUri => format!("{}{}", URI_SKYNET_PREFIX, skylink),
+      Self::PortalUrl => format!("{}/{}", portal_url.trim_end_matches('/'), skylink),
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Skylink {
+  pub bitfield: u16,
+  pub merkle_root: [u8; 32],
+}
+
+impl Skylink {
+  /// Builds a version 1 skylink pointing at `fetch_size` bytes of the sector at `offset`.
+  pub fn new_v1(merkle_root: [u8; 32], offset: u64, fetch_size: u64) -> SkynetResult<Self> {
+    if fetch_size == 0 || fetch_size > SECTOR_SIZE || offset + fetch_size > SECTOR_SIZE {
+      return Err(InvalidSkylink(format!("invalid offset {} and fetch size {}", offset, fetch_size)));
+    }
+
+    let mut mode = 0;
+    while fetch_size > (1 << 15) << mode {
+      mode += 1;
+    }
+
+    let (fetch_size_start, fetch_size_increment) = if mode == 0 {
+      (0, 4096)
+    } else {
+      ((1 << 15) << (mode - 1), 4096 << (mode - 1))
+    };
+    let fetch_size_bits = (fetch_size - fetch_size_start - 1) / fetch_size_increment;
+
+    let offset_increment = 4096 << mode;
+    if offset & (offset_increment - 1) != 0 {
+      return Err(InvalidSkylink(format!("offset {} is not aligned to {}", offset, offset_increment)));
+    }
+    let offset_bits = offset / offset_increment;
+
+    let bitfield = (((1u16 << mode) - 1) << 2)
+      | ((fetch_size_bits as u16) << (3 + mode))
+      | ((offset_bits as u16) << (6 + mode));
+
+    Ok(Self { bitfield, merkle_root })
+  }
+
+  /// The version encoded in the two lowest bits of the bitfield. Versions 3 and 4 are reserved,
+  /// and a v2 skylink must not set any other bits.
+  pub fn version(&self) -> SkynetResult<SkylinkVersion> {
+    match self.bitfield & 3 {
+      0 => Ok(SkylinkVersion::V1),
+      1 if self.bitfield == 1 => Ok(SkylinkVersion::V2),
+      1 => Err(InvalidSkylink(format!("v2 skylink has unexpected bitfield {:#x}", self.bitfield))),
+      version => Err(InvalidSkylink(format!("unknown skylink version {}", version + 1))),
+    }
+  }
+
+  pub fn to_bytes(&self) -> [u8; RAW_SKYLINK_SIZE] {
+    let mut bytes = [0; RAW_SKYLINK_SIZE];
+    bytes[..2].copy_from_slice(&self.bitfield.to_le_bytes());
+    bytes[2..].copy_from_slice(&self.merkle_root);
+    bytes
+  }
+
+  pub fn from_bytes(bytes: &[u8]) -> SkynetResult<Self> {
+    if bytes.len() != RAW_SKYLINK_SIZE {
+      return Err(InvalidSkylink(format!("expected {} bytes, got {}", RAW_SKYLINK_SIZE, bytes.len())));
+    }
+
+    let mut merkle_root = [0; 32];
+    merkle_root.copy_from_slice(&bytes[2..]);
+
+    let skylink = Self {
+      bitfield: u16::from_le_bytes([bytes[0], bytes[1]]),
+      merkle_root,
+    };
+    skylink.version()?;
+    Ok(skylink)
+  }
+
+  /// Encodes the skylink for use as a portal subdomain.
+  pub fn to_base32(&self) -> String {
+    let mut encoded = String::with_capacity(BASE32_SKYLINK_SIZE);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for &byte in self.to_bytes().iter() {
+      buffer = (buffer << 8) | byte as u32;
+      bits += 8;
+      while bits >= 5 {
+        bits -= 5;
+        encoded.push(BASE32_ALPHABET[(buffer >> bits) as usize & 31] as char);
+      }
+    }
+    if bits > 0 {
+      encoded.push(BASE32_ALPHABET[(buffer << (5 - bits)) as usize & 31] as char);
+    }
+    encoded
+  }
+
+  pub fn from_base32(s: &str) -> SkynetResult<Self> {
+    if s.len() != BASE32_SKYLINK_SIZE {
+      return Err(InvalidSkylink(s.to_string()));
+    }
+
+    let mut bytes = Vec::with_capacity(RAW_SKYLINK_SIZE);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in s.bytes() {
+      let value = BASE32_ALPHABET
+        .iter()
+        .position(|&a| a == c.to_ascii_lowercase())
+        .ok_or_else(|| InvalidSkylink(s.to_string()))?;
+      buffer = (buffer << 5) | value as u32;
+      bits += 5;
+      if bits >= 8 {
+        bits -= 8;
+        bytes.push((buffer >> bits) as u8);
+      }
+    }
+    Self::from_bytes(&bytes)
+  }
+}
+
+/// Parses any form a skylink is commonly shared in: raw base64, `sia://` URIs, portal path URLs
+/// (`https://siasky.net/<skylink>/path`) and portal subdomain URLs
+/// (`https://<base32 skylink>.siasky.net/path`). Returns the skylink and the subpath, if any.
+pub fn parse_skylink(input: &str) -> SkynetResult<(Skylink, Option<String>)> {
+  let invalid = || InvalidSkylink(input.to_string());
+
+  let path = if let Some(rest) = input.strip_prefix(URI_SKYNET_PREFIX) {
+    rest.to_string()
+  } else if input.starts_with("https://") || input.starts_with("http://") {
+    let uri: Uri = input.parse().map_err(|_| invalid())?;
+    let host = uri.host().ok_or_else(invalid)?;
+    let subdomain = host.split('.').next().unwrap_or("");
+
+    if subdomain.len() == BASE32_SKYLINK_SIZE && host.len() > subdomain.len() {
+      let skylink = Skylink::from_base32(subdomain)?;
+      let path = uri.path().trim_matches('/');
+      let path = if path.is_empty() { None } else { Some(path.to_string()) };
+      return Ok((skylink, path));
+    }
+
+    uri.path().to_string()
+  } else {
+    input.to_string()
+  };
+
+  let path = path.split(&['?', '#'][..]).next().unwrap_or("");
+  let mut parts = path.trim_start_matches('/').splitn(2, '/');
+  let skylink = parts.next().unwrap_or("").parse::<Skylink>().map_err(|_| invalid())?;
+  let path = parts.next().map(|path| path.trim_end_matches('/')).filter(|path| !path.is_empty());
+
+  Ok((skylink, path.map(|path| path.to_string())))
+}
+
+/// Normalizes any form accepted by `parse_skylink` to `<base64 skylink>[/<path>]`.
+pub fn normalize_skylink(input: &str) -> SkynetResult<String> {
+  let (skylink, path) = parse_skylink(input)?;
+  Ok(match path {
+    Some(path) => format!("{}/{}", skylink, path),
+    None => skylink.to_string(),
+  })
+}
+
+impl fmt::Display for Skylink {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", base64::encode_config(self.to_bytes(), base64::URL_SAFE_NO_PAD))
+  }
+}
+
+impl FromStr for Skylink {
+  type Err = crate::SkynetError;
+
+  fn from_str(s: &str) -> SkynetResult<Self> {
+    if s.len() != BASE64_SKYLINK_SIZE {
+      return Err(InvalidSkylink(s.to_string()));
+    }
+
+    let bytes = base64::decode_config(s, base64::URL_SAFE_NO_PAD)
+      .map_err(|_| InvalidSkylink(s.to_string()))?;
+    Self::from_bytes(&bytes)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_skylink_roundtrip() {
+    let skylink = Skylink::new_v1([7; 32], 0, 11).unwrap();
+    assert_eq!(skylink.bitfield, 0);
+    let encoded = skylink.to_string();
+    assert_eq!(encoded.len(), BASE64_SKYLINK_SIZE);
+    assert_eq!(encoded.parse::<Skylink>().unwrap(), skylink);
+  }
+
+  #[test]
+  fn test_skylink_bitfield() {
+    assert_eq!(Skylink::new_v1([0; 32], 0, 4096 * 8).unwrap().bitfield, 7 << 3);
+    assert_eq!(Skylink::new_v1([0; 32], 0, 4096 * 8 + 1).unwrap().bitfield, 1 << 2);
+    assert_eq!(Skylink::new_v1([0; 32], 0, SECTOR_SIZE).unwrap().bitfield, 0x7f << 2 | 7 << 10);
+    assert!(Skylink::new_v1([0; 32], 0, SECTOR_SIZE + 1).is_err());
+    assert!(Skylink::new_v1([0; 32], 1, 10).is_err());
+    assert!("AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng".parse::<Skylink>().is_ok());
+  }
+
+  #[test]
+  fn test_skylink_version() {
+    let v1 = Skylink::new_v1([0; 32], 0, SECTOR_SIZE).unwrap();
+    assert_eq!(v1.version().unwrap(), SkylinkVersion::V1);
+    let v2 = Skylink { bitfield: 1, merkle_root: [0; 32] };
+    assert_eq!(v2.version().unwrap(), SkylinkVersion::V2);
+    assert!(Skylink { bitfield: 1 | 4, merkle_root: [0; 32] }.version().is_err());
+    assert!(Skylink { bitfield: 2, merkle_root: [0; 32] }.version().is_err());
+    assert!(Skylink { bitfield: 3, merkle_root: [0; 32] }.version().is_err());
+
+    let mut bytes = v2.to_bytes();
+    bytes[0] = 3;
+    assert!(matches!(Skylink::from_bytes(&bytes), Err(InvalidSkylink(_))));
+  }
+
+  #[test]
+  fn test_parse_skylink() {
+    let encoded = "AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng";
+    let skylink = encoded.parse::<Skylink>().unwrap();
+    let base32 = skylink.to_base32();
+    assert_eq!(base32.len(), BASE32_SKYLINK_SIZE);
+    assert_eq!(Skylink::from_base32(&base32).unwrap(), skylink);
+
+    let dir = Some("dir/file.txt".to_string());
+    assert_eq!(parse_skylink(encoded).unwrap(), (skylink, None));
+    assert_eq!(parse_skylink(&format!("sia://{}/dir/file.txt", encoded)).unwrap(), (skylink, dir.clone()));
+    assert_eq!(parse_skylink(&format!("{}/dir/file.txt?format=zip", encoded)).unwrap(), (skylink, dir.clone()));
+    assert_eq!(parse_skylink(&format!("https://siasky.net/{}/", encoded)).unwrap(), (skylink, None));
+    assert_eq!(parse_skylink(&format!("https://siasky.net/{}/dir/file.txt", encoded)).unwrap(), (skylink, dir.clone()));
+    assert_eq!(parse_skylink(&format!("https://{}.siasky.net/dir/file.txt", base32)).unwrap(), (skylink, dir));
+    assert_eq!(normalize_skylink(&format!("sia://{}/a", encoded)).unwrap(), format!("{}/a", encoded));
+
+    assert!(parse_skylink("AAA").is_err());
+    assert!(parse_skylink("https://siasky.net/").is_err());
+    assert!(parse_skylink("sia://").is_err());
+  }
+
+  #[test]
+  fn test_skylink_format() {
+    let skylink = "AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng";
+    let portal_url = "https://siasky.net/";
+    assert_eq!(SkylinkFormat::Raw.format(&format!("sia://{}", skylink), portal_url), skylink);
+    assert_eq!(SkylinkFormat::SiaUri.format(skylink, portal_url), format!("sia://{}", skylink));
+    assert_eq!(SkylinkFormat::SiaUri.format(&format!("sia://{}", skylink), portal_url), format!("sia://{}", skylink));
+    assert_eq!(SkylinkFormat::PortalUrl.format(skylink, portal_url), format!("https://siasky.net/{}", skylink));
+  }
+}
@@ -0,0 +1,120 @@
+use std::{
+  fs::File,
+  io::{self, Read, Seek, SeekFrom},
+  path::Path,
+};
+#[cfg(feature = "directory")]
+use std::{fs, io::Write};
+
+/// `(offset, length)` of each region of a file that holds data. Everything else is a hole and
+/// reads as zeros.
+pub(crate) type DataRanges = Vec<(u64, u64)>;
+
+/// Finds the data regions of `file` with `SEEK_DATA`/`SEEK_HOLE`. Where those aren't available
+/// the whole file counts as data.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+fn data_ranges(file: &File, len: u64) -> io::Result<DataRanges> {
+  use std::os::unix::io::AsRawFd;
+
+  let fd = file.as_raw_fd();
+  let mut ranges = Vec::new();
+  let mut offset = 0;
+
+  while offset < len {
+    let start = unsafe { libc::lseek(fd, offset as libc::off_t, libc::SEEK_DATA) };
+    if start < 0 {
+      let err = io::Error::last_os_error();
+      // ENXIO means there is no data past `offset`.
+      if err.raw_os_error() == Some(libc::ENXIO) {
+        break;
+      }
+      return Err(err);
+    }
+
+    let end = unsafe { libc::lseek(fd, start, libc::SEEK_HOLE) };
+    if end < 0 {
+      return Err(io::Error::last_os_error());
+    }
+
+    let (start, end) = (start as u64, (end as u64).min(len));
+    ranges.push((start, end - start));
+    offset = end;
+  }
+
+  Ok(ranges)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+fn data_ranges(_file: &File, len: u64) -> io::Result<DataRanges> {
+  Ok(if len == 0 { Vec::new() } else { vec![(0, len)] })
+}
+
+/// Reads a file without reading its holes from disk. The buffer still holds the whole file,
+/// with the holes as zeros, so the ranges only save disk reads and let a download recreate the
+/// holes. They are `None` if the file has no holes.
+pub(crate) fn read_sparse(path: &Path) -> io::Result<(Vec<u8>, Option<DataRanges>)> {
+  let mut file = File::open(path)?;
+  let len = file.metadata()?.len();
+  let ranges = data_ranges(&file, len)?;
+
+  let mut data = vec![0; len as usize];
+  for &(offset, length) in &ranges {
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(&mut data[offset as usize..(offset + length) as usize])?;
+  }
+
+  let dense = len == 0 || ranges == [(0, len)];
+  Ok((data, if dense { None } else { Some(ranges) }))
+}
+
+/// Writes `data` to `path`, leaving everything outside `ranges` as holes.
+#[cfg(feature = "directory")]
+pub(crate) fn write_sparse(path: &Path, data: &[u8], ranges: &[(u64, u64)]) -> io::Result<()> {
+  if ranges.iter().any(|&(offset, length)| offset.saturating_add(length) > data.len() as u64) {
+    return fs::write(path, data);
+  }
+
+  let mut file = File::create(path)?;
+  file.set_len(data.len() as u64)?;
+  for &(offset, length) in ranges {
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(&data[offset as usize..(offset + length) as usize])?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::{fs, io::Write};
+
+  #[test]
+  fn test_sparse_roundtrip() {
+    fs::create_dir_all("tmpsparse").unwrap();
+    let path = Path::new("tmpsparse/disk.img");
+    let mut file = File::create(path).unwrap();
+    file.set_len(8 << 20).unwrap();
+    file.seek(SeekFrom::Start(4 << 20)).unwrap();
+    file.write_all(b"hello").unwrap();
+    drop(file);
+
+    let (data, ranges) = read_sparse(path).unwrap();
+    assert_eq!(data, fs::read(path).unwrap());
+    // Filesystems without hole support report the whole file as data.
+    if let Some(ranges) = &ranges {
+      assert!(ranges.iter().map(|(_, length)| length).sum::<u64>() < 8 << 20);
+      assert!(ranges.iter().any(|&(offset, length)| offset <= 4 << 20 && offset + length >= (4 << 20) + 5));
+
+      #[cfg(feature = "directory")]
+      {
+        let copy = Path::new("tmpsparse/copy.img");
+        write_sparse(copy, &data, ranges).unwrap();
+        assert_eq!(fs::read(copy).unwrap(), data);
+      }
+    }
+
+    let (_, ranges) = read_sparse(Path::new("Cargo.toml")).unwrap();
+    assert_eq!(ranges, None);
+    fs::remove_dir_all("tmpsparse").unwrap();
+  }
+}
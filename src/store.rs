@@ -0,0 +1,114 @@
+use crate::{util::{blocking, encode_component}, SkynetError::*, SkynetResult};
+use std::{
+  collections::HashMap,
+  fmt::Debug,
+  fs,
+  future::Future,
+  io,
+  path::{Path, PathBuf},
+  pin::Pin,
+  sync::{Arc, Mutex},
+};
+use bytes::Bytes;
+
+pub type StoreFuture<T> = Pin<Box<dyn Future<Output = SkynetResult<T>> + Send>>;
+
+/// Content kept by skylink, which `SkynetClient` reads through when `local_store` is set:
+/// downloads are answered from the store when it has the skylink and saved to it otherwise.
+/// Keys are v1 skylinks in their normalized `<base64 skylink>[/<path>]` form, followed by the
+/// download's skykey and decompression options when it has any. A failed `put` only means the
+/// content isn't kept.
+pub trait LocalStore: Debug + Send + Sync {
+  fn get(&self, skylink: &str) -> StoreFuture<Option<Bytes>>;
+  fn put(&self, skylink: &str, data: Bytes) -> StoreFuture<()>;
+}
+
+/// A `LocalStore` keeping one file per skylink in a directory.
+#[derive(Debug, Clone)]
+pub struct FsStore {
+  dir: Arc<PathBuf>,
+}
+
+impl FsStore {
+  pub fn open<P: AsRef<Path>>(dir: P) -> SkynetResult<Self> {
+    let dir = dir.as_ref().to_path_buf();
+    fs::create_dir_all(&dir).map_err(FileError)?;
+    Ok(Self { dir: Arc::new(dir) })
+  }
+
+  fn path(&self, skylink: &str) -> PathBuf {
+    // Subfile paths are escaped so that every skylink is a single file name.
+    self.dir.join(encode_component(skylink))
+  }
+}
+
+impl LocalStore for FsStore {
+  fn get(&self, skylink: &str) -> StoreFuture<Option<Bytes>> {
+    let path = self.path(skylink);
+    Box::pin(blocking(move || match fs::read(path) {
+      Ok(data) => Ok(Some(data.into())),
+      Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+      Err(err) => Err(FileError(err)),
+    }))
+  }
+
+  fn put(&self, skylink: &str, data: Bytes) -> StoreFuture<()> {
+    let path = self.path(skylink);
+    Box::pin(blocking(move || {
+      // Written aside and renamed into place, so readers never see part of a file.
+      let partial = path.with_extension("partial");
+      fs::write(&partial, &data).map_err(FileError)?;
+      fs::rename(&partial, &path).map_err(FileError)
+    }))
+  }
+}
+
+/// A `LocalStore` in memory, shared by its clones.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStore {
+  entries: Arc<Mutex<HashMap<String, Bytes>>>,
+}
+
+impl MemoryStore {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn len(&self) -> usize {
+    self.entries.lock().unwrap().len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len() == 0
+  }
+}
+
+impl LocalStore for MemoryStore {
+  fn get(&self, skylink: &str) -> StoreFuture<Option<Bytes>> {
+    let data = self.entries.lock().unwrap().get(skylink).cloned();
+    Box::pin(async move { Ok(data) })
+  }
+
+  fn put(&self, skylink: &str, data: Bytes) -> StoreFuture<()> {
+    self.entries.lock().unwrap().insert(skylink.to_string(), data);
+    Box::pin(async { Ok(()) })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn test_fs_store() {
+    let store = FsStore::open("tmpstore").unwrap();
+    let skylink = "AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng/dir/a.txt";
+    assert_eq!(store.get(skylink).await.unwrap(), None);
+    store.put(skylink, Bytes::from("hello")).await.unwrap();
+    let res = store.get(skylink).await;
+    let files = fs::read_dir("tmpstore").unwrap().count();
+    fs::remove_dir_all("tmpstore").unwrap();
+    assert_eq!(res.unwrap(), Some(Bytes::from("hello")));
+    assert_eq!(files, 1);
+  }
+}
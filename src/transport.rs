@@ -0,0 +1,238 @@
+use crate::{
+  dns::{HyperResolver, Resolve, SystemResolver},
+  SkynetError::*, SkynetResult,
+};
+use std::{
+  fmt::Debug,
+  future::Future,
+  io,
+  pin::Pin,
+  sync::Arc,
+  task::{Context, Poll},
+};
+#[cfg(all(unix, feature = "uds"))]
+use std::path::{Path, PathBuf};
+use hyper::{
+  client::{
+    connect::{Connected, Connection},
+    HttpConnector,
+  },
+  service::Service,
+  Body, Client, Request, Response, Uri,
+};
+use hyper_tls::{HttpsConnector, MaybeHttpsStream};
+use tokio::{
+  io::{AsyncRead, AsyncWrite, ReadBuf},
+  net::TcpStream,
+};
+
+/// Portal URLs starting with this name a Unix domain socket rather than a host, e.g.
+/// `unix:///run/skyd.sock`. `SkynetClient::new` sends their requests through `UnixTransport`.
+#[cfg(all(unix, feature = "uds"))]
+pub const UNIX_SOCKET_PREFIX: &str = "unix://";
+
+/// The portal URL of a client on a Unix socket, naming the host its requests are sent for.
+#[cfg(all(unix, feature = "uds"))]
+pub const UNIX_SOCKET_PORTAL_URL: &str = "http://localhost";
+
+pub type TransportFuture = Pin<Box<dyn Future<Output = SkynetResult<Response<Body>>> + Send>>;
+
+/// Sends the requests built by the client. Implement this to route portal traffic elsewhere.
+pub trait Transport: Debug + Send + Sync {
+  fn request(&self, req: Request<Body>) -> TransportFuture;
+}
+
+#[derive(Debug, Clone)]
+pub struct HyperTransport {
+  http: Client<AlpnConnector>,
+}
+
+impl HyperTransport {
+  /// Panics if the TLS backend can't be set up, as `hyper_tls::HttpsConnector::new` does.
+  pub fn new() -> Self {
+    Self::with_resolver(Arc::new(SystemResolver), false)
+      .unwrap_or_else(|err| panic!("HyperTransport::new() failure: {:?}", err))
+  }
+
+  /// Offers `h2` and `http/1.1` over ALPN and speaks whichever the portal picks. Concurrent
+  /// requests to a portal that picks `h2` are multiplexed as streams on a single connection
+  /// instead of opening one connection each. Plain `http://` portals are spoken HTTP/2 to
+  /// without asking.
+  pub fn http2() -> SkynetResult<Self> {
+    Self::with_resolver(Arc::new(SystemResolver), true)
+  }
+
+  /// Looks hostnames up through `resolver` instead of the system resolver.
+  pub fn with_resolver(resolver: Arc<dyn Resolve>, http2: bool) -> SkynetResult<Self> {
+    let mut tls = native_tls::TlsConnector::builder();
+    if http2 {
+      tls.request_alpns(&["h2", "http/1.1"]);
+    }
+    let tls = tls.build().map_err(TlsError)?;
+    let mut connector = HttpConnector::new_with_resolver(HyperResolver(resolver));
+    connector.enforce_http(false);
+    let https = HttpsConnector::from((connector, tls.into()));
+    let http = Client::builder().build::<_, Body>(AlpnConnector { https, http2 });
+    Ok(Self { http })
+  }
+}
+
+impl Default for HyperTransport {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// Tells hyper which protocol each connection settled on: HTTP/2 when ALPN picked `h2`, or for
+/// a cleartext connection when `http2` is set. hyper-tls doesn't report the ALPN result itself.
+#[derive(Debug, Clone)]
+struct AlpnConnector {
+  https: HttpsConnector<HttpConnector<HyperResolver>>,
+  http2: bool,
+}
+
+impl Service<Uri> for AlpnConnector {
+  type Response = AlpnStream;
+  type Error = <HttpsConnector<HttpConnector<HyperResolver>> as Service<Uri>>::Error;
+  type Future = Pin<Box<dyn Future<Output = Result<AlpnStream, Self::Error>> + Send>>;
+
+  fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    self.https.poll_ready(cx)
+  }
+
+  fn call(&mut self, uri: Uri) -> Self::Future {
+    let connecting = self.https.call(uri);
+    let http2 = self.http2;
+    Box::pin(async move { Ok(AlpnStream { stream: connecting.await?, http2 }) })
+  }
+}
+
+struct AlpnStream {
+  stream: MaybeHttpsStream<TcpStream>,
+  http2: bool,
+}
+
+impl Connection for AlpnStream {
+  fn connected(&self) -> Connected {
+    let h2 = match &self.stream {
+      MaybeHttpsStream::Https(tls) => tls.get_ref().negotiated_alpn().ok().flatten().as_deref() == Some(b"h2"),
+      MaybeHttpsStream::Http(_) => self.http2,
+    };
+    let connected = self.stream.connected();
+    if h2 { connected.negotiated_h2() } else { connected }
+  }
+}
+
+impl AsyncRead for AlpnStream {
+  fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.stream).poll_read(cx, buf)
+  }
+}
+
+impl AsyncWrite for AlpnStream {
+  fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+    Pin::new(&mut self.stream).poll_write(cx, buf)
+  }
+
+  fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.stream).poll_flush(cx)
+  }
+
+  fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+    Pin::new(&mut self.stream).poll_shutdown(cx)
+  }
+}
+
+impl Transport for HyperTransport {
+  fn request(&self, req: Request<Body>) -> TransportFuture {
+    let http = self.http.clone();
+    Box::pin(async move { http.request(req).await.map_err(HyperError) })
+  }
+}
+
+/// Sends every request over one Unix domain socket whatever host its URI names, for a local
+/// skyd or reverse proxy where TCP is unavailable. TUS uploads go through their own HTTP stack,
+/// which can't reach a socket.
+#[cfg(all(unix, feature = "uds"))]
+#[derive(Debug, Clone)]
+pub struct UnixTransport {
+  socket_path: Arc<PathBuf>,
+  http: Client<hyperlocal::UnixConnector>,
+}
+
+#[cfg(all(unix, feature = "uds"))]
+impl UnixTransport {
+  pub fn new(socket_path: impl AsRef<Path>, http2: bool) -> Self {
+    let http = Client::builder().http2_only(http2).build::<_, Body>(hyperlocal::UnixConnector);
+    Self { socket_path: Arc::new(socket_path.as_ref().to_path_buf()), http }
+  }
+
+  pub fn socket_path(&self) -> &Path {
+    &self.socket_path
+  }
+}
+
+#[cfg(all(unix, feature = "uds"))]
+impl Transport for UnixTransport {
+  fn request(&self, mut req: Request<Body>) -> TransportFuture {
+    // The socket takes the place of the host, but a proxy behind it may still route on `Host`.
+    if let Some(authority) = req.uri().authority().cloned() {
+      if let Ok(host) = hyper::header::HeaderValue::from_str(authority.as_str()) {
+        req.headers_mut().entry(hyper::header::HOST).or_insert(host);
+      }
+    }
+    let path_and_query = req.uri().path_and_query().map_or("/", |path_and_query| path_and_query.as_str());
+    *req.uri_mut() = hyperlocal::Uri::new(&*self.socket_path, path_and_query).into();
+
+    let http = self.http.clone();
+    Box::pin(async move { http.request(req).await.map_err(HyperError) })
+  }
+}
+
+#[cfg(all(test, unix, feature = "uds"))]
+mod tests {
+  use super::*;
+  use crate::{QueryParams, SkynetClient};
+  use std::{convert::Infallible, fs};
+  #[cfg(feature = "tus")]
+  use std::fs::File;
+  use bytes::Bytes;
+  use hyper::{header::HOST, service::{make_service_fn, service_fn}, HeaderMap, Method, Server};
+  use hyperlocal::UnixServerExt;
+
+  #[tokio::test]
+  async fn test_unix_socket_portal() {
+    let socket_path = std::env::temp_dir().join(format!("skynet-rs-{}.sock", std::process::id()));
+    let _ = fs::remove_file(&socket_path);
+    let make_service = make_service_fn(|_| async {
+      Ok::<_, Infallible>(service_fn(|req: Request<Body>| async move {
+        let host = req.headers().get(HOST).and_then(|host| host.to_str().ok()).unwrap_or("").to_string();
+        let described = format!("{} {} {}", host, req.method(), req.uri().path());
+        let _ = hyper::body::to_bytes(req.into_body()).await;
+        let body = serde_json::json!({ "skylink": "AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng", "described": described });
+        Ok::<_, Infallible>(Response::new(Body::from(body.to_string())))
+      }))
+    });
+    let server = Server::bind_unix(&socket_path).unwrap().serve(make_service);
+    let handle = tokio::spawn(server);
+
+    let client = SkynetClient::new(&format!("unix://{}", socket_path.display()), Default::default());
+    let res = client.request_raw(Method::GET, "/skynet/stats", QueryParams::new(), HeaderMap::new(), Bytes::new()).await;
+    let body = hyper::body::to_bytes(res.unwrap().into_body()).await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["described"], "localhost GET /skynet/stats");
+
+    // Too large for a multipart upload elsewhere, but tus can't reach the socket.
+    #[cfg(feature = "tus")]
+    {
+      let path = std::env::temp_dir().join(format!("skynet-rs-{}.bin", std::process::id()));
+      File::create(&path).unwrap().set_len(crate::upload::USE_TUS_THRESHOLD_BYTES).unwrap();
+      let res = client.upload_file(&path, Default::default()).await;
+      fs::remove_file(&path).unwrap();
+      assert_eq!(res.unwrap(), "sia://AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng");
+    }
+
+    handle.abort();
+    fs::remove_file(&socket_path).unwrap();
+  }
+}
@@ -0,0 +1,259 @@
+use crate::{
+  dns::ReqwestResolver, events::Event, upload::set_skykey_params, RequestContext, SkynetClient, SkynetError::*,
+  SkynetResult, UploadOptions,
+  util::{make_reqwest_headers, make_uri, QueryParam, QueryParams},
+};
+use std::{collections::HashMap, path::Path, sync::Arc};
+use http::Uri;
+use tus_async_client::{Client, HttpHandler};
+
+/// Skynet uploads data in chunks.
+/// The size of these chunks depends on erasure coding settings specified for the fanout and the specified encryption type.
+/// The formula for the size of these chunks is chunkSize := (4MiB — encryptionOverhead) * fanoutDataPieces.
+/// By default, data uploaded to Skynet uses 10 data pieces for its fanout and Threefish for encryption which doesn’t have any overhead.
+/// As a result, the default chunk size is 40MiB. Since portals have limited amounts of RAM,
+/// they can’t keep these chunks in memory while waiting for users to resume their uploads.
+/// That’s why the chunk size specified in TUS needs to be a multiple of the Skynet chunk size. As long as they match,
+/// the portal can upload the chunks and free up memory while waiting for more data.
+pub(crate) const SKYNET_TUS_CHUNK_SIZE : u64 = (1 << 22) * 10;
+
+pub fn upload_data_tus_headers(
+  client: &SkynetClient,
+  _path: &Path,
+  opt: &UploadOptions,
+) -> SkynetResult<HashMap<String, String>> {
+  let mut headers = HashMap::new();
+
+  if let Some(apikey) = &opt.api_key {
+    headers.insert("Skynet-Api-Key".to_string(), apikey.clone());
+  }
+
+  // TUS goes through reqwest rather than `SkynetClient::request`, so the credentials are added
+  // here, as for the portal: the upload URLs it hands out are on the same host.
+  let portal = client.get_portal_url().parse::<Uri>().map_err(|err| HttpError(err.into()))?;
+  if let Some(auth) = opt.auth.clone().or_else(|| client.auth(&portal)) {
+    let (name, value) = auth.header();
+    headers.entry(name.to_string()).or_insert(value);
+  }
+
+  if let Some(custom_user_agent) = opt.custom_user_agent.clone() {
+    headers.insert("User-Agent".to_string(), custom_user_agent);
+  }
+
+  Ok(headers)
+}
+
+pub fn upload_data_query_params(
+  _client: &SkynetClient,
+  _path: &Path,
+  opt: &UploadOptions,
+) -> SkynetResult<QueryParams> {
+  let filename =
+      if opt.custom_dirname.is_none() {
+        "".to_string()
+      } else {
+        if let Some(ref custom_dirname) = opt.custom_dirname {
+          custom_dirname.clone()
+        } else {
+          return Err(NoCustomDirname);
+        }
+      };
+
+  let mut query = QueryParams::new();
+
+  if !filename.is_empty() {
+    query.set(QueryParam::Filename, filename);
+  }
+
+  set_skykey_params(&mut query, opt);
+
+  Ok(query)
+}
+
+pub fn upload_data_tus_uri(
+  client: &SkynetClient,
+  path: &Path,
+  opt: &UploadOptions,
+) -> SkynetResult<Uri> {
+  Ok(make_uri(
+    &client.get_portal_url(),
+    "/skynet/tus".to_string(),
+    opt.api_key.clone(),
+    None,
+    upload_data_query_params(client, path, opt)?))
+}
+
+fn reqwest_client_builder(client: &SkynetClient) -> reqwest::ClientBuilder {
+  let builder = reqwest::Client::builder();
+  match &client.get_options().resolver {
+    Some(resolver) => builder.dns_resolver(Arc::new(ReqwestResolver(resolver.clone()))),
+    None => builder,
+  }
+}
+
+pub fn create_tus_client(
+  client: &SkynetClient,
+  path: &Path,
+  opt: UploadOptions,
+) -> SkynetResult<tus_async_client::Client> {
+  let headers = make_reqwest_headers(
+    upload_data_tus_headers(
+      client,
+      path,
+      &opt.clone())?);
+
+  let req = reqwest_client_builder(client)
+      .default_headers(headers.clone());
+
+  Ok(Client::new(
+    HttpHandler::new(
+      Arc::new(req
+          .build()
+          .map_err(ReqwestError)?
+      ))))
+}
+
+pub async fn tus_create_upload_url(
+  client: &SkynetClient,
+  path: &Path,
+  opt: UploadOptions,
+) -> SkynetResult<String> {
+  // Built from the upload's own options so the portal sees its filename and skykey.
+  let uri = upload_data_tus_uri(
+    client,
+    path,
+    &opt
+  )?;
+
+  create_tus_client(client, path, opt)?
+      .create(&uri.to_string(), path)
+      .await
+      .map_err(|err| RequestError(RequestContext::new(&uri, None), Box::new(TUSError(err))))
+}
+
+pub async fn upload_data_tus(
+  client: &SkynetClient,
+  path: &Path,
+  opt: UploadOptions,
+) -> SkynetResult<String> {
+  let _transfer = client.begin_transfer()?;
+  let size = std::fs::metadata(path).map_err(FileError)?.len();
+  client.emit(Event::UploadStarted { files: 1, size });
+  let upload_url = tus_create_upload_url(client, path, opt.clone()).await?;
+  let tus_client = create_tus_client(client, path, opt.clone())?;
+
+  // perform upload
+  tus_client
+      .upload_with_chunk_size(&upload_url, path, SKYNET_TUS_CHUNK_SIZE as usize)
+      .await
+      .map_err(|err| tus_error(&upload_url, TUSError(err)))?
+  ;
+
+  // finish upload and retrieve skylink
+  let skylink = get_tus_upload_skylink(client, path, opt.clone(), upload_url).await?;
+  client.emit(Event::UploadCompleted { skylink: skylink.clone(), size });
+  Ok(opt.skylink_format.format(&skylink, &client.get_portal_url()))
+}
+
+/// Wraps an error from a request to a TUS upload URL with where it was going.
+fn tus_error(upload_url: &str, err: crate::SkynetError) -> crate::SkynetError {
+  match upload_url.parse::<Uri>() {
+    Ok(uri) => RequestError(RequestContext::new(&uri, None), Box::new(err)),
+    Err(_) => err,
+  }
+}
+
+/// get skylink from HEAD request headers after all pieces finished upload
+pub async fn get_tus_upload_skylink(
+  client: &SkynetClient,
+  path: &Path,
+  opt: UploadOptions,
+  upload_url: String
+) -> SkynetResult<String> {
+  let headers = upload_data_tus_headers(client, path, &opt)?;
+  let headers = make_reqwest_headers(headers);
+
+  let meta = reqwest_client_builder(client)
+      .build()
+      .map_err(ReqwestError)?
+      .head(&upload_url)
+      .headers(headers)
+      .header("tus-resumable", "1.0.0")
+      .send()
+      .await
+      .map_err(|err| tus_error(&upload_url, ReqwestError(err)))?;
+
+  let headers = meta
+      .headers();
+
+  let skylink = headers
+      .get("skynet-skylink")
+      .expect("skylink header not found")
+      .to_str()
+      .expect("failed to parse skylink header to string");
+
+  Ok(skylink.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{upload::USE_TUS_THRESHOLD_BYTES, SkynetClientOptions};
+  use std::fs;
+
+  #[tokio::test]
+  #[ignore = "requires a live portal with tus support"]
+  async fn test_upload_file_tus_anon() {
+    let client = SkynetClient::default();
+    // generate 50MB file to trigger TUS upload
+    fs::write("tmp.txt", (0..USE_TUS_THRESHOLD_BYTES+10000).map(|_| "X").collect::<String>()).unwrap();
+    let path = Path::new("tmp.txt");
+    let res = upload_data_tus(&client, path, UploadOptions::default()).await;
+    fs::remove_file("tmp.txt").unwrap();
+    println!("skylink: {:?}", &res);
+    assert!(res.is_ok());
+    assert!(!res.unwrap().is_empty());
+  }
+
+  // make sure to set the SKYNET_API_KEY env var for this test
+  #[tokio::test]
+  #[ignore = "requires a live portal with tus support"]
+  async fn test_upload_file_tus_auth() {
+    // generate 50MB file to trigger TUS upload
+    let path = Path::new("tmp.txt");
+    fs::write(path, (0..USE_TUS_THRESHOLD_BYTES+10000).map(|_| "X").collect::<String>()).unwrap();
+
+    let client = SkynetClient::new("https://skynetfree.net", SkynetClientOptions {
+      api_key: Some(std::env::var("SKYNET_API_KEY").unwrap()),
+      custom_user_agent: None,
+      ..Default::default()
+    });
+
+    let res = upload_data_tus(&client, path, UploadOptions::default()).await;
+    fs::remove_file("tmp.txt").unwrap();
+    println!("skylink: {:?}", &res);
+    assert!(res.is_ok());
+    assert!(!res.unwrap().is_empty());
+  }
+
+  // make sure to set the SKYNET_API_KEY env var for this test
+  #[tokio::test]
+  #[ignore = "requires a live portal with tus support"]
+  async fn test_upload_file_tus_auth_large() {
+    // generate 50MB file to trigger TUS upload
+    let path = Path::new("tmp.txt");
+    fs::write(path, (0..(700 * 1024 * 1024)).map(|_| "X").collect::<String>()).unwrap();
+
+    let client = SkynetClient::new("https://skynetfree.net", SkynetClientOptions {
+      api_key: Some(std::env::var("SKYNET_API_KEY").unwrap()),
+      custom_user_agent: None,
+      ..Default::default()
+    });
+
+    let res = upload_data_tus(&client, path, UploadOptions::default()).await;
+    fs::remove_file("tmp.txt").unwrap();
+    println!("skylink: {:?}", &res);
+    assert!(res.is_ok());
+    assert!(!res.unwrap().is_empty());
+  }
+}
@@ -1,47 +1,167 @@
-use crate::{SkynetClient, SkynetError::*, SkynetResult, util::make_uri, URI_SKYNET_PREFIX};
+use crate::{client::{CallAuth, Replayable}, AuthScheme, events::Event, hook::{run_hooks, UploadHook}, sparse, SkynetClient, SkynetError::*, SkynetResult, skylink::{SkylinkFormat, SECTOR_SIZE}, util::{content_disposition, make_uri, parse_response, read_response, QueryParam, QueryParams}};
 use std::{
   collections::HashMap,
   fs,
-  io::Write,
   path::Path,
   str,
+  sync::Arc,
+  time::Duration,
 };
-use hyper::{body, Request};
+use bytes::Bytes;
+use hyper::Request;
 use mime::Mime;
 use serde::Deserialize;
 use textnonce::TextNonce;
+#[cfg(feature = "directory")]
+use serde::Serialize;
+#[cfg(feature = "directory")]
+use crate::sparse::DataRanges;
+#[cfg(feature = "directory")]
+use std::{collections::BTreeMap, time::UNIX_EPOCH};
+#[cfg(feature = "directory")]
 use walkdir::WalkDir;
-use tus_async_client::{Client, HttpHandler};
-use reqwest::{self, ClientBuilder};
-use std::rc::Rc;
-use std::sync::Arc;
-use http::Uri;
-use crate::util::make_reqwest_headers;
-
-/// Skynet uploads data in chunks.
-/// The size of these chunks depends on erasure coding settings specified for the fanout and the specified encryption type.
-/// The formula for the size of these chunks is chunkSize := (4MiB — encryptionOverhead) * fanoutDataPieces.
-/// By default, data uploaded to Skynet uses 10 data pieces for its fanout and Threefish for encryption which doesn’t have any overhead.
-/// As a result, the default chunk size is 40MiB. Since portals have limited amounts of RAM,
-/// they can’t keep these chunks in memory while waiting for users to resume their uploads.
-/// That’s why the chunk size specified in TUS needs to be a multiple of the Skynet chunk size. As long as they match,
-/// the portal can upload the chunks and free up memory while waiting for more data.
-const SKYNET_TUS_CHUNK_SIZE : u64 = (1 << 22) * 10;
+#[cfg(feature = "gzip")]
+use crate::gzip;
+#[cfg(feature = "checksum")]
+use crate::{checksum::checksum, ChecksumAlgorithm};
+#[cfg(feature = "tus")]
+use crate::tus::upload_data_tus;
+#[cfg(feature = "tus")]
+use std::path::Component;
+#[cfg(feature = "encryption")]
+use crate::Skykey;
+#[cfg(all(feature = "directory", feature = "crypto"))]
+use crate::{download::skylink_exists, merkle_root, MetadataOptions, SkynetError};
+#[cfg(all(feature = "directory", feature = "crypto"))]
+use crypto::{blake2b::Blake2b, digest::Digest};
+#[cfg(all(feature = "directory", feature = "crypto"))]
+use futures_util::{stream, StreamExt, TryStreamExt};
+#[cfg(all(feature = "directory", feature = "crypto"))]
+use hex::ToHex;
 
 /// The size at which files are considered "large" and will be uploaded using the tus resumable upload protocol. This is the size of one chunk by default (40 mib). Note that this does not affect the actual size of chunks used by the protocol.
-const USE_TUS_THRESHOLD_BYTES : u64 = SKYNET_TUS_CHUNK_SIZE;
+#[cfg(feature = "tus")]
+pub(crate) const USE_TUS_THRESHOLD_BYTES : u64 = crate::tus::SKYNET_TUS_CHUNK_SIZE;
+
+/// Size of the skyfile layout header at the start of the base sector.
+const LAYOUT_SIZE: u64 = 99;
+/// Number of copies kept of the base sector.
+const BASE_SECTOR_REDUNDANCY: u64 = 10;
+/// Erasure coding used for the fanout of files that don't fit in the base sector.
+const FANOUT_DATA_PIECES: u64 = 10;
+const FANOUT_PARITY_PIECES: u64 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UploadEstimate {
+  /// Size of the data being uploaded.
+  pub size: u64,
+  /// Size of the skyfile once padded to whole sectors and fanout chunks.
+  pub padded_size: u64,
+  /// Number of erasure coded fanout chunks; zero when the file fits in the base sector.
+  pub chunks: u64,
+  /// Bytes stored on hosts including redundancy, which is what account quotas are charged.
+  pub storage: u64,
+}
+
+fn estimate(size: u64, metadata_size: u64) -> UploadEstimate {
+  let base_sector_storage = SECTOR_SIZE * BASE_SECTOR_REDUNDANCY;
+
+  if LAYOUT_SIZE + metadata_size + size <= SECTOR_SIZE {
+    return UploadEstimate {
+      size,
+      padded_size: SECTOR_SIZE,
+      chunks: 0,
+      storage: base_sector_storage,
+    };
+  }
+
+  let chunk_size = SECTOR_SIZE * FANOUT_DATA_PIECES;
+  let chunks = (size - 1) / chunk_size + 1;
+
+  UploadEstimate {
+    size,
+    padded_size: SECTOR_SIZE + chunks * chunk_size,
+    chunks,
+    storage: base_sector_storage + chunks * (FANOUT_DATA_PIECES + FANOUT_PARITY_PIECES) * SECTOR_SIZE,
+  }
+}
+
+/// Estimates the padded skyfile size and the storage charged for uploading `size` bytes.
+pub fn estimate_upload(size: u64) -> UploadEstimate {
+  estimate(size, 0)
+}
+
+/// Like `estimate_upload`, also accounting for the metadata stored in the base sector.
+pub fn estimate_file_upload<P: AsRef<Path>>(path: P) -> SkynetResult<UploadEstimate> {
+  let path = path.as_ref();
+  if !path.is_file() {
+    return Err(NotFile);
+  }
+
+  let size = fs::metadata(path).map_err(FileError)?.len();
+  let filename = path.file_name().unwrap().to_string_lossy();
+  let metadata = serde_json::json!({ "filename": filename, "length": size }).to_string();
+
+  Ok(estimate(size, metadata.len() as u64))
+}
 
 #[derive(Debug, Clone)]
 pub struct UploadOptions {
   pub endpoint_path: String,
   pub api_key: Option<String>,
+  pub auth: Option<AuthScheme>,
   pub custom_user_agent: Option<String>,
   pub portal_file_fieldname: String,
   pub portal_directory_file_fieldname: String,
   pub custom_filename: Option<String>,
   pub custom_dirname: Option<String>,
+  /// Where the skyfile is stored in the portal's renter, under its skynet folder or, with
+  /// `root`, the renter's root. Portals pick a path of their own when unset. tus uploads can't
+  /// name one, so files large enough for tus go up as multipart when this, `force` or `root` is
+  /// set.
+  pub siapath: Option<String>,
+  /// Overwrites an existing file at `siapath`.
+  pub force: bool,
+  /// Takes `siapath` from the renter's root instead of its skynet folder.
+  pub root: bool,
+  /// How the returned skylink is written, for multipart and tus uploads alike. Uploads queued
+  /// offline return their local skylink as it is.
+  pub skylink_format: SkylinkFormat,
   pub skykey_name: Option<String>,
   pub skykey_id: Option<String>,
+  /// Encrypts with this key, e.g. one from the client's skykey cache. Takes precedence over
+  /// `skykey_name` and `skykey_id`.
+  #[cfg(feature = "encryption")]
+  pub skykey: Option<Skykey>,
+  /// Compresses each file and uploads it as a `.gz` skyfile. Files large enough to go through
+  /// tus are sent uncompressed.
+  #[cfg(feature = "gzip")]
+  pub gzip: bool,
+  /// Refuses uploads larger than this many bytes with `UploadTooLarge`.
+  pub max_size: Option<u64>,
+  /// Refuses uploads larger than the account tier allows before sending them, when an API key
+  /// is set. Off by default, as it costs a `/user/limits` request per upload. The upload fails
+  /// if the limits can't be fetched.
+  #[cfg(feature = "account")]
+  pub check_account_limits: bool,
+  /// Wall-clock budget for the whole upload, including retries.
+  pub deadline: Option<Duration>,
+  /// Has a directory upload include a `DIRECTORY_METADATA_FILENAME` subfile recording the mode,
+  /// modification time and holes of every file, which `download_directory` restores.
+  #[cfg(feature = "directory")]
+  pub preserve_attributes: bool,
+  /// How many files `upload_directory_deduped` sends at once.
+  #[cfg(feature = "directory")]
+  pub concurrency: usize,
+  /// Records a checksum of every uploaded file in a `DIRECTORY_METADATA_FILENAME` subfile, which
+  /// downloads then verify. A single file goes up as a directory holding it and that subfile,
+  /// with the file as its default path, so that the skylink still serves the file. Not recorded
+  /// for files large enough to go through tus.
+  #[cfg(feature = "checksum")]
+  pub checksum: Option<ChecksumAlgorithm>,
+  /// Run in order on every file before it is compressed or sent. Directory uploads record the
+  /// checksums and sizes of the files the hooks return.
+  pub hooks: Vec<Arc<dyn UploadHook>>,
 }
 
 impl Default for UploadOptions {
@@ -49,30 +169,183 @@ impl Default for UploadOptions {
   	Self {
       endpoint_path: "/skynet/skyfile".to_string(),
       api_key: None,
+      auth: None,
       custom_user_agent: None,
       portal_file_fieldname: "file".to_string(),
       portal_directory_file_fieldname: "files[]".to_string(),
       custom_filename: None,
       custom_dirname: None,
+      siapath: None,
+      force: false,
+      root: false,
+      skylink_format: SkylinkFormat::default(),
       skykey_name: None,
       skykey_id: None,
+      #[cfg(feature = "encryption")]
+      skykey: None,
+      #[cfg(feature = "gzip")]
+      gzip: false,
+      max_size: None,
+      #[cfg(feature = "account")]
+      check_account_limits: false,
+      deadline: None,
+      #[cfg(feature = "directory")]
+      preserve_attributes: false,
+      #[cfg(feature = "directory")]
+      concurrency: 8,
+      #[cfg(feature = "checksum")]
+      checksum: None,
+      hooks: Vec::new(),
     }
   }
 }
 
-#[derive(Deserialize)]
-struct UploadResponse {
-  skylink: String,
+/// Fails fast with `UploadTooLarge` if `size` exceeds `max_size` or the account's upload limit.
+/// An account limit that can't be fetched fails the upload too, as the check was asked for.
+async fn check_upload_size(client: &SkynetClient, size: u64, opt: &UploadOptions) -> SkynetResult<()> {
+  if let Some(max_size) = opt.max_size {
+    if size > max_size {
+      return Err(UploadTooLarge(size, max_size));
+    }
+  }
+
+  #[cfg(feature = "account")]
+  {
+    if opt.check_account_limits
+      && (opt.api_key.is_some() || opt.auth.is_some() || client.has_auth())
+    {
+      let limits = crate::account::get_user_limits(client, crate::AccountOptions {
+        api_key: opt.api_key.clone(),
+        auth: opt.auth.clone(),
+        custom_user_agent: opt.custom_user_agent.clone(),
+        ..Default::default()
+      }).await?;
+
+      if size > limits.max_upload_size {
+        return Err(UploadTooLarge(size, limits.max_upload_size));
+      }
+    }
+  }
+  #[cfg(not(feature = "account"))]
+  let _ = client;
+
+  Ok(())
+}
+
+/// Adds the skykey to encrypt with, the same way for multipart and tus uploads. A `skykey`
+/// replaces `skykey_name` and `skykey_id`, since portals refuse a name and an ID together.
+pub(crate) fn set_skykey_params(query: &mut QueryParams, opt: &UploadOptions) {
+  #[cfg(feature = "encryption")]
+  if let Some(ref skykey) = opt.skykey {
+    query.set(QueryParam::SkykeyId, skykey.id());
+    return;
+  }
+
+  query.set_opt(QueryParam::SkykeyName, opt.skykey_name.as_ref());
+  query.set_opt(QueryParam::SkykeyId, opt.skykey_id.as_ref());
+}
+
+/// Runs `hooks` on every file of `data`, keyed by the names they leave.
+pub(crate) fn apply_hooks(
+  data: HashMap<String, (Mime, Bytes)>,
+  hooks: &[Arc<dyn UploadHook>],
+) -> SkynetResult<HashMap<String, (Mime, Bytes)>> {
+  if hooks.is_empty() {
+    return Ok(data);
+  }
+
+  let mut hooked = HashMap::with_capacity(data.len());
+  for (filename, (mime, bytes)) in data {
+    let part = run_hooks(hooks, filename, mime, bytes)?;
+    if hooked.contains_key(&part.filename) {
+      return Err(UploadRejected(format!("hooks left two files named {}", part.filename)));
+    }
+    hooked.insert(part.filename, (part.content_type, part.data));
+  }
+  Ok(hooked)
 }
 
+/// What the portal reported about a finished upload.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct UploadResult {
+  pub skylink: String,
+  /// Hex merkle root of the skyfile's base sector. Not sent for tus uploads.
+  #[serde(rename = "merkleroot", default)]
+  pub merkle_root: Option<String>,
+  /// The skylink's bitfield, on portals that send it.
+  #[serde(default)]
+  pub bitfield: Option<u16>,
+  /// Bytes sent, after hooks and compression.
+  #[serde(skip)]
+  pub size: u64,
+}
+
+impl UploadResult {
+  /// A result for an upload that only yielded a skylink, such as a tus upload.
+  #[cfg(any(feature = "tus", feature = "offline"))]
+  pub(crate) fn skylink_only(skylink: String, size: u64) -> Self {
+    Self { skylink, merkle_root: None, bitfield: None, size }
+  }
+}
+
+// `SkynetClient::upload_data` goes through `upload_data_with_result`, to record the upload.
+#[cfg(any(test, feature = "backup", feature = "offline", feature = "skydb"))]
 pub async fn upload_data(
   client: &SkynetClient,
-  data: HashMap<String, (Mime, Vec<u8>)>,
+  data: HashMap<String, (Mime, Bytes)>,
   opt: UploadOptions,
 ) -> SkynetResult<String> {
+  upload_data_with_result(client, data, opt).await.map(|res| res.skylink)
+}
+
+/// Like `upload_data`, but returns everything the portal said about the upload.
+pub async fn upload_data_with_result(
+  client: &SkynetClient,
+  data: HashMap<String, (Mime, Bytes)>,
+  opt: UploadOptions,
+) -> SkynetResult<UploadResult> {
+  let size = data.values().map(|(_, bytes)| bytes.len() as u64).sum();
+  let _reservation = client.reserve_memory(Some(size)).await;
+  send_data(client, data, opt).await
+}
+
+/// `upload_data_with_result` without holding the memory budget, for callers that already do.
+async fn send_data(
+  client: &SkynetClient,
+  data: HashMap<String, (Mime, Bytes)>,
+  opt: UploadOptions,
+) -> SkynetResult<UploadResult> {
   let req = Request::builder().method("POST");
 
-  let mut query = HashMap::new();
+  let mut query = QueryParams::new();
+
+  let data = apply_hooks(data, &opt.hooks)?;
+
+  #[cfg(feature = "gzip")]
+  let data = if opt.gzip {
+    let mut compressed = HashMap::new();
+    for (filename, (_, bytes)) in data {
+      compressed.insert(
+        format!("{}{}", filename, gzip::GZIP_EXTENSION),
+        (gzip::gzip_mime(), gzip::compress(&bytes)?));
+    }
+    compressed
+  } else {
+    data
+  };
+
+  #[cfg(feature = "checksum")]
+  let mut data = data;
+  #[cfg(feature = "checksum")]
+  let default_path = record_checksum(&mut data, &opt)?;
+  #[cfg(feature = "checksum")]
+  let opt = match &default_path {
+    Some(filename) => UploadOptions { custom_dirname: Some(filename.clone()), ..opt },
+    None => opt,
+  };
+
+  let size = data.values().map(|(_, bytes)| bytes.len() as u64).sum();
+  check_upload_size(client, size, &opt).await?;
 
   let (fieldname, filename) =
     if data.len() == 1 && opt.custom_dirname.is_none() {
@@ -86,54 +359,55 @@ pub async fn upload_data(
     };
 
   if !filename.is_empty() {
-    query.insert("filename".into(), filename);
+    query.set(QueryParam::Filename, filename);
   }
 
-  if let Some(ref skykey_name) = opt.skykey_name {
-    query.insert("skykeyname".into(), skykey_name.clone());
+  set_skykey_params(&mut query, &opt);
+
+  if opt.force {
+    query.set(QueryParam::Force, true);
   }
 
-  if let Some(ref skykey_id) = opt.skykey_id {
-    query.insert("skykeyid".into(), skykey_id.clone());
+  if opt.root {
+    query.set(QueryParam::Root, true);
   }
 
-  let mut body = Vec::new();
-  let boundary = TextNonce::sized(68).map_err(TextNonceError)?.into_string().into_bytes();
+  #[cfg(feature = "checksum")]
+  if let Some(filename) = default_path {
+    query.set(QueryParam::DefaultPath, format!("/{}", filename));
+  }
 
-  for (filename, (mime, bytes)) in &data {
-    let disposition = format!("form-data; name=\"{}\"; filename=\"{}\"", fieldname, filename);
-    let headers = format!("Content-Disposition: {}\r\nContent-Type: {}\r\n", disposition, mime);
+  // The parts are sent as they are, so file contents are never copied into one growing buffer.
+  let boundary = TextNonce::sized(68).map_err(TextNonceError)?.into_string();
+  let mut chunks = Vec::with_capacity(2 * data.len() + 1);
 
-    body.write_all(b"--").map_err(WriteError)?;
-    body.write_all(&boundary).map_err(WriteError)?;
-    body.write_all(b"\r\n").map_err(WriteError)?;
-    body.write_all(headers.as_bytes()).map_err(WriteError)?;
-    body.write_all(b"\r\n").map_err(WriteError)?;
-    body.write_all(bytes).map_err(WriteError)?;
-    body.write_all(b"\r\n").map_err(WriteError)?;
+  for (filename, (mime, bytes)) in &data {
+    let disposition = content_disposition(&fieldname, filename);
+    chunks.push(Bytes::from(format!(
+      "--{}\r\nContent-Disposition: {}\r\nContent-Type: {}\r\n\r\n",
+      boundary, disposition, mime)));
+    chunks.push(bytes.clone());
+    chunks.push(Bytes::from_static(b"\r\n"));
   }
 
-  body.write_all(b"--").map_err(WriteError)?;
-  body.write_all(&boundary).map_err(WriteError)?;
-  body.write_all(b"--\r\n").map_err(WriteError)?;
+  chunks.push(Bytes::from(format!("--{}--\r\n", boundary)));
+  let content_length: usize = chunks.iter().map(Bytes::len).sum();
 
-  let content_type = format!(
-    "{}; boundary=\"{}\"",
-    mime::MULTIPART_FORM_DATA,
-    str::from_utf8(&boundary).map_err(Utf8Error)?);
+  let content_type = format!("{}; boundary=\"{}\"", mime::MULTIPART_FORM_DATA, boundary);
 
   let uri = make_uri(
-    client.get_portal_url(),
+    &client.get_portal_url(),
     opt.endpoint_path,
     opt.api_key.clone(),
-    None,
+    opt.siapath.as_ref().map(|siapath| siapath.trim_matches('/').to_string()),
     query);
 
   let mut req = req
-    .uri(uri)
-    .header("Content-Type", content_type);
+    .uri(uri.clone())
+    .header("Content-Type", content_type)
+    .header("Content-Length", content_length);
 
-  if let Some(apikey) = &opt.api_key.or(client.get_options().api_key.clone()) {
+  if let Some(apikey) = &opt.api_key {
     req = req.header("Skynet-Api-Key", apikey.clone());
   }
 
@@ -141,257 +415,518 @@ pub async fn upload_data(
     req = req.header("User-Agent", custom_user_agent);
   }
 
-  let req = req.body(body.into()).map_err(HttpError)?;
-  let res = client.http.request(req).await.map_err(HyperError)?;
-  let body = body::to_bytes(res.into_body()).await.map_err(HyperError)?;
-  let body_str = str::from_utf8(&body).map_err(Utf8Error)?;
-  let res: UploadResponse = serde_json::from_str(body_str)
-    .map_err(|_| PortalResponse(body_str.to_string()))?;
+  req = req.extension(CallAuth(opt.auth.clone()));
 
-  // disabled since the Skynet api itself doesn't return it iike this anymore
-  // let skylink = format!("{}{}", URI_SKYNET_PREFIX, res.skylink);
+  // The body is already in memory, so the client can send it again after a connection blip.
+  let mut req = req.body(client.upload_body(chunks.clone())).map_err(HttpError)?;
+  req.extensions_mut().insert(Replayable(chunks));
+  client.emit(Event::UploadStarted { files: data.len(), size });
+  let res = client.request(req).await?;
+  let status = res.status();
+  let body = read_response(res).await?;
+  let body_str = str::from_utf8(&body).map_err(Utf8Error)?;
+  let res: UploadResult = parse_response(&uri, status, body_str)?;
+  client.emit(Event::UploadCompleted { skylink: res.skylink.clone(), size });
 
-  Ok(res.skylink)
+  let skylink = opt.skylink_format.format(&res.skylink, &client.get_portal_url());
+  Ok(UploadResult { skylink, size, ..res })
 }
 
-pub fn upload_data_tus_headers(
-  client: &SkynetClient,
-  path: &Path,
-  opt: &UploadOptions,
-) -> SkynetResult<HashMap<String, String>> {
-  let mut headers = HashMap::new();
-
-  if let Some(apikey) = &opt.api_key.clone().or(client.get_options().api_key.clone()) {
-    headers.insert("Skynet-API-Key".to_string(), apikey.clone());
-  }
-
-  if let Some(custom_user_agent) = opt.custom_user_agent.clone() {
-    headers.insert("User-Agent".to_string(), custom_user_agent);
-  }
+/// With `opt.checksum` set, adds a `DIRECTORY_METADATA_FILENAME` subfile with the checksum of a
+/// single file, as sent, and returns the file's name to serve it by default. Directories have
+/// their checksums recorded as they are read.
+#[cfg(feature = "checksum")]
+fn record_checksum(data: &mut HashMap<String, (Mime, Bytes)>, opt: &UploadOptions) -> SkynetResult<Option<String>> {
+  let algorithm = match opt.checksum {
+    Some(algorithm) if data.len() == 1 && opt.custom_dirname.is_none() => algorithm,
+    _ => return Ok(None),
+  };
 
-  Ok(headers)
+  let (filename, (_, bytes)) = data.iter().next().unwrap();
+  let filename = filename.clone();
+  let mut attributes = BTreeMap::new();
+  attributes.insert(filename.clone(), FileAttributes {
+    checksum: Some(checksum(algorithm, bytes)),
+    ..Default::default()
+  });
+  let attributes = serde_json::to_vec(&attributes).map_err(JsonError)?;
+  data.insert(DIRECTORY_METADATA_FILENAME.into(), (mime::APPLICATION_JSON, attributes.into()));
+  Ok(Some(filename))
 }
 
-pub fn upload_data_query_params(
-  client: &SkynetClient,
-  path: &Path,
-  opt: &UploadOptions,
-) -> SkynetResult<HashMap<String, String>> {
-  let filename =
-      if opt.custom_dirname.is_none() {
-        "".to_string()
-      } else {
-        if let Some(ref custom_dirname) = opt.custom_dirname {
-          custom_dirname.clone()
-        } else {
-          return Err(NoCustomDirname);
-        }
-      };
-
-  let mut query = HashMap::new();
-
-  if !filename.is_empty() {
-    query.insert("filename".into(), filename);
-  }
-
-  if let Some(ref skykey_name) = opt.skykey_name {
-    query.insert("skykeyname".into(), skykey_name.clone());
-  }
-
-  if let Some(ref skykey_id) = opt.skykey_id {
-    query.insert("skykeyid".into(), skykey_id.clone());
+pub fn read_file(path: &Path) -> SkynetResult<HashMap<String, (Mime, Bytes)>> {
+  if !path.is_file() {
+    return Err(NotFile);
   }
 
-  Ok(query)
-}
-
-pub fn upload_data_tus_uri(
-  client: &SkynetClient,
-  path: &Path,
-  opt: &UploadOptions,
-) -> SkynetResult<Uri> {
-  Ok(make_uri(
-    client.get_portal_url(),
-    "/skynet/tus".to_string(),
-    opt.api_key.clone(),
-    None,
-    upload_data_query_params(client, path, opt)?))
-}
-
-pub fn create_tus_client(
-  client: &SkynetClient,
-  path: &Path,
-  opt: UploadOptions,
-) -> SkynetResult<tus_async_client::Client> {
-  let headers = make_reqwest_headers(
-    upload_data_tus_headers(
-      &client,
-      path,
-      &opt.clone())?);
-
-  let req = reqwest::Client::builder()
-      .default_headers(headers.clone());
-
-  Ok(Client::new(
-    HttpHandler::new(
-      Arc::new(req
-          .build()
-          .map_err(ReqwestError)?
-      ))))
-}
+  let mime = mime_guess::from_path(path)
+      .first()
+      .unwrap_or(mime::APPLICATION_OCTET_STREAM);
 
-pub async fn tus_create_upload_url(
-  client: &SkynetClient,
-  path: &Path,
-  opt: UploadOptions,
-) -> SkynetResult<String> {
-  let uri = upload_data_tus_uri(
-    &client,
-    path,
-    &UploadOptions::default()
-  )?;
-
-  create_tus_client(client, path, opt)?
-      .create(&uri.to_string(), path)
-      .await
-      .map_err(TUSError)
-}
+  let (bytes, _) = sparse::read_sparse(path)
+      .map_err(FileError)?;
 
-pub async fn upload_data_tus(
-  client: &SkynetClient,
-  path: &Path,
-  opt: UploadOptions,
-) -> SkynetResult<String> {
-  let upload_url = tus_create_upload_url(client, path, opt.clone()).await?;
-  let tus_client = create_tus_client(client, path, opt.clone())?;
+  let filename = path
+      .file_name()
+      .unwrap()
+      .to_str()
+      .unwrap()
+      .to_string();
 
-  // perform upload
-  tus_client
-      .upload_with_chunk_size(&upload_url, path, SKYNET_TUS_CHUNK_SIZE as usize)
-      .await
-      .map_err(TUSError)?
-  ;
+  let mut data = HashMap::new();
+  data.insert(filename, (mime, bytes.into()));
 
-  // finish upload and retrieve skylink
-  get_tus_upload_skylink(client, path, opt.clone(), upload_url).await
+  Ok(data)
 }
 
-/// get skylink from HEAD request headers after all pieces finished upload
-pub async fn get_tus_upload_skylink(
+#[cfg(any(test, feature = "backup"))]
+pub async fn upload_file(
   client: &SkynetClient,
   path: &Path,
   opt: UploadOptions,
-  upload_url: String
 ) -> SkynetResult<String> {
-  let headers = upload_data_tus_headers(&client, path, &opt)?;
-  let headers = make_reqwest_headers(headers);
-
-  let meta = reqwest::Client::new()
-      .head(upload_url)
-      .headers(headers)
-      .header("tus-resumable", "1.0.0")
-      .send()
-      .await
-      .map_err(ReqwestError)?;
-
-  let headers = meta
-      .headers();
-
-  let skylink = headers
-      .get("skynet-skylink")
-      .expect("skylink header not found")
-      .to_str()
-      .expect("failed to parse skylink header to string");
-
-  Ok(skylink.to_string())
+  upload_file_with_result(client, path, opt).await.map(|res| res.skylink)
 }
 
-pub async fn upload_file(
+/// Like `upload_file`, but returns everything the portal said about the upload.
+pub async fn upload_file_with_result(
   client: &SkynetClient,
   path: &Path,
   opt: UploadOptions,
-) -> SkynetResult<String> {
+) -> SkynetResult<UploadResult> {
   if !path.is_file() {
     return Err(NotFile);
   }
-
-  let mime = mime_guess::from_path(path)
-      .first()
-      .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+  let size = fs::metadata(path).map_err(FileError)?.len();
 
   // "Large file uploads are automatically supported in skynet-js and skynet-nodejs.
   //  Any file over 40MB will automatically use the built-in tus upload client."
   //   - https://docs.skynetlabs.com/integrations/resumable-uploads-using-tus
-  if fs::metadata(path).map_err(FileError)?.len() >= USE_TUS_THRESHOLD_BYTES {
-    upload_data_tus(client, path, opt).await
+  #[cfg(feature = "tus")]
+  {
+    let placed = opt.siapath.is_some() || opt.force || opt.root;
+    if size >= USE_TUS_THRESHOLD_BYTES && client.uses_tus() && !placed {
+      if !opt.hooks.is_empty() {
+        return upload_hooked_file_tus(client, path, opt).await;
+      }
+      check_upload_size(client, size, &opt).await?;
+      let skylink = upload_data_tus(client, path, opt).await?;
+      return Ok(UploadResult::skylink_only(skylink, size));
+    }
   }
 
   // load data in mem and send
-  else {
-    let bytes = fs::read(path)
-        .map_err(FileError)?;
+  let _reservation = client.reserve_memory(Some(size)).await;
+  send_data(client, read_file(path)?, opt).await
+}
 
-    let filename = path
-        .file_name()
-        .unwrap()
-        .to_str()
-        .unwrap()
-        .to_string();
+/// Runs the hooks on a file too large for a multipart upload and sends what they return over tus,
+/// by way of a temporary copy.
+#[cfg(feature = "tus")]
+async fn upload_hooked_file_tus(client: &SkynetClient, path: &Path, opt: UploadOptions) -> SkynetResult<UploadResult> {
+  let _reservation = client.reserve_memory(Some(fs::metadata(path).map_err(FileError)?.len())).await;
+  let (filename, (mime, bytes)) = read_file(path)?.into_iter().next().unwrap();
+  let part = run_hooks(&opt.hooks, filename, mime, bytes)?;
+  // The name is joined onto the temporary directory below, so it has to stay a plain name.
+  let mut components = Path::new(&part.filename).components();
+  if !matches!((components.next(), components.next()), (Some(Component::Normal(_)), None)) {
+    return Err(UploadRejected(format!("hooks left an invalid filename: {}", part.filename)));
+  }
+  let size = part.data.len() as u64;
+  check_upload_size(client, size, &opt).await?;
 
+  let opt = UploadOptions { hooks: Vec::new(), ..opt };
+  if size < USE_TUS_THRESHOLD_BYTES {
     let mut data = HashMap::new();
-    data.insert(filename, (mime, bytes));
+    data.insert(part.filename, (part.content_type, part.data));
+    return send_data(client, data, opt).await;
+  }
+
+  // tus uploads read from a file, and take the skyfile's name from it.
+  let nonce = TextNonce::sized_urlsafe(32).map_err(TextNonceError)?.into_string();
+  let dir = std::env::temp_dir().join(format!("skynet-upload-{}", nonce));
+  fs::create_dir_all(&dir).map_err(FileError)?;
+  let hooked_path = dir.join(&part.filename);
+  let res = match fs::write(&hooked_path, &part.data) {
+    Ok(()) => upload_data_tus(client, &hooked_path, opt).await,
+    Err(err) => Err(FileError(err)),
+  };
+  let _ = fs::remove_dir_all(&dir);
+  res.map(|skylink| UploadResult::skylink_only(skylink, size))
+}
+
+/// Subfile in which `upload_directory` records the mode and modification time of every file,
+/// keyed by path relative to the directory, when asked to with `preserve_attributes` or
+/// `checksum`. `download_directory` restores them from it.
+#[cfg(feature = "directory")]
+pub const DIRECTORY_METADATA_FILENAME: &str = ".skynet-metadata.json";
+
+#[cfg(feature = "directory")]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct FileAttributes {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub mode: Option<u32>,
+  /// Seconds since the Unix epoch.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub mtime: Option<u64>,
+  /// Where a sparse file holds data, so `download_directory` can leave the holes in place.
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub data_ranges: Option<DataRanges>,
+  #[cfg(feature = "checksum")]
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub checksum: Option<String>,
+}
+
+#[cfg(feature = "directory")]
+impl FileAttributes {
+  fn from_metadata(metadata: &fs::Metadata) -> Self {
+    #[cfg(unix)]
+    let mode = {
+      use std::os::unix::fs::PermissionsExt;
+      Some(metadata.permissions().mode() & 0o7777)
+    };
+    #[cfg(not(unix))]
+    let mode = None;
+
+    let mtime = metadata
+      .modified()
+      .ok()
+      .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+      .map(|mtime| mtime.as_secs());
+
+    Self {
+      mode,
+      mtime,
+      ..Default::default()
+    }
+  }
+
+  /// Applies the attributes to the file at `path`. The mode is only restored on Unix, and
+  /// without its setuid, setgid and sticky bits.
+  pub fn restore(&self, path: &Path) -> SkynetResult<()> {
+    // Set the mtime first, since the mode may take away write access.
+    if let Some(mtime) = self.mtime {
+      let file = fs::OpenOptions::new().write(true).open(path).map_err(FileError)?;
+      file.set_modified(UNIX_EPOCH + Duration::from_secs(mtime)).map_err(FileError)?;
+    }
+
+    #[cfg(unix)]
+    if let Some(mode) = self.mode {
+      use std::os::unix::fs::PermissionsExt;
+      fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777)).map_err(FileError)?;
+    }
 
-    upload_data(client, data, opt).await
+    Ok(())
   }
 }
 
-pub async fn upload_directory(
-  client: &SkynetClient,
-  path: &Path,
-  opt: UploadOptions,
-) -> SkynetResult<String> {
+/// Reads every file under `path`, keyed by its `/`-separated path relative to `path`, plus a
+/// `DIRECTORY_METADATA_FILENAME` subfile with their attributes. Holes in sparse files aren't
+/// read from disk, but are still uploaded as zeros; their data ranges are recorded with the
+/// attributes.
+#[cfg(feature = "directory")]
+pub fn read_directory(path: &Path) -> SkynetResult<HashMap<String, (Mime, Bytes)>> {
   if !path.is_dir() {
     return Err(NotDirectory);
   }
 
   let mut data = HashMap::new();
+  let mut attributes = BTreeMap::new();
   let dirpath = path;
 
   for entry in WalkDir::new(dirpath) {
     let entry = entry.unwrap();
     let path = entry.path();
     if path.is_file() {
-      let filename = path.as_os_str().to_str().unwrap().to_string();
+      let filename = path
+        .strip_prefix(dirpath)
+        .unwrap()
+        .components()
+        .map(|component| component.as_os_str().to_str().unwrap())
+        .collect::<Vec<_>>()
+        .join("/");
+      let metadata = fs::metadata(path).map_err(FileError)?;
+      let (bytes, data_ranges) = sparse::read_sparse(path).map_err(FileError)?;
+      attributes.insert(filename.clone(), FileAttributes {
+        data_ranges,
+        ..FileAttributes::from_metadata(&metadata)
+      });
       let mime = if let Some(mime) = mime_guess::from_path(path).first() {
         mime
       } else {
         mime::APPLICATION_OCTET_STREAM
       };
-      let bytes = fs::read(path).map_err(FileError)?;
 
-      data.insert(filename, (mime, bytes));
+      data.insert(filename, (mime, bytes.into()));
     }
   }
 
-  let dirname = path.file_name().unwrap().to_str().unwrap().to_string();
+  let attributes = serde_json::to_vec(&attributes).map_err(JsonError)?;
+  data.insert(DIRECTORY_METADATA_FILENAME.into(), (mime::APPLICATION_JSON, attributes.into()));
+
+  Ok(data)
+}
+
+/// Reads a directory the way `opt` uploads it: runs the hooks on every file and keeps the
+/// attributes and checksums it asks for. The data is ready to send without running the hooks
+/// again.
+#[cfg(feature = "directory")]
+pub(crate) fn read_directory_for_upload(path: &Path, opt: &UploadOptions) -> SkynetResult<HashMap<String, (Mime, Bytes)>> {
+  let mut data = read_directory(path)?;
+
+  #[cfg(feature = "checksum")]
+  let checksums = opt.checksum.is_some();
+  #[cfg(not(feature = "checksum"))]
+  let checksums = false;
+  if opt.hooks.is_empty() && !checksums {
+    if !opt.preserve_attributes {
+      data.remove(DIRECTORY_METADATA_FILENAME);
+    }
+    return Ok(data);
+  }
+
+  let (_, attributes) = data.remove(DIRECTORY_METADATA_FILENAME).unwrap();
+  let mut attributes: BTreeMap<String, FileAttributes> = serde_json::from_slice(&attributes).map_err(JsonError)?;
+  let mut files = HashMap::with_capacity(data.len() + 1);
+  let mut file_attributes = BTreeMap::new();
+
+  for (filename, (mime, bytes)) in data {
+    let mut attributes = match opt.preserve_attributes {
+      true => attributes.remove(&filename).unwrap_or_default(),
+      false => FileAttributes::default(),
+    };
+    let part = run_hooks(&opt.hooks, filename, mime, bytes.clone())?;
+    // Holes found in the file on disk don't line up with contents a hook changed.
+    if !opt.hooks.is_empty() && part.data != bytes {
+      attributes.data_ranges = None;
+    }
+    #[cfg(feature = "checksum")]
+    if let Some(algorithm) = opt.checksum {
+      attributes.checksum = Some(checksum(algorithm, &part.data));
+    }
+    file_attributes.insert(part.filename.clone(), attributes);
+    files.insert(part.filename, (part.content_type, part.data));
+  }
+
+  if opt.preserve_attributes || checksums {
+    let attributes = serde_json::to_vec(&file_attributes).map_err(JsonError)?;
+    files.insert(DIRECTORY_METADATA_FILENAME.into(), (mime::APPLICATION_JSON, attributes.into()));
+  }
+  Ok(files)
+}
+
+/// A file that went up as part of a directory upload.
+#[cfg(feature = "directory")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadedFile {
+  /// `/`-separated path relative to the uploaded directory.
+  pub path: String,
+  /// Size in bytes as read from disk, before any compression.
+  pub size: u64,
+  pub content_type: Mime,
+}
+
+/// The skylink of an uploaded directory and every subfile it contains, sorted by path. This
+/// includes any `DIRECTORY_METADATA_FILENAME` subfile.
+#[cfg(feature = "directory")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirectoryUploadResult {
+  pub skylink: String,
+  pub files: Vec<UploadedFile>,
+}
+
+#[cfg(feature = "directory")]
+pub(crate) fn uploaded_files(data: &HashMap<String, (Mime, Bytes)>) -> Vec<UploadedFile> {
+  let mut files: Vec<_> = data
+    .iter()
+    .map(|(path, (mime, bytes))| UploadedFile {
+      path: path.clone(),
+      size: bytes.len() as u64,
+      content_type: mime.clone(),
+    })
+    .collect();
+  files.sort_by(|a, b| a.path.cmp(&b.path));
+  files
+}
+
+#[cfg(feature = "directory")]
+pub fn directory_name(path: &Path) -> String {
+  path.file_name().unwrap().to_str().unwrap().to_string()
+}
+
+#[cfg(feature = "directory")]
+pub async fn upload_directory_manifest(
+  client: &SkynetClient,
+  path: &Path,
+  opt: UploadOptions,
+) -> SkynetResult<DirectoryUploadResult> {
+  let _reservation = client.reserve_memory(Some(directory_size(path)?)).await;
+  let data = read_directory_for_upload(path, &opt)?;
+  let files = uploaded_files(&data);
+
+  let opt = UploadOptions {
+    custom_dirname: Some(directory_name(path)),
+    hooks: Vec::new(),
+    ..opt
+  };
+
+  let skylink = send_data(client, data, opt).await?.skylink;
+  Ok(DirectoryUploadResult { skylink, files })
+}
+
+/// Bytes the files under `path` take on disk, which reading them for an upload buffers.
+#[cfg(feature = "directory")]
+fn directory_size(path: &Path) -> SkynetResult<u64> {
+  let mut size = 0;
+  for entry in WalkDir::new(path) {
+    let path = entry.map_err(|err| FileError(err.into()))?.into_path();
+    if path.is_file() {
+      size += fs::metadata(&path).map_err(FileError)?.len();
+    }
+  }
+  Ok(size)
+}
+
+/// A file of a `DirectoryManifest`, uploaded as a skyfile of its own.
+#[cfg(all(feature = "directory", feature = "crypto"))]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManifestFile {
+  pub skylink: String,
+  pub size: u64,
+  /// Hex Sia merkle root of the contents as uploaded, i.e. after upload hooks ran.
+  pub merkle_root: String,
+  /// Hex hash of the upload options the skylink depends on, such as the skykey or compression.
+  /// Empty in manifests written before it was recorded, which never match.
+  #[serde(default)]
+  pub options: String,
+}
+
+/// The skylink of every file of a directory, keyed by `/`-separated path, including any
+/// `DIRECTORY_METADATA_FILENAME` subfile. Keep it between deploys, e.g. by uploading it as JSON,
+/// and pass it to the next `upload_directory_deduped`.
+#[cfg(all(feature = "directory", feature = "crypto"))]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DirectoryManifest {
+  pub files: BTreeMap<String, ManifestFile>,
+}
+
+/// The manifest of a deduplicated directory upload and which of its files were sent. Files are
+/// uploaded one by one, so there is no skylink for the directory as a whole; publish the manifest
+/// itself, or `upload_directory_manifest`, for that.
+#[cfg(all(feature = "directory", feature = "crypto"))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DedupedUploadResult {
+  pub manifest: DirectoryManifest,
+  /// Paths that were new or changed, sorted.
+  pub uploaded: Vec<String>,
+  /// Paths whose skylink in the previous manifest was reused, sorted.
+  pub reused: Vec<String>,
+}
+
+/// Uploads every file of a directory as its own skyfile, up to `opt.concurrency` at once, except
+/// those whose merkle root and upload options match their entry in `previous` and whose skylink
+/// the portal still serves, which are reused. Only the files that changed are sent, at the cost
+/// of a HEAD request per unchanged file. The result is a manifest only: no directory skylink is
+/// uploaded.
+#[cfg(all(feature = "directory", feature = "crypto"))]
+pub async fn upload_directory_deduped(
+  client: &SkynetClient,
+  path: &Path,
+  previous: Option<&DirectoryManifest>,
+  opt: UploadOptions,
+) -> SkynetResult<DedupedUploadResult> {
+  let _reservation = client.reserve_memory(Some(directory_size(path)?)).await;
+  let data: BTreeMap<_, _> = read_directory_for_upload(path, &opt)?.into_iter().collect();
 
   let opt = UploadOptions {
-    custom_dirname: Some(dirname),
+    custom_dirname: None,
+    hooks: Vec::new(),
     ..opt
   };
+  let exists_opt = MetadataOptions {
+    api_key: opt.api_key.clone(),
+    auth: opt.auth.clone(),
+    custom_user_agent: opt.custom_user_agent.clone(),
+    ..Default::default()
+  };
 
-  upload_data(client, data, opt).await
+  let options = reuse_key(&opt);
+  let uploads = data.into_iter().map(|(filename, (mime, bytes))| {
+    let (opt, exists_opt, options) = (opt.clone(), exists_opt.clone(), options.clone());
+    async move {
+      let merkle_root = merkle_root(&bytes).encode_hex::<String>();
+      let unchanged = previous
+        .and_then(|manifest| manifest.files.get(&filename))
+        .filter(|file| file.merkle_root == merkle_root && file.options == options);
+
+      let size = bytes.len() as u64;
+      let (skylink, reused) = match unchanged {
+        Some(file) if skylink_exists(client, &file.skylink, exists_opt).await? => (file.skylink.clone(), true),
+        _ => {
+          let mut file = HashMap::new();
+          file.insert(filename.clone(), (mime, bytes));
+          (send_data(client, file, opt).await?.skylink, false)
+        }
+      };
+      Ok::<_, SkynetError>((filename, ManifestFile { skylink, size, merkle_root, options }, reused))
+    }
+  });
+  let files: Vec<_> = stream::iter(uploads)
+    .buffer_unordered(opt.concurrency.max(1))
+    .try_collect()
+    .await?;
+
+  let mut result = DedupedUploadResult {
+    manifest: DirectoryManifest::default(),
+    uploaded: Vec::new(),
+    reused: Vec::new(),
+  };
+  for (filename, file, reused) in files {
+    if reused {
+      result.reused.push(filename.clone());
+    } else {
+      result.uploaded.push(filename.clone());
+    }
+    result.manifest.files.insert(filename, file);
+  }
+  result.uploaded.sort();
+  result.reused.sort();
+
+  Ok(result)
+}
+
+/// Hashes the options that change the skylink a file uploads to, or how it is written, so that a
+/// file is only reused if it would have been uploaded the same way.
+#[cfg(all(feature = "directory", feature = "crypto"))]
+fn reuse_key(opt: &UploadOptions) -> String {
+  let key = format!(
+    "{}\n{}\n{:?}\n{}\n{}\n{:?}\n{:?}\n{:?}",
+    opt.endpoint_path, opt.portal_file_fieldname, opt.siapath, opt.force, opt.root, opt.skylink_format, opt.skykey_name, opt.skykey_id,
+  );
+  #[cfg(feature = "encryption")]
+  let key = format!("{}\n{:?}", key, opt.skykey.as_ref().map(|skykey| skykey.id()));
+  #[cfg(feature = "gzip")]
+  let key = format!("{}\n{}", key, opt.gzip);
+  #[cfg(feature = "checksum")]
+  let key = format!("{}\n{:?}", key, opt.checksum);
+
+  let mut hash = [0; 32];
+  let mut hasher = Blake2b::new(32);
+  hasher.input(key.as_bytes());
+  hasher.result(&mut hash);
+  hash.encode_hex()
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
 
-  use crate::SkynetClientOptions;
+  use crate::{MockPortal, MockResponse, MockTransport, URI_SKYNET_PREFIX};
 
   #[tokio::test]
   async fn test_upload_data() {
-    let client = SkynetClient::default();
+    let portal = MockPortal::start().await;
+    let client = portal.client();
     let mut data = HashMap::new();
     data.insert("hello.txt".into(), (mime::TEXT_PLAIN, "hello world".into()));
     let res = upload_data(&client, data, UploadOptions::default()).await;
@@ -401,11 +936,95 @@ mod tests {
     assert!(skylink.starts_with(URI_SKYNET_PREFIX));
   }
 
+  #[tokio::test]
+  async fn test_upload_body_chunks() {
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::json(serde_json::json!({ "skylink": "AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng" })));
+    let client = transport.client("https://siasky.net");
+
+    let mut data = HashMap::new();
+    data.insert("a.txt".into(), (mime::TEXT_PLAIN, Bytes::from(vec![b'a'; 1000])));
+    data.insert("b.txt".into(), (mime::TEXT_PLAIN, Bytes::from_static(b"bee")));
+    let opt = UploadOptions { custom_dirname: Some("dir".into()), ..Default::default() };
+    upload_data(&client, data, opt).await.unwrap();
+
+    let req = transport.last_request().unwrap();
+    assert_eq!(req.headers["Content-Length"], req.body.len().to_string());
+    let body = String::from_utf8(req.body).unwrap();
+    assert!(body.contains(&format!("\r\n\r\n{}\r\n", "a".repeat(1000))));
+    assert!(body.contains("\r\n\r\nbee\r\n"));
+    assert!(body.ends_with("--\r\n"));
+  }
+
+  #[tokio::test]
+  async fn test_upload_hooks() {
+    use crate::{FnHook, UploadPart};
+
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::json(serde_json::json!({ "skylink": "AACi1FJOFAoRyl2YJyVz1yzsYrOfz18yXgnnbxNM0_UDng" })));
+    let client = transport.client("https://siasky.net");
+
+    let hooks: Vec<Arc<dyn UploadHook>> = vec![Arc::new(FnHook(|part: &mut UploadPart| {
+      if part.filename.ends_with(".exe") {
+        return Err(UploadRejected(part.filename.clone()));
+      }
+      part.filename = part.filename.replace(".txt", ".md");
+      part.content_type = "text/markdown".parse().unwrap();
+      part.data = Bytes::from_static(b"scrubbed");
+      Ok(())
+    }))];
+
+    let mut data = HashMap::new();
+    data.insert("a.txt".into(), (mime::TEXT_PLAIN, Bytes::from_static(b"secret")));
+    let opt = UploadOptions { hooks: hooks.clone(), ..Default::default() };
+    upload_data(&client, data, opt).await.unwrap();
+
+    let body = String::from_utf8(transport.last_request().unwrap().body).unwrap();
+    assert!(body.contains("filename=\"a.md\""));
+    assert!(body.contains("Content-Type: text/markdown\r\n\r\nscrubbed\r\n"));
+    assert!(!body.contains("secret"));
+
+    let mut data = HashMap::new();
+    data.insert("a.exe".into(), (mime::APPLICATION_OCTET_STREAM, Bytes::new()));
+    let res = upload_data(&client, data, UploadOptions { hooks: hooks.clone(), ..Default::default() }).await;
+    assert!(matches!(res, Err(UploadRejected(filename)) if filename == "a.exe"));
+    assert_eq!(transport.requests().len(), 1);
+
+    let mut data = HashMap::new();
+    data.insert("a.txt".into(), (mime::TEXT_PLAIN, Bytes::new()));
+    data.insert("a.md".into(), (mime::TEXT_PLAIN, Bytes::new()));
+    let res = upload_data(&client, data, UploadOptions { hooks, ..Default::default() }).await;
+    assert!(matches!(res, Err(UploadRejected(_))));
+    assert_eq!(transport.requests().len(), 1);
+  }
+
+  #[test]
+  fn test_estimate_upload() {
+    let small = estimate_upload(11);
+    assert_eq!(small.padded_size, SECTOR_SIZE);
+    assert_eq!(small.chunks, 0);
+    assert_eq!(small.storage, 10 * SECTOR_SIZE);
+
+    assert_eq!(estimate_upload(SECTOR_SIZE - LAYOUT_SIZE).chunks, 0);
+    assert_eq!(estimate_upload(SECTOR_SIZE - LAYOUT_SIZE + 1).chunks, 1);
+
+    let large = estimate_upload(50 << 20);
+    assert_eq!(large.chunks, 2);
+    assert_eq!(large.padded_size, SECTOR_SIZE + 80 * (1 << 20));
+    assert_eq!(large.storage, 10 * SECTOR_SIZE + 60 * SECTOR_SIZE);
+
+    fs::write("tmpestimate.txt", "hello world").unwrap();
+    let res = estimate_file_upload("tmpestimate.txt");
+    fs::remove_file("tmpestimate.txt").unwrap();
+    assert_eq!(res.unwrap(), small);
+  }
+
   #[tokio::test]
   async fn test_upload_file() {
-    let client = SkynetClient::default();
+    let portal = MockPortal::start().await;
+    let client = portal.client();
     fs::write("tmp.txt", "hello world").unwrap();
-    let res = upload_file(&client, &Path::new("tmp.txt"), UploadOptions::default()).await;
+    let res = upload_file(&client, Path::new("tmp.txt"), UploadOptions::default()).await;
     fs::remove_file("tmp.txt").unwrap();
     println!("{:?}", res);
     assert!(res.is_ok());
@@ -414,67 +1033,269 @@ mod tests {
   }
 
   #[tokio::test]
-  async fn test_upload_file_tus_anon() {
-    let client = SkynetClient::default();
-    // generate 50MB file to trigger TUS upload
-    fs::write("tmp.txt", (0..USE_TUS_THRESHOLD_BYTES+10000).map(|_| "X").collect::<String>()).unwrap();
-    let path = Path::new("tmp.txt");
-    let res = upload_data_tus(&client, path, UploadOptions::default()).await;
-    fs::remove_file("tmp.txt").unwrap();
-    println!("skylink: {:?}", &res);
-    assert!(res.is_ok());
-    let skylink = res.unwrap();
+  async fn test_upload_unicode_filenames() {
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let names = ["héllo wörld.txt", "quote\"d.txt", "日本/語.txt"];
+    let mut data = HashMap::new();
+    for name in &names {
+      data.insert(name.to_string(), (mime::TEXT_PLAIN, Bytes::from(name.to_string())));
+    }
+    let opt = UploadOptions { custom_dirname: Some("dir".into()), ..Default::default() };
+    let skylink = upload_data(&client, data, opt).await.unwrap();
+
+    for name in &names {
+      let subfile = format!("{}/{}", skylink, name);
+      let data = client.download_data(&subfile, Default::default()).await.unwrap();
+      assert_eq!(data, name.as_bytes());
+    }
   }
 
-  // make sure to set the SKYNET_API_KEY env var for this test
+  #[cfg(feature = "gzip")]
   #[tokio::test]
-  async fn test_upload_file_tus_auth() {
-    // generate 50MB file to trigger TUS upload
-    let path = Path::new("tmp.txt");
-    fs::write(path, (0..USE_TUS_THRESHOLD_BYTES+10000).map(|_| "X").collect::<String>()).unwrap();
-
-    let client = SkynetClient::new("https://skynetfree.net", SkynetClientOptions {
-      api_key: Some(std::env::var("SKYNET_API_KEY").unwrap()),
-      custom_user_agent: None
-    });
-
-    let res = upload_data_tus(&client, path, UploadOptions::default()).await;
-    fs::remove_file("tmp.txt").unwrap();
-    println!("skylink: {:?}", &res);
+  async fn test_upload_data_gzip() {
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let text = "hello world ".repeat(100);
+    let mut data = HashMap::new();
+    data.insert("hello.txt".into(), (mime::TEXT_PLAIN, text.clone().into()));
+    let skylink = upload_data(&client, data, UploadOptions {
+      gzip: true,
+      ..Default::default()
+    }).await.unwrap();
+
+    let raw = crate::download::download_data(&client, &skylink, Default::default()).await.unwrap();
+    assert!(raw.len() < text.len());
+    let res = crate::download::download_data(&client, &skylink, crate::DownloadOptions {
+      decompress: true,
+      ..Default::default()
+    }).await;
+    println!("{:?}", res);
     assert!(res.is_ok());
-    let skylink = res.unwrap();
+    assert_eq!(res.unwrap(), text.as_bytes());
   }
 
-  // make sure to set the SKYNET_API_KEY env var for this test
   #[tokio::test]
-  async fn test_upload_file_tus_auth_large() {
-    // generate 50MB file to trigger TUS upload
-    let path = Path::new("tmp.txt");
-    fs::write(path, (0..(700 * 1024 * 1024)).map(|_| "X").collect::<String>()).unwrap();
+  async fn test_upload_max_size() {
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let mut data = HashMap::new();
+    data.insert("hello.txt".into(), (mime::TEXT_PLAIN, Bytes::from("hello world")));
+    let res = upload_data(&client, data, UploadOptions {
+      max_size: Some(10),
+      ..Default::default()
+    }).await;
+    assert!(matches!(res, Err(UploadTooLarge(11, 10))));
+    assert_eq!(portal.skyfile_count(), 0);
+  }
 
-    let client = SkynetClient::new("https://skynetfree.net", SkynetClientOptions {
-      api_key: Some(std::env::var("SKYNET_API_KEY").unwrap()),
-      custom_user_agent: None
-    });
+  #[cfg(feature = "account")]
+  #[tokio::test]
+  async fn test_upload_account_limits() {
+    use crate::{MockResponse, MockTransport};
+
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::json(serde_json::json!({
+      "tierID": 1,
+      "tierName": "free",
+      "maxUploadSize": 5,
+      "storageLimit": 100,
+    })));
+    let client = transport.client("https://siasky.net");
+    let mut data = HashMap::new();
+    data.insert("hello.txt".into(), (mime::TEXT_PLAIN, Bytes::from("hello world")));
+    let res = upload_data(&client, data, UploadOptions {
+      api_key: Some("foo".into()),
+      check_account_limits: true,
+      ..Default::default()
+    }).await;
+    println!("{:?}", res);
+    assert!(matches!(res, Err(UploadTooLarge(11, 5))));
+    let requests = transport.requests();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].uri.path(), "/user/limits");
 
-    let res = upload_data_tus(&client, path, UploadOptions::default()).await;
-    fs::remove_file("tmp.txt").unwrap();
-    println!("skylink: {:?}", &res);
-    assert!(res.is_ok());
-    let skylink = res.unwrap();
+    transport.push_response(MockResponse::new(hyper::StatusCode::UNAUTHORIZED, "unauthorized"));
+    let mut data = HashMap::new();
+    data.insert("hello.txt".into(), (mime::TEXT_PLAIN, Bytes::from("hello world")));
+    let res = upload_data(&client, data, UploadOptions {
+      api_key: Some("foo".into()),
+      check_account_limits: true,
+      ..Default::default()
+    }).await;
+    assert!(res.is_err());
+    assert_eq!(transport.requests().len(), 2);
   }
 
+  #[cfg(feature = "directory")]
   #[tokio::test]
   async fn test_upload_directory() {
-    let client = SkynetClient::default();
+    let portal = MockPortal::start().await;
+    let client = portal.client();
     fs::create_dir("tmpdir").unwrap();
     fs::write("tmpdir/1.txt", "hello 1").unwrap();
     fs::write("tmpdir/2.txt", "hello 2").unwrap();
-    let res = upload_directory(&client, &Path::new("tmpdir"), UploadOptions::default()).await;
+    let res = upload_directory_manifest(&client, Path::new("tmpdir"), UploadOptions::default())
+      .await
+      .map(|result| result.skylink);
     fs::remove_dir_all("tmpdir").unwrap();
     println!("{:?}", res);
     assert!(res.is_ok());
     let skylink = res.unwrap();
     assert!(skylink.starts_with(URI_SKYNET_PREFIX));
   }
+
+  #[cfg(feature = "directory")]
+  #[tokio::test]
+  async fn test_upload_directory_manifest() {
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    fs::create_dir_all("tmpdir_manifest/sub").unwrap();
+    fs::write("tmpdir_manifest/index.html", "<p>hi</p>").unwrap();
+    fs::write("tmpdir_manifest/sub/data.json", "{}").unwrap();
+    let res = upload_directory_manifest(&client, Path::new("tmpdir_manifest"), UploadOptions::default()).await;
+    let opt = UploadOptions { preserve_attributes: true, ..Default::default() };
+    let preserved = upload_directory_manifest(&client, Path::new("tmpdir_manifest"), opt).await;
+    fs::remove_dir_all("tmpdir_manifest").unwrap();
+
+    let result = res.unwrap();
+    assert!(!result.skylink.is_empty());
+    let files: Vec<_> = result.files.iter().map(|file| (file.path.as_str(), file.size)).collect();
+    assert_eq!(files, &[("index.html", 9), ("sub/data.json", 2)]);
+    assert_eq!(result.files[0].content_type, mime::TEXT_HTML);
+    assert_eq!(preserved.unwrap().files[0].path, DIRECTORY_METADATA_FILENAME);
+  }
+
+  #[cfg(feature = "encryption")]
+  #[tokio::test]
+  async fn test_upload_encrypted() {
+    use crate::{DownloadOptions, SkykeyOptions};
+
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let skykey = client.create_skykey("photos", "private-id", SkykeyOptions::default()).await.unwrap();
+
+    let mut data = HashMap::new();
+    data.insert("hello.txt".into(), (mime::TEXT_PLAIN, Bytes::from("hello world")));
+    let opt = UploadOptions { skykey_name: Some("photos".into()), ..Default::default() };
+    let skylink = upload_data(&client, data.clone(), opt).await.unwrap();
+    assert_eq!(portal.skyfile_skykey_id(&skylink).as_deref(), Some(skykey.id()));
+    let downloaded = client.download_data(&skylink, DownloadOptions::default()).await.unwrap();
+    assert_eq!(downloaded, "hello world");
+
+    // A `Skykey` is sent by ID alone, even alongside a name.
+    let opt = UploadOptions { skykey: Some(skykey.clone()), skykey_name: Some("other".into()), ..Default::default() };
+    let skylink = upload_data(&client, data.clone(), opt).await.unwrap();
+    assert_eq!(portal.skyfile_skykey_id(&skylink).as_deref(), Some(skykey.id()));
+
+    let opt = UploadOptions { skykey_name: Some("missing".into()), ..Default::default() };
+    assert!(matches!(upload_data(&client, data, opt).await, Err(PortalResponse(_))));
+  }
+
+  #[cfg(all(feature = "encryption", feature = "directory"))]
+  #[tokio::test]
+  async fn test_upload_directory_encrypted() {
+    use crate::{DownloadOptions, SkykeyOptions};
+
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let skykey = client.create_skykey("site", "private-id", SkykeyOptions::default()).await.unwrap();
+    fs::create_dir("tmpdir_encrypted").unwrap();
+    fs::write("tmpdir_encrypted/index.html", "<p>secret</p>").unwrap();
+    let opt = UploadOptions { skykey_id: Some(skykey.id().into()), ..Default::default() };
+    let res = upload_directory_manifest(&client, Path::new("tmpdir_encrypted"), opt).await;
+    fs::remove_dir_all("tmpdir_encrypted").unwrap();
+
+    let skylink = res.unwrap().skylink;
+    assert_eq!(portal.skyfile_skykey_id(&skylink).as_deref(), Some(skykey.id()));
+    let downloaded = client.download_data(&skylink, DownloadOptions::default()).await.unwrap();
+    assert_eq!(downloaded, "<p>secret</p>");
+  }
+
+  #[cfg(feature = "tus")]
+  #[test]
+  fn test_upload_tus_skykey() {
+    let client = SkynetClient::new("https://siasky.net", Default::default());
+    let opt = UploadOptions { skykey_name: Some("photos".into()), ..Default::default() };
+    let uri = crate::tus::upload_data_tus_uri(&client, Path::new("large.bin"), &opt).unwrap();
+    assert_eq!(uri.to_string(), "https://siasky.net/skynet/tus?skykeyname=photos");
+  }
+
+  #[cfg(all(feature = "directory", feature = "crypto"))]
+  #[tokio::test]
+  async fn test_upload_directory_deduped() {
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    fs::create_dir_all("tmpdir_deduped/sub").unwrap();
+    fs::write("tmpdir_deduped/index.html", "<p>v1</p>").unwrap();
+    fs::write("tmpdir_deduped/sub/big.bin", vec![7; 4096]).unwrap();
+    let first = upload_directory_deduped(&client, Path::new("tmpdir_deduped"), None, UploadOptions::default()).await;
+    fs::write("tmpdir_deduped/index.html", "<p>v2</p>").unwrap();
+    let first = first.unwrap();
+    let second = upload_directory_deduped(&client, Path::new("tmpdir_deduped"), Some(&first.manifest), UploadOptions::default()).await;
+    let opt = UploadOptions { skylink_format: SkylinkFormat::Raw, concurrency: 1, ..Default::default() };
+    let third = upload_directory_deduped(&client, Path::new("tmpdir_deduped"), second.as_ref().ok().map(|res| &res.manifest), opt).await;
+    fs::remove_dir_all("tmpdir_deduped").unwrap();
+
+    assert!(first.reused.is_empty());
+    assert_eq!(first.uploaded.len(), 2);
+    let second = second.unwrap();
+    assert!(second.uploaded.contains(&"index.html".to_string()));
+    assert!(second.reused.contains(&"sub/big.bin".to_string()));
+    assert_eq!(second.manifest.files["sub/big.bin"], first.manifest.files["sub/big.bin"]);
+    assert_ne!(second.manifest.files["index.html"].skylink, first.manifest.files["index.html"].skylink);
+    assert_eq!(portal.skyfile_count(), 2 + second.uploaded.len());
+    let third = third.unwrap();
+    assert!(third.reused.is_empty());
+    assert_eq!(third.uploaded, vec!["index.html".to_string(), "sub/big.bin".to_string()]);
+    assert!(!third.manifest.files["index.html"].skylink.starts_with(URI_SKYNET_PREFIX));
+  }
+
+  #[tokio::test]
+  async fn test_upload_data_with_result() {
+    let portal = MockPortal::start().await;
+    let client = portal.client();
+    let mut data = HashMap::new();
+    data.insert("hello.txt".into(), (mime::TEXT_PLAIN, Bytes::from("hello world")));
+    let res = upload_data_with_result(&client, data.clone(), UploadOptions::default()).await.unwrap();
+    assert_eq!(res.size, 11);
+    assert_eq!(res.bitfield, Some(0));
+    assert_eq!(res.merkle_root.map(|root| root.len()), Some(64));
+
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::json(serde_json::json!({ "skylink": "AAA" })));
+    let client = transport.client("https://siasky.net");
+    let res = upload_data_with_result(&client, data, UploadOptions::default()).await.unwrap();
+    assert_eq!(res, UploadResult { skylink: "sia://AAA".into(), merkle_root: None, bitfield: None, size: 11 });
+  }
+
+  #[tokio::test]
+  async fn test_upload_siapath() {
+    let transport = MockTransport::new();
+    transport.push_response(MockResponse::json(serde_json::json!({ "skylink": "AAA" })));
+    let client = transport.client("https://siasky.net");
+    let mut data = HashMap::new();
+    data.insert("hello.txt".into(), (mime::TEXT_PLAIN, Bytes::from("hello world")));
+    let opt = UploadOptions {
+      siapath: Some("/backups/2021 06/hello.txt".into()),
+      force: true,
+      root: true,
+      ..Default::default()
+    };
+    upload_data(&client, data, opt.clone()).await.unwrap();
+    let uri = transport.last_request().unwrap().uri;
+    assert_eq!(uri.path(), "/skynet/skyfile/backups/2021%2006/hello.txt");
+    assert_eq!(uri.query(), Some("force=true&root=true"));
+
+    // Too large for a multipart upload by default, but tus can't take a siapath.
+    #[cfg(feature = "tus")]
+    {
+      transport.push_response(MockResponse::json(serde_json::json!({ "skylink": "AAA" })));
+      fs::File::create("tmpsiapath.bin").unwrap().set_len(USE_TUS_THRESHOLD_BYTES).unwrap();
+      let res = upload_file(&client, Path::new("tmpsiapath.bin"), opt).await;
+      fs::remove_file("tmpsiapath.bin").unwrap();
+      res.unwrap();
+      assert_eq!(transport.last_request().unwrap().uri.path(), "/skynet/skyfile/backups/2021%2006/hello.txt");
+    }
+  }
 }
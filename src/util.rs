@@ -1,29 +1,204 @@
-use std::collections::HashMap;
-use std::iter::FromIterator;
+#[cfg(feature = "tus")]
+use std::{collections::HashMap, iter::FromIterator};
+#[cfg(feature = "tus")]
 use std::str::FromStr;
+use std::{convert::Infallible, fs::File, future::Future, io, path::Path, time::Duration};
+use bytes::Bytes;
+use futures_util::stream;
 use http::uri::Authority;
-use hyper::Uri;
+use hyper::{body, http::{response::Parts, Extensions}, Body, Response, StatusCode, Uri};
+use serde::de::DeserializeOwned;
+use textnonce::TextNonce;
+use tokio::task::JoinError;
+use crate::{RequestContext, SkynetError, SkynetError::*, SkynetResult};
 
 pub const DEFAULT_PORTAL_URL: &str = "https://siasky.net";
 pub const URI_SKYNET_PREFIX: &str = "sia://";
 
+/// A body that sends `chunks` one after another without joining them into one buffer. Its length
+/// isn't known up front, so set `Content-Length` on the request unless there is only one chunk.
+pub(crate) fn chunked_body(mut chunks: Vec<Bytes>) -> Body {
+  match chunks.len() {
+    0 => Body::empty(),
+    1 => Body::from(chunks.remove(0)),
+    _ => Body::wrap_stream(stream::iter(chunks.into_iter().map(Ok::<_, Infallible>))),
+  }
+}
+
+/// Wraps `err` in the `RequestError` the request of a response, going by its `extensions`, would
+/// have failed with, so it says which portal and endpoint it came from.
+pub(crate) fn in_context(extensions: &Extensions, err: SkynetError) -> SkynetError {
+  match extensions.get::<RequestContext>() {
+    Some(context) => RequestError(context.clone(), Box::new(err)),
+    None => err,
+  }
+}
+
+/// The error for a portal response that didn't succeed: a `PortalResponse` describing it, in the
+/// context of its request.
+pub(crate) fn status_error(extensions: &Extensions, uri: &Uri, status: StatusCode, body: &[u8]) -> SkynetError {
+  in_context(extensions, PortalResponse(describe_response(uri, status, &String::from_utf8_lossy(body))))
+}
+
+/// Reads a portal response body in full. A failed read is wrapped in the context of its request.
+pub(crate) async fn read_body(parts: &Parts, body: Body) -> SkynetResult<Bytes> {
+  body::to_bytes(body).await.map_err(|err| in_context(&parts.extensions, HyperError(err)))
+}
+
+/// `read_body` for a response whose status and headers aren't needed any more.
+pub(crate) async fn read_response(res: Response<Body>) -> SkynetResult<Bytes> {
+  let (parts, body) = res.into_parts();
+  read_body(&parts, body).await
+}
+
+/// How much of a response body `describe_response` keeps.
+const BODY_SNIPPET_LEN: usize = 200;
+
+/// Summarizes a portal response for error messages: the endpoint path (leaving out the query,
+/// which may hold an API key), the status and the start of the body.
+pub(crate) fn describe_response(uri: &Uri, status: StatusCode, body: &str) -> String {
+  let body = body.trim();
+  let mut snippet: String = body.chars().take(BODY_SNIPPET_LEN).collect();
+  if snippet.len() < body.len() {
+    snippet.push_str("...");
+  }
+  format!("{} returned {}: {}", uri.path(), status, snippet)
+}
+
+/// Parses a portal response body as JSON. On failure the `PortalResponse` error describes the
+/// response, so a rate-limit page can be told apart from a schema mismatch.
+pub(crate) fn parse_response<T: DeserializeOwned>(uri: &Uri, status: StatusCode, body: &str) -> SkynetResult<T> {
+  serde_json::from_str(body)
+    .map_err(|err| PortalResponse(format!("{} (invalid JSON: {})", describe_response(uri, status, body), err)))
+}
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+pub(crate) fn new_request_id() -> SkynetResult<String> {
+  TextNonce::sized_urlsafe(24).map(TextNonce::into_string).map_err(TextNonceError)
+}
+
+#[cfg(feature = "tus")]
 pub fn make_reqwest_headers(headers: HashMap<String, String>) -> reqwest::header::HeaderMap {
   reqwest::header::HeaderMap::from_iter(headers
                                             .into_iter()
                                             .map(|(name, value)| (
-                                              reqwest::header::HeaderName::from_str(&*name)
+                                              reqwest::header::HeaderName::from_str(&name)
                                                   .expect("failed to parse header name"),
-                                              reqwest::header::HeaderValue::from_str(&*value)
+                                              reqwest::header::HeaderValue::from_str(&value)
                                                   .expect("failed to parse header value")
                                             )))
 }
 
+/// A query parameter of the portal API.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum QueryParam {
+  Filename,
+  SkykeyName,
+  SkykeyId,
+  Skykey,
+  Name,
+  Type,
+  Id,
+  PublicKey,
+  DataKey,
+  Timeout,
+  Offset,
+  PageSize,
+  Force,
+  Root,
+  /// Archive format a directory is served as, e.g. `tar`.
+  Format,
+  /// The subfile a directory skylink serves when no path is given, e.g. `/index.html`.
+  DefaultPath,
+  /// An API key sent in the URL, e.g. in a shared link, where a header can't go.
+  ApiKey,
+  /// A parameter without a variant of its own, by name.
+  Custom(String),
+}
+
+impl QueryParam {
+  /// The name the portal expects.
+  pub fn name(&self) -> &str {
+    match self {
+      Self::Filename => "filename",
+      Self::SkykeyName => "skykeyname",
+      Self::SkykeyId => "skykeyid",
+      Self::Skykey => "skykey",
+      Self::Name => "name",
+      Self::Type => "type",
+      Self::Id => "id",
+      Self::PublicKey => "publickey",
+      Self::DataKey => "datakey",
+      Self::Timeout => "timeout",
+      Self::Offset => "offset",
+      Self::PageSize => "pageSize",
+      Self::Force => "force",
+      Self::Root => "root",
+      Self::Format => "format",
+      Self::DefaultPath => "defaultpath",
+      Self::ApiKey => "apiKey",
+      Self::Custom(name) => name,
+    }
+  }
+}
+
+/// The query of a portal request, kept in the order the parameters were added. Names and values
+/// are percent-encoded when the URI is built.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryParams(Vec<(QueryParam, String)>);
+
+impl QueryParams {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Sets `param` to `value`, replacing any value it already had.
+  pub fn set(&mut self, param: QueryParam, value: impl ToString) {
+    let value = value.to_string();
+    match self.0.iter_mut().find(|(existing, _)| *existing == param) {
+      Some((_, existing)) => *existing = value,
+      None => self.0.push((param, value)),
+    }
+  }
+
+  /// Like `set`, taking and returning the parameters to chain calls.
+  pub fn with(mut self, param: QueryParam, value: impl ToString) -> Self {
+    self.set(param, value);
+    self
+  }
+
+  /// Sets `param` if `value` is `Some`.
+  pub fn set_opt(&mut self, param: QueryParam, value: Option<impl ToString>) {
+    if let Some(value) = value {
+      self.set(param, value);
+    }
+  }
+
+  pub fn get(&self, param: &QueryParam) -> Option<&str> {
+    self.0.iter().find(|(existing, _)| existing == param).map(|(_, value)| value.as_str())
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.0.is_empty()
+  }
+
+  /// `name=value` pairs joined by `&`, without the leading `?`.
+  pub(crate) fn encode(&self) -> String {
+    self
+      .0
+      .iter()
+      .map(|(param, value)| format!("{}={}", encode_component(param.name()), encode_component(value)))
+      .collect::<Vec<_>>()
+      .join("&")
+  }
+}
+
 pub fn make_uri(
   portal_url: &str,
   path: String,
-  api_key: Option<String>,
+  _api_key: Option<String>,
   extra_path: Option<String>,
-  query: HashMap<String, String>,
+  query: QueryParams,
 ) -> Uri {
   let parts: Vec<&str> = portal_url.split("://").collect();
   let scheme = parts[0];
@@ -38,7 +213,7 @@ pub fn make_uri(
   let authority: Authority = host.parse().unwrap();
 
   let extra_path = if let Some(extra_path) = extra_path {
-    format!("/{}", extra_path)
+    format!("/{}", encode_path(&extra_path))
   } else {
     "".to_string()
   };
@@ -46,12 +221,7 @@ pub fn make_uri(
   let query = if query.is_empty() {
     "".to_string()
   } else {
-    let query = query
-      .iter()
-      .map(|(k, v)| format!("{}={}", k, v))
-      .collect::<Vec<String>>()
-      .join("&");
-    format!("?{}", query)
+    format!("?{}", query.encode())
   };
 
   let path_and_query = format!("{}{}{}", path, extra_path, query);
@@ -63,3 +233,268 @@ pub fn make_uri(
     .build()
     .unwrap()
 }
+
+/// Percent-encodes every byte of `value` that `keep` rejects.
+fn percent_encode(value: &str, keep: impl Fn(u8) -> bool) -> String {
+  let mut encoded = String::with_capacity(value.len());
+  for byte in value.bytes() {
+    if keep(byte) {
+      encoded.push(byte as char);
+    } else {
+      encoded.push_str(&format!("%{:02X}", byte));
+    }
+  }
+  encoded
+}
+
+/// Escapes everything but unreserved characters, e.g. for a query value or a file name.
+pub(crate) fn encode_component(value: &str) -> String {
+  percent_encode(value, |byte| byte.is_ascii_alphanumeric() || b"-._~".contains(&byte))
+}
+
+/// Decodes `%XX` escapes, leaving malformed ones as they are.
+pub(crate) fn percent_decode(value: &str) -> String {
+  let bytes = value.as_bytes();
+  let mut decoded = Vec::with_capacity(bytes.len());
+  let mut i = 0;
+  while i < bytes.len() {
+    let escaped = bytes
+      .get(i + 1..i + 3)
+      .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))
+      .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+    match (bytes[i], escaped) {
+      (b'%', Some(byte)) => {
+        decoded.push(byte);
+        i += 3;
+      }
+      (byte, _) => {
+        decoded.push(byte);
+        i += 1;
+      }
+    }
+  }
+  String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Escapes a URI path, e.g. a subfile path with spaces or non-ASCII characters. Existing `%XX`
+/// escapes are kept as they are.
+pub(crate) fn encode_path(path: &str) -> String {
+  percent_encode(path, |byte| byte.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=:@/%".contains(&byte))
+}
+
+/// Escapes each `%` that doesn't start a `%XX` escape, so that it reaches the portal as itself.
+pub(crate) fn escape_stray_percents(value: &str) -> String {
+  let bytes = value.as_bytes();
+  let mut escaped = String::with_capacity(value.len());
+  for (i, c) in value.char_indices() {
+    let starts_escape = bytes.get(i + 1..i + 3).is_some_and(|hex| hex.iter().all(u8::is_ascii_hexdigit));
+    match c {
+      '%' if !starts_escape => escaped.push_str("%25"),
+      c => escaped.push(c),
+    }
+  }
+  escaped
+}
+
+/// The `Content-Disposition` of a multipart form field. Quotes and backslashes in `filename`
+/// are escaped. If it isn't printable ASCII it also goes in an RFC 5987 `filename*` parameter,
+/// and the plain `filename` gets an ASCII fallback.
+pub(crate) fn content_disposition(name: &str, filename: &str) -> String {
+  let quote = |value: &str| {
+    value
+      .chars()
+      .map(|c| match c {
+        '"' | '\\' => format!("\\{}", c),
+        c if c.is_ascii() && !c.is_ascii_control() => c.to_string(),
+        _ => "_".to_string(),
+      })
+      .collect::<String>()
+  };
+
+  let mut disposition = format!("form-data; name=\"{}\"; filename=\"{}\"", quote(name), quote(filename));
+  if filename.chars().any(|c| !c.is_ascii() || c.is_ascii_control()) {
+    let attr_char = |byte: u8| byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte);
+    disposition.push_str(&format!("; filename*=UTF-8''{}", percent_encode(filename, attr_char)));
+  }
+  disposition
+}
+
+/// The filename in a `Content-Disposition` value, preferring `filename*` over `filename`.
+pub(crate) fn content_disposition_filename(value: &str) -> Option<String> {
+  let mut filename = None;
+  let mut rest = value;
+
+  while let Some(start) = rest.find(';') {
+    rest = rest[start + 1..].trim_start();
+    let eq = rest.find('=')?;
+    let key = rest[..eq].trim().to_ascii_lowercase();
+    rest = &rest[eq + 1..];
+
+    let param = if let Some(quoted) = rest.strip_prefix('"') {
+      let mut param = String::new();
+      let mut end = quoted.len();
+      let mut chars = quoted.char_indices();
+      while let Some((i, c)) = chars.next() {
+        match c {
+          '\\' => param.extend(chars.next().map(|(_, c)| c)),
+          '"' => {
+            end = i + 1;
+            break;
+          }
+          c => param.push(c),
+        }
+      }
+      rest = &quoted[end..];
+      param
+    } else {
+      let end = rest.find(';').unwrap_or(rest.len());
+      let param = rest[..end].trim().to_string();
+      rest = &rest[end..];
+      param
+    };
+
+    match key.as_str() {
+      "filename*" => {
+        if let Some(encoded) = param.splitn(3, '\'').nth(2) {
+          return Some(percent_decode(encoded));
+        }
+      }
+      "filename" => filename = Some(param),
+      _ => {}
+    }
+  }
+
+  filename
+}
+
+/// The result of a task from `spawn_blocking`. Panics in the task are resumed on the caller, and
+/// a task cancelled by the runtime shutting down fails with `TaskCancelled`.
+pub(crate) fn joined<T>(res: Result<T, JoinError>) -> SkynetResult<T> {
+  match res {
+    Ok(value) => Ok(value),
+    Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+    Err(_) => Err(TaskCancelled),
+  }
+}
+
+/// Runs CPU-bound work such as hashing and signing on tokio's blocking pool so it doesn't stall
+/// other tasks on the executor. Panics in `f` are resumed on the caller, and work cancelled by
+/// the runtime shutting down fails with `TaskCancelled`.
+pub(crate) async fn blocking<F, T>(f: F) -> SkynetResult<T>
+where
+  F: FnOnce() -> SkynetResult<T> + Send + 'static,
+  T: Send + 'static,
+{
+  joined(tokio::task::spawn_blocking(f).await)?
+}
+
+/// Bytes free for unprivileged use on the filesystem holding `dir`, where that can be told.
+#[cfg(unix)]
+pub(crate) fn available_space(dir: &Path) -> io::Result<Option<u64>> {
+  use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+  let dir = CString::new(dir.as_os_str().as_bytes()).map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+  let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+  if unsafe { libc::statvfs(dir.as_ptr(), &mut stat) } != 0 {
+    return Err(io::Error::last_os_error());
+  }
+  Ok(Some(stat.f_bavail as u64 * stat.f_frsize as u64))
+}
+
+#[cfg(not(unix))]
+pub(crate) fn available_space(_dir: &Path) -> io::Result<Option<u64>> {
+  Ok(None)
+}
+
+/// Grows `file` to `size` bytes with its blocks allocated, so later writes can't run out of
+/// space. Elsewhere than Linux the file is only extended, which may leave a hole.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub(crate) fn allocate(file: &File, size: u64) -> io::Result<()> {
+  use std::os::unix::io::AsRawFd;
+
+  // `posix_fallocate` returns the error rather than setting errno.
+  match unsafe { libc::posix_fallocate(file.as_raw_fd(), 0, size as libc::off_t) } {
+    0 => Ok(()),
+    err => Err(io::Error::from_raw_os_error(err)),
+  }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub(crate) fn allocate(file: &File, size: u64) -> io::Result<()> {
+  file.set_len(size)
+}
+
+/// Fails `fut` with `DeadlineExceeded` if it hasn't finished within `deadline`.
+pub(crate) async fn with_deadline<F, T>(deadline: Option<Duration>, fut: F) -> SkynetResult<T>
+where
+  F: Future<Output = SkynetResult<T>>,
+{
+  match deadline {
+    Some(deadline) => tokio::time::timeout(deadline, fut)
+      .await
+      .unwrap_or(Err(DeadlineExceeded(deadline))),
+    None => fut.await,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_response() {
+    let uri: Uri = "https://siasky.net/skynet/skyfile?apikey=secret".parse().unwrap();
+    let value: serde_json::Value = parse_response(&uri, StatusCode::OK, "{\"a\":1}").unwrap();
+    assert_eq!(value["a"], 1);
+
+    let page = format!("<html>{}</html>", "x".repeat(500));
+    let err = parse_response::<serde_json::Value>(&uri, StatusCode::TOO_MANY_REQUESTS, &page).unwrap_err();
+    let message = match err {
+      PortalResponse(message) => message,
+      err => panic!("unexpected error {:?}", err),
+    };
+    assert!(message.starts_with("/skynet/skyfile returned 429 Too Many Requests: <html>xxx"));
+    assert!(message.contains("...") && message.contains("invalid JSON"));
+    assert!(!message.contains("secret"));
+    assert!(message.len() < 350);
+  }
+
+  #[test]
+  fn test_content_disposition() {
+    let plain = content_disposition("file", "a \"b\".txt");
+    assert_eq!(plain, r#"form-data; name="file"; filename="a \"b\".txt""#);
+    assert_eq!(content_disposition_filename(&plain), Some("a \"b\".txt".into()));
+
+    let unicode = content_disposition("files[]", "dir/héllo\nwörld.txt");
+    assert_eq!(
+      unicode,
+      "form-data; name=\"files[]\"; filename=\"dir/h_llo_w_rld.txt\"; filename*=UTF-8''dir%2Fh%C3%A9llo%0Aw%C3%B6rld.txt");
+    assert_eq!(content_disposition_filename(&unicode), Some("dir/héllo\nwörld.txt".into()));
+
+    assert_eq!(content_disposition_filename("attachment; filename=plain.txt"), Some("plain.txt".into()));
+    assert_eq!(content_disposition_filename("inline"), None);
+  }
+
+  #[test]
+  fn test_encode_path() {
+    assert_eq!(encode_path("dir/héllo wörld.txt"), "dir/h%C3%A9llo%20w%C3%B6rld.txt");
+    assert_eq!(encode_path("already%20encoded"), "already%20encoded");
+    assert_eq!(escape_stray_percents("100%/a%2Fb/%zz%"), "100%25/a%2Fb/%25zz%25");
+    assert_eq!(percent_decode("h%C3%A9llo%20w%C3%B6rld%zz"), "héllo wörld%zz");
+  }
+
+  #[test]
+  fn test_query_params() {
+    let mut query = QueryParams::new()
+      .with(QueryParam::Skykey, "ab+c/d==")
+      .with(QueryParam::Custom("x y".into()), "1");
+    query.set(QueryParam::Skykey, "ab+c/d=");
+    query.set_opt(QueryParam::SkykeyId, None::<String>);
+    query.set_opt(QueryParam::PageSize, Some(100));
+    assert_eq!(query.get(&QueryParam::Skykey), Some("ab+c/d="));
+    assert_eq!(query.encode(), "skykey=ab%2Bc%2Fd%3D&x%20y=1&pageSize=100");
+
+    let uri = make_uri("https://siasky.net", "/skynet/skyfile".into(), None, None, query);
+    assert_eq!(uri.query(), Some("skykey=ab%2Bc%2Fd%3D&x%20y=1&pageSize=100"));
+  }
+}